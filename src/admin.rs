@@ -0,0 +1,321 @@
+//! Facade mirroring the messaging API of the Firebase Admin SDKs.
+//!
+//! Method names, arguments and results follow `getMessaging()` of the Node.js SDK and `messaging.Client` of the Go SDK,
+//! so push services can be ported without redesigning their call sites.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! use firebase_messaging_rs::{admin::{Messaging, MulticastMessage}, FCMClient};
+//!
+//! let messaging = Messaging::new(FCMClient::new().await?);
+//! let response = messaging
+//!     .send_each_for_multicast(
+//!         &MulticastMessage {
+//!             tokens: vec!["token-a".to_string(), "token-b".to_string()],
+//!             ..Default::default()
+//!         },
+//!         false,
+//!     )
+//!     .await?;
+//! println!("{} messages were sent", response.success_count);
+//! messaging.subscribe_to_topic(&["token-a".to_string()], "/topics/news").await?;
+//! # Ok(())
+//! # }
+//! ```
+use crate::{
+    fcm::{
+        android::AndroidConfig, ios::ApnsConfig, webpush::WebPushConfig, FCMApi, FCMError,
+        FcmOptions, Message, MessageRef, Notification,
+    },
+    topic::{TopicManagementError, TopicManagementSupport},
+    FCMClient, GenericGoogleRestAPISupport,
+};
+use futures::{stream, StreamExt};
+use std::collections::HashMap;
+
+/// The maximum number of messages accepted by [Messaging::send_each] and tokens by [Messaging::send_each_for_multicast].
+pub const MAX_MESSAGES_PER_BATCH: usize = 500;
+
+/// The number of requests in flight in [Messaging::send_each].
+pub const SEND_EACH_CONCURRENCY: usize = 16;
+
+/// Messaging API with the naming of the Firebase Admin SDKs. See [crate::admin].
+#[derive(Debug, Clone)]
+pub struct Messaging<C = FCMClient> {
+    client: C,
+}
+
+/// A message sent to many registration tokens. Every token receives the same payload.
+#[derive(Default)]
+pub struct MulticastMessage {
+    pub tokens: Vec<String>,
+    pub data: Option<HashMap<String, String>>,
+    pub notification: Option<Notification>,
+    pub android: Option<AndroidConfig>,
+    pub webpush: Option<WebPushConfig>,
+    pub apns: Option<ApnsConfig>,
+    pub fcm_options: Option<FcmOptions>,
+}
+
+impl std::fmt::Debug for MulticastMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MulticastMessage")
+            .field("tokens", &format_args!("<{} tokens>", self.tokens.len()))
+            .field("data", &self.data)
+            .field("notification", &self.notification)
+            .field("android", &self.android)
+            .field("webpush", &self.webpush)
+            .field("apns", &self.apns)
+            .field("fcm_options", &self.fcm_options)
+            .finish()
+    }
+}
+
+impl MulticastMessage {
+    fn to_token_ref<'a>(&'a self, token: &'a str) -> MessageRef<'a> {
+        MessageRef::Token {
+            name: None,
+            data: self.data.as_ref(),
+            token,
+            fcm_options: self.fcm_options.as_ref(),
+            notification: self.notification.as_ref(),
+            android: self.android.as_ref(),
+            webpush: self.webpush.as_ref(),
+            apns: self.apns.as_ref(),
+        }
+    }
+}
+
+/// Result of a message sent by [Messaging::send_each] or [Messaging::send_each_for_multicast].
+#[derive(Debug, Clone)]
+pub struct SendResponse {
+    /// Identifier of the sent message in the format of `projects/*/messages/{message_id}`.
+    pub message_id: Option<String>,
+    pub error: Option<FCMError>,
+}
+
+impl SendResponse {
+    pub fn success(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+impl From<Result<String, FCMError>> for SendResponse {
+    fn from(result: Result<String, FCMError>) -> Self {
+        match result {
+            Ok(message_id) => Self {
+                message_id: Some(message_id),
+                error: None,
+            },
+            Err(error) => Self {
+                message_id: None,
+                error: Some(error),
+            },
+        }
+    }
+}
+
+/// Results of a batch send, in the order of the messages or tokens given.
+#[derive(Debug, Clone, Default)]
+pub struct BatchResponse {
+    pub responses: Vec<SendResponse>,
+    pub success_count: usize,
+    pub failure_count: usize,
+}
+
+impl FromIterator<SendResponse> for BatchResponse {
+    fn from_iter<T: IntoIterator<Item = SendResponse>>(iter: T) -> Self {
+        let responses = iter.into_iter().collect::<Vec<_>>();
+        let success_count = responses.iter().filter(|r| r.success()).count();
+        Self {
+            failure_count: responses.len() - success_count,
+            success_count,
+            responses,
+        }
+    }
+}
+
+/// A token rejected by [Messaging::subscribe_to_topic] or [Messaging::unsubscribe_from_topic].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorInfo {
+    /// Position of the token in the list given.
+    pub index: usize,
+    /// Error code returned from the API, e.g. `NOT_FOUND`.
+    pub reason: String,
+}
+
+/// Result of [Messaging::subscribe_to_topic] or [Messaging::unsubscribe_from_topic].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TopicManagementResponse {
+    pub success_count: usize,
+    pub failure_count: usize,
+    pub errors: Vec<ErrorInfo>,
+}
+
+impl TopicManagementResponse {
+    fn new(response: crate::topic::TopicManagementResponse, tokens: &[String]) -> Self {
+        let errors = response
+            .failures(tokens)
+            .into_iter()
+            .map(|failure| ErrorInfo {
+                index: failure.index,
+                reason: failure.code.to_string(),
+            })
+            .collect::<Vec<_>>();
+        Self {
+            success_count: response.results.len() - errors.len(),
+            failure_count: errors.len(),
+            errors,
+        }
+    }
+}
+
+fn too_many(what: &str, count: usize) -> FCMError {
+    FCMError::InvalidRequestDescriptive {
+        reason: format!(
+            "{what} must not contain more than {MAX_MESSAGES_PER_BATCH} elements, got {count}"
+        ),
+    }
+}
+
+impl<C> Messaging<C>
+where
+    C: FCMApi + TopicManagementSupport + GenericGoogleRestAPISupport + Sync,
+{
+    pub fn new(client: C) -> Self {
+        Self { client }
+    }
+    /// The underlying client, to use APIs that are not part of the Admin SDKs.
+    pub fn client(&self) -> &C {
+        &self.client
+    }
+    /// Send a message and return its identifier. With `dry_run`, the message is validated but not delivered.
+    pub async fn send(&self, message: &Message, dry_run: bool) -> Result<String, FCMError> {
+        let output = if dry_run {
+            self.client.validate(message).await?
+        } else {
+            self.client.send(message).await?
+        };
+        Ok(output.name.into_string())
+    }
+    /// Send each message in its own request. A failure of a message does not affect the others.
+    /// Returns an error only if more than [MAX_MESSAGES_PER_BATCH] messages are given.
+    pub async fn send_each(
+        &self,
+        messages: &[Message],
+        dry_run: bool,
+    ) -> Result<BatchResponse, FCMError> {
+        if messages.len() > MAX_MESSAGES_PER_BATCH {
+            return Err(too_many("messages", messages.len()));
+        }
+        let requests = messages
+            .iter()
+            .map(|message| self.send_ref(MessageRef::from(message), dry_run))
+            .collect::<Vec<_>>();
+        let responses = stream::iter(requests)
+            .buffered(SEND_EACH_CONCURRENCY)
+            .map(SendResponse::from)
+            .collect::<Vec<_>>()
+            .await;
+        Ok(responses.into_iter().collect())
+    }
+    /// Send `message` to each of its tokens. See [Messaging::send_each].
+    pub async fn send_each_for_multicast(
+        &self,
+        message: &MulticastMessage,
+        dry_run: bool,
+    ) -> Result<BatchResponse, FCMError> {
+        if message.tokens.len() > MAX_MESSAGES_PER_BATCH {
+            return Err(too_many("tokens", message.tokens.len()));
+        }
+        let requests = message
+            .tokens
+            .iter()
+            .map(|token| self.send_ref(message.to_token_ref(token), dry_run))
+            .collect::<Vec<_>>();
+        let responses = stream::iter(requests)
+            .buffered(SEND_EACH_CONCURRENCY)
+            .map(SendResponse::from)
+            .collect::<Vec<_>>()
+            .await;
+        Ok(responses.into_iter().collect())
+    }
+    /// Subscribe `tokens` to `topic`. `topic` may or may not start with `/topics/`.
+    pub async fn subscribe_to_topic(
+        &self,
+        tokens: &[String],
+        topic: &str,
+    ) -> Result<TopicManagementResponse, TopicManagementError> {
+        let response = self
+            .client
+            .register_tokens_to_topic(topic_name(topic), tokens)
+            .await?;
+        Ok(TopicManagementResponse::new(response, tokens))
+    }
+    /// Unsubscribe `tokens` from `topic`. `topic` may or may not start with `/topics/`.
+    pub async fn unsubscribe_from_topic(
+        &self,
+        tokens: &[String],
+        topic: &str,
+    ) -> Result<TopicManagementResponse, TopicManagementError> {
+        let response = self
+            .client
+            .unregister_tokens_from_topic(topic_name(topic), tokens)
+            .await?;
+        Ok(TopicManagementResponse::new(response, tokens))
+    }
+    async fn send_ref(&self, message: MessageRef<'_>, dry_run: bool) -> Result<String, FCMError> {
+        let output = if dry_run {
+            self.client.validate_ref(&message).await?
+        } else {
+            self.client.send_ref(&message).await?
+        };
+        Ok(output.name.into_string())
+    }
+}
+
+fn topic_name(topic: &str) -> &str {
+    topic.strip_prefix("/topics/").unwrap_or(topic)
+}
+
+#[cfg(all(test, feature = "test-util"))]
+mod tests {
+    use super::{Messaging, MulticastMessage};
+    use crate::test_util::{FakeClient, FakeResponse};
+
+    #[tokio::test]
+    async fn admin_facade_reports_counts() {
+        let client = FakeClient::new();
+        client
+            .push(FakeResponse::ok(r#"{"name":"projects/p/messages/1"}"#))
+            .push(FakeResponse::new(http::StatusCode::NOT_FOUND, ""))
+            .push(FakeResponse::batch(&[None, Some("NOT_FOUND")]));
+        let messaging = Messaging::new(client);
+        let response = messaging
+            .send_each_for_multicast(
+                &MulticastMessage {
+                    tokens: vec!["a".to_string(), "b".to_string()],
+                    ..Default::default()
+                },
+                false,
+            )
+            .await
+            .unwrap();
+        assert_eq!((response.success_count, response.failure_count), (1, 1));
+        assert_eq!(
+            response.responses[0].message_id.as_deref(),
+            Some("projects/p/messages/1")
+        );
+
+        let response = messaging
+            .subscribe_to_topic(&["a".to_string(), "b".to_string()], "/topics/news")
+            .await
+            .unwrap();
+        assert_eq!((response.success_count, response.failure_count), (1, 1));
+        assert_eq!(response.errors[0].index, 1);
+        let requests = messaging.client().requests();
+        let body: serde_json::Value =
+            serde_json::from_slice(requests[2].body.as_ref().unwrap()).unwrap();
+        assert_eq!(body["to"], "/topics/news");
+    }
+}
@@ -0,0 +1,180 @@
+//! Structured audit records of outbound requests.
+//!
+//! Attach an [AuditSink] with [crate::FCMClient::with_audit_sink] to receive an [AuditRecord]
+//! for every send and topic management call, after retries have finished.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+//! use firebase_messaging_rs::{audit::JsonLinesAuditSink, FCMClient};
+//! use std::sync::Arc;
+//!
+//! let sink = JsonLinesAuditSink::open("/var/log/fcm-audit.jsonl")?;
+//! let client = FCMClient::new().await?.with_audit_sink(Arc::new(sink));
+//! # Ok(())
+//! # }
+//! ```
+use crate::Labels;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::Path,
+    sync::Mutex,
+    time::Duration,
+};
+
+/// Receives an [AuditRecord] for each outbound operation.
+///
+/// `record` is called on the task that sent the request, so implementations should not block for long.
+pub trait AuditSink: Send + Sync {
+    fn record(&self, record: &AuditRecord);
+}
+
+/// What was sent, where, and how it ended.
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditRecord {
+    /// When the operation started.
+    pub timestamp: DateTime<Utc>,
+    /// Operation name such as `fcm.send` or `iid.batchAdd`.
+    pub operation: String,
+    /// HTTP method and endpoint, e.g. `POST https://fcm.googleapis.com/v1/projects/my-project/messages:send`.
+    /// Endpoints of single-token operations contain the registration token.
+    pub target: String,
+    /// Hex encoded SHA-256 of the request body, if any.
+    pub payload_sha256: Option<String>,
+    pub outcome: AuditOutcome,
+    /// Time spent on the operation including retries.
+    #[serde(rename = "latency_ms", serialize_with = "serialize_millis")]
+    pub latency: Duration,
+    /// Number of requests sent, including retries.
+    pub attempts: u32,
+    /// Labels of the client that sent the request. See [crate::FCMClient::with_labels].
+    #[serde(skip_serializing_if = "Labels::is_empty")]
+    pub labels: Labels,
+    /// Correlation ID sent with every attempt, if any. See [crate::FCMClient::with_correlation_id].
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub correlation_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "snake_case", tag = "status")]
+pub enum AuditOutcome {
+    Success,
+    Failure { error: String },
+}
+
+fn serialize_millis<S: serde::Serializer>(
+    duration: &Duration,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u128(duration.as_millis())
+}
+
+/// Name an operation after the API it calls.
+pub(crate) fn operation_name(endpoint: &str) -> &'static str {
+    let path = endpoint.split('?').next().unwrap_or_default();
+    if path.ends_with("/messages:send") {
+        "fcm.send"
+    } else if path.ends_with(":batchAdd") {
+        "iid.batchAdd"
+    } else if path.ends_with(":batchRemove") {
+        "iid.batchRemove"
+    } else if path.ends_with(":batchImport") {
+        "iid.batchImport"
+    } else if path.contains("/rel/topics/") {
+        "iid.addTopic"
+    } else if path.contains("/iid/info/") {
+        "iid.info"
+    } else {
+        "other"
+    }
+}
+
+/// Hex encoded SHA-256 of `payload`.
+pub(crate) fn sha256_hex(payload: &[u8]) -> String {
+    use sha2::Digest;
+
+    sha2::Sha256::digest(payload)
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// Append each record as a line of JSON to a file.
+/// Every record is flushed before the request returns.
+#[derive(Debug)]
+pub struct JsonLinesAuditSink {
+    file: Mutex<File>,
+}
+
+impl JsonLinesAuditSink {
+    /// Open `path` for appending, creating it if it does not exist.
+    pub fn open<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self::new(file))
+    }
+    pub fn new(file: File) -> Self {
+        Self {
+            file: Mutex::new(file),
+        }
+    }
+}
+
+impl AuditSink for JsonLinesAuditSink {
+    fn record(&self, record: &AuditRecord) {
+        let mut line = match serde_json::to_vec(record) {
+            Ok(line) => line,
+            Err(e) => {
+                log::error!("unable to serialize audit record: {e}");
+                return;
+            }
+        };
+        line.push(b'\n');
+        let mut file = match self.file.lock() {
+            Ok(file) => file,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if let Err(e) = file.write_all(&line).and_then(|_| file.flush()) {
+            log::error!("unable to write audit record: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{operation_name, sha256_hex, AuditOutcome, AuditRecord};
+    use crate::Labels;
+    use std::time::Duration;
+
+    #[test]
+    fn records_are_rendered_as_json_lines() {
+        let record = AuditRecord {
+            timestamp: "2024-01-02T03:04:05Z".parse().unwrap(),
+            operation: operation_name("https://iid.googleapis.com/iid/v1:batchAdd").to_string(),
+            target: "POST https://iid.googleapis.com/iid/v1:batchAdd".to_string(),
+            payload_sha256: Some(sha256_hex(b"")),
+            outcome: AuditOutcome::Failure {
+                error: "invalid request".to_string(),
+            },
+            latency: Duration::from_millis(1500),
+            attempts: 2,
+            labels: Labels::from([("platform".to_string(), "ios".to_string())]),
+            correlation_id: Some("id".to_string()),
+        };
+        let json = serde_json::to_value(&record).unwrap();
+        assert_eq!(json["operation"], "iid.batchAdd");
+        assert_eq!(
+            json["payload_sha256"],
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(json["outcome"]["status"], "failure");
+        assert_eq!(json["latency_ms"], 1500);
+        assert_eq!(json["labels"]["platform"], "ios");
+        assert_eq!(json["correlation_id"], "id");
+        assert_eq!(
+            operation_name("https://fcm.googleapis.com/v1/projects/p/messages:send"),
+            "fcm.send"
+        );
+    }
+}
@@ -0,0 +1,125 @@
+//! Small command line tool for quick checks against FCM and IID APIs.
+//!
+//! ```text
+//! fcm-cli send <message.json|message.yaml> [--dry-run]
+//! fcm-cli validate <message.json|message.yaml>
+//! fcm-cli subscribe <topic> <token>...
+//! fcm-cli unsubscribe <topic> <token>...
+//! fcm-cli info <token> [--details]
+//! ```
+//!
+//! Credentials are resolved in the same way as [FCMClient::new].
+use firebase_messaging_rs::{
+    fcm::{FCMApi, FCMError, MessageOutput},
+    topic::TopicManagementSupport,
+    FCMClient, GenericGoogleRestAPISupport,
+};
+use std::{path::Path, process::ExitCode};
+
+const USAGE: &str = "usage:
+  fcm-cli send <message.json|message.yaml> [--dry-run]
+  fcm-cli validate <message.json|message.yaml>
+  fcm-cli subscribe <topic> <token>...
+  fcm-cli unsubscribe <topic> <token>...
+  fcm-cli info <token> [--details]";
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let (flags, args): (Vec<_>, Vec<_>) = args.into_iter().partition(|arg| arg.starts_with("--"));
+    let has_flag = |name: &str| flags.iter().any(|flag| flag == name);
+    let (command, args) = match args.split_first() {
+        Some(command_and_args) => command_and_args,
+        None => {
+            eprintln!("{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+    // reject unknown flags before anything is sent, e.g. a mistyped `--dry-run`
+    let known_flags: &[&str] = match command.as_str() {
+        "send" => &["--dry-run"],
+        "info" => &["--details"],
+        _ => &[],
+    };
+    if let Some(flag) = flags
+        .iter()
+        .find(|flag| !known_flags.contains(&flag.as_str()))
+    {
+        eprintln!("unknown option: {flag}\n{USAGE}");
+        return ExitCode::FAILURE;
+    }
+    let client = match FCMClient::new().await {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("unable to initialize client: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let result = match (command.as_str(), args) {
+        ("send", [path]) => send(&client, path, has_flag("--dry-run")).await,
+        ("validate", [path]) => send(&client, path, true).await,
+        ("subscribe", [topic, tokens @ ..]) if !tokens.is_empty() => client
+            .register_tokens_to_topic(topic, tokens)
+            .await
+            .map(|res| format!("{res:#?}"))
+            .map_err(|e| e.to_string()),
+        ("unsubscribe", [topic, tokens @ ..]) if !tokens.is_empty() => client
+            .unregister_tokens_from_topic(topic, tokens)
+            .await
+            .map(|res| format!("{res:#?}"))
+            .map_err(|e| e.to_string()),
+        ("info", [token]) => client
+            .get_info_by_iid_token(token, has_flag("--details"))
+            .await
+            .map(|res| format!("{res:#?}"))
+            .map_err(|e| e.to_string()),
+        _ => Err(USAGE.to_string()),
+    };
+    match result {
+        Ok(output) => {
+            println!("{output}");
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Send the message in `path` as is, so that any payload accepted by FCM can be tested.
+async fn send(client: &FCMClient, path: &str, dry_run: bool) -> Result<String, String> {
+    let message = read_message(Path::new(path))?;
+    let payload = serde_json::json!({
+        "validate_only": dry_run,
+        "message": message,
+    });
+    client
+        .post_request::<_, MessageOutput, FCMError>(
+            &client
+                .send_endpoint()
+                .map_err(|e| FCMError::from(e).to_string())?,
+            &payload,
+        )
+        .await
+        .map(|res| format!("{res:#?}"))
+        .map_err(|e| e.to_string())
+}
+
+fn read_message(path: &Path) -> Result<serde_json::Value, String> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("unable to read {}: {e}", path.display()))?;
+    let value = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("yaml" | "yml") => serde_yaml::from_str::<serde_json::Value>(&content)
+            .map_err(|e| format!("unable to parse {}: {e}", path.display()))?,
+        _ => serde_json::from_str::<serde_json::Value>(&content)
+            .map_err(|e| format!("unable to parse {}: {e}", path.display()))?,
+    };
+    // accept both a bare message and a full request body
+    match value {
+        serde_json::Value::Object(mut map) if map.contains_key("message") => {
+            Ok(map.remove("message").unwrap_or_default())
+        }
+        value => Ok(value),
+    }
+}
@@ -0,0 +1,291 @@
+//! Fail fast while Google APIs keep returning server errors, instead of adding load to an outage.
+//!
+//! A circuit is kept for each host. It opens after [CircuitBreakerPolicy::failure_threshold] consecutive
+//! `5xx` responses. While it is open, requests fail with [crate::RPCError::CircuitOpen] without being sent.
+//! After [CircuitBreakerPolicy::open_for], the circuit is half-open: a single request is let through as a probe,
+//! and closes the circuit if it succeeds or opens it again if it fails.
+//!
+//! Transport errors and timeouts neither open nor close circuits. Retry or time them out with [crate::RetryPolicy].
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), String> {
+//! use firebase_messaging_rs::{circuit_breaker::CircuitBreakerPolicy, FCMClient};
+//! use std::time::Duration;
+//!
+//! let client = FCMClient::builder()
+//!     .circuit_breaker(Some(CircuitBreakerPolicy::new(5, Duration::from_secs(30))))
+//!     .build()
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+use crate::{Labels, RPCError, ResponseMetadata};
+use std::{
+    collections::BTreeMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// When circuits open and how long they stay open.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CircuitBreakerPolicy {
+    /// Consecutive server errors that open a circuit. `0` is treated as `1`.
+    pub failure_threshold: u32,
+    /// Time an open circuit rejects requests before a probe is let through.
+    pub open_for: Duration,
+}
+
+impl CircuitBreakerPolicy {
+    pub fn new(failure_threshold: u32, open_for: Duration) -> Self {
+        Self {
+            failure_threshold,
+            open_for,
+        }
+    }
+}
+
+impl Default for CircuitBreakerPolicy {
+    fn default() -> Self {
+        Self::new(5, Duration::from_secs(30))
+    }
+}
+
+/// State of the circuit of a host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Requests are sent.
+    Closed,
+    /// Requests fail without being sent.
+    Open,
+    /// A probe is let through to decide whether to close the circuit.
+    HalfOpen,
+}
+
+/// Notified when a circuit changes its state. Use it to export metrics or alert on outages.
+pub trait CircuitObserver: Send + Sync {
+    /// `labels` are the labels of the client. See [crate::FCMClient::with_labels].
+    fn on_state_change(&self, host: &str, from: CircuitState, to: CircuitState, labels: &Labels);
+}
+
+/// How an attempt affects the circuit of its host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Outcome {
+    Success,
+    Failure,
+    /// No response from the host, e.g. a transport error or a timeout.
+    Inconclusive,
+}
+
+impl Outcome {
+    /// `5xx` responses are failures, and any other response is a success.
+    /// Errors without a response, including requests the client rejected itself, are inconclusive.
+    pub(crate) fn of(error: Option<&RPCError>, response: Option<&ResponseMetadata>) -> Self {
+        match (error, response) {
+            (None, _) => Self::Success,
+            (Some(_), None) => Self::Inconclusive,
+            (Some(_), Some(response)) if (500..600).contains(&response.status) => Self::Failure,
+            (Some(_), Some(_)) => Self::Success,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Circuit {
+    Closed { failures: u32 },
+    Open { until: Instant },
+    HalfOpen { next_probe: Instant },
+}
+
+impl Circuit {
+    fn state(&self) -> CircuitState {
+        match self {
+            Self::Closed { .. } => CircuitState::Closed,
+            Self::Open { .. } => CircuitState::Open,
+            Self::HalfOpen { .. } => CircuitState::HalfOpen,
+        }
+    }
+}
+
+/// A state change of the circuit of a host.
+pub(crate) type Transition = (CircuitState, CircuitState);
+
+/// Circuits of a client by host, shared by all its clones.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    policy: CircuitBreakerPolicy,
+    circuits: Mutex<BTreeMap<String, Circuit>>,
+}
+
+impl CircuitBreaker {
+    pub fn new(policy: CircuitBreakerPolicy) -> Self {
+        Self {
+            policy,
+            circuits: Mutex::default(),
+        }
+    }
+    /// Current state of the circuit of `host`.
+    pub fn state(&self, host: &str) -> CircuitState {
+        self.circuits()
+            .get(host)
+            .map_or(CircuitState::Closed, Circuit::state)
+    }
+    fn circuits(&self) -> std::sync::MutexGuard<'_, BTreeMap<String, Circuit>> {
+        match self.circuits.lock() {
+            Ok(circuits) => circuits,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+    /// Ask to send a request to `host`. Returns the time until the next probe if the circuit rejects it.
+    pub(crate) fn acquire(
+        &self,
+        host: &str,
+        now: Instant,
+    ) -> (Result<(), Duration>, Option<Transition>) {
+        let mut circuits = self.circuits();
+        let circuit = match circuits.get_mut(host) {
+            Some(circuit) => circuit,
+            None => return (Ok(()), None),
+        };
+        let next_probe = now + self.policy.open_for;
+        match *circuit {
+            Circuit::Closed { .. } => (Ok(()), None),
+            Circuit::Open { until } if now >= until => {
+                *circuit = Circuit::HalfOpen { next_probe };
+                (Ok(()), Some((CircuitState::Open, CircuitState::HalfOpen)))
+            }
+            Circuit::Open { until } => (Err(until - now), None),
+            Circuit::HalfOpen {
+                next_probe: ref mut next,
+            } if now >= *next => {
+                // The previous probe never reported back, e.g. because its caller gave up on it.
+                *next = next_probe;
+                (Ok(()), None)
+            }
+            Circuit::HalfOpen { next_probe: next } => (Err(next - now), None),
+        }
+    }
+    /// Report the outcome of a request to `host` let through by [CircuitBreaker::acquire].
+    pub(crate) fn record(&self, host: &str, outcome: Outcome, now: Instant) -> Option<Transition> {
+        let mut circuits = self.circuits();
+        let circuit = circuits
+            .entry(host.to_string())
+            .or_insert(Circuit::Closed { failures: 0 });
+        let from = circuit.state();
+        let open = Circuit::Open {
+            until: now + self.policy.open_for,
+        };
+        match (&mut *circuit, outcome) {
+            (Circuit::Closed { failures }, Outcome::Failure) => {
+                *failures += 1;
+                if *failures >= self.policy.failure_threshold.max(1) {
+                    *circuit = open;
+                }
+            }
+            (Circuit::Closed { failures }, Outcome::Success) => *failures = 0,
+            (Circuit::HalfOpen { .. }, Outcome::Success) => {
+                *circuit = Circuit::Closed { failures: 0 }
+            }
+            (Circuit::HalfOpen { .. }, Outcome::Failure) => *circuit = open,
+            (Circuit::HalfOpen { next_probe }, Outcome::Inconclusive) => *next_probe = now,
+            // Requests sent before the circuit opened do not change it.
+            (Circuit::Open { .. }, _) | (Circuit::Closed { .. }, Outcome::Inconclusive) => {}
+        }
+        let to = circuit.state();
+        (from != to).then_some((from, to))
+    }
+}
+
+/// Log `transition` of the circuit of `host` and tell `observer` about it.
+pub(crate) fn report(
+    host: &str,
+    transition: Option<Transition>,
+    observer: Option<&dyn CircuitObserver>,
+    labels: Option<&Labels>,
+) {
+    let (from, to) = match transition {
+        Some(transition) => transition,
+        None => return,
+    };
+    match to {
+        CircuitState::Open => log::warn!("circuit of {host} is open after repeated server errors"),
+        CircuitState::HalfOpen => log::info!("circuit of {host} is half-open, sending a probe"),
+        CircuitState::Closed => log::info!("circuit of {host} is closed"),
+    }
+    if let Some(observer) = observer {
+        let empty = Labels::new();
+        observer.on_state_change(host, from, to, labels.unwrap_or(&empty));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CircuitBreaker, CircuitBreakerPolicy, CircuitState, Outcome};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn circuit_opens_and_probes() {
+        let breaker = CircuitBreaker::new(CircuitBreakerPolicy::new(2, Duration::from_secs(10)));
+        let host = "fcm.googleapis.com";
+        let now = Instant::now();
+        assert_eq!(breaker.record(host, Outcome::Failure, now), None);
+        assert_eq!(breaker.record(host, Outcome::Success, now), None);
+        assert_eq!(breaker.record(host, Outcome::Failure, now), None);
+        assert_eq!(breaker.record(host, Outcome::Inconclusive, now), None);
+        assert_eq!(
+            breaker.record(host, Outcome::Failure, now),
+            Some((CircuitState::Closed, CircuitState::Open))
+        );
+        assert_eq!(
+            breaker.acquire(host, now + Duration::from_secs(4)).0,
+            Err(Duration::from_secs(6))
+        );
+        assert_eq!(breaker.acquire("iid.googleapis.com", now).0, Ok(()));
+
+        let later = now + Duration::from_secs(10);
+        assert_eq!(
+            breaker.acquire(host, later),
+            (Ok(()), Some((CircuitState::Open, CircuitState::HalfOpen)))
+        );
+        // Only one probe at a time.
+        assert!(breaker.acquire(host, later).0.is_err());
+        assert_eq!(
+            breaker.record(host, Outcome::Failure, later),
+            Some((CircuitState::HalfOpen, CircuitState::Open))
+        );
+
+        let later = later + Duration::from_secs(10);
+        assert!(breaker.acquire(host, later).0.is_ok());
+        assert_eq!(breaker.record(host, Outcome::Inconclusive, later), None);
+        assert!(breaker.acquire(host, later).0.is_ok());
+        assert_eq!(
+            breaker.record(host, Outcome::Success, later),
+            Some((CircuitState::HalfOpen, CircuitState::Closed))
+        );
+        assert_eq!(breaker.state(host), CircuitState::Closed);
+    }
+
+    #[test]
+    fn only_server_error_responses_are_failures() {
+        use crate::{RPCError, ResponseMetadata};
+
+        let response = |status| ResponseMetadata {
+            status,
+            ..Default::default()
+        };
+        let internal = RPCError::Internal { retry_after: None };
+        assert_eq!(
+            Outcome::of(Some(&internal), Some(&response(503))),
+            Outcome::Failure
+        );
+        assert_eq!(
+            Outcome::of(Some(&internal), Some(&response(429))),
+            Outcome::Success
+        );
+        let quota = RPCError::QuotaExceeded {
+            project_id: "noisy".to_string(),
+            resets_in: Duration::from_secs(1),
+        };
+        assert_eq!(Outcome::of(Some(&quota), None), Outcome::Inconclusive);
+        assert_eq!(Outcome::of(None, None), Outcome::Success);
+    }
+}
@@ -0,0 +1,414 @@
+//! Client settings read from environment variables or a JSON file, to tune clients without code changes.
+//!
+//! Every setting is optional and overrides the default of [FCMClientBuilder] only when present.
+//! Durations are written like `"1.5s"`. `"0s"` disables the timeouts and intervals that are optional.
+//!
+//! | JSON field               | environment variable         | builder method                          |
+//! | ------------------------ | ---------------------------- | --------------------------------------- |
+//! | `project_id`             | `FCM_PROJECT_ID`             | [FCMClientBuilder::project_id]          |
+//! | `scopes`                 | `FCM_SCOPES` (comma separated) | [FCMClientBuilder::scopes]            |
+//! | `ip_preference`          | `FCM_IP_PREFERENCE`          | [FCMClientBuilder::ip_preference]       |
+//! | `happy_eyeballs_timeout` | `FCM_HAPPY_EYEBALLS_TIMEOUT` | [FCMClientBuilder::happy_eyeballs_timeout] |
+//! | `tcp_keepalive`          | `FCM_TCP_KEEPALIVE`          | [FCMClientBuilder::tcp_keepalive]       |
+//! | `keep_alive_interval`    | `FCM_KEEP_ALIVE_INTERVAL`    | [FCMClientBuilder::keep_alive_interval] |
+//! | `pool_idle_timeout`      | `FCM_POOL_IDLE_TIMEOUT`      | [FCMClientBuilder::pool_idle_timeout]   |
+//! | `max_connection_lifetime` | `FCM_MAX_CONNECTION_LIFETIME` | [FCMClientBuilder::max_connection_lifetime] |
+//! | `tcp_nodelay`            | `FCM_TCP_NODELAY`            | [FCMClientBuilder::tcp_nodelay]         |
+//! | `http2`                  | `FCM_HTTP2`                  | [FCMClientBuilder::http2]               |
+//! | `connect_timeout`        | `FCM_CONNECT_TIMEOUT`        | [FCMClientBuilder::connect_timeout]     |
+//! | `request_timeout`        | `FCM_REQUEST_TIMEOUT`        | [FCMClientBuilder::request_timeout]     |
+//! | `max_response_size`      | `FCM_MAX_RESPONSE_SIZE`      | [FCMClientBuilder::max_response_size]   |
+//! | `max_retries`            | `FCM_MAX_RETRIES`            | [RetryPolicy::max_retries]              |
+//! | `initial_backoff`        | `FCM_INITIAL_BACKOFF`        | [RetryPolicy::initial_backoff]          |
+//! | `max_backoff`            | `FCM_MAX_BACKOFF`            | [RetryPolicy::max_backoff]              |
+//! | `retry_transport_errors` | `FCM_RETRY_TRANSPORT_ERRORS` | [RetryPolicy::retry_transport_errors]   |
+//! | `retry_server_errors`    | `FCM_RETRY_SERVER_ERRORS`    | [RetryPolicy::retry_server_errors]      |
+//! | `retry_deadline`         | `FCM_RETRY_DEADLINE`         | [RetryPolicy::deadline]                 |
+//! | `tls_backend`            | `FCM_TLS_BACKEND`            | [FCMClientBuilder::tls_backend]         |
+//! | `google_access`          | `FCM_GOOGLE_ACCESS`          | [FCMClientBuilder::google_access]       |
+//! | `proxy`                  | `FCM_PROXY`                  | [FCMClientBuilder::proxy]               |
+//! | `fcm_base_url`           | `FCM_FCM_BASE_URL`           | [FCMClientBuilder::fcm_base_url]        |
+//! | `iid_base_url`           | `FCM_IID_BASE_URL`           | [FCMClientBuilder::iid_base_url]        |
+//! | `user_agent`             | `FCM_USER_AGENT`             | [FCMClientBuilder::user_agent]          |
+//! | `send_rate_limit`        | `FCM_SEND_RATE_LIMIT`        | [FCMClientBuilder::rate_limit] of [EndpointClass::Send] |
+//! | `topic_batch_rate_limit` | `FCM_TOPIC_BATCH_RATE_LIMIT` | [FCMClientBuilder::rate_limit] of [EndpointClass::TopicBatch] |
+//! | `circuit_breaker_threshold` | `FCM_CIRCUIT_BREAKER_THRESHOLD` | [crate::circuit_breaker::CircuitBreakerPolicy::failure_threshold] |
+//! | `circuit_breaker_open_for`  | `FCM_CIRCUIT_BREAKER_OPEN_FOR`  | [crate::circuit_breaker::CircuitBreakerPolicy::open_for] |
+//! | `gzip_min_bytes`         | `FCM_GZIP_MIN_BYTES`         | `FCMClientBuilder::gzip_requests` (`gzip` feature) |
+//! | `labels`                 | `FCM_LABELS` (`key=value,...`) | [FCMClientBuilder::label]             |
+//!
+//! `ip_preference` is one of `any`, `v4_only` and `v6_only`; `tls_backend` is `native_tls` or `rustls`;
+//! `google_access` is `public`, `private` or `restricted`, or `{"custom": "<host>"}` in JSON.
+//! `max_response_size` is in bytes, and `0` accepts responses of any size.
+//! Rate limits are requests per second, with a burst of one second worth of requests.
+//! Either circuit breaker setting enables [FCMClientBuilder::circuit_breaker], with the defaults of
+//! [crate::circuit_breaker::CircuitBreakerPolicy] for the other one.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), String> {
+//! use firebase_messaging_rs::FCMClientBuilder;
+//!
+//! // e.g. FCM_MAX_RETRIES=5 FCM_CONNECT_TIMEOUT=2s
+//! let client = FCMClientBuilder::from_env()?.build().await?;
+//! # Ok(())
+//! # }
+//! ```
+use crate::{
+    dns::GoogleAccess,
+    proxy::Proxy,
+    rate_limit::{EndpointClass, RateLimit},
+    serde_helpers, FCMClientBuilder, IpPreference, Labels, RetryPolicy, TlsBackend,
+};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::{path::Path, time::Duration};
+
+/// Prefix of the environment variables read by [ClientConfig::from_env].
+pub const ENV_PREFIX: &str = "FCM_";
+
+/// Settings applied to [FCMClientBuilder] by [FCMClientBuilder::config]. See the [module](self) documentation.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClientConfig {
+    pub project_id: Option<String>,
+    pub scopes: Option<Vec<String>>,
+    pub ip_preference: Option<IpPreference>,
+    #[serde(with = "serde_helpers::option_duration", default)]
+    pub happy_eyeballs_timeout: Option<Duration>,
+    #[serde(with = "serde_helpers::option_duration", default)]
+    pub tcp_keepalive: Option<Duration>,
+    #[serde(with = "serde_helpers::option_duration", default)]
+    pub keep_alive_interval: Option<Duration>,
+    #[serde(with = "serde_helpers::option_duration", default)]
+    pub pool_idle_timeout: Option<Duration>,
+    #[serde(with = "serde_helpers::option_duration", default)]
+    pub max_connection_lifetime: Option<Duration>,
+    pub tcp_nodelay: Option<bool>,
+    pub http2: Option<bool>,
+    #[serde(with = "serde_helpers::option_duration", default)]
+    pub connect_timeout: Option<Duration>,
+    #[serde(with = "serde_helpers::option_duration", default)]
+    pub request_timeout: Option<Duration>,
+    /// Bytes. `0` accepts responses of any size.
+    pub max_response_size: Option<usize>,
+    pub max_retries: Option<u32>,
+    #[serde(with = "serde_helpers::option_duration", default)]
+    pub initial_backoff: Option<Duration>,
+    #[serde(with = "serde_helpers::option_duration", default)]
+    pub max_backoff: Option<Duration>,
+    pub retry_transport_errors: Option<bool>,
+    pub retry_server_errors: Option<bool>,
+    #[serde(with = "serde_helpers::option_duration", default)]
+    pub retry_deadline: Option<Duration>,
+    pub tls_backend: Option<TlsBackend>,
+    pub google_access: Option<GoogleAccess>,
+    /// Proxy URL such as `http://proxy.internal:3128`. `HTTPS_PROXY` is honored when omitted.
+    pub proxy: Option<Proxy>,
+    pub fcm_base_url: Option<String>,
+    pub iid_base_url: Option<String>,
+    /// Application identifier such as `my-app/1.2`, sent before [crate::DEFAULT_USER_AGENT].
+    pub user_agent: Option<String>,
+    /// Requests per second. See [crate::rate_limit].
+    pub send_rate_limit: Option<u32>,
+    /// Requests per second. See [crate::rate_limit].
+    pub topic_batch_rate_limit: Option<u32>,
+    pub circuit_breaker_threshold: Option<u32>,
+    #[serde(with = "serde_helpers::option_duration", default)]
+    pub circuit_breaker_open_for: Option<Duration>,
+    #[cfg(feature = "gzip")]
+    pub gzip_min_bytes: Option<usize>,
+    pub labels: Option<Labels>,
+}
+
+/// How an environment variable is converted to JSON before deserialization.
+#[derive(Clone, Copy)]
+enum Kind {
+    String,
+    Number,
+    Bool,
+    List,
+    Map,
+}
+
+const FIELDS: &[(&str, Kind)] = &[
+    ("project_id", Kind::String),
+    ("scopes", Kind::List),
+    ("ip_preference", Kind::String),
+    ("happy_eyeballs_timeout", Kind::String),
+    ("tcp_keepalive", Kind::String),
+    ("keep_alive_interval", Kind::String),
+    ("pool_idle_timeout", Kind::String),
+    ("max_connection_lifetime", Kind::String),
+    ("tcp_nodelay", Kind::Bool),
+    ("http2", Kind::Bool),
+    ("connect_timeout", Kind::String),
+    ("request_timeout", Kind::String),
+    ("max_response_size", Kind::Number),
+    ("max_retries", Kind::Number),
+    ("initial_backoff", Kind::String),
+    ("max_backoff", Kind::String),
+    ("retry_transport_errors", Kind::Bool),
+    ("retry_server_errors", Kind::Bool),
+    ("retry_deadline", Kind::String),
+    ("tls_backend", Kind::String),
+    ("google_access", Kind::String),
+    ("proxy", Kind::String),
+    ("fcm_base_url", Kind::String),
+    ("iid_base_url", Kind::String),
+    ("user_agent", Kind::String),
+    ("send_rate_limit", Kind::Number),
+    ("topic_batch_rate_limit", Kind::Number),
+    ("circuit_breaker_threshold", Kind::Number),
+    ("circuit_breaker_open_for", Kind::String),
+    #[cfg(feature = "gzip")]
+    ("gzip_min_bytes", Kind::Number),
+    ("labels", Kind::Map),
+];
+
+impl ClientConfig {
+    /// Read the `FCM_*` environment variables listed in the [module](self) documentation.
+    pub fn from_env() -> Result<Self, String> {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+    /// Same as [ClientConfig::from_env] but looks variables up with `var`.
+    pub fn from_vars<F: Fn(&str) -> Option<String>>(var: F) -> Result<Self, String> {
+        let mut fields = Map::new();
+        for (field, kind) in FIELDS {
+            let name = format!("{ENV_PREFIX}{}", field.to_ascii_uppercase());
+            let raw = match var(&name) {
+                Some(raw) if !raw.trim().is_empty() => raw,
+                _ => continue,
+            };
+            let value = Self::env_value(raw.trim(), *kind)
+                .ok_or_else(|| format!("{name}: unable to parse {raw:?}"))?;
+            fields.insert(field.to_string(), value);
+        }
+        serde_json::from_value(Value::Object(fields)).map_err(|e| format!("{ENV_PREFIX}*: {e}"))
+    }
+    /// Read settings from a JSON object with the fields listed in the [module](self) documentation.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let json = std::fs::read_to_string(path)
+            .map_err(|e| format!("unable to read {}: {e}", path.display()))?;
+        serde_json::from_str(&json).map_err(|e| format!("{}: {e}", path.display()))
+    }
+
+    fn env_value(raw: &str, kind: Kind) -> Option<Value> {
+        let list = || {
+            raw.split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+        };
+        match kind {
+            Kind::String => Some(Value::from(raw)),
+            Kind::Number => raw.parse::<u64>().ok().map(Value::from),
+            Kind::Bool => match raw.to_ascii_lowercase().as_str() {
+                "true" | "1" | "yes" => Some(Value::Bool(true)),
+                "false" | "0" | "no" => Some(Value::Bool(false)),
+                _ => None,
+            },
+            Kind::List => Some(list().collect()),
+            Kind::Map => list()
+                .map(|pair| {
+                    let (key, value) = pair.split_once('=')?;
+                    Some((key.trim().to_string(), Value::from(value.trim())))
+                })
+                .collect::<Option<Map<_, _>>>()
+                .map(Value::Object),
+        }
+    }
+}
+
+/// `None` for `0s`, which disables optional timeouts and intervals.
+fn non_zero(duration: Duration) -> Option<Duration> {
+    (!duration.is_zero()).then_some(duration)
+}
+
+impl FCMClientBuilder {
+    /// Builder configured by the `FCM_*` environment variables. See [crate::config].
+    pub fn from_env() -> Result<Self, String> {
+        Ok(Self::default().config(&ClientConfig::from_env()?))
+    }
+    /// Builder configured by a JSON file. See [crate::config].
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        Ok(Self::default().config(&ClientConfig::from_file(path)?))
+    }
+    /// Override the settings present in `config`, keeping the others.
+    pub fn config(mut self, config: &ClientConfig) -> Self {
+        if let Some(project_id) = &config.project_id {
+            self = self.project_id(project_id);
+        }
+        if let Some(scopes) = &config.scopes {
+            self = self.scopes(scopes);
+        }
+        if let Some(ip_preference) = config.ip_preference {
+            self = self.ip_preference(ip_preference);
+        }
+        if let Some(timeout) = config.happy_eyeballs_timeout {
+            self = self.happy_eyeballs_timeout(non_zero(timeout));
+        }
+        if let Some(interval) = config.tcp_keepalive {
+            self = self.tcp_keepalive(non_zero(interval));
+        }
+        if let Some(interval) = config.keep_alive_interval {
+            self = self.keep_alive_interval(non_zero(interval));
+        }
+        if let Some(timeout) = config.pool_idle_timeout {
+            self = self.pool_idle_timeout(non_zero(timeout));
+        }
+        if let Some(lifetime) = config.max_connection_lifetime {
+            self = self.max_connection_lifetime(non_zero(lifetime));
+        }
+        if let Some(nodelay) = config.tcp_nodelay {
+            self = self.tcp_nodelay(nodelay);
+        }
+        if let Some(http2) = config.http2 {
+            self = self.http2(http2);
+        }
+        if let Some(timeout) = config.connect_timeout {
+            self = self.connect_timeout(non_zero(timeout));
+        }
+        if let Some(timeout) = config.request_timeout {
+            self = self.request_timeout(non_zero(timeout));
+        }
+        if let Some(max_size) = config.max_response_size {
+            self = self.max_response_size((max_size > 0).then_some(max_size));
+        }
+        let policy: &mut RetryPolicy = &mut self.retry_policy;
+        if let Some(max_retries) = config.max_retries {
+            policy.max_retries = max_retries;
+        }
+        if let Some(backoff) = config.initial_backoff {
+            policy.initial_backoff = backoff;
+        }
+        if let Some(backoff) = config.max_backoff {
+            policy.max_backoff = backoff;
+        }
+        if let Some(retry) = config.retry_transport_errors {
+            policy.retry_transport_errors = retry;
+        }
+        if let Some(retry) = config.retry_server_errors {
+            policy.retry_server_errors = retry;
+        }
+        if let Some(deadline) = config.retry_deadline {
+            policy.deadline = non_zero(deadline);
+        }
+        if let Some(backend) = config.tls_backend {
+            self = self.tls_backend(backend);
+        }
+        if let Some(access) = &config.google_access {
+            self = self.google_access(access.clone());
+        }
+        if let Some(proxy) = &config.proxy {
+            self = self.proxy(Some(proxy.clone()));
+        }
+        if let Some(url) = &config.fcm_base_url {
+            self = self.fcm_base_url(url);
+        }
+        if let Some(url) = &config.iid_base_url {
+            self = self.iid_base_url(url);
+        }
+        if let Some(application) = &config.user_agent {
+            self = self.user_agent(application);
+        }
+        if let Some(rate) = config.send_rate_limit {
+            self = self.rate_limit(EndpointClass::Send, RateLimit::per_second(f64::from(rate)));
+        }
+        if let Some(rate) = config.topic_batch_rate_limit {
+            self = self.rate_limit(
+                EndpointClass::TopicBatch,
+                RateLimit::per_second(f64::from(rate)),
+            );
+        }
+        if config.circuit_breaker_threshold.is_some() || config.circuit_breaker_open_for.is_some() {
+            let mut policy = self.circuit_breaker.unwrap_or_default();
+            if let Some(threshold) = config.circuit_breaker_threshold {
+                policy.failure_threshold = threshold;
+            }
+            if let Some(open_for) = config.circuit_breaker_open_for {
+                policy.open_for = open_for;
+            }
+            self = self.circuit_breaker(Some(policy));
+        }
+        #[cfg(feature = "gzip")]
+        if let Some(min_bytes) = config.gzip_min_bytes {
+            self = self.gzip_requests(Some(min_bytes));
+        }
+        if let Some(labels) = &config.labels {
+            for (key, value) in labels {
+                self = self.label(key, value);
+            }
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ClientConfig;
+    use crate::{
+        circuit_breaker::CircuitBreakerPolicy,
+        rate_limit::{EndpointClass, RateLimit},
+        FCMClientBuilder, IpPreference,
+    };
+    use std::{collections::HashMap, time::Duration};
+
+    #[test]
+    fn environment_overrides_defaults() {
+        let vars = HashMap::from([
+            ("FCM_PROJECT_ID", "my-project"),
+            ("FCM_SCOPES", "a, b"),
+            ("FCM_IP_PREFERENCE", "v4_only"),
+            ("FCM_TCP_KEEPALIVE", "0s"),
+            ("FCM_KEEP_ALIVE_INTERVAL", "30s"),
+            ("FCM_POOL_IDLE_TIMEOUT", "45s"),
+            ("FCM_MAX_CONNECTION_LIFETIME", "600s"),
+            ("FCM_HTTP2", "false"),
+            ("FCM_USER_AGENT", "my-app/1.2"),
+            ("FCM_CONNECT_TIMEOUT", "1.5s"),
+            ("FCM_MAX_RESPONSE_SIZE", "0"),
+            ("FCM_MAX_RETRIES", "5"),
+            ("FCM_SEND_RATE_LIMIT", "100"),
+            ("FCM_CIRCUIT_BREAKER_THRESHOLD", "3"),
+            ("FCM_RETRY_SERVER_ERRORS", "false"),
+            ("FCM_LABELS", "team=push,env=prod"),
+        ]);
+        let config =
+            ClientConfig::from_vars(|name| vars.get(name).map(|value| value.to_string())).unwrap();
+        assert_eq!(config.scopes, Some(vec!["a".to_string(), "b".to_string()]));
+        let builder = FCMClientBuilder::default()
+            .tcp_keepalive(Some(Duration::from_secs(60)))
+            .config(&config);
+        assert_eq!(builder.project_id.as_deref(), Some("my-project"));
+        assert_eq!(builder.ip_preference, IpPreference::V4Only);
+        assert_eq!(builder.tcp_keepalive, None);
+        assert_eq!(builder.keep_alive_interval, Some(Duration::from_secs(30)));
+        assert_eq!(builder.pool_idle_timeout, Some(Duration::from_secs(45)));
+        assert_eq!(
+            builder.max_connection_lifetime,
+            Some(Duration::from_secs(600))
+        );
+        assert!(!builder.http2);
+        assert_eq!(builder.user_agent.as_deref(), Some("my-app/1.2"));
+        assert_eq!(builder.connect_timeout, Some(Duration::from_millis(1500)));
+        assert_eq!(builder.max_response_size, None);
+        assert_eq!(builder.retry_policy.max_retries, 5);
+        assert!(!builder.retry_policy.retry_server_errors);
+        assert!(builder.retry_policy.retry_transport_errors);
+        assert_eq!(builder.labels["env"], "prod");
+        assert_eq!(
+            builder.circuit_breaker,
+            Some(CircuitBreakerPolicy::new(3, Duration::from_secs(30)))
+        );
+        assert_eq!(
+            builder.rate_limits[&EndpointClass::Send],
+            RateLimit::per_second(100.0)
+        );
+
+        let error =
+            ClientConfig::from_vars(|name| (name == "FCM_MAX_RETRIES").then(|| "many".to_string()))
+                .unwrap_err();
+        assert!(error.starts_with("FCM_MAX_RETRIES"), "{error}");
+        assert!(serde_json::from_str::<ClientConfig>(r#"{"max_retry": 1}"#).is_err());
+    }
+}
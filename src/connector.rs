@@ -0,0 +1,114 @@
+//! HTTPS connector used when both `native-tls` and `rustls` features are enabled.
+//!
+//! The TLS library is then chosen at runtime with [crate::FCMClientBuilder::tls_backend].
+use crate::proxy::ProxyConnector;
+use http::Uri;
+use hyper::rt::{Read, ReadBufCursor, Write};
+use hyper_util::client::legacy::connect::{Connected, Connection};
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tower_service::Service;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+type TcpStream = <ProxyConnector as Service<Uri>>::Response;
+
+/// Either of the HTTPS connectors of hyper-tls and hyper-rustls.
+#[derive(Clone)]
+pub enum HttpsConnector<H> {
+    NativeTls(hyper_tls::HttpsConnector<H>),
+    Rustls(hyper_rustls::HttpsConnector<H>),
+}
+
+impl<H> std::fmt::Debug for HttpsConnector<H> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NativeTls(_) => f.write_str("HttpsConnector::NativeTls"),
+            Self::Rustls(_) => f.write_str("HttpsConnector::Rustls"),
+        }
+    }
+}
+
+impl Service<Uri> for HttpsConnector<ProxyConnector> {
+    type Response = MaybeHttpsStream;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<MaybeHttpsStream, BoxError>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), BoxError>> {
+        match self {
+            Self::NativeTls(connector) => connector.poll_ready(cx),
+            Self::Rustls(connector) => connector.poll_ready(cx),
+        }
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        match self {
+            Self::NativeTls(connector) => {
+                let connecting = connector.call(uri);
+                Box::pin(async move { Ok(MaybeHttpsStream::NativeTls(connecting.await?)) })
+            }
+            Self::Rustls(connector) => {
+                let connecting = connector.call(uri);
+                Box::pin(async move { Ok(MaybeHttpsStream::Rustls(Box::new(connecting.await?))) })
+            }
+        }
+    }
+}
+
+/// Connection established by [HttpsConnector].
+pub enum MaybeHttpsStream {
+    NativeTls(hyper_tls::MaybeHttpsStream<TcpStream>),
+    Rustls(Box<hyper_rustls::MaybeHttpsStream<TcpStream>>),
+}
+
+impl Connection for MaybeHttpsStream {
+    fn connected(&self) -> Connected {
+        match self {
+            Self::NativeTls(stream) => stream.connected(),
+            Self::Rustls(stream) => stream.connected(),
+        }
+    }
+}
+
+impl Read for MaybeHttpsStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: ReadBufCursor<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::NativeTls(stream) => Pin::new(stream).poll_read(cx, buf),
+            Self::Rustls(stream) => Pin::new(stream.as_mut()).poll_read(cx, buf),
+        }
+    }
+}
+
+impl Write for MaybeHttpsStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Self::NativeTls(stream) => Pin::new(stream).poll_write(cx, buf),
+            Self::Rustls(stream) => Pin::new(stream.as_mut()).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::NativeTls(stream) => Pin::new(stream).poll_flush(cx),
+            Self::Rustls(stream) => Pin::new(stream.as_mut()).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Self::NativeTls(stream) => Pin::new(stream).poll_shutdown(cx),
+            Self::Rustls(stream) => Pin::new(stream.as_mut()).poll_shutdown(cx),
+        }
+    }
+}
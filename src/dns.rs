@@ -0,0 +1,253 @@
+//! Name resolution of the connections to Google APIs. See [crate::FCMClientBuilder::google_access].
+//!
+//! Deployments with restricted DNS or split-horizon setups can replace `getaddrinfo` with a [Resolve]
+//! implementation, e.g. one backed by hickory-resolver or a [StaticResolver] of fixed addresses,
+//! with [crate::FCMClientBuilder::resolver].
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), String> {
+//! use firebase_messaging_rs::{dns::StaticResolver, FCMClient};
+//! use std::{net::Ipv4Addr, sync::Arc};
+//!
+//! let resolver = StaticResolver::new().host("fcm.googleapis.com", [Ipv4Addr::new(10, 0, 0, 8).into()]);
+//! let client = FCMClient::builder().resolver(Arc::new(resolver)).build().await?;
+//! # Ok(())
+//! # }
+//! ```
+use async_trait::async_trait;
+use hyper_util::client::legacy::connect::dns::{GaiResolver, Name};
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    io,
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    str::FromStr,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tower_service::Service;
+
+/// HTTP connector used by [crate::FCMClient].
+pub type HttpConnector = hyper_util::client::legacy::connect::HttpConnector<Resolver>;
+
+/// Addresses through which `*.googleapis.com` is reached.
+///
+/// With [Private Google Access](https://cloud.google.com/vpc/docs/configure-private-google-access),
+/// API hosts are resolved to the virtual IPs of another host name while URLs, `Host` headers and
+/// TLS server names stay the same, so certificates of e.g. `fcm.googleapis.com` are still verified.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GoogleAccess {
+    /// Resolve every host as is.
+    #[default]
+    Public,
+    /// Connect through the VIPs of `private.googleapis.com`.
+    Private,
+    /// Connect through the VIPs of `restricted.googleapis.com`, required inside VPC Service Controls perimeters.
+    Restricted,
+    /// Connect through the addresses of this host, e.g. a Private Service Connect endpoint name.
+    Custom(String),
+}
+
+impl GoogleAccess {
+    /// Host to resolve in place of `host`, if any.
+    fn resolve_as(&self, host: &str) -> Option<&str> {
+        let via = match self {
+            Self::Public => return None,
+            Self::Private => "private.googleapis.com",
+            Self::Restricted => "restricted.googleapis.com",
+            Self::Custom(via) => via,
+        };
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        (host.ends_with(".googleapis.com") && host != via).then_some(via)
+    }
+}
+
+/// Resolves host names in place of `getaddrinfo`. See [crate::FCMClientBuilder::resolver].
+#[async_trait]
+pub trait Resolve: Send + Sync {
+    /// Addresses of `host`, tried in order. Their ports are replaced with the port of the URL unless it is `0`.
+    async fn resolve(&self, host: &str) -> io::Result<Vec<SocketAddr>>;
+}
+
+impl std::fmt::Debug for dyn Resolve {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Resolve")
+    }
+}
+
+/// Fixed addresses of some hosts, like `/etc/hosts`. Other hosts are resolved with `getaddrinfo`.
+#[derive(Debug, Clone, Default)]
+pub struct StaticResolver {
+    hosts: BTreeMap<String, Vec<IpAddr>>,
+}
+
+impl StaticResolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Resolve `host` to `addrs`. Host names are case-insensitive.
+    pub fn host<I: IntoIterator<Item = IpAddr>>(mut self, host: &str, addrs: I) -> Self {
+        self.hosts
+            .insert(normalize(host), addrs.into_iter().collect());
+        self
+    }
+}
+
+#[async_trait]
+impl Resolve for StaticResolver {
+    async fn resolve(&self, host: &str) -> io::Result<Vec<SocketAddr>> {
+        if let Some(addrs) = self.hosts.get(&normalize(host)) {
+            return Ok(addrs.iter().map(|ip| SocketAddr::new(*ip, 0)).collect());
+        }
+        let name =
+            Name::from_str(host).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        Ok(GaiResolver::new().call(name).await?.collect())
+    }
+}
+
+fn normalize(host: &str) -> String {
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// Resolver of [HttpConnector], which applies [GoogleAccess] on top of `getaddrinfo` or a custom [Resolve].
+#[derive(Debug, Clone)]
+pub struct Resolver {
+    access: GoogleAccess,
+    gai: GaiResolver,
+    custom: Option<Arc<dyn Resolve>>,
+}
+
+impl Resolver {
+    pub fn new(access: GoogleAccess) -> Self {
+        Self {
+            access,
+            gai: GaiResolver::new(),
+            custom: None,
+        }
+    }
+    /// Resolve hosts with `resolver` instead of `getaddrinfo`. [GoogleAccess] still picks the host to resolve.
+    pub fn with_custom(mut self, resolver: Arc<dyn Resolve>) -> Self {
+        self.custom = Some(resolver);
+        self
+    }
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Self::new(GoogleAccess::default())
+    }
+}
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+impl Service<Name> for Resolver {
+    type Response = std::vec::IntoIter<SocketAddr>;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, BoxError>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), BoxError>> {
+        match self.custom {
+            Some(_) => Poll::Ready(Ok(())),
+            None => self.gai.poll_ready(cx).map_err(Into::into),
+        }
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        let name = match self.access.resolve_as(name.as_str()) {
+            Some(via) => match Name::from_str(via) {
+                Ok(via) => via,
+                Err(e) => {
+                    let e = io::Error::new(io::ErrorKind::InvalidInput, e);
+                    return Box::pin(async move { Err(e.into()) });
+                }
+            },
+            None => name,
+        };
+        if let Some(custom) = self.custom.clone() {
+            return Box::pin(async move {
+                let addrs = custom.resolve(name.as_str()).await?;
+                if addrs.is_empty() {
+                    let e = format!("no addresses found for {}", name.as_str());
+                    return Err(io::Error::new(io::ErrorKind::NotFound, e).into());
+                }
+                Ok(addrs.into_iter())
+            });
+        }
+        let resolving = self.gai.call(name);
+        Box::pin(async move {
+            let addrs = resolving.await?;
+            Ok(addrs.collect::<Vec<_>>().into_iter())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{GoogleAccess, Resolver, StaticResolver};
+    use hyper_util::client::legacy::connect::dns::Name;
+    use std::{
+        net::{IpAddr, Ipv4Addr, SocketAddr},
+        str::FromStr,
+        sync::Arc,
+    };
+    use tower_service::Service;
+
+    #[test]
+    fn only_google_api_hosts_are_redirected() {
+        let restricted = GoogleAccess::Restricted;
+        assert_eq!(
+            restricted.resolve_as("fcm.googleapis.com"),
+            Some("restricted.googleapis.com")
+        );
+        assert_eq!(restricted.resolve_as("restricted.googleapis.com"), None);
+        assert_eq!(restricted.resolve_as("example.com"), None);
+        assert_eq!(GoogleAccess::Public.resolve_as("fcm.googleapis.com"), None);
+    }
+
+    #[tokio::test]
+    async fn api_hosts_resolve_to_the_access_host() {
+        let mut resolver = Resolver::new(GoogleAccess::Custom("localhost".to_string()));
+        let addrs = resolver
+            .call(Name::from_str("fcm.googleapis.com").unwrap())
+            .await
+            .unwrap()
+            .collect::<Vec<_>>();
+        assert!(!addrs.is_empty());
+        assert!(addrs.iter().all(|addr| addr.ip().is_loopback()));
+    }
+
+    #[tokio::test]
+    async fn custom_resolver_replaces_getaddrinfo() {
+        let psc = IpAddr::from(Ipv4Addr::new(10, 0, 0, 8));
+        let hosts = StaticResolver::new()
+            .host("PSC.internal.", [psc])
+            .host("fcm.test", [Ipv4Addr::LOCALHOST.into()]);
+        let mut resolver = Resolver::new(GoogleAccess::Custom("psc.internal".to_string()))
+            .with_custom(Arc::new(hosts.clone()));
+        let addrs = resolver
+            .call(Name::from_str("fcm.googleapis.com").unwrap())
+            .await
+            .unwrap()
+            .collect::<Vec<_>>();
+        assert_eq!(addrs, [SocketAddr::new(psc, 0)]);
+        let addrs = resolver
+            .call(Name::from_str("localhost").unwrap())
+            .await
+            .unwrap()
+            .collect::<Vec<_>>();
+        assert!(addrs.iter().all(|addr| addr.ip().is_loopback()));
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mut connector = crate::FCMClient::builder()
+            .resolver(Arc::new(hosts))
+            .http_connector();
+        let uri = format!("http://fcm.test:{port}").parse().unwrap();
+        let (connected, accepted) = tokio::join!(connector.call(uri), listener.accept());
+        assert!(connected.is_ok());
+        assert!(accepted.is_ok());
+    }
+}
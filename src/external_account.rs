@@ -0,0 +1,922 @@
+//! Workload identity federation: access tokens for workloads outside Google Cloud, such as CI jobs or AWS,
+//! exchanged with the Security Token Service for credentials of their own platform.
+//!
+//! Credential configuration files of type `external_account`, e.g. generated by
+//! `gcloud iam workload-identity-pools create-cred-config`, are accepted wherever a credentials file is.
+//! Subject tokens are read from a file, a URL or AWS. Executable-sourced credentials are not supported.
+use crate::{
+    auth::{self, AuthError, TokenCache, TokenProvider, TokenSource},
+    percent_encode,
+    transport::HttpTransport,
+    CLOUD_PLATFORM_SCOPE,
+};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use http::{Method, Request};
+use hyper::body::Bytes;
+use serde::Deserialize;
+use std::{collections::BTreeMap, sync::Arc};
+
+/// `type` of external account credentials.
+const EXTERNAL_ACCOUNT: &str = "external_account";
+
+const TOKEN_EXCHANGE_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:token-exchange";
+const ACCESS_TOKEN_TYPE: &str = "urn:ietf:params:oauth:token-type:access_token";
+
+/// Configuration of external account credentials.
+pub(crate) struct ExternalAccount {
+    audience: String,
+    subject_token_type: String,
+    token_url: String,
+    impersonation_url: Option<String>,
+    token_lifetime: Option<u64>,
+    user_project: Option<String>,
+    subject: Subject,
+}
+
+/// Where subject tokens come from.
+#[derive(Debug, PartialEq, Eq)]
+enum Subject {
+    File {
+        path: String,
+        format: Format,
+    },
+    Url {
+        url: String,
+        headers: BTreeMap<String, String>,
+        format: Format,
+    },
+    Aws(Aws),
+}
+
+/// Format of a subject token read from a file or a URL.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Format {
+    #[default]
+    Text,
+    Json {
+        subject_token_field_name: String,
+    },
+}
+
+/// Signed `GetCallerIdentity` requests of the AWS role of the workload, used as subject tokens.
+#[derive(Debug, PartialEq, Eq)]
+struct Aws {
+    region_url: Option<String>,
+    credentials_url: Option<String>,
+    verification_url: String,
+    imdsv2_session_token_url: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Config {
+    audience: String,
+    subject_token_type: String,
+    token_url: String,
+    service_account_impersonation_url: Option<String>,
+    #[serde(default)]
+    service_account_impersonation: ImpersonationConfig,
+    workforce_pool_user_project: Option<String>,
+    credential_source: CredentialSourceConfig,
+}
+
+#[derive(Default, Deserialize)]
+struct ImpersonationConfig {
+    token_lifetime_seconds: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct CredentialSourceConfig {
+    file: Option<String>,
+    url: Option<String>,
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+    #[serde(default)]
+    format: Format,
+    environment_id: Option<String>,
+    region_url: Option<String>,
+    regional_cred_verification_url: Option<String>,
+    imdsv2_session_token_url: Option<String>,
+    executable: Option<serde_json::Value>,
+}
+
+fn invalid(reason: impl std::fmt::Display) -> AuthError {
+    AuthError::Provider(format!("invalid external account credentials: {reason}"))
+}
+
+impl ExternalAccount {
+    /// The external account configuration `source` points to, if any.
+    /// Files that cannot be read are left to gcloud-sdk to report.
+    pub(crate) fn of(source: &TokenSource) -> Result<Option<Self>, AuthError> {
+        let json = match source {
+            TokenSource::Default => std::env::var_os("GOOGLE_APPLICATION_CREDENTIALS")
+                .and_then(|path| std::fs::read(path).ok()),
+            TokenSource::File(path) => std::fs::read(path).ok(),
+            TokenSource::Json(json) => Some(json.clone().into_bytes()),
+            TokenSource::MetadataServer | TokenSource::MetadataServerAccount(_) => None,
+        };
+        json.map_or(Ok(None), |json| Self::parse(&json))
+    }
+
+    /// Parse credentials JSON. Credentials of another `type` are `None`.
+    fn parse(json: &[u8]) -> Result<Option<Self>, AuthError> {
+        #[derive(Deserialize)]
+        struct Typed {
+            #[serde(rename = "type")]
+            kind: Option<String>,
+        }
+        match serde_json::from_slice::<Typed>(json) {
+            Ok(Typed { kind: Some(kind) }) if kind == EXTERNAL_ACCOUNT => {}
+            _ => return Ok(None),
+        }
+        let config: Config = serde_json::from_slice(json).map_err(invalid)?;
+        Ok(Some(Self {
+            audience: config.audience,
+            subject_token_type: config.subject_token_type,
+            token_url: config.token_url,
+            impersonation_url: config.service_account_impersonation_url,
+            token_lifetime: config.service_account_impersonation.token_lifetime_seconds,
+            user_project: config.workforce_pool_user_project,
+            subject: Subject::of(config.credential_source)?,
+        }))
+    }
+}
+
+impl Subject {
+    fn of(source: CredentialSourceConfig) -> Result<Self, AuthError> {
+        if let Some(environment_id) = source.environment_id {
+            return match environment_id.as_str() {
+                "aws1" => Ok(Self::Aws(Aws {
+                    region_url: source.region_url,
+                    credentials_url: source.url,
+                    verification_url: source
+                        .regional_cred_verification_url
+                        .ok_or_else(|| invalid("regional_cred_verification_url is missing"))?,
+                    imdsv2_session_token_url: source.imdsv2_session_token_url,
+                })),
+                _ => Err(invalid(format!(
+                    "unsupported environment_id {environment_id:?}"
+                ))),
+            };
+        }
+        if source.executable.is_some() {
+            return Err(invalid("executable-sourced credentials are not supported"));
+        }
+        match (source.file, source.url) {
+            (Some(path), _) => Ok(Self::File {
+                path,
+                format: source.format,
+            }),
+            (None, Some(url)) => Ok(Self::Url {
+                url,
+                headers: source.headers,
+                format: source.format,
+            }),
+            (None, None) => Err(invalid(
+                "credential_source needs one of file, url or environment_id",
+            )),
+        }
+    }
+
+    async fn token(
+        &self,
+        transport: &dyn HttpTransport,
+        user_agent: &str,
+        audience: &str,
+    ) -> Result<String, AuthError> {
+        match self {
+            Self::File { path, format } => {
+                let content = std::fs::read_to_string(path).map_err(|e| {
+                    AuthError::Provider(format!("unable to read subject token from {path}: {e}"))
+                })?;
+                format.subject_token(&content)
+            }
+            Self::Url {
+                url,
+                headers,
+                format,
+            } => {
+                let headers: Vec<_> = headers
+                    .iter()
+                    .map(|(name, value)| (name.as_str(), value.as_str()))
+                    .collect();
+                let request = request(Method::GET, url, &headers, Bytes::new(), user_agent)?;
+                let body = auth::exchange(transport, request).await?;
+                format.subject_token(&String::from_utf8_lossy(&body))
+            }
+            Self::Aws(aws) => {
+                let env = |name: &str| std::env::var(name).ok();
+                aws.subject_token(transport, user_agent, audience, &env, Utc::now())
+                    .await
+            }
+        }
+    }
+}
+
+impl Format {
+    fn subject_token(&self, content: &str) -> Result<String, AuthError> {
+        match self {
+            Self::Text => Ok(content.trim().to_string()),
+            Self::Json {
+                subject_token_field_name,
+            } => serde_json::from_str::<serde_json::Value>(content)
+                .ok()
+                .and_then(|value| {
+                    value
+                        .get(subject_token_field_name)?
+                        .as_str()
+                        .map(str::to_string)
+                })
+                .ok_or_else(|| {
+                    AuthError::Provider(format!(
+                        "subject token has no {subject_token_field_name:?} field"
+                    ))
+                }),
+        }
+    }
+}
+
+/// Build a request to a credential endpoint.
+fn request(
+    method: Method,
+    url: &str,
+    headers: &[(&str, &str)],
+    body: Bytes,
+    user_agent: &str,
+) -> Result<Request<Bytes>, AuthError> {
+    let mut builder = Request::builder()
+        .method(method)
+        .uri(url)
+        .header(http::header::USER_AGENT, user_agent);
+    for (name, value) in headers {
+        builder = builder.header(*name, *value);
+    }
+    builder
+        .body(body)
+        .map_err(|e| AuthError::Provider(format!("invalid request to {url}: {e}")))
+}
+
+/// Credentials of an AWS role.
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct AwsCredentials {
+    access_key_id: String,
+    secret_access_key: String,
+    #[serde(rename = "Token")]
+    session_token: Option<String>,
+}
+
+impl Aws {
+    /// A `GetCallerIdentity` request signed with the credentials of the workload, serialized as
+    /// the Security Token Service expects it. `env` looks up environment variables.
+    async fn subject_token(
+        &self,
+        transport: &dyn HttpTransport,
+        user_agent: &str,
+        audience: &str,
+        env: &(dyn Fn(&str) -> Option<String> + Sync),
+        now: DateTime<Utc>,
+    ) -> Result<String, AuthError> {
+        let region = env("AWS_REGION").or_else(|| env("AWS_DEFAULT_REGION"));
+        let credentials = match (env("AWS_ACCESS_KEY_ID"), env("AWS_SECRET_ACCESS_KEY")) {
+            (Some(access_key_id), Some(secret_access_key)) => Some(AwsCredentials {
+                access_key_id,
+                secret_access_key,
+                session_token: env("AWS_SESSION_TOKEN"),
+            }),
+            _ => None,
+        };
+        let session_token = match &self.imdsv2_session_token_url {
+            Some(url) if region.is_none() || credentials.is_none() => {
+                let ttl = [("x-aws-ec2-metadata-token-ttl-seconds", "300")];
+                let request = request(Method::PUT, url, &ttl, Bytes::new(), user_agent)?;
+                let token = auth::exchange(transport, request).await?;
+                Some(String::from_utf8_lossy(&token).trim().to_string())
+            }
+            _ => None,
+        };
+        let metadata = |url: &str| {
+            let headers: Vec<_> = session_token
+                .iter()
+                .map(|token| ("x-aws-ec2-metadata-token", token.as_str()))
+                .collect();
+            request(Method::GET, url, &headers, Bytes::new(), user_agent)
+        };
+        let region = match region {
+            Some(region) => region,
+            None => {
+                let url = self.region_url.as_deref().ok_or_else(|| {
+                    AuthError::Provider("AWS region is unknown: set AWS_REGION".to_string())
+                })?;
+                let zone = auth::exchange(transport, metadata(url)?).await?;
+                region_of_zone(String::from_utf8_lossy(&zone).trim())?
+            }
+        };
+        let credentials = match credentials {
+            Some(credentials) => credentials,
+            None => {
+                let url = self.credentials_url.as_deref().ok_or_else(|| {
+                    AuthError::Provider(
+                        "AWS credentials are unknown: set AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY"
+                            .to_string(),
+                    )
+                })?;
+                let role = auth::exchange(transport, metadata(url)?).await?;
+                let role = String::from_utf8_lossy(&role);
+                let url = format!("{}/{}", url.trim_end_matches('/'), role.trim());
+                let credentials = auth::exchange(transport, metadata(&url)?).await?;
+                serde_json::from_slice(&credentials).map_err(|e| {
+                    AuthError::Provider(format!("invalid AWS credentials from {url}: {e}"))
+                })?
+            }
+        };
+
+        let url = self.verification_url.replace("{region}", &region);
+        let uri = url
+            .parse::<http::Uri>()
+            .map_err(|e| invalid(format!("regional_cred_verification_url {url:?}: {e}")))?;
+        let mut headers = BTreeMap::from([
+            (
+                "host".to_string(),
+                uri.host().unwrap_or_default().to_string(),
+            ),
+            (
+                "x-amz-date".to_string(),
+                now.format("%Y%m%dT%H%M%SZ").to_string(),
+            ),
+            (
+                "x-goog-cloud-target-resource".to_string(),
+                audience.to_string(),
+            ),
+        ]);
+        if let Some(session_token) = &credentials.session_token {
+            headers.insert("x-amz-security-token".to_string(), session_token.clone());
+        }
+        let authorization = sigv4_authorization(
+            "POST",
+            &uri,
+            &headers,
+            b"",
+            &credentials,
+            &region,
+            "sts",
+            now,
+        );
+        headers.insert("Authorization".to_string(), authorization);
+        let signed = serde_json::json!({
+            "url": url,
+            "method": "POST",
+            "headers": headers
+                .iter()
+                .map(|(key, value)| serde_json::json!({ "key": key, "value": value }))
+                .collect::<Vec<_>>(),
+        });
+        Ok(percent_encode(&signed.to_string(), b""))
+    }
+}
+
+/// Region of an availability zone, e.g. `us-east-1` of `us-east-1b`, which is the region followed by a letter.
+fn region_of_zone(zone: &str) -> Result<String, AuthError> {
+    match zone.strip_suffix(|c: char| c.is_ascii_lowercase()) {
+        Some(region) if !region.is_empty() => Ok(region.to_string()),
+        _ => Err(AuthError::Provider(format!(
+            "invalid AWS availability zone {zone:?}"
+        ))),
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn hmac_sha256(key: &[u8], data: &str) -> Vec<u8> {
+    use hmac::Mac;
+
+    let mut mac = hmac::Hmac::<sha2::Sha256>::new_from_slice(key).expect("any key length is valid");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn sha256(data: &[u8]) -> String {
+    use sha2::Digest;
+
+    hex(&sha2::Sha256::digest(data))
+}
+
+/// `Authorization` header of a request signed with AWS Signature Version 4.
+/// `headers` are the headers to sign, with lowercase names, including `host` and `x-amz-date`.
+#[allow(clippy::too_many_arguments)]
+fn sigv4_authorization(
+    method: &str,
+    uri: &http::Uri,
+    headers: &BTreeMap<String, String>,
+    body: &[u8],
+    credentials: &AwsCredentials,
+    region: &str,
+    service: &str,
+    now: DateTime<Utc>,
+) -> String {
+    let mut query: Vec<_> = uri
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.split_once('=') {
+            Some((name, value)) => (name, value),
+            None => (pair, ""),
+        })
+        .collect();
+    query.sort_unstable();
+    let query: Vec<_> = query
+        .iter()
+        .map(|(name, value)| format!("{name}={value}"))
+        .collect();
+    let signed_headers = headers.keys().cloned().collect::<Vec<_>>().join(";");
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(name, value)| format!("{name}:{}\n", value.trim()))
+        .collect();
+    let path = match uri.path() {
+        "" => "/",
+        path => path,
+    };
+    let canonical_request = format!(
+        "{method}\n{path}\n{}\n{canonical_headers}\n{signed_headers}\n{}",
+        query.join("&"),
+        sha256(body)
+    );
+    let date = now.format("%Y%m%d").to_string();
+    let scope = format!("{date}/{region}/{service}/aws4_request");
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{scope}\n{}",
+        now.format("%Y%m%dT%H%M%SZ"),
+        sha256(canonical_request.as_bytes())
+    );
+    let key = format!("AWS4{}", credentials.secret_access_key);
+    let key = hmac_sha256(key.as_bytes(), &date);
+    let key = hmac_sha256(&key, region);
+    let key = hmac_sha256(&key, service);
+    let key = hmac_sha256(&key, "aws4_request");
+    let signature = hex(&hmac_sha256(&key, &string_to_sign));
+    format!(
+        "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+        credentials.access_key_id
+    )
+}
+
+/// [TokenProvider] of tokens exchanged with the Security Token Service for subject tokens.
+struct StsTokenProvider {
+    account: ExternalAccount,
+    scopes: Vec<String>,
+    transport: Arc<dyn HttpTransport>,
+    user_agent: String,
+    cache: TokenCache,
+}
+
+impl StsTokenProvider {
+    async fn exchange(&self) -> Result<(String, DateTime<Utc>), AuthError> {
+        #[derive(Deserialize)]
+        struct ExchangedToken {
+            access_token: String,
+            expires_in: Option<i64>,
+        }
+        let account = &self.account;
+        let subject_token = account
+            .subject
+            .token(self.transport.as_ref(), &self.user_agent, &account.audience)
+            .await?;
+        let scope = self.scopes.join(" ");
+        let mut form = vec![
+            ("grant_type", TOKEN_EXCHANGE_GRANT_TYPE),
+            ("audience", &account.audience),
+            ("scope", &scope),
+            ("requested_token_type", ACCESS_TOKEN_TYPE),
+            ("subject_token", &subject_token),
+            ("subject_token_type", &account.subject_token_type),
+        ];
+        // Workforce pools bill the user project unless a service account is impersonated.
+        let options = match (&account.user_project, &account.impersonation_url) {
+            (Some(project), None) => {
+                Some(serde_json::json!({ "userProject": project }).to_string())
+            }
+            _ => None,
+        };
+        if let Some(options) = &options {
+            form.push(("options", options));
+        }
+        let body = form
+            .iter()
+            .map(|(name, value)| format!("{name}={}", percent_encode(value, b"")))
+            .collect::<Vec<_>>()
+            .join("&");
+        let content_type = [(
+            http::header::CONTENT_TYPE.as_str(),
+            "application/x-www-form-urlencoded",
+        )];
+        let request = request(
+            Method::POST,
+            &account.token_url,
+            &content_type,
+            Bytes::from(body),
+            &self.user_agent,
+        )?;
+        let body = auth::exchange(self.transport.as_ref(), request).await?;
+        let token: ExchangedToken =
+            serde_json::from_slice(&body).map_err(|e| AuthError::Provider(e.to_string()))?;
+        let expires_in = chrono::Duration::seconds(token.expires_in.unwrap_or(3600));
+        Ok((token.access_token, Utc::now() + expires_in))
+    }
+}
+
+#[async_trait]
+impl TokenProvider for StsTokenProvider {
+    async fn bearer(&self) -> Result<String, AuthError> {
+        Ok(self.bearer_with_expiry().await?.0)
+    }
+    async fn bearer_with_expiry(&self) -> Result<(String, Option<DateTime<Utc>>), AuthError> {
+        self.cache.get_or_mint(self.exchange()).await
+    }
+}
+
+/// [TokenProvider] of `account` for `scopes`, sending its requests with `transport`.
+pub(crate) fn token_provider(
+    account: ExternalAccount,
+    scopes: &[String],
+    transport: Arc<dyn HttpTransport>,
+    user_agent: &str,
+) -> Arc<dyn TokenProvider> {
+    let impersonation = account
+        .impersonation_url
+        .clone()
+        .map(|url| (url, account.token_lifetime));
+    let sts = Arc::new(StsTokenProvider {
+        account,
+        scopes: match impersonation {
+            Some(_) => vec![CLOUD_PLATFORM_SCOPE.to_string()],
+            None => scopes.to_vec(),
+        },
+        transport: transport.clone(),
+        user_agent: user_agent.to_string(),
+        cache: TokenCache::default(),
+    });
+    match impersonation {
+        Some((url, lifetime)) => Arc::new(
+            auth::ImpersonatedTokenProvider::new(Some(sts), transport, url, scopes, user_agent)
+                .with_lifetime(lifetime),
+        ),
+        None => sts,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        region_of_zone, sigv4_authorization, Aws, AwsCredentials, ExternalAccount, Format, Subject,
+    };
+    use crate::{auth::AuthError, transport::HttpTransport, RPCError};
+    use http::{Method, Request, Response, StatusCode};
+    use hyper::body::Bytes;
+    use std::{
+        collections::{BTreeMap, HashMap},
+        sync::{Arc, Mutex},
+    };
+
+    fn subject(credential_source: serde_json::Value) -> Result<Option<Subject>, AuthError> {
+        let config = serde_json::json!({
+            "type": "external_account",
+            "audience": "//iam.googleapis.com/projects/1/locations/global/workloadIdentityPools/ci/providers/github",
+            "subject_token_type": "urn:ietf:params:oauth:token-type:jwt",
+            "token_url": "https://sts.googleapis.com/v1/token",
+            "credential_source": credential_source,
+        });
+        ExternalAccount::parse(config.to_string().as_bytes())
+            .map(|account| account.map(|account| account.subject))
+    }
+
+    #[test]
+    fn credential_types_are_told_apart() {
+        for other in [
+            serde_json::json!({ "type": "service_account", "client_email": "a@b" }),
+            serde_json::json!({ "type": "authorized_user", "client_id": "c" }),
+            serde_json::json!({ "client_id": "c" }),
+        ] {
+            assert!(matches!(
+                ExternalAccount::parse(other.to_string().as_bytes()),
+                Ok(None)
+            ));
+        }
+        assert!(ExternalAccount::parse(br#"{"type":"external_account"}"#).is_err());
+
+        assert_eq!(
+            subject(serde_json::json!({ "file": "/var/run/token" })).unwrap(),
+            Some(Subject::File {
+                path: "/var/run/token".to_string(),
+                format: Format::Text,
+            })
+        );
+        assert_eq!(
+            subject(serde_json::json!({
+                "url": "http://localhost:8080/token",
+                "headers": { "Metadata": "True" },
+                "format": { "type": "json", "subject_token_field_name": "value" },
+            }))
+            .unwrap(),
+            Some(Subject::Url {
+                url: "http://localhost:8080/token".to_string(),
+                headers: BTreeMap::from([("Metadata".to_string(), "True".to_string())]),
+                format: Format::Json {
+                    subject_token_field_name: "value".to_string()
+                },
+            })
+        );
+        assert_eq!(
+            subject(serde_json::json!({
+                "environment_id": "aws1",
+                "region_url": "http://169.254.169.254/latest/meta-data/placement/availability-zone",
+                "url": "http://169.254.169.254/latest/meta-data/iam/security-credentials",
+                "regional_cred_verification_url": "https://sts.{region}.amazonaws.com?Action=GetCallerIdentity&Version=2011-06-15",
+            }))
+            .unwrap(),
+            Some(Subject::Aws(Aws {
+                region_url: Some(
+                    "http://169.254.169.254/latest/meta-data/placement/availability-zone"
+                        .to_string()
+                ),
+                credentials_url: Some(
+                    "http://169.254.169.254/latest/meta-data/iam/security-credentials".to_string()
+                ),
+                verification_url:
+                    "https://sts.{region}.amazonaws.com?Action=GetCallerIdentity&Version=2011-06-15"
+                        .to_string(),
+                imdsv2_session_token_url: None,
+            }))
+        );
+        for unsupported in [
+            serde_json::json!({ "environment_id": "aws2", "regional_cred_verification_url": "https://sts" }),
+            serde_json::json!({ "environment_id": "aws1" }),
+            serde_json::json!({ "executable": { "command": "/bin/token" } }),
+            serde_json::json!({}),
+        ] {
+            assert!(matches!(subject(unsupported), Err(AuthError::Provider(_))));
+        }
+    }
+
+    #[test]
+    fn regions_are_taken_from_availability_zones() {
+        assert_eq!(region_of_zone("us-east-1b").unwrap(), "us-east-1");
+        for zone in ["", "b", "us-east-1", "us-east-1\u{fffd}"] {
+            assert!(
+                matches!(region_of_zone(zone), Err(AuthError::Provider(_))),
+                "{zone}"
+            );
+        }
+    }
+
+    #[test]
+    fn requests_are_signed_like_aws() {
+        // Example of the AWS Signature Version 4 documentation.
+        let uri = "https://iam.amazonaws.com/?Action=ListUsers&Version=2010-05-08"
+            .parse()
+            .unwrap();
+        let headers = BTreeMap::from([
+            (
+                "content-type".to_string(),
+                "application/x-www-form-urlencoded; charset=utf-8".to_string(),
+            ),
+            ("host".to_string(), "iam.amazonaws.com".to_string()),
+            ("x-amz-date".to_string(), "20150830T123600Z".to_string()),
+        ]);
+        let credentials = AwsCredentials {
+            access_key_id: "AKIDEXAMPLE".to_string(),
+            secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string(),
+            session_token: None,
+        };
+        let now = "2015-08-30T12:36:00Z".parse().unwrap();
+        assert_eq!(
+            sigv4_authorization(
+                "GET",
+                &uri,
+                &headers,
+                b"",
+                &credentials,
+                "us-east-1",
+                "iam",
+                now
+            ),
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/iam/aws4_request, \
+             SignedHeaders=content-type;host;x-amz-date, \
+             Signature=5d672d79c15b13162d9279b0855cfba6789a8edb4c82c400e06b5924a6f2b5d7"
+        );
+    }
+
+    /// Serve `routes` by method and URL, and record the requests.
+    #[derive(Clone, Default)]
+    struct Endpoints {
+        routes: HashMap<(Method, String), String>,
+        requests: Arc<Mutex<Vec<Request<Bytes>>>>,
+    }
+
+    impl Endpoints {
+        fn route(mut self, method: Method, url: &str, body: serde_json::Value) -> Self {
+            let body = match body {
+                serde_json::Value::String(text) => text,
+                json => json.to_string(),
+            };
+            self.routes.insert((method, url.to_string()), body);
+            self
+        }
+        fn form(&self, i: usize) -> BTreeMap<String, String> {
+            let requests = self.requests.lock().unwrap();
+            std::str::from_utf8(requests[i].body())
+                .unwrap()
+                .split('&')
+                .map(|pair| {
+                    let (name, value) = pair.split_once('=').unwrap();
+                    (name.to_string(), percent_decode(value))
+                })
+                .collect()
+        }
+    }
+
+    fn percent_decode(s: &str) -> String {
+        let mut decoded = Vec::new();
+        let mut bytes = s.bytes();
+        while let Some(byte) = bytes.next() {
+            match byte {
+                b'%' => {
+                    let hex = [bytes.next().unwrap(), bytes.next().unwrap()];
+                    let hex = std::str::from_utf8(&hex).unwrap();
+                    decoded.push(u8::from_str_radix(hex, 16).unwrap());
+                }
+                byte => decoded.push(byte),
+            }
+        }
+        String::from_utf8(decoded).unwrap()
+    }
+
+    #[async_trait::async_trait]
+    impl HttpTransport for Endpoints {
+        async fn send(&self, request: Request<Bytes>) -> Result<Response<Bytes>, RPCError> {
+            let route = (request.method().clone(), request.uri().to_string());
+            let body = self.routes.get(&route).cloned();
+            self.requests.lock().unwrap().push(request);
+            let (status, body) = match body {
+                Some(body) => (StatusCode::OK, body),
+                None => (StatusCode::NOT_FOUND, String::new()),
+            };
+            Ok(Response::builder()
+                .status(status)
+                .body(Bytes::from(body))
+                .unwrap())
+        }
+    }
+
+    #[tokio::test]
+    async fn subject_tokens_are_exchanged_and_impersonated() {
+        let path = std::env::temp_dir().join(format!("fcm-oidc-{}.json", uuid::Uuid::new_v4()));
+        std::fs::write(&path, r#"{"id_token":"github-oidc-token"}"#).unwrap();
+        let impersonation_url =
+            crate::auth::generate_access_token_url("sender@my-project.iam.gserviceaccount.com");
+        let config = serde_json::json!({
+            "type": "external_account",
+            "audience": "//iam.googleapis.com/projects/1/locations/global/workloadIdentityPools/ci/providers/github",
+            "subject_token_type": "urn:ietf:params:oauth:token-type:jwt",
+            "token_url": "https://sts.googleapis.com/v1/token",
+            "service_account_impersonation_url": impersonation_url,
+            "service_account_impersonation": { "token_lifetime_seconds": 600 },
+            "credential_source": {
+                "file": path,
+                "format": { "type": "json", "subject_token_field_name": "id_token" },
+            },
+        });
+        let account = ExternalAccount::parse(config.to_string().as_bytes())
+            .unwrap()
+            .unwrap();
+        let endpoints = Endpoints::default()
+            .route(
+                Method::POST,
+                "https://sts.googleapis.com/v1/token",
+                serde_json::json!({ "access_token": "federated-token", "expires_in": 3600 }),
+            )
+            .route(
+                Method::POST,
+                &impersonation_url,
+                serde_json::json!({
+                    "accessToken": "impersonated-token",
+                    "expireTime": chrono::Utc::now() + chrono::Duration::minutes(10),
+                }),
+            );
+        let scopes = [crate::FIREBASE_MESSAGING_SCOPE.to_string()];
+        let provider = super::token_provider(account, &scopes, Arc::new(endpoints.clone()), "test");
+        for _ in 0..2 {
+            assert_eq!(provider.bearer().await.unwrap(), "impersonated-token");
+        }
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(endpoints.requests.lock().unwrap().len(), 2);
+        let form = endpoints.form(0);
+        assert_eq!(form["subject_token"], "github-oidc-token");
+        assert_eq!(
+            form["subject_token_type"],
+            "urn:ietf:params:oauth:token-type:jwt"
+        );
+        assert_eq!(
+            form["scope"],
+            "https://www.googleapis.com/auth/cloud-platform"
+        );
+        let requests = endpoints.requests.lock().unwrap();
+        assert_eq!(
+            requests[1].headers()[http::header::AUTHORIZATION],
+            "Bearer federated-token"
+        );
+        let body: serde_json::Value = serde_json::from_slice(requests[1].body()).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({ "scope": scopes, "lifetime": "600s" })
+        );
+    }
+
+    #[tokio::test]
+    async fn aws_subject_tokens_are_signed_caller_identity_requests() {
+        let metadata = "http://169.254.169.254/latest";
+        let aws = Aws {
+            region_url: Some(format!("{metadata}/meta-data/placement/availability-zone")),
+            credentials_url: Some(format!("{metadata}/meta-data/iam/security-credentials")),
+            verification_url:
+                "https://sts.{region}.amazonaws.com?Action=GetCallerIdentity&Version=2011-06-15"
+                    .to_string(),
+            imdsv2_session_token_url: Some(format!("{metadata}/api/token")),
+        };
+        let endpoints = Endpoints::default()
+            .route(
+                Method::PUT,
+                &format!("{metadata}/api/token"),
+                "session".into(),
+            )
+            .route(
+                Method::GET,
+                &format!("{metadata}/meta-data/placement/availability-zone"),
+                "us-east-1b".into(),
+            )
+            .route(
+                Method::GET,
+                &format!("{metadata}/meta-data/iam/security-credentials"),
+                "fcm-sender".into(),
+            )
+            .route(
+                Method::GET,
+                &format!("{metadata}/meta-data/iam/security-credentials/fcm-sender"),
+                serde_json::json!({
+                    "AccessKeyId": "AKID",
+                    "SecretAccessKey": "secret",
+                    "Token": "aws-session-token",
+                }),
+            );
+        let now = "2024-01-02T03:04:05Z".parse().unwrap();
+        let token = aws
+            .subject_token(
+                &endpoints,
+                "test",
+                "//iam.googleapis.com/aws",
+                &|_| None,
+                now,
+            )
+            .await
+            .unwrap();
+
+        let requests = endpoints.requests.lock().unwrap();
+        assert_eq!(requests.len(), 4);
+        assert!(requests[1..]
+            .iter()
+            .all(|request| request.headers()["x-aws-ec2-metadata-token"] == "session"));
+        let signed: serde_json::Value = serde_json::from_str(&percent_decode(&token)).unwrap();
+        assert_eq!(
+            signed["url"],
+            "https://sts.us-east-1.amazonaws.com?Action=GetCallerIdentity&Version=2011-06-15"
+        );
+        assert_eq!(signed["method"], "POST");
+        let headers: BTreeMap<_, _> = signed["headers"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|header| {
+                (
+                    header["key"].as_str().unwrap(),
+                    header["value"].as_str().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(headers["host"], "sts.us-east-1.amazonaws.com");
+        assert_eq!(headers["x-amz-date"], "20240102T030405Z");
+        assert_eq!(headers["x-amz-security-token"], "aws-session-token");
+        assert_eq!(
+            headers["x-goog-cloud-target-resource"],
+            "//iam.googleapis.com/aws"
+        );
+        assert!(headers["Authorization"].starts_with(
+            "AWS4-HMAC-SHA256 Credential=AKID/20240102/us-east-1/sts/aws4_request, \
+             SignedHeaders=host;x-amz-date;x-amz-security-token;x-goog-cloud-target-resource, "
+        ));
+    }
+}
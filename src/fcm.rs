@@ -1,14 +1,35 @@
-use std::{collections::HashMap, time::Duration};
+use std::{
+    borrow::Borrow,
+    collections::{BTreeMap, HashMap},
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
 
 use async_trait::async_trait;
+use futures::{stream, StreamExt};
+use hyper::body::Bytes;
 use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
 /// Android specific options for messages sent through FCM connection server.
 pub mod android;
+#[cfg(feature = "fake")]
+pub mod fake;
 /// Apple Push Notification Service specific options.
 pub mod ios;
+/// Conversion of legacy FCM HTTP API payloads.
+pub mod legacy;
 /// Webpush protocol options.
 pub mod webpush;
-use crate::{GenericGoogleRestAPISupport, RPCError};
+use crate::{
+    FailedAttempt, GenericGoogleRestAPISupport, RPCError, Redacted, RequestContext,
+    ResponseMetadata, TransportErrorKind, FCM_BASE_URL,
+};
+
+fn send_path(project_id: &str) -> String {
+    format!("/v1/projects/{project_id}/messages:send")
+}
 
 use android::AndroidConfig;
 use ios::ApnsConfig;
@@ -18,46 +39,696 @@ use webpush::WebPushConfig;
 /// [FCMApi] trait supports APIs in <https://firebase.google.com/docs/reference/fcm/rest>
 /// This trait provides firebase cloud messaging utilities.
 pub trait FCMApi: GenericGoogleRestAPISupport {
+    /// Endpoint to send messages of `project_id` under [crate::FCM_BASE_URL].
     fn post_endpoint(project_id: &str) -> String {
-        format!("https://fcm.googleapis.com/v1/projects/{project_id}/messages:send")
+        format!("{FCM_BASE_URL}{}", send_path(project_id))
+    }
+    /// Endpoint to send messages of [GenericGoogleRestAPISupport::project_id] under [GenericGoogleRestAPISupport::fcm_base_url].
+    /// Fails if the project id is unknown, e.g. for a client created only for topic management.
+    fn send_endpoint(&self) -> Result<String, RPCError> {
+        let project_id = self.project_id();
+        if project_id.is_empty() {
+            return Err(RPCError::BuildRequestFailure(
+                "project id is required to send messages. Provide it by FCMClientBuilder::project_id or GOOGLE_CLOUD_PROJECT env var".to_string(),
+            ));
+        }
+        Ok(format!("{}{}", self.fcm_base_url(), send_path(&project_id)))
     }
     /// Send the message to firebase messaging API.
-    async fn send(&self, message: &Message) -> Result<MessageOutput, FCMError> {
+    /// `message` can be a [Message] or anything that borrows one, e.g. `&Message` or `Box<Message>`.
+    async fn send<M>(&self, message: M) -> Result<MessageOutput, FCMError>
+    where
+        M: Borrow<Message> + Send + Sync,
+    {
         let payload = MessagePayload {
             validate_only: false,
-            message,
+            message: message.borrow(),
         };
-        self.post_request(&Self::post_endpoint(&self.project_id()), &payload)
+        self.post_request(&self.send_endpoint()?, &payload).await
+    }
+    /// Send the message, giving up with [FCMError::DeadlineExceeded] if it is not sent within `deadline`, retries included.
+    /// The request in flight is aborted then, so the message may or may not have been delivered.
+    /// `attempts` of the error are always empty.
+    async fn send_with_deadline<M>(
+        &self,
+        message: M,
+        deadline: Duration,
+    ) -> Result<MessageOutput, FCMError>
+    where
+        M: Borrow<Message> + Send + Sync,
+    {
+        crate::until(self.send(message), tokio::time::sleep(deadline))
+            .await
+            .unwrap_or_else(|| {
+                Err(FCMError::from(RPCError::DeadlineExceeded {
+                    deadline,
+                    attempts: Vec::new(),
+                }))
+            })
+    }
+    /// Send the message unless `cancel` completes first, e.g. a shutdown signal or
+    /// `tokio_util::sync::CancellationToken::cancelled`, in which case it fails with [FCMError::Cancelled].
+    /// The request in flight is aborted then, so the message may or may not have been delivered.
+    async fn send_until<M, C>(&self, message: M, cancel: C) -> Result<MessageOutput, FCMError>
+    where
+        M: Borrow<Message> + Send + Sync,
+        C: Future<Output = ()> + Send,
+    {
+        crate::until(self.send(message), cancel)
             .await
+            .unwrap_or_else(|| Err(FCMError::from(RPCError::Cancelled)))
     }
     /// Send the message to firebase messaging API with dry run option.
-    async fn validate(&self, message: &Message) -> Result<MessageOutput, FCMError> {
+    async fn validate<M>(&self, message: M) -> Result<MessageOutput, FCMError>
+    where
+        M: Borrow<Message> + Send + Sync,
+    {
+        let payload = MessagePayload {
+            validate_only: true,
+            message: message.borrow(),
+        };
+        self.post_request(&self.send_endpoint()?, &payload).await
+    }
+    /// Send the message with additional HTTP headers, e.g. tracing or API gateway headers.
+    /// `Authorization`, `Content-Type` and `Accept` headers are set by this crate and must not be passed.
+    async fn send_with_headers<M>(
+        &self,
+        message: M,
+        headers: &[(&str, &str)],
+    ) -> Result<MessageOutput, FCMError>
+    where
+        M: Borrow<Message> + Send + Sync,
+    {
+        let payload = MessagePayload {
+            validate_only: false,
+            message: message.borrow(),
+        };
+        self.post_request_with(&self.send_endpoint()?, &payload, headers)
+            .await
+    }
+    /// Same as [FCMApi::send_with_headers] with dry run option.
+    async fn validate_with_headers<M>(
+        &self,
+        message: M,
+        headers: &[(&str, &str)],
+    ) -> Result<MessageOutput, FCMError>
+    where
+        M: Borrow<Message> + Send + Sync,
+    {
+        let payload = MessagePayload {
+            validate_only: true,
+            message: message.borrow(),
+        };
+        self.post_request_with(&self.send_endpoint()?, &payload, headers)
+            .await
+    }
+    /// Validate many messages concurrently with up to [DEFAULT_VALIDATION_CONCURRENCY] requests in flight.
+    /// Results are returned in the order of `messages`.
+    /// Use [FCMError::validation_report] to inspect why a message was rejected.
+    ///
+    /// `messages` can be any collection of messages, e.g. `&[Message]`, `Vec<Message>` or an iterator of `&Message`.
+    async fn validate_all<I>(&self, messages: I) -> Vec<Result<MessageOutput, FCMError>>
+    where
+        I: IntoIterator + Send,
+        I::IntoIter: Send,
+        I::Item: Borrow<Message> + Send + Sync,
+    {
+        self.validate_all_with_concurrency(messages, DEFAULT_VALIDATION_CONCURRENCY)
+            .await
+    }
+    /// Same as [FCMApi::validate_all] but with at most `concurrency` requests in flight.
+    async fn validate_all_with_concurrency<I>(
+        &self,
+        messages: I,
+        concurrency: usize,
+    ) -> Vec<Result<MessageOutput, FCMError>>
+    where
+        I: IntoIterator + Send,
+        I::IntoIter: Send,
+        I::Item: Borrow<Message> + Send + Sync,
+    {
+        let requests = messages
+            .into_iter()
+            .map(|message| self.validate(message))
+            .collect::<Vec<_>>();
+        stream::iter(requests)
+            .buffered(concurrency.max(1))
+            .collect()
+            .await
+    }
+    /// Send messages addressed to any mix of tokens, topics and conditions with at most `concurrency` requests in flight.
+    /// Each message is retried according to the retry policy of the client like [FCMApi::send],
+    /// and a failed message does not stop the others. Results are reported in the order of `messages`.
+    async fn send_all<I>(&self, messages: I, concurrency: usize) -> SendReport
+    where
+        I: IntoIterator + Send,
+        I::IntoIter: Send,
+        I::Item: Borrow<Message> + Send + Sync,
+    {
+        let requests = messages
+            .into_iter()
+            .map(|message| {
+                let target = message.borrow().target_kind();
+                async move {
+                    TargetedResult {
+                        target,
+                        result: self.send(message).await,
+                    }
+                }
+            })
+            .collect::<Vec<_>>();
+        let results = stream::iter(requests)
+            .buffered(concurrency.max(1))
+            .collect()
+            .await;
+        SendReport { results }
+    }
+    /// Send the borrowed message to firebase messaging API.
+    async fn send_ref(&self, message: &MessageRef<'_>) -> Result<MessageOutput, FCMError> {
+        let payload = MessagePayload {
+            validate_only: false,
+            message,
+        };
+        self.post_request(&self.send_endpoint()?, &payload).await
+    }
+    /// Send the borrowed message to firebase messaging API with dry run option.
+    async fn validate_ref(&self, message: &MessageRef<'_>) -> Result<MessageOutput, FCMError> {
+        let payload = MessagePayload {
+            validate_only: true,
+            message,
+        };
+        self.post_request(&self.send_endpoint()?, &payload).await
+    }
+    /// Send the message and keep the raw response body alongside the deserialized output.
+    /// This is useful to log exactly what firebase messaging API returned.
+    async fn send_with_raw_body(&self, message: &Message) -> Result<SendOutcome, FCMError> {
+        let payload = MessagePayload {
+            validate_only: false,
+            message,
+        };
+        let payload = crate::serialize_payload(&payload)?;
+        let (raw, response) = self
+            .request_with_metadata::<Box<RawValue>, FCMError>(
+                http::Method::POST,
+                &self.send_endpoint()?,
+                Some(payload),
+                &[],
+            )
+            .await?;
+        SendOutcome::from_raw(&raw, response)
+    }
+    /// Validate the message and keep the raw response body alongside the deserialized output.
+    async fn validate_with_raw_body(&self, message: &Message) -> Result<SendOutcome, FCMError> {
         let payload = MessagePayload {
             validate_only: true,
             message,
         };
-        self.post_request(&Self::post_endpoint(&self.project_id()), &payload)
+        let payload = crate::serialize_payload(&payload)?;
+        let (raw, response) = self
+            .request_with_metadata::<Box<RawValue>, FCMError>(
+                http::Method::POST,
+                &self.send_endpoint()?,
+                Some(payload),
+                &[],
+            )
+            .await?;
+        SendOutcome::from_raw(&raw, response)
+    }
+    /// Build the request [FCMApi::send] would issue without sending it and without `Authorization` header.
+    /// This is useful to route requests through your own transport or queue.
+    fn render_request(&self, message: &Message) -> Result<http::Request<Bytes>, FCMError> {
+        let payload = crate::serialize_payload(&MessagePayload {
+            validate_only: false,
+            message,
+        })?;
+        Ok(crate::build_request(
+            http::Method::POST,
+            &self.send_endpoint()?,
+            None,
+            Some(payload),
+            &[],
+        )?)
+    }
+    /// Same as [FCMApi::render_request] but with `Authorization` header from [GenericGoogleRestAPISupport::get_header_token].
+    async fn render_authorized_request(
+        &self,
+        message: &Message,
+    ) -> Result<http::Request<Bytes>, FCMError> {
+        let mut req = self.render_request(message)?;
+        let authorization = self
+            .get_header_token()
+            .await
+            .map_err(|e| RPCError::Unauthorized(format!("unable to get header token: {e}")))
+            .and_then(crate::sensitive_header_value)?;
+        req.headers_mut()
+            .insert(http::header::AUTHORIZATION, authorization);
+        Ok(req)
+    }
+    /// Render the request [FCMApi::send] would issue as a `curl` command. See [crate::curl_command].
+    /// Fails like [FCMApi::send] if the project id is unknown.
+    fn send_as_curl(&self, message: &Message) -> Result<String, FCMError> {
+        let payload = crate::serialize_payload(&MessagePayload {
+            validate_only: false,
+            message,
+        })?;
+        Ok(crate::curl_command(
+            "POST",
+            &self.send_endpoint()?,
+            &[],
+            Some(&payload),
+        ))
+    }
+    /// Send a message serialized in advance by [PreparedMessage::new].
+    /// The serialized payload is shared, not copied, between calls.
+    async fn send_prepared(&self, message: &PreparedMessage) -> Result<MessageOutput, FCMError> {
+        self.post_bytes_with(&self.send_endpoint()?, message.payload.clone(), &[])
             .await
     }
+    /// Check whether FCM knows `token` by validating an empty message to it. Nothing is delivered.
+    async fn is_token_valid(&self, token: &str) -> TokenStatus {
+        let message = Message::Token {
+            name: None,
+            data: None,
+            token: token.to_string(),
+            fcm_options: None,
+            notification: None,
+            android: None,
+            webpush: None,
+            apns: None,
+        };
+        match self.validate(&message).await {
+            Ok(_) => TokenStatus::Valid,
+            Err(e) if e.is_unregistered_token() => TokenStatus::Unregistered,
+            Err(e) => TokenStatus::Error(e),
+        }
+    }
+}
+
+/// Send messages as a `tower::Service`, to compose the send path with tower middleware such as
+/// timeouts, retries, concurrency limits or load shedding.
+///
+/// Each call sends the message with a clone of the client, like [FCMApi::send].
+/// The client is always ready: its own rate limits and circuit breaker apply once the request is called.
+impl<T> tower_service::Service<Message> for crate::FCMClient<T>
+where
+    T: crate::transport::HttpTransport + Clone + 'static,
+{
+    type Response = MessageOutput;
+    type Error = FCMError;
+    type Future = Pin<Box<dyn Future<Output = Result<MessageOutput, FCMError>> + Send>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), FCMError>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, message: Message) -> Self::Future {
+        let client = self.clone();
+        Box::pin(async move { client.send(message).await })
+    }
 }
 
+/// Result of [FCMApi::is_token_valid].
+#[derive(Debug)]
+pub enum TokenStatus {
+    /// FCM accepted a message to the token.
+    Valid,
+    /// FCM does not know the token: it was unregistered or has expired.
+    Unregistered,
+    /// The token could not be checked, e.g. because the API was unreachable or the project was not found.
+    /// Malformed tokens are rejected with [FCMError::InvalidRequestDescriptive].
+    Error(FCMError),
+}
+
+impl TokenStatus {
+    pub fn is_valid(&self) -> bool {
+        matches!(self, Self::Valid)
+    }
+}
+
+/// The default number of concurrent requests used by [FCMApi::validate_all].
+pub const DEFAULT_VALIDATION_CONCURRENCY: usize = 16;
+
 #[derive(Debug, Serialize)]
 /// Message payload sent to firebase messaging API.
-pub(crate) struct MessagePayload<'a> {
+pub(crate) struct MessagePayload<'a, M: Serialize = Message> {
     validate_only: bool,
-    message: &'a Message,
+    message: &'a M,
+}
+
+/// Message payload serialized once so that it can be sent repeatedly by [FCMApi::send_prepared].
+#[derive(Clone)]
+pub struct PreparedMessage {
+    payload: Bytes,
+}
+
+impl std::fmt::Debug for PreparedMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PreparedMessage")
+            .field("payload", &format_args!("<{} bytes>", self.payload.len()))
+            .finish()
+    }
+}
+
+impl PreparedMessage {
+    pub fn new(message: &Message) -> Result<Self, FCMError> {
+        let payload = serde_json::to_vec(&MessagePayload {
+            validate_only: false,
+            message,
+        })
+        .map_err(serialize_failure)?;
+        Ok(Self {
+            payload: Bytes::from(payload),
+        })
+    }
+    /// Serialized request body.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.payload
+    }
+}
+
+/// [FCMError::SerializeFailure] for a message that cannot be rendered as JSON.
+fn serialize_failure(e: serde_json::Error) -> FCMError {
+    FCMError::SerializeFailure {
+        reason: e.to_string(),
+    }
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub enum FCMError {
-    InternalRequestError { reason: String },
-    InternalResponseError { reason: String },
+    InternalRequestError {
+        reason: String,
+    },
+    /// The message could not be serialized to JSON. Nothing was sent.
+    SerializeFailure {
+        reason: String,
+    },
+    InternalResponseError {
+        reason: String,
+    },
+    /// Firebase messaging API accepted the request but its response did not match the expected type,
+    /// e.g. because Google added unexpected fields. The message may have been sent.
+    /// `body` is the response as returned from the API; see [FCMError::message_name].
+    UnexpectedResponse {
+        reason: String,
+        body: serde_json::Value,
+    },
     Unauthorized(String),
-    InvalidRequestDescriptive { reason: String },
+    InvalidRequestDescriptive {
+        reason: String,
+    },
     InvalidRequest,
-    RetryableInternal { retry_after: Duration },
+    RetryableInternal {
+        retry_after: Duration,
+    },
     Internal,
-    Unknown { code: u16, hint: Option<String> },
+    /// Firebase messaging API rejected the request with `429 Too Many Requests`.
+    /// `details` is the response body; see [FCMError::validation_report] for its error code, e.g. `QUOTA_EXCEEDED`.
+    TooManyRequests {
+        details: Option<String>,
+        retry_after: Option<Duration>,
+    },
+    /// The request did not complete at the connection level.
+    Transport {
+        kind: TransportErrorKind,
+        reason: String,
+    },
+    /// An attempt did not complete within [crate::FCMClientBuilder::request_timeout].
+    Timeout {
+        timeout: Duration,
+    },
+    /// The request was not sent because Firebase messaging API kept failing. See [crate::circuit_breaker].
+    CircuitOpen {
+        retry_after: Duration,
+    },
+    /// The message was not sent because it exceeds an enforced [crate::stats::QuotaBudget] of `project_id`.
+    QuotaExceeded {
+        project_id: String,
+        resets_in: Duration,
+    },
+    Unknown {
+        code: u16,
+        hint: Option<String>,
+    },
+    /// [crate::RetryPolicy::deadline] ran out. `attempts` are all failed attempts, oldest first.
+    DeadlineExceeded {
+        deadline: Duration,
+        #[serde(skip)]
+        attempts: Vec<FailedAttempt>,
+    },
+    /// The caller cancelled the send, e.g. with [FCMApi::send_until]. The message may have been delivered.
+    Cancelled,
+    /// The response was larger than [crate::FCMClientBuilder::max_response_size] bytes.
+    ResponseTooLarge {
+        limit: usize,
+    },
+    /// `source` annotated with the request that failed. Use [FCMError::kind] to match on the cause.
+    ///
+    /// Since 0.9, errors returned from requests are wrapped in this variant, so matching them directly,
+    /// e.g. `matches!(e, FCMError::InvalidRequest)`, no longer succeeds. Match on `e.kind()` instead.
+    Request {
+        context: RequestContext,
+        source: Box<FCMError>,
+    },
+}
+
+impl std::fmt::Display for FCMError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InternalRequestError { reason } => write!(f, "unable to send request: {reason}"),
+            Self::SerializeFailure { reason } => {
+                write!(f, "unable to serialize request payload: {reason}")
+            }
+            Self::InternalResponseError { reason } => {
+                write!(f, "unable to handle response: {reason}")
+            }
+            Self::UnexpectedResponse { reason, .. } => {
+                write!(f, "unexpected response body: {reason}")
+            }
+            Self::Unauthorized(reason) => write!(f, "unauthorized: {reason}"),
+            Self::InvalidRequestDescriptive { reason } => write!(f, "invalid request: {reason}"),
+            Self::InvalidRequest => write!(f, "invalid request"),
+            Self::RetryableInternal { retry_after } => write!(
+                f,
+                "firebase messaging API internal error, retry after {}s",
+                retry_after.as_secs()
+            ),
+            Self::Internal => write!(f, "firebase messaging API internal error"),
+            Self::TooManyRequests { details, .. } => match details {
+                Some(details) => write!(f, "too many requests: {details}"),
+                None => write!(f, "too many requests"),
+            },
+            Self::Transport { kind, reason } => write!(f, "transport error ({kind:?}): {reason}"),
+            Self::Timeout { timeout } => write!(f, "request timed out after {timeout:?}"),
+            Self::CircuitOpen { retry_after } => write!(
+                f,
+                "firebase messaging API is failing, circuit is open for {retry_after:?}"
+            ),
+            Self::QuotaExceeded {
+                project_id,
+                resets_in,
+            } => write!(
+                f,
+                "quota budget of project {project_id} is exhausted, resets in {resets_in:?}"
+            ),
+            Self::Unknown { code, hint } => match hint {
+                Some(hint) => write!(f, "unknown error (status {code}): {hint}"),
+                None => write!(f, "unknown error (status {code})"),
+            },
+            Self::DeadlineExceeded { deadline, attempts } => write!(
+                f,
+                "deadline of {deadline:?} exceeded after {} failed attempts",
+                attempts.len()
+            ),
+            Self::Cancelled => write!(f, "request was cancelled"),
+            Self::ResponseTooLarge { limit } => {
+                write!(f, "response body is larger than {limit} bytes")
+            }
+            Self::Request { context, source } => write!(f, "{context}: {source}"),
+        }
+    }
+}
+
+impl std::error::Error for FCMError {}
+
+impl FCMError {
+    /// The cause of the error without [FCMError::Request] context.
+    pub fn kind(&self) -> &Self {
+        match self {
+            Self::Request { source, .. } => source.kind(),
+            other => other,
+        }
+    }
+    /// The request that failed, if known.
+    pub fn context(&self) -> Option<&RequestContext> {
+        match self {
+            Self::Request { context, .. } => Some(context),
+            _ => None,
+        }
+    }
+    /// HTTP status with which a service relaying this error should respond to its own clients:
+    /// invalid messages are `400 Bad Request` and upstream failures are `5xx`.
+    pub fn status_code(&self) -> http::StatusCode {
+        use http::StatusCode;
+        match self.kind() {
+            Self::InvalidRequest | Self::InvalidRequestDescriptive { .. } => {
+                StatusCode::BAD_REQUEST
+            }
+            Self::RetryableInternal { .. }
+            | Self::Internal
+            | Self::CircuitOpen { .. }
+            | Self::Cancelled => StatusCode::SERVICE_UNAVAILABLE,
+            Self::QuotaExceeded { .. } | Self::TooManyRequests { .. } => {
+                StatusCode::TOO_MANY_REQUESTS
+            }
+            Self::Transport { kind, .. } => kind.gateway_status(),
+            Self::Timeout { .. } | Self::DeadlineExceeded { .. } => StatusCode::GATEWAY_TIMEOUT,
+            Self::Unauthorized(_) | Self::Unknown { .. } | Self::ResponseTooLarge { .. } => {
+                StatusCode::BAD_GATEWAY
+            }
+            Self::InternalRequestError { .. }
+            | Self::SerializeFailure { .. }
+            | Self::InternalResponseError { .. }
+            | Self::UnexpectedResponse { .. }
+            | Self::Request { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+    /// Delay suggested by Firebase messaging API before retrying, to be sent as `Retry-After`.
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self.kind() {
+            Self::RetryableInternal { retry_after } | Self::CircuitOpen { retry_after } => {
+                Some(*retry_after)
+            }
+            Self::QuotaExceeded { resets_in, .. } => Some(*resets_in),
+            Self::TooManyRequests { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+    /// Description of the error that is safe to show to clients of a service relaying it.
+    /// Unlike [std::fmt::Display] output, it never contains upstream response bodies or credential details.
+    pub fn client_message(&self) -> &'static str {
+        match self.kind() {
+            Self::InvalidRequest | Self::InvalidRequestDescriptive { .. } => "invalid message",
+            Self::RetryableInternal { .. } | Self::Internal | Self::CircuitOpen { .. } => {
+                "messaging service is temporarily unavailable"
+            }
+            Self::QuotaExceeded { .. } | Self::TooManyRequests { .. } => {
+                "too many messages, try again later"
+            }
+            Self::Transport {
+                kind: TransportErrorKind::Timeout,
+                ..
+            }
+            | Self::Timeout { .. }
+            | Self::DeadlineExceeded { .. } => "messaging service timed out",
+            Self::Transport { .. } | Self::Unauthorized(_) | Self::Unknown { .. } => {
+                "messaging service is unreachable"
+            }
+            Self::ResponseTooLarge { .. } => "messaging service returned an oversized response",
+            Self::Cancelled => "request was cancelled",
+            Self::InternalRequestError { .. }
+            | Self::SerializeFailure { .. }
+            | Self::InternalResponseError { .. }
+            | Self::UnexpectedResponse { .. }
+            | Self::Request { .. } => "internal error",
+        }
+    }
+    /// Identifier of the sent message, `projects/*/messages/{message_id}`, recovered from a response
+    /// that could not be deserialized as a whole. See [FCMError::UnexpectedResponse].
+    pub fn message_name(&self) -> Option<&str> {
+        match self.kind() {
+            Self::UnexpectedResponse { body, .. } => body.get("name")?.as_str(),
+            _ => None,
+        }
+    }
+    /// Whether FCM rejected the registration token of the message as unknown (`UNREGISTERED`).
+    ///
+    /// Only the FCM error details are trusted: a `404 Not Found` without them, e.g. because of a wrong project id
+    /// or [crate::FCMClientBuilder::fcm_base_url], says nothing about the token.
+    pub fn is_unregistered_token(&self) -> bool {
+        self.validation_report().map_or(false, |report| {
+            report.error_code.as_deref() == Some("UNREGISTERED")
+        })
+    }
+    /// Parse the error details returned with `400 Bad Request`, e.g. from [FCMApi::validate], or `429 Too Many Requests`.
+    /// Returns `None` if the error does not carry a response body in the google.rpc.Status format.
+    pub fn validation_report(&self) -> Option<ValidationReport> {
+        match self.kind() {
+            Self::InvalidRequestDescriptive { reason }
+            | Self::TooManyRequests {
+                details: Some(reason),
+                ..
+            } => ValidationReport::parse(reason),
+            _ => None,
+        }
+    }
+}
+
+/// Typed view of a [google.rpc.Status](https://cloud.google.com/apis/design/errors#error_model) error response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationReport {
+    /// Developer-facing error message.
+    pub message: String,
+    /// Canonical error status, e.g. `INVALID_ARGUMENT`.
+    pub status: String,
+    /// FCM specific [error code](https://firebase.google.com/docs/reference/fcm/rest/v1/ErrorCode), e.g. `INVALID_ARGUMENT` or `UNREGISTERED`.
+    pub error_code: Option<String>,
+    /// Fields rejected by FCM, taken from `google.rpc.BadRequest` details.
+    pub field_violations: Vec<FieldViolation>,
+}
+
+/// A rejected field of a request.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct FieldViolation {
+    /// Path to the field, e.g. `message.android.notification.color`.
+    pub field: String,
+    pub description: String,
+}
+
+impl ValidationReport {
+    /// Parse an error response body.
+    pub fn parse(body: &str) -> Option<Self> {
+        #[derive(Deserialize)]
+        struct Envelope {
+            error: Status,
+        }
+        #[derive(Deserialize)]
+        struct Status {
+            #[serde(default)]
+            message: String,
+            #[serde(default)]
+            status: String,
+            #[serde(default)]
+            details: Vec<Detail>,
+        }
+        #[derive(Deserialize)]
+        struct Detail {
+            #[serde(rename = "@type")]
+            kind: String,
+            #[serde(rename = "errorCode")]
+            error_code: Option<String>,
+            #[serde(rename = "fieldViolations", default)]
+            field_violations: Vec<FieldViolation>,
+        }
+        let Envelope { error } = serde_json::from_str(body).ok()?;
+        let mut report = ValidationReport {
+            message: error.message,
+            status: error.status,
+            error_code: None,
+            field_violations: vec![],
+        };
+        for detail in error.details {
+            if detail.kind.ends_with("google.firebase.fcm.v1.FcmError") {
+                report.error_code = detail.error_code;
+            } else if detail.kind.ends_with("google.rpc.BadRequest") {
+                report.field_violations.extend(detail.field_violations);
+            }
+        }
+        Some(report)
+    }
+    /// Find the violation for `field`.
+    pub fn violation(&self, field: &str) -> Option<&FieldViolation> {
+        self.field_violations.iter().find(|v| v.field == field)
+    }
 }
 
 impl From<RPCError> for FCMError {
@@ -68,12 +739,30 @@ impl From<RPCError> for FCMError {
             RPCError::HttpRequestFailure => Self::InternalRequestError {
                 reason: "unable to process http request".to_string(),
             },
+            RPCError::Transport { kind, reason } => Self::Transport { kind, reason },
+            RPCError::Timeout { timeout } => Self::Timeout { timeout },
+            RPCError::CircuitOpen { retry_after } => Self::CircuitOpen { retry_after },
+            RPCError::QuotaExceeded {
+                project_id,
+                resets_in,
+            } => Self::QuotaExceeded {
+                project_id,
+                resets_in,
+            },
             RPCError::DecodeFailure => Self::InternalResponseError {
                 reason: "unable to decode response body bytes".to_string(),
             },
-            RPCError::DeserializeFailure { reason, source } => Self::InternalResponseError {
-                reason: format!("unable to deserialize response body to type: {reason}: {source}"),
-            },
+            RPCError::SerializeFailure { reason } => Self::SerializeFailure { reason },
+            RPCError::DeserializeFailure { reason, source } => {
+                match serde_json::from_str::<serde_json::Value>(&source) {
+                    Ok(body) => Self::UnexpectedResponse { reason, body },
+                    Err(_) => Self::InternalResponseError {
+                        reason: format!(
+                            "unable to deserialize response body to type: {reason}: {source}"
+                        ),
+                    },
+                }
+            }
             RPCError::InvalidRequest {
                 details: Some(details),
             } => Self::InvalidRequestDescriptive { reason: details },
@@ -82,13 +771,32 @@ impl From<RPCError> for FCMError {
                 retry_after: Some(retry_after),
             } => Self::RetryableInternal { retry_after },
             RPCError::Internal { retry_after: None } => Self::Internal,
+            RPCError::TooManyRequests {
+                details,
+                retry_after,
+            } => Self::TooManyRequests {
+                details,
+                retry_after,
+            },
             RPCError::Unknown(code) => Self::Unknown { code, hint: None },
+            RPCError::DeadlineExceeded { deadline, attempts } => {
+                Self::DeadlineExceeded { deadline, attempts }
+            }
+            RPCError::Cancelled => Self::Cancelled,
+            RPCError::ResponseTooLarge { limit } => Self::ResponseTooLarge { limit },
+            RPCError::Request { context, source } => Self::Request {
+                context,
+                source: Box::new(Self::from(*source)),
+            },
         }
     }
 }
 /// Low-level type representing FCM Message type.
 /// See <https://fcm.googleapis.com/$discovery/rest?version=v1> for details.
-#[derive(Debug, Serialize)]
+///
+/// [std::fmt::Debug] output never contains the registration token.
+#[derive(Serialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
 #[serde(untagged)]
 pub enum Message {
     Token {
@@ -118,6 +826,10 @@ pub enum Message {
     Topic {
         /// Topic name to send a message to, e.g. "weather". Note: "/topics/" prefix should not be provided.
         topic: String,
+        /// Arbitrary key/value payload, which must be UTF-8 encoded. The key should not be a reserved word
+        /// ("from", "message_type", or any word starting with "google" or "gcm").
+        #[serde(skip_serializing_if = "Option::is_none")]
+        data: Option<HashMap<String, String>>,
         /// Template for FCM SDK feature options to use across all platforms.
         #[serde(skip_serializing_if = "Option::is_none")]
         fcm_options: Option<FcmOptions>,
@@ -139,6 +851,10 @@ pub enum Message {
     Condition {
         /// "Condition to send a message to, e.g. "'foo' in topics && 'bar' in topics".
         condition: String,
+        /// Arbitrary key/value payload, which must be UTF-8 encoded. The key should not be a reserved word
+        /// ("from", "message_type", or any word starting with "google" or "gcm").
+        #[serde(skip_serializing_if = "Option::is_none")]
+        data: Option<HashMap<String, String>>,
         /// Template for FCM SDK feature options to use across all platforms.
         #[serde(skip_serializing_if = "Option::is_none")]
         fcm_options: Option<FcmOptions>,
@@ -158,56 +874,1426 @@ pub enum Message {
     },
 }
 
-#[derive(Debug, Serialize, Default)]
-/// Platform independent options for features provided by the FCM SDKs.
-pub struct FcmOptions {
-    /// Label associated with the message's analytics data.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    analytics_label: Option<String>,
+impl Message {
+    /// Render the exact request body posted by [FCMApi::send] (`validate_only = false`)
+    /// or [FCMApi::validate] (`validate_only = true`) without constructing a client.
+    pub fn to_fcm_json(&self, validate_only: bool) -> Result<String, FCMError> {
+        serde_json::to_string(&MessagePayload {
+            validate_only,
+            message: self,
+        })
+        .map_err(serialize_failure)
+    }
+    /// Render this message as compact JSON whose object keys are sorted recursively.
+    ///
+    /// The output is stable across calls and map iteration orders, which makes it suitable
+    /// for payload hashing, deduplication keys and snapshot tests.
+    pub fn to_canonical_json(&self) -> Result<String, FCMError> {
+        let value = serde_json::to_value(self).map_err(serialize_failure)?;
+        serde_json::to_string(&canonicalize(value)).map_err(serialize_failure)
+    }
+    /// Parse a v1 message, e.g. one written in a message authoring UI, rejecting anything that [Message] cannot represent.
+    ///
+    /// Both a bare message object and `{"message": {...}}` are accepted. Request options such as `validate_only` are rejected,
+    /// since they are chosen by the caller with [FCMApi::send] or [FCMApi::validate].
+    /// Unknown fields, wrongly typed values and messages rejected by [Message::validate] are reported with the path of the offending field.
+    ///
+    /// ```rust
+    /// # use firebase_messaging_rs::fcm::*;
+    /// let err = Message::from_json(r#"{"topic": "news", "android": {"notification": {"colour": "red"}}}"#).unwrap_err();
+    /// assert_eq!(err.path, "message.android.notification");
+    /// ```
+    pub fn from_json(json: &str) -> Result<Message, MessageParseError> {
+        let message = Self::parse_json(json)?;
+        message
+            .validate()
+            .map_err(|e| MessageParseError::new(&e.path, e.reason))?;
+        Ok(message)
+    }
+    fn parse_json(json: &str) -> Result<Message, MessageParseError> {
+        let value: serde_json::Value =
+            serde_json::from_str(json).map_err(|e| MessageParseError::new("", e))?;
+        let mut object = match value {
+            serde_json::Value::Object(mut envelope) if envelope.contains_key("message") => {
+                for key in envelope.keys() {
+                    match key.as_str() {
+                        "message" => {}
+                        "validate_only" => {
+                            return Err(MessageParseError::new(
+                                key,
+                                "is a request option, not part of the message",
+                            ))
+                        }
+                        _ => return Err(MessageParseError::new(key, "unknown field")),
+                    }
+                }
+                match envelope.remove("message") {
+                    Some(serde_json::Value::Object(object)) => object,
+                    _ => return Err(MessageParseError::new("message", "expected an object")),
+                }
+            }
+            serde_json::Value::Object(object) => object,
+            _ => return Err(MessageParseError::new("", "expected an object")),
+        };
+        const FIELDS: [&str; 10] = [
+            "name",
+            "data",
+            "token",
+            "topic",
+            "condition",
+            "fcm_options",
+            "notification",
+            "android",
+            "webpush",
+            "apns",
+        ];
+        if let Some(key) = object.keys().find(|key| !FIELDS.contains(&key.as_str())) {
+            return Err(MessageParseError::new(
+                &format!("message.{key}"),
+                "unknown field",
+            ));
+        }
+        let name = take_field::<String>(&mut object, "name")?;
+        let data = take_field::<HashMap<String, String>>(&mut object, "data")?;
+        let token = take_field::<String>(&mut object, "token")?;
+        let topic = take_field::<String>(&mut object, "topic")?;
+        let condition = take_field::<String>(&mut object, "condition")?;
+        let fcm_options = take_field(&mut object, "fcm_options")?;
+        let notification = take_field(&mut object, "notification")?;
+        let android = take_field(&mut object, "android")?;
+        let webpush = take_field(&mut object, "webpush")?;
+        let apns = take_field(&mut object, "apns")?;
+        match (token, topic, condition) {
+            (Some(token), None, None) => {
+                if token.is_empty() {
+                    return Err(MessageParseError::new("message.token", "must not be empty"));
+                }
+                Ok(Message::Token {
+                    name,
+                    data,
+                    token,
+                    fcm_options,
+                    notification,
+                    android,
+                    webpush,
+                    apns,
+                })
+            }
+            (None, topic, condition) if topic.is_some() != condition.is_some() => {
+                if name.is_some() {
+                    return Err(MessageParseError::new(
+                        "message.name",
+                        "is supported only for messages to a token",
+                    ));
+                }
+                match (topic, condition) {
+                    (Some(topic), _) if topic.starts_with("/topics/") => Err(
+                        MessageParseError::new("message.topic", "must not start with `/topics/`"),
+                    ),
+                    (Some(topic), _) => Ok(Message::Topic {
+                        topic,
+                        data,
+                        fcm_options,
+                        notification,
+                        android,
+                        webpush,
+                        apns,
+                    }),
+                    (_, Some(condition)) => Ok(Message::Condition {
+                        condition,
+                        data,
+                        fcm_options,
+                        notification,
+                        android,
+                        webpush,
+                        apns,
+                    }),
+                    (None, None) => unreachable!(),
+                }
+            }
+            _ => Err(MessageParseError::new(
+                "message",
+                "exactly one of `token`, `topic` or `condition` must be set",
+            )),
+        }
+    }
 }
-impl FcmOptions {
-    pub fn new(analytics_label: &str) -> Self {
+
+/// Remove `key` from `object` and deserialize it. `null` is treated as absent.
+///
+/// The types of the message accept fields they do not model, so that newer FCM fields survive a round trip.
+/// They are rejected here instead, pointing at the object that contains the first of them.
+fn take_field<T: serde::de::DeserializeOwned>(
+    object: &mut serde_json::Map<String, serde_json::Value>,
+    key: &str,
+) -> Result<Option<T>, MessageParseError> {
+    let value = match object.remove(key) {
+        None | Some(serde_json::Value::Null) => return Ok(None),
+        Some(value) => value,
+    };
+    let mut unknown = None;
+    let parsed = serde_ignored::deserialize(&value, |path| {
+        unknown.get_or_insert_with(|| path_segments(&path));
+    })
+    .map_err(|e| {
+        locate_error(key, &value)
+            .unwrap_or_else(|| MessageParseError::new(&format!("message.{key}"), e))
+    })?;
+    match unknown {
+        None => Ok(Some(parsed)),
+        Some(mut segments) => {
+            let field = segments.pop().unwrap_or_default();
+            let path = std::iter::once(format!("message.{key}"))
+                .chain(segments)
+                .collect::<Vec<_>>()
+                .join(".");
+            Err(MessageParseError::new(
+                &path,
+                format!("unknown field `{field}`"),
+            ))
+        }
+    }
+}
+
+/// Keys and indices leading to `path`, without the wrappers of optional values.
+fn path_segments(path: &serde_ignored::Path) -> Vec<String> {
+    use serde_ignored::Path;
+    let mut segments = vec![];
+    let mut path = path;
+    loop {
+        path = match path {
+            Path::Root => break,
+            Path::Seq { parent, index } => {
+                segments.push(index.to_string());
+                parent
+            }
+            Path::Map { parent, key } => {
+                segments.push(key.clone());
+                parent
+            }
+            Path::Some { parent }
+            | Path::NewtypeStruct { parent }
+            | Path::NewtypeVariant { parent } => parent,
+        };
+    }
+    segments.reverse();
+    segments
+}
+
+/// Find the innermost nested object of `message.{key}` that fails to deserialize.
+fn locate_error(key: &str, value: &serde_json::Value) -> Option<MessageParseError> {
+    fn check<T: serde::de::DeserializeOwned>(value: &serde_json::Value) -> Result<(), String> {
+        serde_json::from_value::<T>(value.clone())
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+    type Check = fn(&serde_json::Value) -> Result<(), String>;
+    // Deepest objects come first so that the innermost failure is reported.
+    let nested: &[(&str, Check)] = match key {
+        "android" => &[
+            (
+                "/notification/light_settings/color",
+                check::<android::Color>,
+            ),
+            (
+                "/notification/light_settings",
+                check::<android::LightSettings>,
+            ),
+            ("/notification", check::<android::AndroidNotification>),
+            ("/fcm_options", check::<android::AndroidFcmOptions>),
+        ],
+        "apns" => &[
+            ("/headers", check::<ios::ApnsHeaders>),
+            ("/fcm_options", check::<ios::APNSFcmOptions>),
+        ],
+        "webpush" => &[("/fcm_options", check::<webpush::WebPushFcmOptions>)],
+        _ => &[],
+    };
+    nested.iter().find_map(|(pointer, check)| {
+        let reason = check(value.pointer(pointer).filter(|v| !v.is_null())?).err()?;
+        Some(MessageParseError::new(
+            &format!("message.{key}{}", pointer.replace('/', ".")),
+            reason,
+        ))
+    })
+}
+
+/// Error returned by [Message::from_json].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageParseError {
+    /// Path of the offending field, e.g. `message.android.notification`.
+    /// Errors inside platform options point at the innermost object that failed and name the field in `reason`.
+    pub path: String,
+    pub reason: String,
+}
+
+impl MessageParseError {
+    fn new(path: &str, reason: impl std::fmt::Display) -> Self {
         Self {
-            analytics_label: Some(analytics_label.to_string()),
+            path: path.to_string(),
+            reason: reason.to_string(),
         }
     }
 }
 
-#[derive(Debug, Serialize, Default)]
-///  Basic notification template to use across all platforms.
-pub struct Notification {
-    /// The notification title.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub title: Option<String>,
-    /// The notification's body text.
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub body: Option<String>,
-    /// Contains the URL of an image that is going to be downloaded on the device
-    /// and displayed in a notification. JPEG, PNG, BMP have full support across platforms.
-    /// Animated GIF and video only work on iOS. WebP and HEIF have varying levels of
-    /// support across platforms and platform versions. Android has 1MB image size limit.
-    /// Quota usage and implications/costs for hosting image on Firebase Storage: <https://firebase.google.com/pricing>
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub image: Option<String>,
+impl std::fmt::Display for MessageParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "invalid message: {}", self.reason)
+        } else {
+            write!(f, "invalid message at `{}`: {}", self.path, self.reason)
+        }
+    }
 }
 
-#[derive(Debug, Deserialize, Clone)]
-/// Payload returned from firebase messaging API.
-pub struct MessageOutput {
-    /// "Output Only. The identifier of the message sent, in the format of `projects/*/messages/{message_id}`."
-    pub name: String,
+impl std::error::Error for MessageParseError {}
+
+/// Borrowed counterpart of [Message]. It serializes identically to [Message],
+/// so a template can be sent to many targets without cloning its fields.
+///
+/// ```rust
+/// # use firebase_messaging_rs::fcm::*;
+/// let template = Message::Topic {
+///     topic: "news".to_string(),
+///     data: None,
+///     fcm_options: None,
+///     notification: Some(Notification {
+///         title: Some("Breaking".to_string()),
+///         ..Default::default()
+///     }),
+///     android: None,
+///     webpush: None,
+///     apns: None,
+/// };
+/// for token in ["token_0", "token_1"] {
+///     let message = template.to_token_ref(token);
+///     // client.send_ref(&message).await
+/// }
+/// ```
+#[derive(Serialize, Clone, Copy)]
+#[serde(untagged)]
+pub enum MessageRef<'a> {
+    Token {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        name: Option<&'a str>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        data: Option<&'a HashMap<String, String>>,
+        token: &'a str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        fcm_options: Option<&'a FcmOptions>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        notification: Option<&'a Notification>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        android: Option<&'a AndroidConfig>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        webpush: Option<&'a WebPushConfig>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        apns: Option<&'a ApnsConfig>,
+    },
+    Topic {
+        topic: &'a str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        data: Option<&'a HashMap<String, String>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        fcm_options: Option<&'a FcmOptions>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        notification: Option<&'a Notification>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        android: Option<&'a AndroidConfig>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        webpush: Option<&'a WebPushConfig>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        apns: Option<&'a ApnsConfig>,
+    },
+    Condition {
+        condition: &'a str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        data: Option<&'a HashMap<String, String>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        fcm_options: Option<&'a FcmOptions>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        notification: Option<&'a Notification>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        android: Option<&'a AndroidConfig>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        webpush: Option<&'a WebPushConfig>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        apns: Option<&'a ApnsConfig>,
+    },
 }
 
-#[cfg(test)]
-mod tests {
-    use std::collections::HashMap;
+impl std::fmt::Debug for Message {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        MessageRef::from(self).fmt(f)
+    }
+}
 
-    use super::{Message, Notification};
-    use crate::fcm::ApnsConfig;
-    #[test]
+impl std::fmt::Debug for MessageRef<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MessageRef::Token {
+                name,
+                data,
+                token: _,
+                fcm_options,
+                notification,
+                android,
+                webpush,
+                apns,
+            } => f
+                .debug_struct("Token")
+                .field("name", name)
+                .field("data", data)
+                .field("token", &Redacted)
+                .field("fcm_options", fcm_options)
+                .field("notification", notification)
+                .field("android", android)
+                .field("webpush", webpush)
+                .field("apns", apns)
+                .finish(),
+            MessageRef::Topic {
+                topic,
+                data,
+                fcm_options,
+                notification,
+                android,
+                webpush,
+                apns,
+            } => f
+                .debug_struct("Topic")
+                .field("topic", topic)
+                .field("data", data)
+                .field("fcm_options", fcm_options)
+                .field("notification", notification)
+                .field("android", android)
+                .field("webpush", webpush)
+                .field("apns", apns)
+                .finish(),
+            MessageRef::Condition {
+                condition,
+                data,
+                fcm_options,
+                notification,
+                android,
+                webpush,
+                apns,
+            } => f
+                .debug_struct("Condition")
+                .field("condition", condition)
+                .field("data", data)
+                .field("fcm_options", fcm_options)
+                .field("notification", notification)
+                .field("android", android)
+                .field("webpush", webpush)
+                .field("apns", apns)
+                .finish(),
+        }
+    }
+}
+
+impl<'a> From<&'a Message> for MessageRef<'a> {
+    fn from(message: &'a Message) -> Self {
+        match message {
+            Message::Token {
+                name,
+                data,
+                token,
+                fcm_options,
+                notification,
+                android,
+                webpush,
+                apns,
+            } => MessageRef::Token {
+                name: name.as_deref(),
+                data: data.as_ref(),
+                token,
+                fcm_options: fcm_options.as_ref(),
+                notification: notification.as_ref(),
+                android: android.as_ref(),
+                webpush: webpush.as_ref(),
+                apns: apns.as_ref(),
+            },
+            Message::Topic {
+                topic,
+                data,
+                fcm_options,
+                notification,
+                android,
+                webpush,
+                apns,
+            } => MessageRef::Topic {
+                topic,
+                data: data.as_ref(),
+                fcm_options: fcm_options.as_ref(),
+                notification: notification.as_ref(),
+                android: android.as_ref(),
+                webpush: webpush.as_ref(),
+                apns: apns.as_ref(),
+            },
+            Message::Condition {
+                condition,
+                data,
+                fcm_options,
+                notification,
+                android,
+                webpush,
+                apns,
+            } => MessageRef::Condition {
+                condition,
+                data: data.as_ref(),
+                fcm_options: fcm_options.as_ref(),
+                notification: notification.as_ref(),
+                android: android.as_ref(),
+                webpush: webpush.as_ref(),
+                apns: apns.as_ref(),
+            },
+        }
+    }
+}
+
+impl Message {
+    /// Borrow this message as a [MessageRef] that targets `token` instead of the original target.
+    /// `name` is kept only if this message is [Message::Token].
+    pub fn to_token_ref<'a>(&'a self, token: &'a str) -> MessageRef<'a> {
+        match MessageRef::from(self) {
+            MessageRef::Token {
+                name,
+                data,
+                fcm_options,
+                notification,
+                android,
+                webpush,
+                apns,
+                ..
+            } => MessageRef::Token {
+                name,
+                data,
+                token,
+                fcm_options,
+                notification,
+                android,
+                webpush,
+                apns,
+            },
+            MessageRef::Topic {
+                data,
+                fcm_options,
+                notification,
+                android,
+                webpush,
+                apns,
+                ..
+            }
+            | MessageRef::Condition {
+                data,
+                fcm_options,
+                notification,
+                android,
+                webpush,
+                apns,
+                ..
+            } => MessageRef::Token {
+                name: None,
+                data,
+                token,
+                fcm_options,
+                notification,
+                android,
+                webpush,
+                apns,
+            },
+        }
+    }
+}
+
+impl Message {
+    /// Set `label` as the analytics label of the message and of each platform config present,
+    /// so that the platforms report under the same label. Platform configs are not created.
+    pub fn with_analytics_label(mut self, label: &str) -> Result<Self, MessageValidationError> {
+        FcmOptions::validate_analytics_label(label).map_err(|e| e.within("message.fcm_options"))?;
+        let (fcm_options, android, webpush, apns) = match &mut self {
+            Message::Token {
+                fcm_options,
+                android,
+                webpush,
+                apns,
+                ..
+            }
+            | Message::Topic {
+                fcm_options,
+                android,
+                webpush,
+                apns,
+                ..
+            }
+            | Message::Condition {
+                fcm_options,
+                android,
+                webpush,
+                apns,
+                ..
+            } => (fcm_options, android, webpush, apns),
+        };
+        let label = Some(label.to_string());
+        fcm_options
+            .get_or_insert_with(Default::default)
+            .analytics_label = label.clone();
+        if let Some(android) = android {
+            android
+                .fcm_options
+                .get_or_insert_with(Default::default)
+                .analytics_label = label.clone();
+        }
+        if let Some(webpush) = webpush {
+            webpush
+                .fcm_options
+                .get_or_insert_with(Default::default)
+                .analytics_label = label.clone();
+        }
+        if let Some(apns) = apns {
+            apns.fcm_options
+                .get_or_insert_with(Default::default)
+                .analytics_label = label;
+        }
+        Ok(self)
+    }
+    /// Check the message for mistakes that FCM reports only as `INVALID_ARGUMENT`, without sending it.
+    /// See [MessageRef::validate].
+    pub fn validate(&self) -> Result<(), MessageValidationError> {
+        MessageRef::from(self).validate()
+    }
+    pub fn target_kind(&self) -> TargetKind {
+        match self {
+            Message::Token { .. } => TargetKind::Token,
+            Message::Topic { .. } => TargetKind::Topic,
+            Message::Condition { .. } => TargetKind::Condition,
+        }
+    }
+}
+
+impl MessageRef<'_> {
+    pub fn target_kind(&self) -> TargetKind {
+        match self {
+            MessageRef::Token { .. } => TargetKind::Token,
+            MessageRef::Topic { .. } => TargetKind::Topic,
+            MessageRef::Condition { .. } => TargetKind::Condition,
+        }
+    }
+    /// Check the message for mistakes that FCM reports only as `INVALID_ARGUMENT`, without sending it.
+    /// The first problem found is returned with the path of the offending field, e.g. `message.notification.image`.
+    ///
+    /// This is not a substitute for [FCMApi::validate], which also checks the target and the payload size.
+    pub fn validate(&self) -> Result<(), MessageValidationError> {
+        let (notification, android, apns) = match self {
+            MessageRef::Token {
+                notification,
+                android,
+                apns,
+                ..
+            }
+            | MessageRef::Topic {
+                notification,
+                android,
+                apns,
+                ..
+            }
+            | MessageRef::Condition {
+                notification,
+                android,
+                apns,
+                ..
+            } => (notification, android, apns),
+        };
+        if let Some(notification) = notification {
+            notification
+                .validate()
+                .map_err(|e| e.within("message.notification"))?;
+        }
+        if let Some(android) = android {
+            android
+                .validate()
+                .map_err(|e| e.within("message.android"))?;
+        }
+        if let Some(apns) = apns {
+            apns.validate().map_err(|e| e.within("message.apns"))?;
+        }
+        Ok(())
+    }
+}
+
+/// Rebuild `value` so that every object inserts its keys in sorted order.
+/// This keeps the output sorted even if `serde_json/preserve_order` is enabled downstream.
+fn canonicalize(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(map) => {
+            let mut entries = map.into_iter().collect::<Vec<_>>();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            serde_json::Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(k, v)| (k, canonicalize(v)))
+                    .collect(),
+            )
+        }
+        serde_json::Value::Array(values) => {
+            serde_json::Value::Array(values.into_iter().map(canonicalize).collect())
+        }
+        other => other,
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+/// Platform independent options for features provided by the FCM SDKs.
+pub struct FcmOptions {
+    /// Label associated with the message's analytics data.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    analytics_label: Option<String>,
+}
+impl FcmOptions {
+    pub fn new(analytics_label: &str) -> Self {
+        Self {
+            analytics_label: Some(analytics_label.to_string()),
+        }
+    }
+    /// The maximum length of an analytics label.
+    pub const MAX_ANALYTICS_LABEL_LEN: usize = 50;
+
+    /// Check that `label` matches `^[a-zA-Z0-9-_.~%]{1,50}$` as required by FCM.
+    pub fn validate_analytics_label(label: &str) -> Result<(), MessageValidationError> {
+        if label.is_empty() || label.len() > Self::MAX_ANALYTICS_LABEL_LEN {
+            return Err(MessageValidationError::new(
+                "analytics_label",
+                format!(
+                    "must be 1 to {} characters long",
+                    Self::MAX_ANALYTICS_LABEL_LEN
+                ),
+            ));
+        }
+        if let Some(c) = label
+            .chars()
+            .find(|c| !(c.is_ascii_alphanumeric() || "-_.~%".contains(*c)))
+        {
+            return Err(MessageValidationError::new(
+                "analytics_label",
+                format!("must not contain {c:?}; use letters, digits and -_.~%"),
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+///  Basic notification template to use across all platforms.
+pub struct Notification {
+    /// The notification title.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// The notification's body text.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+    /// Contains the URL of an image that is going to be downloaded on the device
+    /// and displayed in a notification. JPEG, PNG, BMP have full support across platforms.
+    /// Animated GIF and video only work on iOS. WebP and HEIF have varying levels of
+    /// support across platforms and platform versions. Android has 1MB image size limit.
+    /// Quota usage and implications/costs for hosting image on Firebase Storage: <https://firebase.google.com/pricing>
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+}
+
+impl From<(&str, &str)> for Notification {
+    /// Notification with `(title, body)`.
+    fn from((title, body): (&str, &str)) -> Self {
+        Notification {
+            title: Some(title.to_string()),
+            body: Some(body.to_string()),
+            image: None,
+        }
+    }
+}
+
+/// Builder for [Notification]. Create one with [Notification::builder].
+///
+/// ```
+/// use firebase_messaging_rs::fcm::Notification;
+///
+/// let notification = Notification::builder()
+///     .title("Breaking")
+///     .body("Something happened")
+///     .image("https://example.com/news.png")
+///     .build()
+///     .unwrap();
+/// assert_eq!(notification.title.as_deref(), Some("Breaking"));
+/// ```
+#[derive(Debug, Default)]
+pub struct NotificationBuilder {
+    notification: Notification,
+}
+
+impl NotificationBuilder {
+    pub fn title(mut self, title: &str) -> Self {
+        self.notification.title = Some(title.to_string());
+        self
+    }
+    pub fn body(mut self, body: &str) -> Self {
+        self.notification.body = Some(body.to_string());
+        self
+    }
+    /// HTTPS URL of an image. See [Notification::image].
+    pub fn image(mut self, image: &str) -> Self {
+        self.notification.image = Some(image.to_string());
+        self
+    }
+    /// Create the notification if it passes [Notification::validate].
+    pub fn build(self) -> Result<Notification, MessageValidationError> {
+        self.notification.validate()?;
+        Ok(self.notification)
+    }
+}
+
+impl Notification {
+    /// Start building a notification.
+    pub fn builder() -> NotificationBuilder {
+        NotificationBuilder::default()
+    }
+    /// Image formats displayed on at least one platform, matched against the extension of the URL path.
+    pub const IMAGE_EXTENSIONS: [&'static str; 8] =
+        ["jpg", "jpeg", "png", "bmp", "gif", "webp", "heif", "heic"];
+    /// The maximum size of a message payload. Neither title nor body can be longer than this.
+    pub const MAX_TEXT_BYTES: usize = 4096;
+
+    /// Check what FCM would reject with a bare `INVALID_ARGUMENT`:
+    /// a notification must have a non-blank title or body, and the image must be an HTTPS URL
+    /// of a format listed in [Notification::IMAGE_EXTENSIONS].
+    pub fn validate(&self) -> Result<(), MessageValidationError> {
+        let is_blank =
+            |text: &Option<String>| text.as_deref().map_or(true, |t| t.trim().is_empty());
+        if is_blank(&self.title) && is_blank(&self.body) {
+            return Err(MessageValidationError::new(
+                "",
+                "a notification must have a non-empty title or body",
+            ));
+        }
+        for (path, text) in [("title", &self.title), ("body", &self.body)] {
+            if text.as_ref().map_or(0, String::len) > Self::MAX_TEXT_BYTES {
+                return Err(MessageValidationError::new(
+                    path,
+                    format!("must not be longer than {} bytes", Self::MAX_TEXT_BYTES),
+                ));
+            }
+        }
+        if let Some(image) = &self.image {
+            validate_image_url(image)
+                .map_err(|reason| MessageValidationError::new("image", reason))?;
+        }
+        Ok(())
+    }
+}
+
+/// Localization keys and arguments of the title and body, resolved on the device from the app's
+/// string resources. It fills the differently named fields of [android::AndroidNotification]
+/// (`title_loc_key`, `body_loc_key`) and [ios::RichAlert] (`title-loc-key`, `loc-key`) alike.
+///
+/// ```
+/// use firebase_messaging_rs::fcm::LocalizedNotification;
+///
+/// let localized = LocalizedNotification::new()
+///     .title("new_message_title", ["Alice"])
+///     .body("new_message_body", ["Alice", "3"]);
+/// let android = localized.android_notification();
+/// let alert = localized.rich_alert();
+/// assert_eq!(android.body_loc_key, alert.loc_key);
+/// assert_eq!(android.title_loc_args, alert.title_loc_args);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LocalizedNotification {
+    pub title_key: Option<String>,
+    pub title_args: Vec<String>,
+    pub body_key: Option<String>,
+    pub body_args: Vec<String>,
+}
+
+impl LocalizedNotification {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Localize the title with the string resource `key`, formatted with `args`.
+    pub fn title<I, S>(mut self, key: &str, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.title_key = Some(key.to_string());
+        self.title_args = args.into_iter().map(Into::into).collect();
+        self
+    }
+    /// Localize the body with the string resource `key`, formatted with `args`.
+    pub fn body<I, S>(mut self, key: &str, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.body_key = Some(key.to_string());
+        self.body_args = args.into_iter().map(Into::into).collect();
+        self
+    }
+    /// Set the localization fields of `notification`, leaving the others as they are.
+    pub fn apply_to_android(&self, notification: &mut android::AndroidNotification) {
+        notification.title_loc_key = self.title_key.clone();
+        notification.title_loc_args = Self::args(&self.title_args);
+        notification.body_loc_key = self.body_key.clone();
+        notification.body_loc_args = Self::args(&self.body_args);
+    }
+    /// Set the localization fields of `alert`, leaving the others as they are.
+    pub fn apply_to_rich_alert(&self, alert: &mut ios::RichAlert) {
+        alert.title_loc_key = self.title_key.clone();
+        alert.title_loc_args = Self::args(&self.title_args);
+        alert.loc_key = self.body_key.clone();
+        alert.loc_args = Self::args(&self.body_args);
+    }
+    /// Android notification with only the localization fields set.
+    pub fn android_notification(&self) -> android::AndroidNotification {
+        let mut notification = android::AndroidNotification::default();
+        self.apply_to_android(&mut notification);
+        notification
+    }
+    /// APNs alert with only the localization fields set. Wrap it in [ios::Alert::Structural] for [ios::Aps].
+    pub fn rich_alert(&self) -> ios::RichAlert {
+        let mut alert = ios::RichAlert::default();
+        self.apply_to_rich_alert(&mut alert);
+        alert
+    }
+    fn args(args: &[String]) -> Option<Vec<String>> {
+        (!args.is_empty()).then(|| args.to_vec())
+    }
+}
+
+fn validate_image_url(url: &str) -> Result<(), String> {
+    let rest = url
+        .strip_prefix("https://")
+        .ok_or_else(|| "must be an https URL".to_string())?;
+    let end = rest.find(['?', '#']).unwrap_or(rest.len());
+    let (host, path) = rest[..end].split_at(rest[..end].find('/').unwrap_or(end));
+    if host.is_empty() {
+        return Err("must have a host".to_string());
+    }
+    let extension = path
+        .rsplit('/')
+        .next()
+        .and_then(|file| file.rsplit_once('.'))
+        .map(|(_, extension)| extension.to_ascii_lowercase());
+    match extension {
+        Some(extension) if Notification::IMAGE_EXTENSIONS.contains(&extension.as_str()) => Ok(()),
+        _ => Err(format!(
+            "must point to an image with one of the extensions {}",
+            Notification::IMAGE_EXTENSIONS.join(", ")
+        )),
+    }
+}
+
+/// Error returned by [Message::validate] and `validate` methods of message parts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageValidationError {
+    /// Path of the offending field, e.g. `message.notification.image`.
+    pub path: String,
+    pub reason: String,
+}
+
+impl MessageValidationError {
+    pub(crate) fn new(path: &str, reason: impl std::fmt::Display) -> Self {
+        Self {
+            path: path.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+    /// Prefix the path with the path of the enclosing object.
+    pub(crate) fn within(mut self, parent: &str) -> Self {
+        self.path = if self.path.is_empty() {
+            parent.to_string()
+        } else {
+            format!("{parent}.{}", self.path)
+        };
+        self
+    }
+}
+
+impl std::fmt::Display for MessageValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid message at `{}`: {}", self.path, self.reason)
+    }
+}
+
+impl std::error::Error for MessageValidationError {}
+
+#[derive(Debug, Deserialize, Clone)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+/// Payload returned from firebase messaging API.
+pub struct MessageOutput {
+    /// "Output Only. The identifier of the message sent, in the format of `projects/*/messages/{message_id}`."
+    pub name: MessageName,
+}
+
+/// Identifier of a sent message in the format of `projects/{project_id}/messages/{message_id}`.
+///
+/// Parsing with [str::parse] checks the format. Deserializing accepts any string, so that a
+/// successful response whose `name` does not follow it (e.g. from an emulator) is not turned
+/// into an error; [MessageName::project_id] and [MessageName::message_id] return `None` then.
+///
+/// ```
+/// use firebase_messaging_rs::fcm::MessageName;
+///
+/// let name: MessageName = "projects/example/messages/0:1500415314455276%31bd1c9631bd1c96".parse().unwrap();
+/// assert_eq!(name.project_id(), Some("example"));
+/// assert_eq!(name.message_id(), Some("0:1500415314455276%31bd1c9631bd1c96"));
+/// assert_eq!(name.as_str(), "projects/example/messages/0:1500415314455276%31bd1c9631bd1c96");
+/// assert!("messages/1".parse::<MessageName>().is_err());
+///
+/// let name: MessageName = serde_json::from_str(r#""fake-message-1""#).unwrap();
+/// assert_eq!(name.message_id(), None);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(transparent)]
+pub struct MessageName(String);
+
+impl MessageName {
+    /// Offset of `/messages/` in a valid name.
+    fn separator(name: &str) -> Option<usize> {
+        let rest = name.strip_prefix("projects/")?;
+        let (project_id, message_id) = rest.split_once("/messages/")?;
+        if project_id.is_empty()
+            || project_id.contains('/')
+            || message_id.is_empty()
+            || message_id.contains('/')
+        {
+            return None;
+        }
+        Some("projects/".len() + project_id.len())
+    }
+    pub fn project_id(&self) -> Option<&str> {
+        let end = Self::separator(&self.0)?;
+        Some(&self.0["projects/".len()..end])
+    }
+    pub fn message_id(&self) -> Option<&str> {
+        let start = Self::separator(&self.0)? + "/messages/".len();
+        Some(&self.0[start..])
+    }
+    /// The whole identifier, e.g. to persist it as is.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+    pub fn into_string(self) -> String {
+        self.0
+    }
+}
+
+impl std::str::FromStr for MessageName {
+    type Err = InvalidMessageName;
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Self::try_from(name.to_string())
+    }
+}
+
+impl TryFrom<String> for MessageName {
+    type Error = InvalidMessageName;
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        match Self::separator(&name) {
+            Some(_) => Ok(Self(name)),
+            None => Err(InvalidMessageName(name)),
+        }
+    }
+}
+
+impl From<MessageName> for String {
+    fn from(name: MessageName) -> Self {
+        name.0
+    }
+}
+
+impl AsRef<str> for MessageName {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq<str> for MessageName {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == other
+    }
+}
+
+impl PartialEq<&str> for MessageName {
+    fn eq(&self, other: &&str) -> bool {
+        self.0 == *other
+    }
+}
+
+impl std::fmt::Display for MessageName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// A string that is not in the format of `projects/{project_id}/messages/{message_id}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidMessageName(pub String);
+
+impl std::fmt::Display for InvalidMessageName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "`{}` is not in the format of `projects/{{project_id}}/messages/{{message_id}}`",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for InvalidMessageName {}
+
+/// Kind of target a message is addressed to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TargetKind {
+    Token,
+    Topic,
+    Condition,
+}
+
+/// Result of a message sent by [FCMApi::send_all].
+#[derive(Debug, Clone)]
+pub struct TargetedResult {
+    pub target: TargetKind,
+    pub result: Result<MessageOutput, FCMError>,
+}
+
+/// Number of messages sent and failed for a [TargetKind]. See [SendReport::counts].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TargetCounts {
+    pub succeeded: usize,
+    pub failed: usize,
+}
+
+/// Results of [FCMApi::send_all] in the order of the messages given.
+#[derive(Debug, Clone, Default)]
+pub struct SendReport {
+    pub results: Vec<TargetedResult>,
+}
+
+impl SendReport {
+    pub fn success_count(&self) -> usize {
+        self.results.iter().filter(|r| r.result.is_ok()).count()
+    }
+    pub fn failure_count(&self) -> usize {
+        self.results.len() - self.success_count()
+    }
+    /// Failed messages with their position in the messages given.
+    pub fn failures(&self) -> impl Iterator<Item = (usize, TargetKind, &FCMError)> {
+        self.results
+            .iter()
+            .enumerate()
+            .filter_map(|(index, r)| r.result.as_ref().err().map(|e| (index, r.target, e)))
+    }
+    /// Counts of succeeded and failed messages by target kind.
+    pub fn counts(&self) -> BTreeMap<TargetKind, TargetCounts> {
+        let mut counts = BTreeMap::<TargetKind, TargetCounts>::new();
+        for r in &self.results {
+            let count = counts.entry(r.target).or_default();
+            match r.result {
+                Ok(_) => count.succeeded += 1,
+                Err(_) => count.failed += 1,
+            }
+        }
+        counts
+    }
+}
+
+/// [MessageOutput] together with the response it was deserialized from.
+#[derive(Debug, Clone)]
+pub struct SendOutcome {
+    output: MessageOutput,
+    raw_body: String,
+    response: Option<ResponseMetadata>,
+}
+
+impl SendOutcome {
+    fn from_raw(raw: &RawValue, response: Option<ResponseMetadata>) -> Result<Self, FCMError> {
+        let raw_body = raw.get().to_string();
+        let output = serde_json::from_str(&raw_body).map_err(|e| RPCError::DeserializeFailure {
+            reason: format!("{e:?}"),
+            source: raw_body.clone(),
+        })?;
+        Ok(Self {
+            output,
+            raw_body,
+            response,
+        })
+    }
+    pub fn output(&self) -> &MessageOutput {
+        &self.output
+    }
+    pub fn into_output(self) -> MessageOutput {
+        self.output
+    }
+    /// Response body exactly as returned from firebase messaging API.
+    pub fn raw_body(&self) -> &str {
+        &self.raw_body
+    }
+    /// Status and debug headers of the response, e.g. its request ID.
+    pub fn response_metadata(&self) -> Option<&ResponseMetadata> {
+        self.response.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{
+        FCMError, Message, MessagePayload, MessageRef, Notification, PreparedMessage, SendOutcome,
+    };
+    use crate::fcm::ApnsConfig;
+    use crate::{RPCError, TransportErrorKind};
+    use http::StatusCode;
+    use std::time::Duration;
+
+    #[test]
+    fn localized_notification_fills_both_platforms() {
+        let localized = super::LocalizedNotification::new()
+            .title("greeting_title", Vec::<String>::new())
+            .body("greeting_body", ["Alice"]);
+        let android = serde_json::to_value(localized.android_notification()).unwrap();
+        assert_eq!(
+            android,
+            serde_json::json!({"title_loc_key": "greeting_title", "body_loc_key": "greeting_body", "body_loc_args": ["Alice"]})
+        );
+        let mut alert = super::ios::RichAlert {
+            title: Some("fallback".to_string()),
+            ..Default::default()
+        };
+        localized.apply_to_rich_alert(&mut alert);
+        assert_eq!(
+            serde_json::to_value(alert).unwrap(),
+            serde_json::json!({"title": "fallback", "title-loc-key": "greeting_title", "loc-key": "greeting_body", "loc-args": ["Alice"]})
+        );
+    }
+
+    #[test]
+    fn analytics_label_is_set_on_present_platforms() {
+        let message = Message::Topic {
+            topic: "news".to_string(),
+            data: None,
+            fcm_options: None,
+            notification: None,
+            android: Some(super::android::AndroidConfig::default()),
+            webpush: Some(super::webpush::WebPushConfig::default()),
+            apns: None,
+        }
+        .with_analytics_label("spring_sale-2024")
+        .unwrap();
+        let json = serde_json::to_value(&message).unwrap();
+        assert_eq!(json["fcm_options"]["analytics_label"], "spring_sale-2024");
+        assert_eq!(
+            json["android"]["fcm_options"]["analytics_label"],
+            "spring_sale-2024"
+        );
+        assert_eq!(
+            json["webpush"]["fcm_options"]["analytics_label"],
+            "spring_sale-2024"
+        );
+        assert!(json.get("apns").is_none());
+
+        let error = message.with_analytics_label("spring sale").unwrap_err();
+        assert_eq!(error.path, "message.fcm_options.analytics_label");
+        assert!(super::FcmOptions::validate_analytics_label(&"a".repeat(51)).is_err());
+    }
+
+    #[cfg(feature = "test-util")]
+    #[tokio::test]
+    async fn sending_requires_project_id() {
+        use super::FCMApi;
+        use crate::test_util::FakeClient;
+
+        let client = FakeClient::new().with_project_id("");
+        let message = Message::Topic {
+            topic: "news".to_string(),
+            data: None,
+            fcm_options: None,
+            notification: None,
+            android: None,
+            webpush: None,
+            apns: None,
+        };
+        let error = client.send(&message).await.unwrap_err();
+        assert!(matches!(error, FCMError::InternalRequestError { .. }));
+        assert!(matches!(
+            client.send_as_curl(&message),
+            Err(FCMError::InternalRequestError { .. })
+        ));
+        assert!(client.requests().is_empty());
+    }
+
+    #[cfg(feature = "test-util")]
+    #[tokio::test]
+    async fn token_validity_is_classified() {
+        use super::{FCMApi, TokenStatus};
+        use crate::test_util::{FakeClient, FakeResponse};
+        use crate::{RetryPolicy, TransportErrorKind};
+
+        let client = FakeClient::new().with_retry_policy(RetryPolicy::with_max_retries(0));
+        client
+            .push(FakeResponse::ok(r#"{"name":"projects/p/messages/1"}"#))
+            .push(FakeResponse::new(
+                http::StatusCode::NOT_FOUND,
+                r#"{"error":{"code":404,"message":"Requested entity was not found.","status":"NOT_FOUND","details":[{"@type":"type.googleapis.com/google.firebase.fcm.v1.FcmError","errorCode":"UNREGISTERED"}]}}"#,
+            ))
+            .push(FakeResponse::new(
+                http::StatusCode::NOT_FOUND,
+                r#"{"error":{"code":404,"message":"Requested entity was not found.","status":"NOT_FOUND"}}"#,
+            ))
+            .push(FakeResponse::new(http::StatusCode::NOT_FOUND, ""))
+            .push(FakeResponse::new(
+                http::StatusCode::NOT_FOUND,
+                r#"{"error":{"code":404,"message":"Requested entity was not found.","status":"NOT_FOUND","details":[{"@type":"type.googleapis.com/google.firebase.fcm.v1.FcmError","errorCode":"SENDER_ID_MISMATCH"}]}}"#,
+            ))
+            .push(FakeResponse::new(
+                http::StatusCode::BAD_REQUEST,
+                r#"{"error":{"code":400,"message":"The registration token is not a valid FCM registration token","status":"INVALID_ARGUMENT","details":[{"@type":"type.googleapis.com/google.firebase.fcm.v1.FcmError","errorCode":"INVALID_ARGUMENT"}]}}"#,
+            ))
+            .push(FakeResponse::transport_error(TransportErrorKind::Connect));
+        assert!(client.is_token_valid("a").await.is_valid());
+        assert!(matches!(
+            client.is_token_valid("b").await,
+            TokenStatus::Unregistered
+        ));
+        for token in [
+            "wrong-project",
+            "wrong-base-url",
+            "other-sender",
+            "malformed",
+            "unreachable",
+        ] {
+            assert!(
+                matches!(client.is_token_valid(token).await, TokenStatus::Error(_)),
+                "{token}"
+            );
+        }
+        let body: serde_json::Value =
+            serde_json::from_slice(client.requests()[0].body.as_deref().unwrap()).unwrap();
+        assert_eq!(body["validate_only"], true);
+    }
+
+    #[cfg(feature = "test-util")]
+    #[tokio::test]
+    async fn mixed_targets_are_sent_together() {
+        use super::{FCMApi, TargetCounts, TargetKind};
+        use crate::test_util::{FakeClient, FakeResponse};
+
+        let client = FakeClient::new();
+        client
+            .push(FakeResponse::ok(r#"{"name":"projects/p/messages/1"}"#))
+            .push(FakeResponse::new(http::StatusCode::NOT_FOUND, ""))
+            .push(FakeResponse::ok(r#"{"name":"projects/p/messages/3"}"#));
+        let messages = vec![
+            Message::Token {
+                name: None,
+                data: None,
+                token: "token".to_string(),
+                fcm_options: None,
+                notification: None,
+                android: None,
+                webpush: None,
+                apns: None,
+            },
+            Message::Topic {
+                topic: "news".to_string(),
+                data: None,
+                fcm_options: None,
+                notification: None,
+                android: None,
+                webpush: None,
+                apns: None,
+            },
+            Message::Condition {
+                condition: "'news' in topics".to_string(),
+                data: None,
+                fcm_options: None,
+                notification: None,
+                android: None,
+                webpush: None,
+                apns: None,
+            },
+        ];
+        let report = client.send_all(&messages, 1).await;
+        assert_eq!((report.success_count(), report.failure_count()), (2, 1));
+        let (index, target, _) = report.failures().next().unwrap();
+        assert_eq!((index, target), (1, TargetKind::Topic));
+        assert_eq!(
+            report.counts()[&TargetKind::Condition],
+            TargetCounts {
+                succeeded: 1,
+                failed: 0
+            }
+        );
+    }
+
+    #[test]
+    fn notification_builder_validates() {
+        let notification = Notification::from(("Breaking", "Something happened"));
+        assert_eq!(notification.body.as_deref(), Some("Something happened"));
+        let err = Notification::builder()
+            .title("Breaking")
+            .image("http://example.com/news.png")
+            .build()
+            .unwrap_err();
+        assert_eq!(err.path, "image");
+        assert!(Notification::builder().build().is_err());
+    }
+
+    #[test]
+    fn notifications_are_validated() {
+        let message = |notification: Notification| Message::Topic {
+            topic: "news".to_string(),
+            data: None,
+            fcm_options: None,
+            notification: Some(notification),
+            android: None,
+            webpush: None,
+            apns: None,
+        };
+        let valid = Notification {
+            title: Some("Breaking".to_string()),
+            image: Some("https://example.com/images/news.PNG?size=large".to_string()),
+            ..Default::default()
+        };
+        assert!(message(valid).validate().is_ok());
+        let err = message(Notification {
+            body: Some(" ".to_string()),
+            ..Default::default()
+        })
+        .validate()
+        .unwrap_err();
+        assert_eq!(err.path, "message.notification");
+        for image in [
+            "http://example.com/news.png",
+            "https:///news.png",
+            "https://example.com/news.svg",
+            "https://example.com/images",
+        ] {
+            let err = message(Notification {
+                title: Some("Breaking".to_string()),
+                image: Some(image.to_string()),
+                ..Default::default()
+            })
+            .validate()
+            .unwrap_err();
+            assert_eq!(err.path, "message.notification.image", "{image}");
+        }
+    }
+    #[test]
     pub fn ios_background_notification() {
         let background_notification = Message::Topic {
             topic: "background_channel".to_string(),
+            data: None,
             fcm_options: None,
             notification: Some(Notification {
                 title: Some("example".to_string()),
@@ -240,4 +2326,448 @@ mod tests {
         });
         assert_eq!(result, expected)
     }
+    #[test]
+    pub fn canonical_json_sorts_keys() {
+        let message = Message::Token {
+            name: None,
+            data: Some(HashMap::from_iter([
+                ("zeta".to_string(), "1".to_string()),
+                ("alpha".to_string(), "2".to_string()),
+                ("mu".to_string(), "3".to_string()),
+            ])),
+            token: "token".to_string(),
+            fcm_options: None,
+            notification: Some(Notification {
+                title: Some("title".to_string()),
+                body: Some("body".to_string()),
+                ..Default::default()
+            }),
+            android: None,
+            webpush: None,
+            apns: None,
+        };
+        let json = message.to_canonical_json().expect("should always succeed");
+        assert_eq!(
+            json,
+            r#"{"data":{"alpha":"2","mu":"3","zeta":"1"},"notification":{"body":"body","title":"title"},"token":"token"}"#
+        );
+        assert_eq!(json, message.to_canonical_json().unwrap())
+    }
+    #[test]
+    pub fn prepared_message_matches_payload() {
+        let message = Message::Topic {
+            topic: "example".to_string(),
+            data: None,
+            fcm_options: None,
+            notification: Some(Notification {
+                title: Some("example".to_string()),
+                ..Default::default()
+            }),
+            android: None,
+            webpush: None,
+            apns: None,
+        };
+        let prepared = PreparedMessage::new(&message).expect("should always succeed");
+        let expected = serde_json::to_vec(&MessagePayload {
+            validate_only: false,
+            message: &message,
+        })
+        .unwrap();
+        assert_eq!(prepared.as_bytes(), expected.as_slice());
+        assert_eq!(prepared.clone().as_bytes(), prepared.as_bytes())
+    }
+    #[test]
+    pub fn message_ref_serializes_identically() {
+        let message = Message::Token {
+            name: None,
+            data: Some(HashMap::from_iter([("foo".to_string(), "bar".to_string())])),
+            token: "token".to_string(),
+            fcm_options: None,
+            notification: Some(Notification {
+                title: Some("example".to_string()),
+                ..Default::default()
+            }),
+            android: None,
+            webpush: None,
+            apns: Some(ApnsConfig::ios_background_notification(HashMap::default())),
+        };
+        assert_eq!(
+            serde_json::to_value(&message).unwrap(),
+            serde_json::to_value(MessageRef::from(&message)).unwrap()
+        );
+        let template = Message::Topic {
+            topic: "example".to_string(),
+            data: None,
+            fcm_options: None,
+            notification: Some(Notification {
+                title: Some("example".to_string()),
+                ..Default::default()
+            }),
+            android: None,
+            webpush: None,
+            apns: None,
+        };
+        assert_eq!(
+            serde_json::to_value(template.to_token_ref("token")).unwrap(),
+            serde_json::json!({
+                "token": "token",
+                "notification": {
+                    "title": "example"
+                }
+            })
+        )
+    }
+    #[test]
+    pub fn send_outcome_keeps_raw_body() {
+        let raw = serde_json::value::RawValue::from_string(
+            r#"{ "name": "projects/example/messages/1" }"#.to_string(),
+        )
+        .unwrap();
+        let outcome = SendOutcome::from_raw(&raw, None).expect("should always succeed");
+        assert_eq!(outcome.output().name, "projects/example/messages/1");
+        assert_eq!(outcome.output().name.message_id(), Some("1"));
+        assert_eq!(
+            outcome.raw_body(),
+            r#"{ "name": "projects/example/messages/1" }"#
+        );
+        let raw = serde_json::value::RawValue::from_string(r#"{"foo":"bar"}"#.to_string()).unwrap();
+        assert!(SendOutcome::from_raw(&raw, None).is_err())
+    }
+    #[test]
+    pub fn send_outcome_accepts_unstructured_message_name() {
+        let raw =
+            serde_json::value::RawValue::from_string(r#"{"name":"fake-message-1"}"#.to_string())
+                .unwrap();
+        let outcome = SendOutcome::from_raw(&raw, None).expect("any name should be accepted");
+        assert_eq!(outcome.output().name, "fake-message-1");
+        assert_eq!(outcome.output().name.project_id(), None);
+        assert_eq!(outcome.output().name.message_id(), None);
+    }
+    #[test]
+    pub fn debug_output_redacts_token() {
+        let message = Message::Token {
+            name: None,
+            data: None,
+            token: "secret-registration-token".to_string(),
+            fcm_options: None,
+            notification: None,
+            android: None,
+            webpush: None,
+            apns: None,
+        };
+        assert!(!format!("{message:?}").contains("secret-registration-token"));
+        let prepared = PreparedMessage::new(&message).unwrap();
+        assert!(!format!("{prepared:?}").contains("secret-registration-token"))
+    }
+    #[test]
+    pub fn parse_validation_report() {
+        let body = r#"{
+          "error": {
+            "code": 400,
+            "message": "Invalid value at 'message.android.notification.color'",
+            "status": "INVALID_ARGUMENT",
+            "details": [
+              {
+                "@type": "type.googleapis.com/google.rpc.BadRequest",
+                "fieldViolations": [
+                  {
+                    "field": "message.android.notification.color",
+                    "description": "Invalid color value"
+                  }
+                ]
+              },
+              {
+                "@type": "type.googleapis.com/google.firebase.fcm.v1.FcmError",
+                "errorCode": "INVALID_ARGUMENT"
+              }
+            ]
+          }
+        }"#;
+        let error = crate::fcm::FCMError::from(crate::RPCError::invalid_request_descriptive(body));
+        let report = error.validation_report().expect("should be parsed");
+        assert_eq!(report.status, "INVALID_ARGUMENT");
+        assert_eq!(report.error_code.as_deref(), Some("INVALID_ARGUMENT"));
+        assert_eq!(
+            report
+                .violation("message.android.notification.color")
+                .map(|v| v.description.as_str()),
+            Some("Invalid color value")
+        );
+        assert!(crate::fcm::FCMError::InvalidRequest
+            .validation_report()
+            .is_none())
+    }
+    #[test]
+    pub fn render_request_without_authorization() {
+        struct Offline;
+        #[async_trait::async_trait]
+        impl crate::GenericGoogleRestAPISupport for Offline {
+            async fn get_header_token(&self) -> Result<String, crate::auth::AuthError> {
+                unreachable!()
+            }
+            fn project_id(&self) -> String {
+                "example".to_string()
+            }
+            type Transport = crate::HttpClient;
+            fn transport(&self) -> &crate::HttpClient {
+                unreachable!()
+            }
+        }
+        impl super::FCMApi for Offline {}
+        let message = Message::Topic {
+            topic: "example".to_string(),
+            data: None,
+            fcm_options: None,
+            notification: None,
+            android: None,
+            webpush: None,
+            apns: None,
+        };
+        let req = super::FCMApi::render_request(&Offline, &message).unwrap();
+        assert_eq!(req.method(), http::Method::POST);
+        assert_eq!(
+            req.uri(),
+            "https://fcm.googleapis.com/v1/projects/example/messages:send"
+        );
+        assert!(req.headers().get(http::header::AUTHORIZATION).is_none());
+        assert_eq!(
+            req.body().as_ref(),
+            PreparedMessage::new(&message).unwrap().as_bytes()
+        )
+    }
+    #[test]
+    pub fn fcm_json_matches_posted_body() {
+        let message = Message::Topic {
+            topic: "example".to_string(),
+            data: None,
+            fcm_options: None,
+            notification: Some(Notification {
+                title: Some("example".to_string()),
+                ..Default::default()
+            }),
+            android: None,
+            webpush: None,
+            apns: None,
+        };
+        assert_eq!(
+            message.to_fcm_json(true).unwrap(),
+            r#"{"validate_only":true,"message":{"topic":"example","notification":{"title":"example"}}}"#
+        );
+        assert_eq!(
+            message.to_fcm_json(false).unwrap().as_bytes(),
+            PreparedMessage::new(&message).unwrap().as_bytes()
+        )
+    }
+
+    #[test]
+    fn strict_parser_reports_error_paths() {
+        let json = r#"{
+            "message": {
+                "token": "token",
+                "data": {"foo": "bar"},
+                "notification": {"title": "example"},
+                "android": {"ttl": "3.5s", "notification": {"light_settings": {"color": {"red": 1, "green": 0, "blue": 0, "alpha": 1}}}},
+                "apns": {"headers": {"apns-priority": "10", "apns-expiration": "1700000000"}, "payload": {"aps": {"badge": 1}}}
+            }
+        }"#;
+        let message = Message::from_json(json).unwrap();
+        assert!(matches!(message, Message::Token { ref token, .. } if token == "token"));
+        let roundtrip = serde_json::to_value(&message).unwrap();
+        assert_eq!(roundtrip["android"]["ttl"], "3.5s");
+        assert_eq!(
+            roundtrip["apns"]["headers"]["apns-expiration"],
+            "1700000000"
+        );
+
+        let err = Message::from_json(
+            r#"{"topic": "news", "android": {"notification": {"light_settings": {"color": {"red": "1"}}}}}"#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.path,
+            "message.android.notification.light_settings.color"
+        );
+        let err =
+            Message::from_json(r#"{"topic": "news", "apns": {"headers": {"apns-priority": "7"}}}"#)
+                .unwrap_err();
+        assert_eq!(err.path, "message.apns.headers");
+        let err = Message::from_json(r#"{"topic": "news", "badge": 1}"#).unwrap_err();
+        assert_eq!(err.path, "message.badge");
+        let err = Message::from_json(r#"{"topic": "news", "token": "token"}"#).unwrap_err();
+        assert_eq!(err.path, "message");
+        let err = Message::from_json(r#"{"topic": "/topics/news"}"#).unwrap_err();
+        assert_eq!(err.path, "message.topic");
+        assert!(
+            Message::from_json(r#"{"condition": "'a' in topics", "data": {"a": "1"}}"#).is_ok()
+        );
+        let err =
+            Message::from_json(r#"{"condition": "'a' in topics", "data": {"a": 1}}"#).unwrap_err();
+        assert_eq!(err.path, "message.data");
+        let err = Message::from_json(r#"{"token": "token", "android": {"ttl": "3"}}"#).unwrap_err();
+        assert_eq!(err.path, "message.android");
+        assert!(err.to_string().contains("3.5s"));
+        let err = Message::from_json(r#"{"validate_only": true, "message": {"topic": "news"}}"#)
+            .unwrap_err();
+        assert_eq!(err.path, "validate_only");
+        let err =
+            Message::from_json(r#"{"topic": "news", "notification": {"body": " "}}"#).unwrap_err();
+        assert_eq!(err.path, "message.notification");
+        let err = Message::from_json(
+            r#"{"topic": "news", "android": {"notification": {"light_settings": {"color": {"red": 1, "green": 0, "blue": 0, "alpha": 1}, "flash": true}}}}"#,
+        )
+        .unwrap_err();
+        assert_eq!(err.path, "message.android.notification.light_settings");
+        assert_eq!(err.reason, "unknown field `flash`");
+    }
+
+    #[test]
+    fn unmodeled_fields_are_ignored_outside_strict_parser() {
+        let json = r#"{"notification": {"colour": "red", "proxy": "ALLOW"}, "unknown": true}"#;
+        assert!(serde_json::from_str::<crate::fcm::android::AndroidConfig>(json).is_ok());
+        assert!(serde_json::from_str::<Notification>(r#"{"title": "a", "unknown": 1}"#).is_ok());
+        let err =
+            Message::from_json(&format!(r#"{{"topic": "news", "android": {json}}}"#)).unwrap_err();
+        assert_eq!(err.path, "message.android.notification");
+        assert_eq!(err.reason, "unknown field `colour`");
+    }
+
+    #[test]
+    fn unexpected_response_keeps_body() {
+        let error = FCMError::from(RPCError::DeserializeFailure {
+            reason: "invalid type".to_string(),
+            source: r#"{"name": "projects/p/messages/1", "unexpected": 1}"#.to_string(),
+        });
+        assert_eq!(error.message_name(), Some("projects/p/messages/1"));
+        let error = FCMError::from(RPCError::DeserializeFailure {
+            reason: "EOF".to_string(),
+            source: r#"{"name": "#.to_string(),
+        });
+        assert!(matches!(error, FCMError::InternalResponseError { .. }));
+        assert_eq!(error.message_name(), None);
+    }
+
+    #[tokio::test]
+    async fn client_is_a_tower_service() {
+        use crate::transport::HttpTransport;
+        use async_trait::async_trait;
+        use http::{Request, Response};
+        use hyper::body::Bytes;
+        use tower_service::Service;
+
+        struct Stub;
+
+        #[async_trait]
+        impl HttpTransport for Stub {
+            async fn send(&self, request: Request<Bytes>) -> Result<Response<Bytes>, RPCError> {
+                let body: serde_json::Value = serde_json::from_slice(request.body()).unwrap();
+                match body["message"]["topic"].as_str() {
+                    Some("news") => Ok(Response::new(Bytes::from(
+                        r#"{"name": "projects/stub/messages/1"}"#,
+                    ))),
+                    _ => Ok(Response::builder().status(404).body(Bytes::new()).unwrap()),
+                }
+            }
+        }
+
+        let mut service = crate::FCMClient::builder()
+            .project_id("stub")
+            .without_credentials()
+            .build_with_transport(std::sync::Arc::new(Stub))
+            .await
+            .unwrap()
+            .with_access_token("token");
+        let topic = |topic: &str| Message::Topic {
+            topic: topic.to_string(),
+            data: None,
+            fcm_options: None,
+            notification: None,
+            android: None,
+            webpush: None,
+            apns: None,
+        };
+        futures::future::poll_fn(|cx| service.poll_ready(cx))
+            .await
+            .unwrap();
+        let sent = service.call(topic("news"));
+        let missing = service.call(topic("missing"));
+        assert_eq!(
+            sent.await.unwrap().name.into_string(),
+            "projects/stub/messages/1"
+        );
+        assert!(missing.await.is_err());
+    }
+
+    #[tokio::test]
+    async fn sends_can_be_abandoned() {
+        use super::FCMApi;
+        use crate::transport::HttpTransport;
+        use async_trait::async_trait;
+        use http::{Request, Response};
+        use hyper::body::Bytes;
+        use std::time::Duration;
+
+        /// Never answers.
+        struct Hanging;
+
+        #[async_trait]
+        impl HttpTransport for Hanging {
+            async fn send(&self, _: Request<Bytes>) -> Result<Response<Bytes>, RPCError> {
+                futures::future::pending().await
+            }
+        }
+
+        let client = crate::FCMClient::builder()
+            .project_id("stub")
+            .without_credentials()
+            .build_with_transport(Hanging)
+            .await
+            .unwrap()
+            .with_access_token("token");
+        let message = Message::Topic {
+            topic: "news".to_string(),
+            data: None,
+            fcm_options: None,
+            notification: None,
+            android: None,
+            webpush: None,
+            apns: None,
+        };
+        let error = client
+            .send_with_deadline(&message, Duration::from_millis(10))
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            FCMError::DeadlineExceeded { deadline, .. } if deadline == Duration::from_millis(10)
+        ));
+        let (tx, rx) = futures::channel::oneshot::channel::<()>();
+        let sending = client.send_until(&message, async {
+            let _ = rx.await;
+        });
+        drop(tx);
+        let error = sending.await.unwrap_err();
+        assert!(matches!(error, FCMError::Cancelled));
+        assert_eq!(error.status_code(), http::StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[test]
+    fn errors_are_mapped_to_status() {
+        assert_eq!(
+            FCMError::InvalidRequest.status_code(),
+            StatusCode::BAD_REQUEST
+        );
+        let error = FCMError::RetryableInternal {
+            retry_after: Duration::from_secs(3),
+        };
+        assert_eq!(error.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(error.retry_after(), Some(Duration::from_secs(3)));
+        let error = FCMError::Transport {
+            kind: TransportErrorKind::Timeout,
+            reason: "deadline elapsed".to_string(),
+        };
+        assert_eq!(error.status_code(), StatusCode::GATEWAY_TIMEOUT);
+        let error = FCMError::Unauthorized("service account key is disabled".to_string());
+        assert_eq!(error.status_code(), StatusCode::BAD_GATEWAY);
+        assert_eq!(error.client_message(), "messaging service is unreachable");
+    }
 }
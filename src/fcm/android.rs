@@ -1,6 +1,8 @@
 use std::collections::HashMap;
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+
+use super::MessageValidationError;
 /// In JSON format, the Duration type is encoded as a string rather than an object,
 /// where the string ends in the suffix "s" (indicating seconds) and is preceded by
 /// the number of seconds, with nanoseconds expressed as fractional seconds.
@@ -13,6 +15,9 @@ impl Duration {
     pub fn from_secs(secs: f32) -> Self {
         Self(secs)
     }
+    pub fn from_millis(millis: u64) -> Self {
+        Self(millis as f32 / 1000.0)
+    }
 }
 impl From<f32> for Duration {
     fn from(value: f32) -> Self {
@@ -27,9 +32,26 @@ impl Serialize for Duration {
         format!("{}s", self.0).serialize(serializer)
     }
 }
+impl<'de> Deserialize<'de> for Duration {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        crate::serde_helpers::parse_duration(&s)
+            .map(|duration| Self(duration.as_secs_f32()))
+            .ok_or_else(|| {
+                serde::de::Error::invalid_value(
+                    serde::de::Unexpected::Str(&s),
+                    &"a non-negative number of seconds with `s` suffix, e.g. \"3.5s\"",
+                )
+            })
+    }
+}
 
 /// Android specific options for messages sent through [FCM connection server](https://goo.gl/4GLdUl).
-#[derive(Debug, Serialize, Default)]
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
 pub struct AndroidConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     /// Options for features provided by the FCM SDK for Android.
@@ -60,6 +82,7 @@ pub struct AndroidConfig {
     /// while 3 seconds and 1 nanosecond should be expressed in JSON format as "3.000000001s".
     /// The ttl will be rounded down to the nearest second.
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "utoipa", schema(value_type = Option<String>, example = "3.5s"))]
     pub ttl: Option<Duration>,
 
     /// If set to true, messages will be allowed to be delivered to the app while the device is in direct boot mode. See [Support Direct Boot mode](https://developer.android.com/training/articles/direct-boot).
@@ -71,8 +94,73 @@ pub struct AndroidConfig {
     pub collapse_key: Option<String>,
 }
 
+impl AndroidConfig {
+    /// Time to live set by [AndroidConfig::high_priority_data].
+    pub const HIGH_PRIORITY_DATA_TTL_SECS: f32 = 3600.0;
+
+    /// Time-sensitive data message, e.g. an incoming call or a chat message handled by the app.
+    ///
+    /// High priority lets FCM wake the device immediately. Because a stale time-sensitive message is rarely useful,
+    /// it is dropped if it cannot be delivered within [AndroidConfig::HIGH_PRIORITY_DATA_TTL_SECS].
+    /// Note that Android may deprioritize apps which send high priority messages that do not result in a user-visible notification.
+    pub fn high_priority_data(data: HashMap<String, String>) -> Self {
+        AndroidConfig {
+            priority: Some(AndroidMessagePriority::High),
+            data: Some(data),
+            ttl: Some(Duration::from_secs(Self::HIGH_PRIORITY_DATA_TTL_SECS)),
+            ..Default::default()
+        }
+    }
+    /// Data message that can wait until the device wakes up, e.g. a background sync trigger.
+    /// FCM keeps it up to 4 weeks while the device is offline.
+    pub fn normal_priority_data(data: HashMap<String, String>) -> Self {
+        AndroidConfig {
+            priority: Some(AndroidMessagePriority::Normal),
+            data: Some(data),
+            ..Default::default()
+        }
+    }
+    /// User-visible notification posted to `channel_id`.
+    /// It is sent with high priority because it is displayed to the user right away.
+    pub fn notification(channel_id: &str, title: &str, body: &str) -> Self {
+        AndroidConfig {
+            priority: Some(AndroidMessagePriority::High),
+            notification: Some(AndroidNotification {
+                channel_id: Some(channel_id.to_string()),
+                title: Some(title.to_string()),
+                body: Some(body.to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+    }
+    /// Keep only the latest message with the same `collapse_key` while the device is offline.
+    pub fn with_collapse_key(mut self, collapse_key: &str) -> Self {
+        self.collapse_key = Some(collapse_key.to_string());
+        self
+    }
+    /// Drop the message if it cannot be delivered within `ttl`.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+}
+
+impl AndroidConfig {
+    /// Check the notification. See [AndroidNotification::validate].
+    pub fn validate(&self) -> Result<(), MessageValidationError> {
+        if let Some(notification) = &self.notification {
+            notification
+                .validate()
+                .map_err(|e| e.within("notification"))?;
+        }
+        Ok(())
+    }
+}
+
 /// Notification to send to android devices.
-#[derive(Debug, Serialize, Default)]
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
 pub struct AndroidNotification {
     /// Set whether or not this notification is relevant only to the current device.
     /// Some notifications can be bridged to other devices for remote display,
@@ -173,6 +261,7 @@ pub struct AndroidNotification {
     /// If `vibrate_timings` is set and `default_vibrate_timings` is set to `true`,
     /// the default value is used instead of the user-specified `vibrate_timings`.
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "utoipa", schema(value_type = Option<Vec<String>>, example = json!(["0s", "0.2s"])))]
     pub vibrate_timings: Option<Vec<Duration>>,
 
     /// The key to the body string in the app's string resources to use
@@ -255,22 +344,79 @@ pub struct AndroidNotification {
     pub light_settings: Option<LightSettings>,
 }
 
+impl AndroidNotification {
+    /// The maximum number of entries accepted by [AndroidNotification::validate] in `vibrate_timings`.
+    pub const MAX_VIBRATE_TIMINGS: usize = 64;
+
+    /// Set `vibrate_timings` from milliseconds, alternating between waiting and vibrating,
+    /// e.g. `&[0, 200, 100, 200]` vibrates twice for 200ms right away.
+    pub fn vibrate_pattern_ms(mut self, pattern_ms: &[u64]) -> Self {
+        self.vibrate_timings = Some(
+            pattern_ms
+                .iter()
+                .copied()
+                .map(Duration::from_millis)
+                .collect(),
+        );
+        self
+    }
+    /// Check `vibrate_timings`: it must have a delay and at least one vibration, at most
+    /// [AndroidNotification::MAX_VIBRATE_TIMINGS] entries, and no negative durations.
+    /// It must not be set together with `default_vibrate_timings`, which would take precedence.
+    pub fn validate(&self) -> Result<(), MessageValidationError> {
+        let timings = match &self.vibrate_timings {
+            Some(timings) => timings,
+            None => return Ok(()),
+        };
+        if self.default_vibrate_timings == Some(true) {
+            return Err(MessageValidationError::new(
+                "vibrate_timings",
+                "is ignored because default_vibrate_timings is true",
+            ));
+        }
+        if timings.len() < 2 || timings.len() > Self::MAX_VIBRATE_TIMINGS {
+            return Err(MessageValidationError::new(
+                "vibrate_timings",
+                format!(
+                    "must have between 2 and {} entries, got {}",
+                    Self::MAX_VIBRATE_TIMINGS,
+                    timings.len()
+                ),
+            ));
+        }
+        if let Some(index) = timings
+            .iter()
+            .position(|timing| !(timing.0.is_finite() && timing.0 >= 0.0))
+        {
+            return Err(MessageValidationError::new(
+                &format!("vibrate_timings[{index}]"),
+                "must be a non-negative duration",
+            ));
+        }
+        Ok(())
+    }
+}
+
 /// Settings to control notification LED.
-#[derive(Debug, Serialize, Default)]
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
 pub struct LightSettings {
     pub color: Color,
     /// Along with `light_off_duration`, define the blink rate of LED flashes.
     /// Resolution defined by [proto.Duration](https://developers.google.com/protocol-buffers/docs/reference/google.protobuf#google.protobuf.Duration)
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "utoipa", schema(value_type = Option<String>, example = "0.5s"))]
     pub light_on_duration: Option<Duration>,
     /// Along with `light_on_duration `, define the blink rate of LED flashes.
     /// Resolution defined by [proto.Duration](https://developers.google.com/protocol-buffers/docs/reference/google.protobuf#google.protobuf.Duration)
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "utoipa", schema(value_type = Option<String>, example = "0.5s"))]
     pub light_off_duration: Option<Duration>,
 }
 
 /// Set `color` of the LED with [google.type.Color](https://github.com/googleapis/googleapis/blob/master/google/type/color.proto).
-#[derive(Debug, Serialize, Default)]
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
 pub struct Color {
     /// The amount of red in the color as a value in the interval [0, 1].
     pub red: f32,
@@ -289,7 +435,8 @@ pub struct Color {
     pub alpha: f32,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
 /// Setting to control when a notification may be proxied.
 pub enum Proxy {
     #[serde(rename = "PROXY_UNSPECIFIED")]
@@ -304,17 +451,13 @@ pub enum Proxy {
     Deny,
 
     /// Only try to proxy this notification if its `AndroidMessagePriority` was lowered from `HIGH` to `NORMAL` on the device.
+    #[default]
     #[serde(rename = "IF_PRIORITY_LOWERED")]
     IfPriorityLowered,
 }
 
-impl Default for Proxy {
-    fn default() -> Self {
-        Self::IfPriorityLowered
-    }
-}
-
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
 /// Set the relative priority for this notification. Priority is an indication
 /// of how much of the user's attention should be consumed by this notification.
 /// Low-priority notifications may be hidden from the user in certain situations,
@@ -338,6 +481,7 @@ pub enum NotificationPriority {
     PriorityLow,
 
     /// Default notification priority. If the application does not prioritize its own notifications, use this value for all notifications.
+    #[default]
     #[serde(rename = "PRIORITY_DEFAULT")]
     PriorityDefault,
 
@@ -350,13 +494,8 @@ pub enum NotificationPriority {
     PriorityMax,
 }
 
-impl Default for NotificationPriority {
-    fn default() -> Self {
-        Self::PriorityDefault
-    }
-}
-
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
 /// Different visibility levels of a notification.
 pub enum Visibility {
     /// If unspecified, default to `Visibility.PRIVATE`.
@@ -364,6 +503,7 @@ pub enum Visibility {
     VisibilityUnspecified,
 
     /// Show this notification on all lockscreens, but conceal sensitive or private information on secure lockscreens.
+    #[default]
     #[serde(rename = "PRIVATE")]
     Private,
 
@@ -376,15 +516,10 @@ pub enum Visibility {
     Secret,
 }
 
-impl Default for Visibility {
-    fn default() -> Self {
-        Self::Private
-    }
-}
-
 /// Message priority. Can take "normal" and "high" values.
 /// For more information, see [Setting the priority of a message](https://goo.gl/GjONJv).
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
 pub enum AndroidMessagePriority {
     /// Default priority for notification messages.
     /// FCM attempts to deliver high priority messages immediately,
@@ -397,6 +532,7 @@ pub enum AndroidMessagePriority {
     /// the user's immediate interaction, but beware that setting your
     /// messages to high priority contributes more to battery drain compared
     /// with normal priority messages.
+    #[default]
     #[serde(rename = "HIGH")]
     High,
     /// Default priority for data messages. Normal priority messages won't
@@ -408,18 +544,13 @@ pub enum AndroidMessagePriority {
     Normal,
 }
 
-impl Default for AndroidMessagePriority {
-    fn default() -> Self {
-        Self::High
-    }
-}
-
 /// Options for features provided by the FCM SDK for Android.
-#[derive(Debug, Serialize, Default)]
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
 pub struct AndroidFcmOptions {
     /// Label associated with the message's analytics data.
     #[serde(skip_serializing_if = "Option::is_none")]
-    analytics_label: Option<String>,
+    pub(crate) analytics_label: Option<String>,
 }
 
 impl AndroidFcmOptions {
@@ -429,3 +560,57 @@ impl AndroidFcmOptions {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{AndroidConfig, AndroidNotification, Duration};
+
+    #[test]
+    fn vibrate_pattern_is_converted_and_validated() {
+        let notification = AndroidNotification::default().vibrate_pattern_ms(&[0, 200, 1500]);
+        assert_eq!(
+            serde_json::to_value(&notification).unwrap()["vibrate_timings"],
+            serde_json::json!(["0s", "0.2s", "1.5s"])
+        );
+        assert!(notification.validate().is_ok());
+        let err = AndroidNotification::default()
+            .vibrate_pattern_ms(&[100])
+            .validate()
+            .unwrap_err();
+        assert_eq!(err.path, "vibrate_timings");
+        let notification = AndroidNotification {
+            vibrate_timings: Some(vec![Duration::from_secs(0.0), Duration::from_secs(-1.0)]),
+            ..Default::default()
+        };
+        assert_eq!(
+            notification.validate().unwrap_err().path,
+            "vibrate_timings[1]"
+        );
+    }
+    use std::collections::HashMap;
+
+    #[test]
+    fn check_serialization_for_presets() {
+        let data = HashMap::from_iter([("call_id".to_string(), "42".to_string())]);
+        let config = AndroidConfig::high_priority_data(data).with_collapse_key("call");
+        assert_eq!(
+            serde_json::to_value(&config).unwrap(),
+            serde_json::json!({
+                "priority": "HIGH",
+                "data": {"call_id": "42"},
+                "ttl": "3600s",
+                "collapse_key": "call",
+            })
+        );
+        let config = AndroidConfig::notification("news", "title", "body")
+            .with_ttl(Duration::from_secs(60.0));
+        assert_eq!(
+            serde_json::to_value(&config).unwrap(),
+            serde_json::json!({
+                "priority": "HIGH",
+                "notification": {"channel_id": "news", "title": "title", "body": "body"},
+                "ttl": "60s",
+            })
+        );
+    }
+}
@@ -0,0 +1,216 @@
+//! Fixture generators for tests and examples.
+//!
+//! Every `fake()` constructor returns a realistic payload that FCM accepts,
+//! so that tests can start from a valid message and override only the fields they care about.
+//!
+//! ```
+//! use firebase_messaging_rs::fcm::{android::AndroidConfig, Message};
+//!
+//! let mut message = Message::fake();
+//! if let Message::Token { android, .. } = &mut message {
+//!     *android = Some(AndroidConfig {
+//!         collapse_key: Some("score-update".to_string()),
+//!         ..AndroidConfig::fake()
+//!     });
+//! }
+//! assert!(message.to_fcm_json(true).is_ok());
+//! ```
+use std::collections::HashMap;
+
+use super::{
+    android::{
+        self, AndroidConfig, AndroidFcmOptions, AndroidMessagePriority, AndroidNotification, Color,
+        LightSettings, NotificationPriority, Visibility,
+    },
+    ios::{
+        self, Alert, ApnsConfig, ApnsHeaders, ApnsPriority, ApnsPushType, Aps, MutableContent,
+        RichAlert,
+    },
+    webpush::{WebNotification, WebNotificationAction, WebPushConfig, WebPushFcmOptions},
+    FcmOptions, Message, Notification,
+};
+
+/// A registration token in the format issued by FCM SDKs. Each call returns a different token.
+pub fn fake_token() -> String {
+    let id = uuid::Uuid::new_v4().simple().to_string();
+    format!(
+        "{}:APA91b{}",
+        &id[..22],
+        id.repeat(5).chars().take(134).collect::<String>()
+    )
+}
+
+fn fake_data() -> HashMap<String, String> {
+    HashMap::from_iter([
+        ("article_id".to_string(), "42".to_string()),
+        ("kind".to_string(), "breaking-news".to_string()),
+    ])
+}
+
+impl Message {
+    /// A message to a [fake_token] with a notification and options for every platform.
+    pub fn fake() -> Self {
+        Message::Token {
+            name: None,
+            data: Some(fake_data()),
+            token: fake_token(),
+            fcm_options: Some(FcmOptions::new("fake-campaign")),
+            notification: Some(Notification::fake()),
+            android: Some(AndroidConfig::fake()),
+            webpush: Some(WebPushConfig::fake()),
+            apns: Some(ApnsConfig::fake()),
+        }
+    }
+}
+
+impl Notification {
+    pub fn fake() -> Self {
+        Notification {
+            title: Some("Breaking news".to_string()),
+            body: Some("Something happened. Tap to read more.".to_string()),
+            image: Some("https://example.com/images/news.png".to_string()),
+        }
+    }
+}
+
+impl AndroidConfig {
+    pub fn fake() -> Self {
+        AndroidConfig {
+            fcm_options: Some(AndroidFcmOptions::new("fake-campaign")),
+            priority: Some(AndroidMessagePriority::High),
+            notification: Some(AndroidNotification::fake()),
+            data: Some(fake_data()),
+            restricted_package_name: Some("com.example.app".to_string()),
+            ttl: Some(android::Duration::from_secs(3600.0)),
+            direct_boot_ok: Some(false),
+            collapse_key: Some("news".to_string()),
+        }
+    }
+}
+
+impl AndroidNotification {
+    pub fn fake() -> Self {
+        AndroidNotification {
+            title: Some("Breaking news".to_string()),
+            body: Some("Something happened. Tap to read more.".to_string()),
+            icon: Some("ic_notification".to_string()),
+            color: Some("#1A73E8".to_string()),
+            sound: Some("default".to_string()),
+            tag: Some("news".to_string()),
+            click_action: Some("OPEN_ARTICLE".to_string()),
+            channel_id: Some("news".to_string()),
+            event_time: Some("2024-01-01T00:00:00Z".to_string()),
+            notification_priority: Some(NotificationPriority::PriorityHigh),
+            visibility: Some(Visibility::Public),
+            notification_count: Some(1),
+            vibrate_timings: Some(vec![
+                android::Duration::from_secs(0.5),
+                android::Duration::from_secs(0.25),
+            ]),
+            light_settings: Some(LightSettings {
+                color: Color {
+                    red: 0.1,
+                    green: 0.45,
+                    blue: 0.9,
+                    alpha: 1.0,
+                },
+                light_on_duration: Some(android::Duration::from_secs(1.0)),
+                light_off_duration: Some(android::Duration::from_secs(2.0)),
+            }),
+            ..Default::default()
+        }
+    }
+}
+
+impl Aps {
+    pub fn fake() -> Self {
+        Aps {
+            alert: Some(Alert::Structural(Box::new(RichAlert {
+                title: Some("Breaking news".to_string()),
+                subtitle: Some("World".to_string()),
+                body: Some("Something happened. Tap to read more.".to_string()),
+                ..Default::default()
+            }))),
+            badge: Some(1),
+            thread_id: Some("news".to_string()),
+            mutable_content: Some(MutableContent::On),
+            ..Default::default()
+        }
+    }
+}
+
+impl ApnsHeaders {
+    pub fn fake() -> Self {
+        ApnsHeaders {
+            apns_push_type: Some(ApnsPushType::Alert),
+            apns_expiration: Some(ios::Duration::from_secs(1_700_000_000)),
+            apns_priority: Some(ApnsPriority::SendImmediately),
+            apns_topic: Some("com.example.app".to_string()),
+            apns_collapse_id: Some("news".to_string()),
+            ..Default::default()
+        }
+    }
+}
+
+impl ApnsConfig {
+    pub fn fake() -> Self {
+        ApnsConfig::new(&Aps::fake(), &fake_data(), Some(ApnsHeaders::fake()))
+    }
+}
+
+impl WebNotification {
+    pub fn fake() -> Self {
+        WebNotification {
+            title: Some("Breaking news".to_string()),
+            body: Some("Something happened. Tap to read more.".to_string()),
+            icon: Some("https://example.com/icons/news.png".to_string()),
+            tag: Some("news".to_string()),
+            lang: Some("en-US".to_string()),
+            actions: Some(vec![WebNotificationAction {
+                action: "open".to_string(),
+                title: "Read".to_string(),
+                icon: None,
+            }]),
+            ..Default::default()
+        }
+    }
+}
+
+impl WebPushConfig {
+    pub fn fake() -> Self {
+        WebPushConfig {
+            headers: Some(HashMap::from_iter([(
+                "TTL".to_string(),
+                "3600".to_string(),
+            )])),
+            data: Some(fake_data()),
+            notification: None,
+            fcm_options: Some(WebPushFcmOptions {
+                analytics_label: Some("fake-campaign".to_string()),
+                link: Some("https://example.com/news/42".to_string()),
+            }),
+        }
+        .with_notification(&WebNotification::fake())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fake_token;
+    use crate::fcm::Message;
+
+    #[test]
+    fn fake_messages_are_serializable() {
+        let token = fake_token();
+        assert_eq!(token.len(), 22 + 1 + 6 + 134);
+        assert_ne!(token, fake_token());
+        let json: serde_json::Value =
+            serde_json::from_str(&Message::fake().to_fcm_json(false).unwrap()).unwrap();
+        assert_eq!(json["message"]["android"]["priority"], "HIGH");
+        assert_eq!(
+            json["message"]["apns"]["payload"]["aps"]["alert"]["title"],
+            "Breaking news"
+        );
+        assert_eq!(json["message"]["webpush"]["notification"]["lang"], "en-US");
+    }
+}
@@ -1,6 +1,9 @@
 use std::collections::HashMap;
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+
+use super::MessageValidationError;
+use crate::Redacted;
 
 #[derive(Debug)]
 pub struct Duration(std::time::Duration);
@@ -17,20 +20,45 @@ impl Serialize for Duration {
         serializer.serialize_str(&self.0.as_secs().to_string())
     }
 }
+impl<'de> Deserialize<'de> for Duration {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        s.parse::<u64>().map(Self::from_secs).map_err(|_| {
+            serde::de::Error::invalid_value(
+                serde::de::Unexpected::Str(&s),
+                &"a UNIX epoch in seconds, e.g. \"1700000000\"",
+            )
+        })
+    }
+}
 
-#[derive(Debug, Serialize, Default)]
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
 /// Options for features provided by the FCM SDK for iOS.
 pub struct APNSFcmOptions {
     /// Label associated with the message's analytics data.
     #[serde(skip_serializing_if = "Option::is_none")]
-    analytics_label: Option<String>,
+    pub(crate) analytics_label: Option<String>,
     /// Contains the URL of an image that is going to be displayed in a notification.
     /// If present, it will override [[MessageLike]]::fcmOptions.
     #[serde(skip_serializing_if = "Option::is_none")]
     image: Option<String>,
 }
 
-#[derive(Debug, Serialize, Default)]
+impl APNSFcmOptions {
+    pub fn new(analytics_label: &str) -> Self {
+        Self {
+            analytics_label: Some(analytics_label.to_string()),
+            image: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
 /// APNs HTTP headers properties
 /// See <https://developer.apple.com/documentation/usernotifications/sending-notification-requests-to-apns>
 pub struct ApnsHeaders {
@@ -64,6 +92,7 @@ pub struct ApnsHeaders {
     /// If you omit this header, APNs stores the push according to APNs storage policy.
     #[serde(rename = "apns-expiration")]
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "utoipa", schema(value_type = Option<String>, example = "1700000000"))]
     pub apns_expiration: Option<Duration>,
     /// The priority of the notification.
     ///
@@ -72,7 +101,7 @@ pub struct ApnsHeaders {
     /// - Specify 10 to send the notification immediately.
     /// - Specify 5 to send the notification based on power considerations on the user’s device.
     /// - Specify 1 to prioritize the device’s power considerations over all other
-    /// factors for delivery, and prevent awakening the device.
+    ///   factors for delivery, and prevent awakening the device.
     #[serde(rename = "apns-priority")]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub apns_priority: Option<ApnsPriority>,
@@ -95,6 +124,23 @@ pub struct ApnsHeaders {
     pub apns_collapse_id: Option<String>,
 }
 
+impl std::fmt::Debug for ApnsHeaders {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ApnsHeaders")
+            .field(
+                "authorization",
+                &self.authorization.as_ref().map(|_| Redacted),
+            )
+            .field("apns_id", &self.apns_id)
+            .field("apns_push_type", &self.apns_push_type)
+            .field("apns_expiration", &self.apns_expiration)
+            .field("apns_priority", &self.apns_priority)
+            .field("apns_topic", &self.apns_topic)
+            .field("apns_collapse_id", &self.apns_collapse_id)
+            .finish()
+    }
+}
+
 impl ApnsHeaders {
     pub fn ios_background_notification() -> ApnsHeaders {
         ApnsHeaders {
@@ -103,9 +149,30 @@ impl ApnsHeaders {
             ..Default::default()
         }
     }
+    /// Headers for a user-visible alert. `topic` is the bundle ID of your app.
+    pub fn ios_alert_notification(topic: &str) -> ApnsHeaders {
+        ApnsHeaders {
+            apns_push_type: Some(ApnsPushType::Alert),
+            apns_priority: Some(ApnsPriority::SendImmediately),
+            apns_topic: Some(topic.to_string()),
+            ..Default::default()
+        }
+    }
+    /// Headers for a VoIP notification. `bundle_id` is the bundle ID of your app without `.voip` suffix.
+    /// The notification is delivered immediately and only once, since a late incoming call is useless.
+    pub fn ios_voip_notification(bundle_id: &str) -> ApnsHeaders {
+        ApnsHeaders {
+            apns_push_type: Some(ApnsPushType::VoiP),
+            apns_priority: Some(ApnsPriority::SendImmediately),
+            apns_topic: Some(format!("{bundle_id}.voip")),
+            apns_expiration: Some(Duration::from_secs(0)),
+            ..Default::default()
+        }
+    }
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
 /// The priority of the notification.
 pub enum ApnsPriority {
     #[serde(rename = "10")]
@@ -116,13 +183,16 @@ pub enum ApnsPriority {
     RespectEnergySavingModeNoAwaking,
 }
 
-#[derive(Debug, Serialize, Default)]
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
 /// Apple Push Notification Service specific options.
 pub struct ApnsConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     payload: Option<serde_json::Value>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    headers: Option<ApnsHeaders>,
+    pub(super) headers: Option<ApnsHeaders>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) fcm_options: Option<APNSFcmOptions>,
 }
 
 impl ApnsConfig {
@@ -139,6 +209,7 @@ impl ApnsConfig {
         ApnsConfig {
             payload: Some(payload),
             headers,
+            fcm_options: None,
         }
     }
     pub fn ios_background_notification(data_payload: HashMap<String, String>) -> ApnsConfig {
@@ -154,8 +225,111 @@ impl ApnsConfig {
         ApnsConfig {
             payload: Some(payload),
             headers: Some(ApnsHeaders::ios_background_notification()),
+            fcm_options: None,
         }
     }
+    pub fn with_fcm_options(mut self, fcm_options: APNSFcmOptions) -> Self {
+        self.fcm_options = Some(fcm_options);
+        self
+    }
+    /// User-visible alert with `title` and `body`. `topic` is the bundle ID of your app.
+    pub fn alert(title: &str, body: &str, topic: &str) -> ApnsConfig {
+        let aps = Aps {
+            alert: Some(Alert::Structural(Box::new(RichAlert {
+                title: Some(title.to_string()),
+                body: Some(body.to_string()),
+                ..Default::default()
+            }))),
+            ..Default::default()
+        };
+        ApnsConfig::new(
+            &aps,
+            &HashMap::default(),
+            Some(ApnsHeaders::ios_alert_notification(topic)),
+        )
+    }
+    /// VoIP notification carrying `data_payload`, e.g. a call identifier, to PushKit.
+    /// See [ApnsHeaders::ios_voip_notification].
+    pub fn ios_voip_notification(
+        bundle_id: &str,
+        data_payload: HashMap<String, String>,
+    ) -> ApnsConfig {
+        ApnsConfig::new(
+            &Aps::default(),
+            &data_payload,
+            Some(ApnsHeaders::ios_voip_notification(bundle_id)),
+        )
+    }
+    /// Check that the `aps` dictionary of the payload agrees with `apns-push-type` and `apns-priority`,
+    /// which APNs otherwise rejects or silently drops:
+    ///
+    /// - `background` requires `content-available: 1` and a priority of 5 or lower.
+    /// - `alert` requires an alert, a sound or a badge.
+    /// - `liveactivity` requires `event` and `timestamp`, and `attributes-type` to start an activity.
+    ///
+    /// Nothing is checked unless both headers and payload are present.
+    pub fn validate(&self) -> Result<(), MessageValidationError> {
+        let (headers, aps) = match (
+            &self.headers,
+            self.payload.as_ref().and_then(|p| p.get("aps")),
+        ) {
+            (Some(headers), Some(aps)) => (headers, aps),
+            _ => return Ok(()),
+        };
+        let has = |key: &str| aps.get(key).map_or(false, |value| !value.is_null());
+        match headers.apns_push_type {
+            Some(ApnsPushType::Background) => {
+                if aps.get("content-available").and_then(|v| v.as_u64()) != Some(1) {
+                    return Err(MessageValidationError::new(
+                        "payload.aps.content-available",
+                        "must be 1 for the background push type",
+                    ));
+                }
+                if matches!(
+                    headers.apns_priority,
+                    None | Some(ApnsPriority::SendImmediately)
+                ) {
+                    return Err(MessageValidationError::new(
+                        "headers.apns-priority",
+                        "must be 5 or 1 for the background push type",
+                    ));
+                }
+            }
+            Some(ApnsPushType::Alert) if !(has("alert") || has("sound") || has("badge")) => {
+                return Err(MessageValidationError::new(
+                    "payload.aps",
+                    "must have an alert, a sound or a badge for the alert push type",
+                ));
+            }
+            Some(ApnsPushType::LiveActivity) => {
+                for key in ["event", "timestamp"] {
+                    if !has(key) {
+                        return Err(MessageValidationError::new(
+                            &format!("payload.aps.{key}"),
+                            "is required for the liveactivity push type",
+                        ));
+                    }
+                }
+                match aps.get("event").and_then(|v| v.as_str()) {
+                    Some("start") if !has("attributes-type") => {
+                        return Err(MessageValidationError::new(
+                            "payload.aps.attributes-type",
+                            "is required to start a live activity",
+                        ))
+                    }
+                    Some("start" | "update" | "end") => {}
+                    _ => {
+                        return Err(MessageValidationError::new(
+                            "payload.aps.event",
+                            "must be one of start, update or end",
+                        ))
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
     fn merge(a: &mut serde_json::Value, b: &serde_json::Value) {
         match (a, b) {
             (serde_json::Value::Object(a), serde_json::Value::Object(b)) => {
@@ -168,7 +342,8 @@ impl ApnsConfig {
     }
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
 #[serde(rename_all = "lowercase")]
 pub enum ApnsPushType {
     /// The push type for notifications that trigger a user interaction—for example, an alert, badge, or sound.
@@ -255,6 +430,7 @@ pub enum ApnsPushType {
 
 /// See <https://developer.apple.com/documentation/usernotifications/generating-a-remote-notification>
 #[derive(Debug, Serialize, Default)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
 pub struct Aps {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub alert: Option<Alert>,
@@ -265,9 +441,11 @@ pub struct Aps {
     pub thread_id: Option<String>,
     #[serde(rename = "content-available")]
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "utoipa", schema(value_type = Option<u8>, minimum = 0, maximum = 1))]
     pub content_available: Option<ContentAvailable>,
     #[serde(rename = "mutable-content")]
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "utoipa", schema(value_type = Option<u8>, minimum = 0, maximum = 1))]
     pub mutable_content: Option<MutableContent>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub timestamp: Option<u32>,
@@ -338,7 +516,24 @@ impl Serialize for Alert {
     }
 }
 
+/// Either a plain string or a [RichAlert] object, as serialized.
+#[cfg(feature = "utoipa")]
+impl<'s> utoipa::ToSchema<'s> for Alert {
+    fn schema() -> (
+        &'s str,
+        utoipa::openapi::RefOr<utoipa::openapi::schema::Schema>,
+    ) {
+        use utoipa::openapi::{ObjectBuilder, OneOfBuilder, Ref, SchemaType};
+        let schema = OneOfBuilder::new()
+            .item(ObjectBuilder::new().schema_type(SchemaType::String))
+            .item(Ref::from_schema_name("RichAlert"))
+            .description(Some("The information for displaying an alert."));
+        ("Alert", schema.into())
+    }
+}
+
 #[derive(Debug, Serialize, Clone, Default)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
 pub struct RichAlert {
     /// The title of the notification. Apple Watch displays this string in
     /// the short look notification interface. Specify a string that’s quickly
@@ -423,7 +618,47 @@ mod tests {
 
     use crate::fcm::ios::RichAlert;
 
-    use super::{Alert, ApnsConfig};
+    use super::{Alert, ApnsConfig, ApnsHeaders, ApnsPushType, Aps};
+
+    #[test]
+    fn apns_headers_are_checked_against_aps() {
+        assert!(ApnsConfig::ios_background_notification(HashMap::new())
+            .validate()
+            .is_ok());
+        assert!(ApnsConfig::alert("title", "body", "com.example.app")
+            .validate()
+            .is_ok());
+        let err = ApnsConfig::new(
+            &Aps::default(),
+            &HashMap::new(),
+            Some(ApnsHeaders::ios_background_notification()),
+        )
+        .validate()
+        .unwrap_err();
+        assert_eq!(err.path, "payload.aps.content-available");
+        let err = ApnsConfig::new(
+            &Aps {
+                badge: Some(1),
+                ..Default::default()
+            },
+            &HashMap::new(),
+            Some(ApnsHeaders {
+                apns_push_type: Some(ApnsPushType::LiveActivity),
+                ..Default::default()
+            }),
+        )
+        .validate()
+        .unwrap_err();
+        assert_eq!(err.path, "payload.aps.event");
+        let err = ApnsConfig::new(
+            &Aps::default(),
+            &HashMap::new(),
+            Some(ApnsHeaders::ios_alert_notification("com.example.app")),
+        )
+        .validate()
+        .unwrap_err();
+        assert_eq!(err.path, "payload.aps");
+    }
 
     #[test]
     fn check_serialization_for_union_like_type() {
@@ -481,4 +716,36 @@ mod tests {
         });
         assert_eq!(json, expect)
     }
+
+    #[test]
+    fn check_serialization_for_presets() {
+        let alert = ApnsConfig::alert("title", "body", "com.example.app");
+        assert_eq!(
+            serde_json::to_value(&alert).unwrap(),
+            serde_json::json!({
+                "payload": {"aps": {"alert": {"title": "title", "body": "body"}}},
+                "headers": {
+                    "apns-push-type": "alert",
+                    "apns-priority": "10",
+                    "apns-topic": "com.example.app",
+                },
+            })
+        );
+        let voip = ApnsConfig::ios_voip_notification(
+            "com.example.app",
+            HashMap::from_iter([("call_id".to_string(), "42".to_string())]),
+        );
+        assert_eq!(
+            serde_json::to_value(&voip).unwrap(),
+            serde_json::json!({
+                "payload": {"aps": {}, "call_id": "42"},
+                "headers": {
+                    "apns-push-type": "voip",
+                    "apns-expiration": "0",
+                    "apns-priority": "10",
+                    "apns-topic": "com.example.app.voip",
+                },
+            })
+        );
+    }
 }
@@ -0,0 +1,443 @@
+//! Conversion of payloads written for the legacy FCM HTTP API (`https://fcm.googleapis.com/fcm/send`).
+//!
+//! ```
+//! use firebase_messaging_rs::fcm::Message;
+//!
+//! let legacy = serde_json::json!({
+//!     "registration_ids": ["token-a", "token-b"],
+//!     "priority": "high",
+//!     "notification": {"title": "Hello", "body": "World", "android_channel_id": "news"},
+//!     "delay_while_idle": true
+//! });
+//! let converted = Message::from_legacy_json(&legacy).unwrap();
+//! assert_eq!(converted.messages.len(), 2);
+//! assert_eq!(converted.warnings[0].field, "delay_while_idle");
+//! ```
+use std::collections::HashMap;
+
+use serde_json::{Map, Value};
+
+use super::{
+    android::{self, AndroidConfig, AndroidMessagePriority, AndroidNotification},
+    ios::{ApnsConfig, ApnsHeaders, ApnsPriority, Aps, ContentAvailable, MutableContent},
+    Message, MessageParseError, Notification,
+};
+
+/// The maximum `time_to_live` accepted by the legacy API, 4 weeks.
+const MAX_TIME_TO_LIVE_SECS: u64 = 2_419_200;
+
+/// Result of [Message::from_legacy_json].
+#[derive(Debug)]
+pub struct LegacyConversion {
+    /// One message per target. `registration_ids` yields a message per token in the same order.
+    pub messages: Vec<Message>,
+    /// Fields that were dropped or whose meaning changed in the conversion.
+    pub warnings: Vec<LegacyWarning>,
+}
+
+/// A legacy field that could not be converted faithfully.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LegacyWarning {
+    /// Path of the field in the legacy payload, e.g. `notification.subtitle`.
+    pub field: String,
+    pub reason: String,
+}
+
+enum Target {
+    Token(String),
+    Topic(String),
+    Condition(String),
+}
+
+/// Fields of the legacy payload that are copied to every converted message.
+#[derive(Default)]
+struct Parts {
+    data: Option<HashMap<String, String>>,
+    title: Option<String>,
+    body: Option<String>,
+    image: Option<String>,
+    android_notification: Option<Map<String, Value>>,
+    priority: Option<bool>,
+    ttl: Option<u64>,
+    collapse_key: Option<String>,
+    restricted_package_name: Option<String>,
+    badge: Option<u32>,
+    content_available: bool,
+    mutable_content: bool,
+}
+
+impl Parts {
+    fn build(&self, target: Target) -> Message {
+        let notification = (self.title.is_some() || self.body.is_some() || self.image.is_some())
+            .then(|| Notification {
+                title: self.title.clone(),
+                body: self.body.clone(),
+                image: self.image.clone(),
+            });
+        let android_notification = self.android_notification.as_ref().map(|fields| {
+            let string = |key: &str| fields.get(key).and_then(Value::as_str).map(str::to_string);
+            let args = |key: &str| {
+                fields.get(key).and_then(Value::as_array).map(|args| {
+                    args.iter()
+                        .map(|arg| arg.as_str().map_or_else(|| arg.to_string(), str::to_string))
+                        .collect()
+                })
+            };
+            AndroidNotification {
+                icon: string("icon"),
+                sound: string("sound"),
+                tag: string("tag"),
+                color: string("color"),
+                click_action: string("click_action"),
+                channel_id: string("android_channel_id"),
+                body_loc_key: string("body_loc_key"),
+                body_loc_args: args("body_loc_args"),
+                title_loc_key: string("title_loc_key"),
+                title_loc_args: args("title_loc_args"),
+                ..Default::default()
+            }
+        });
+        let android = AndroidConfig {
+            priority: self.priority.map(|high| {
+                if high {
+                    AndroidMessagePriority::High
+                } else {
+                    AndroidMessagePriority::Normal
+                }
+            }),
+            notification: android_notification,
+            restricted_package_name: self.restricted_package_name.clone(),
+            ttl: self.ttl.map(|ttl| android::Duration::from_secs(ttl as f32)),
+            collapse_key: self.collapse_key.clone(),
+            ..Default::default()
+        };
+        let has_android = android.priority.is_some()
+            || android.notification.is_some()
+            || android.restricted_package_name.is_some()
+            || android.ttl.is_some()
+            || android.collapse_key.is_some();
+        let aps = Aps {
+            badge: self.badge,
+            content_available: self.content_available.then_some(ContentAvailable::On),
+            mutable_content: self.mutable_content.then_some(MutableContent::On),
+            ..Default::default()
+        };
+        let has_aps = aps.badge.is_some() || self.content_available || self.mutable_content;
+        let apns = (has_aps || self.priority.is_some()).then(|| {
+            let headers = self.priority.map(|high| ApnsHeaders {
+                apns_priority: Some(if high {
+                    ApnsPriority::SendImmediately
+                } else {
+                    ApnsPriority::RespectEnergySavingMode
+                }),
+                ..Default::default()
+            });
+            if has_aps {
+                ApnsConfig::new(&aps, &HashMap::new(), headers)
+            } else {
+                let mut config = ApnsConfig::default();
+                config.headers = headers;
+                config
+            }
+        });
+        let android = has_android.then_some(android);
+        match target {
+            Target::Token(token) => Message::Token {
+                name: None,
+                data: self.data.clone(),
+                token,
+                fcm_options: None,
+                notification,
+                android,
+                webpush: None,
+                apns,
+            },
+            Target::Topic(topic) => Message::Topic {
+                topic,
+                data: self.data.clone(),
+                fcm_options: None,
+                notification,
+                android,
+                webpush: None,
+                apns,
+            },
+            Target::Condition(condition) => Message::Condition {
+                condition,
+                data: self.data.clone(),
+                fcm_options: None,
+                notification,
+                android,
+                webpush: None,
+                apns,
+            },
+        }
+    }
+}
+
+fn string_field(
+    object: &Map<String, Value>,
+    path: &str,
+    key: &str,
+) -> Result<Option<String>, MessageParseError> {
+    match object.get(key) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(value)) => Ok(Some(value.clone())),
+        Some(_) => Err(MessageParseError::new(path, "expected a string")),
+    }
+}
+
+fn bool_field(object: &Map<String, Value>, path: &str) -> Result<bool, MessageParseError> {
+    match object.get(path) {
+        None | Some(Value::Null) => Ok(false),
+        Some(Value::Bool(value)) => Ok(*value),
+        Some(_) => Err(MessageParseError::new(path, "expected a boolean")),
+    }
+}
+
+impl Message {
+    /// Convert a payload of the legacy FCM HTTP API into v1 messages.
+    ///
+    /// `to`, `registration_ids` and `condition` select the targets. `notification`, `data`, `priority`, `time_to_live`,
+    /// `collapse_key`, `restricted_package_name`, `content_available` and `mutable_content` are mapped to their
+    /// v1 counterparts in [Notification], [AndroidConfig] and [ApnsConfig].
+    /// Constructs without an equivalent are dropped and reported in [LegacyConversion::warnings].
+    /// Payloads that cannot be converted at all, e.g. without a target, are rejected with the path of the offending field.
+    pub fn from_legacy_json(value: &Value) -> Result<LegacyConversion, MessageParseError> {
+        let object = value
+            .as_object()
+            .ok_or_else(|| MessageParseError::new("", "expected an object"))?;
+        let mut warnings = vec![];
+        let mut warn = |field: &str, reason: &str| {
+            warnings.push(LegacyWarning {
+                field: field.to_string(),
+                reason: reason.to_string(),
+            })
+        };
+        let mut parts = Parts::default();
+
+        let targets = match (
+            object.get("to"),
+            object.get("registration_ids"),
+            object.get("condition"),
+        ) {
+            (Some(Value::String(to)), None, None) => match to.strip_prefix("/topics/") {
+                Some(topic) => vec![Target::Topic(topic.to_string())],
+                None => vec![Target::Token(to.clone())],
+            },
+            (Some(_), None, None) => return Err(MessageParseError::new("to", "expected a string")),
+            (None, Some(Value::Array(ids)), None) => {
+                if ids.is_empty() {
+                    return Err(MessageParseError::new(
+                        "registration_ids",
+                        "expected at least one token",
+                    ));
+                }
+                ids.iter()
+                    .enumerate()
+                    .map(|(i, id)| match id {
+                        Value::String(token) => Ok(Target::Token(token.clone())),
+                        _ => Err(MessageParseError::new(
+                            &format!("registration_ids[{i}]"),
+                            "expected a string",
+                        )),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?
+            }
+            (None, Some(_), None) => {
+                return Err(MessageParseError::new(
+                    "registration_ids",
+                    "expected an array of strings",
+                ))
+            }
+            (None, None, Some(Value::String(condition))) => {
+                vec![Target::Condition(condition.clone())]
+            }
+            (None, None, Some(_)) => {
+                return Err(MessageParseError::new("condition", "expected a string"))
+            }
+            _ => {
+                return Err(MessageParseError::new(
+                    "",
+                    "exactly one of `to`, `registration_ids` and `condition` must be set",
+                ))
+            }
+        };
+
+        for (key, value) in object {
+            match key.as_str() {
+                "to" | "registration_ids" | "condition" => {}
+                "data" => {
+                    let data = value
+                        .as_object()
+                        .ok_or_else(|| MessageParseError::new("data", "expected an object"))?;
+                    let mut converted = HashMap::new();
+                    for (key, value) in data {
+                        let field = format!("data.{key}");
+                        let value = match value {
+                            Value::String(value) => value.clone(),
+                            other => {
+                                warn(&field, "non-string value is converted to its JSON text");
+                                other.to_string()
+                            }
+                        };
+                        converted.insert(key.clone(), value);
+                    }
+                    parts.data = Some(converted);
+                }
+                "notification" => {
+                    let notification = value.as_object().ok_or_else(|| {
+                        MessageParseError::new("notification", "expected an object")
+                    })?;
+                    let mut android_notification = Map::new();
+                    for (key, value) in notification {
+                        let field = format!("notification.{key}");
+                        match key.as_str() {
+                            "title" => parts.title = string_field(notification, &field, key)?,
+                            "body" => parts.body = string_field(notification, &field, key)?,
+                            "image" => parts.image = string_field(notification, &field, key)?,
+                            "icon" | "sound" | "tag" | "color" | "click_action"
+                            | "android_channel_id" | "body_loc_key" | "title_loc_key" => {
+                                string_field(notification, &field, key)?;
+                                android_notification.insert(key.clone(), value.clone());
+                            }
+                            "body_loc_args" | "title_loc_args" => {
+                                // The legacy API accepts the arguments either as an array or as a JSON encoded array.
+                                let args = match value {
+                                    Value::String(json) => {
+                                        serde_json::from_str(json).map_err(|_| {
+                                            MessageParseError::new(
+                                                &field,
+                                                "expected an array of strings",
+                                            )
+                                        })?
+                                    }
+                                    other => other.clone(),
+                                };
+                                if !args.is_array() {
+                                    return Err(MessageParseError::new(
+                                        &field,
+                                        "expected an array of strings",
+                                    ));
+                                }
+                                android_notification.insert(key.clone(), args);
+                            }
+                            "badge" => {
+                                let badge = match value {
+                                    Value::String(badge) => badge.parse().ok(),
+                                    other => other.as_u64().and_then(|badge| badge.try_into().ok()),
+                                };
+                                parts.badge = Some(badge.ok_or_else(|| {
+                                    MessageParseError::new(
+                                        &field,
+                                        "expected a non-negative integer",
+                                    )
+                                })?);
+                            }
+                            _ => warn(&field, "not supported by v1 API and dropped"),
+                        }
+                    }
+                    if !android_notification.is_empty() {
+                        parts.android_notification = Some(android_notification);
+                    }
+                }
+                "priority" => {
+                    parts.priority = match value.as_str() {
+                        Some("high") => Some(true),
+                        Some("normal") => Some(false),
+                        _ => {
+                            return Err(MessageParseError::new(
+                                "priority",
+                                "expected `high` or `normal`",
+                            ))
+                        }
+                    }
+                }
+                "time_to_live" => {
+                    let ttl = value
+                        .as_u64()
+                        .filter(|ttl| *ttl <= MAX_TIME_TO_LIVE_SECS)
+                        .ok_or_else(|| {
+                            MessageParseError::new(
+                                "time_to_live",
+                                format!(
+                                    "expected an integer between 0 and {MAX_TIME_TO_LIVE_SECS}"
+                                ),
+                            )
+                        })?;
+                    warn(
+                        "time_to_live",
+                        "applied to Android only; set `apns-expiration` header for APNs",
+                    );
+                    parts.ttl = Some(ttl);
+                }
+                "collapse_key" => {
+                    parts.collapse_key = string_field(object, key, key)?;
+                }
+                "restricted_package_name" => {
+                    parts.restricted_package_name = string_field(object, key, key)?;
+                }
+                "content_available" => parts.content_available = bool_field(object, key)?,
+                "mutable_content" => parts.mutable_content = bool_field(object, key)?,
+                "dry_run" => warn(key, "use `FCMApi::validate` instead"),
+                "delay_while_idle" => warn(key, "removed from FCM and ignored"),
+                _ => warn(key, "not supported by v1 API and dropped"),
+            }
+        }
+
+        Ok(LegacyConversion {
+            messages: targets
+                .into_iter()
+                .map(|target| parts.build(target))
+                .collect(),
+            warnings,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fcm::Message;
+
+    #[test]
+    fn legacy_payloads_are_converted() {
+        let converted = Message::from_legacy_json(&serde_json::json!({
+            "to": "/topics/news",
+            "priority": "normal",
+            "time_to_live": 60,
+            "data": {"article_id": 42},
+            "notification": {"title": "Hello", "title_loc_args": "[\"a\"]", "subtitle": "dropped"}
+        }))
+        .unwrap();
+        let json: serde_json::Value =
+            serde_json::from_str(&converted.messages[0].to_fcm_json(false).unwrap()).unwrap();
+        let message = &json["message"];
+        assert_eq!(message["topic"], "news");
+        assert_eq!(message["notification"]["title"], "Hello");
+        assert_eq!(message["android"]["priority"], "NORMAL");
+        assert_eq!(message["android"]["ttl"], "60s");
+        assert_eq!(message["data"]["article_id"], "42");
+        assert_eq!(message["android"]["notification"]["title_loc_args"][0], "a");
+        assert_eq!(message["apns"]["headers"]["apns-priority"], "5");
+        let fields = converted
+            .warnings
+            .iter()
+            .map(|w| w.field.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            fields,
+            ["data.article_id", "notification.subtitle", "time_to_live"]
+        );
+
+        let err = Message::from_legacy_json(&serde_json::json!({
+            "to": "a-token",
+            "condition": "'news' in topics"
+        }))
+        .unwrap_err();
+        assert_eq!(err.path, "");
+        let err = Message::from_legacy_json(&serde_json::json!({
+            "registration_ids": ["a", 1]
+        }))
+        .unwrap_err();
+        assert_eq!(err.path, "registration_ids[1]");
+    }
+}
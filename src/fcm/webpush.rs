@@ -1,9 +1,10 @@
 use std::collections::HashMap;
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 /// [Webpush protocol](https://tools.ietf.org/html/rfc8030) options.,
-#[derive(Debug, Serialize, Default)]
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
 pub struct WebPushConfig {
     /// HTTP headers defined in webpush protocol. Refer to [Webpush protocol](https://tools.ietf.org/html/rfc8030#section-5) for supported headers, e.g. \"TTL\": \"15\".
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -14,6 +15,8 @@ pub struct WebPushConfig {
     /// [Web Notification API](https://developer.mozilla.org/en-US/docs/Web/API/Notification).
     /// If present, "title" and "body" fields override [google.firebase.fcm.v1.Notification.title] and
     /// [google.firebase.fcm.v1.Notification.body].
+    ///
+    /// Prefer [WebPushConfig::with_notification] to set this field from a typed [WebNotification].
     #[serde(skip_serializing_if = "Option::is_none")]
     pub notification: Option<serde_json::Value>,
     /// Options for features provided by the FCM SDK for Web
@@ -21,7 +24,96 @@ pub struct WebPushConfig {
     pub fcm_options: Option<WebPushFcmOptions>,
 }
 
-#[derive(Debug, Serialize, Default)]
+impl WebPushConfig {
+    /// `TTL` header set by presets, in seconds.
+    pub const DEFAULT_TTL_SECS: u64 = 86400;
+
+    /// Data message handled by the service worker without showing a notification.
+    /// Sent with `Urgency: normal` and [WebPushConfig::DEFAULT_TTL_SECS].
+    pub fn data_only(data: HashMap<String, String>) -> Self {
+        WebPushConfig {
+            headers: Some(Self::preset_headers("normal")),
+            data: Some(data),
+            ..Default::default()
+        }
+    }
+    /// Notification that opens `link` when clicked. `link` must be an HTTPS URL.
+    /// Sent with `Urgency: high` and [WebPushConfig::DEFAULT_TTL_SECS].
+    pub fn notification(title: &str, body: &str, link: &str) -> Self {
+        WebPushConfig {
+            headers: Some(Self::preset_headers("high")),
+            fcm_options: Some(WebPushFcmOptions {
+                link: Some(link.to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+        .with_notification(&WebNotification {
+            title: Some(title.to_string()),
+            body: Some(body.to_string()),
+            ..Default::default()
+        })
+    }
+    fn preset_headers(urgency: &str) -> HashMap<String, String> {
+        HashMap::from_iter([
+            ("TTL".to_string(), Self::DEFAULT_TTL_SECS.to_string()),
+            ("Urgency".to_string(), urgency.to_string()),
+        ])
+    }
+    /// Set web notification options from a typed [WebNotification].
+    pub fn with_notification(mut self, notification: &WebNotification) -> Self {
+        self.notification = serde_json::to_value(notification).ok();
+        self
+    }
+    /// Deep-merge `extra` into the notification options following [JSON Merge Patch](https://www.rfc-editor.org/rfc/rfc7386),
+    /// e.g. to add browser-specific options that [WebNotification] does not cover.
+    /// Nested objects are merged, `null` removes a field and any other value replaces the existing one.
+    ///
+    /// ```
+    /// use firebase_messaging_rs::fcm::webpush::{WebNotification, WebPushConfig};
+    ///
+    /// let config = WebPushConfig::default()
+    ///     .with_notification(&WebNotification {
+    ///         title: Some("title".to_string()),
+    ///         ..Default::default()
+    ///     })
+    ///     .merge_notification_json(serde_json::json!({"data": {"campaign": "spring"}}));
+    /// assert_eq!(
+    ///     config.notification,
+    ///     Some(serde_json::json!({"title": "title", "data": {"campaign": "spring"}}))
+    /// );
+    /// ```
+    pub fn merge_notification_json(mut self, extra: serde_json::Value) -> Self {
+        let notification = self
+            .notification
+            .get_or_insert_with(|| serde_json::Value::Object(Default::default()));
+        merge_patch(notification, extra);
+        self
+    }
+}
+
+fn merge_patch(target: &mut serde_json::Value, patch: serde_json::Value) {
+    match patch {
+        serde_json::Value::Object(patch) => {
+            if !target.is_object() {
+                *target = serde_json::Value::Object(Default::default());
+            }
+            if let serde_json::Value::Object(target) = target {
+                for (key, value) in patch {
+                    if value.is_null() {
+                        target.remove(&key);
+                    } else {
+                        merge_patch(target.entry(key).or_insert(serde_json::Value::Null), value);
+                    }
+                }
+            }
+        }
+        patch => *target = patch,
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Default)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
 pub struct WebPushFcmOptions {
     /// Label associated with the message's analytics data.
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -30,3 +122,142 @@ pub struct WebPushFcmOptions {
     /// The link to open when the user clicks on the notification. For all URL values, HTTPS is required.
     pub link: Option<String>,
 }
+
+/// Typed subset of [Web Notification API](https://developer.mozilla.org/en-US/docs/Web/API/Notification/Notification) options.
+#[derive(Debug, Serialize, Default, Clone)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(rename_all = "camelCase")]
+pub struct WebNotification {
+    /// The title of the notification.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// The body text of the notification.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body: Option<String>,
+    /// URL of an icon to be displayed in the notification.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+    /// URL of an image to be displayed in the notification.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+    /// URL of an image to represent the notification when there is not enough space to display the notification itself.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub badge: Option<String>,
+    /// Identifying tag for the notification. A notification with the same tag replaces the previous one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    /// Language of the notification as a BCP 47 language tag.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lang: Option<String>,
+    /// Text direction of the notification.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dir: Option<WebNotificationDirection>,
+    /// Whether the user should be notified after a new notification replaces an old one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub renotify: Option<bool>,
+    /// Whether the notification should remain active until the user clicks or dismisses it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub require_interaction: Option<bool>,
+    /// Whether the notification should be silent, i.e. no sounds or vibrations.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub silent: Option<bool>,
+    /// Vibration pattern in milliseconds for devices with vibration hardware.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub vibrate: Option<Vec<u32>>,
+    /// Time associated with the notification in milliseconds since UNIX epoch.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+    /// Actions to display in the notification.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub actions: Option<Vec<WebNotificationAction>>,
+}
+
+/// Text direction of [WebNotification].
+#[derive(Debug, Serialize, Clone, Copy)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum WebNotificationDirection {
+    Auto,
+    Ltr,
+    Rtl,
+}
+
+/// An action button displayed in [WebNotification].
+#[derive(Debug, Serialize, Default, Clone)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+pub struct WebNotificationAction {
+    /// Identifier of the action passed to the service worker.
+    pub action: String,
+    /// Text shown to the user.
+    pub title: String,
+    /// URL of an icon to display with the action.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub icon: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{WebNotification, WebNotificationDirection, WebPushConfig};
+    use std::collections::HashMap;
+
+    #[test]
+    fn check_serialization_for_typed_notification() {
+        let config = WebPushConfig::default().with_notification(&WebNotification {
+            title: Some("example".to_string()),
+            require_interaction: Some(true),
+            dir: Some(WebNotificationDirection::Rtl),
+            ..Default::default()
+        });
+        assert_eq!(
+            serde_json::to_value(config).unwrap(),
+            serde_json::json!({
+                "notification": {
+                    "title": "example",
+                    "requireInteraction": true,
+                    "dir": "rtl"
+                }
+            })
+        )
+    }
+
+    #[test]
+    fn extra_notification_json_is_deep_merged() {
+        let config = WebPushConfig::notification("title", "body", "https://example.com")
+            .merge_notification_json(serde_json::json!({
+                "body": null,
+                "data": {"a": 1},
+                "vibrate": [100, 50]
+            }))
+            .merge_notification_json(serde_json::json!({"data": {"b": 2}}));
+        assert_eq!(
+            config.notification,
+            Some(serde_json::json!({
+                "title": "title",
+                "data": {"a": 1, "b": 2},
+                "vibrate": [100, 50]
+            }))
+        );
+    }
+
+    #[test]
+    fn check_serialization_for_presets() {
+        let config = WebPushConfig::notification("title", "body", "https://example.com");
+        assert_eq!(
+            serde_json::to_value(&config).unwrap(),
+            serde_json::json!({
+                "headers": {"TTL": "86400", "Urgency": "high"},
+                "notification": {"title": "title", "body": "body"},
+                "fcm_options": {"link": "https://example.com"},
+            })
+        );
+        let config =
+            WebPushConfig::data_only(HashMap::from_iter([("foo".to_string(), "bar".to_string())]));
+        assert_eq!(
+            serde_json::to_value(&config).unwrap(),
+            serde_json::json!({
+                "headers": {"TTL": "86400", "Urgency": "normal"},
+                "data": {"foo": "bar"},
+            })
+        );
+    }
+}
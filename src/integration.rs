@@ -0,0 +1,11 @@
+//! Thin helpers to wire [crate::FCMClient] into web frameworks.
+//!
+//! [crate::FCMClient] is cheap to clone, so it can be shared as application state as is.
+//! Error types are mapped to HTTP responses from the point of view of a backend calling FCM:
+//! invalid messages are reported as `400 Bad Request`, and upstream failures as `5xx`.
+//! Response bodies contain only the `client_message` of errors, so upstream details are not leaked.
+//! See [crate::fcm::FCMError::status_code] to build responses for other frameworks.
+#[cfg(feature = "actix-web")]
+pub mod actix;
+#[cfg(feature = "axum")]
+pub mod axum;
@@ -0,0 +1,76 @@
+//! [actix-web](https://docs.rs/actix-web) integration.
+//!
+//! Register the client with `App::app_data(client)` or `App::app_data(web::Data::new(client))`
+//! and take [FCMClient] as a handler argument.
+use crate::FCMClient;
+use actix_web::http::StatusCode;
+use actix_web::{
+    dev::Payload, error::ErrorInternalServerError, http::header::RETRY_AFTER, web::Data,
+    FromRequest, HttpRequest, HttpResponse, ResponseError,
+};
+use std::{
+    future::{ready, Ready},
+    time::Duration,
+};
+
+impl FromRequest for FCMClient {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let client = req
+            .app_data::<FCMClient>()
+            .cloned()
+            .or_else(|| {
+                req.app_data::<Data<FCMClient>>()
+                    .map(|data| data.get_ref().clone())
+            })
+            .ok_or_else(|| ErrorInternalServerError("FCMClient is not registered as app data"));
+        ready(client)
+    }
+}
+
+/// actix-web is built on `http` 0.2, so status codes are converted by their number.
+fn status(status: http::StatusCode) -> StatusCode {
+    StatusCode::from_u16(status.as_u16()).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+fn error_response(
+    status: StatusCode,
+    retry_after: Option<Duration>,
+    body: &'static str,
+) -> HttpResponse {
+    let mut builder = HttpResponse::build(status);
+    if let Some(retry_after) = retry_after {
+        builder.insert_header((RETRY_AFTER, retry_after.as_secs().to_string()));
+    }
+    builder.body(body)
+}
+
+#[cfg(feature = "fcm")]
+impl ResponseError for crate::fcm::FCMError {
+    fn status_code(&self) -> StatusCode {
+        status(crate::fcm::FCMError::status_code(self))
+    }
+    fn error_response(&self) -> HttpResponse {
+        error_response(
+            ResponseError::status_code(self),
+            self.retry_after(),
+            self.client_message(),
+        )
+    }
+}
+
+#[cfg(feature = "topic-management")]
+impl ResponseError for crate::topic::TopicManagementError {
+    fn status_code(&self) -> StatusCode {
+        status(crate::topic::TopicManagementError::status_code(self))
+    }
+    fn error_response(&self) -> HttpResponse {
+        error_response(
+            ResponseError::status_code(self),
+            None,
+            self.client_message(),
+        )
+    }
+}
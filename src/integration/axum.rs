@@ -0,0 +1,80 @@
+//! [axum](https://docs.rs/axum) integration.
+//!
+//! ```rust no_run
+//! use axum::{routing::post, Router};
+//! use firebase_messaging_rs::{fcm::*, FCMClient};
+//!
+//! async fn notify(client: FCMClient) -> Result<String, FCMError> {
+//!     let message = Message::Topic {
+//!         topic: "news".to_string(),
+//!         data: None,
+//!         fcm_options: None,
+//!         notification: None,
+//!         android: None,
+//!         webpush: None,
+//!         apns: None,
+//!     };
+//!     client.send(&message).await.map(|output| output.name.into_string())
+//! }
+//!
+//! # async fn run() {
+//! let client = FCMClient::new().await.unwrap();
+//! let app: Router = Router::new().route("/notify", post(notify)).with_state(client);
+//! # }
+//! ```
+use crate::FCMClient;
+use async_trait::async_trait;
+use axum::{
+    extract::{FromRef, FromRequestParts},
+    response::{IntoResponse, Response},
+};
+use http::{header::RETRY_AFTER, request::Parts, StatusCode};
+use std::{convert::Infallible, time::Duration};
+
+/// Extract [FCMClient] from any application state `S` that provides it through [FromRef].
+#[async_trait]
+impl<S> FromRequestParts<S> for FCMClient
+where
+    FCMClient: FromRef<S>,
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(_parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        Ok(FCMClient::from_ref(state))
+    }
+}
+
+fn error_response(
+    status: StatusCode,
+    retry_after: Option<Duration>,
+    body: &'static str,
+) -> Response {
+    match retry_after {
+        Some(retry_after) => (
+            status,
+            [(RETRY_AFTER, retry_after.as_secs().to_string())],
+            body,
+        )
+            .into_response(),
+        None => (status, body).into_response(),
+    }
+}
+
+#[cfg(feature = "fcm")]
+impl IntoResponse for crate::fcm::FCMError {
+    fn into_response(self) -> Response {
+        error_response(
+            self.status_code(),
+            self.retry_after(),
+            self.client_message(),
+        )
+    }
+}
+
+#[cfg(feature = "topic-management")]
+impl IntoResponse for crate::topic::TopicManagementError {
+    fn into_response(self) -> Response {
+        error_response(self.status_code(), None, self.client_message())
+    }
+}
@@ -0,0 +1,178 @@
+//! Hooks that see every request sent by a client and every response it gets.
+//!
+//! Register an [Interceptor] with [crate::FCMClient::with_interceptor] to add custom headers, to audit
+//! raw exchanges, or to answer requests from a cache, without implementing [crate::GenericGoogleRestAPISupport].
+//!
+//! Interceptors run around each attempt, including retries. Requests go through them in the order they were
+//! registered and responses in the reverse order, so the first interceptor registered is the outermost one.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), String> {
+//! use firebase_messaging_rs::{interceptor::Interceptor, FCMClient, RPCError};
+//! use http::{HeaderValue, Request, Response};
+//! use hyper::body::Bytes;
+//! use std::sync::Arc;
+//!
+//! /// Tag every request with the name of the calling service.
+//! struct Tagged;
+//!
+//! impl Interceptor for Tagged {
+//!     fn on_request(&self, request: &mut Request<Bytes>) -> Result<Option<Response<Bytes>>, RPCError> {
+//!         request
+//!             .headers_mut()
+//!             .insert("x-caller", HeaderValue::from_static("billing"));
+//!         Ok(None)
+//!     }
+//! }
+//!
+//! let client = FCMClient::new().await?.with_interceptor(Arc::new(Tagged));
+//! # Ok(())
+//! # }
+//! ```
+use crate::{transport::HttpTransport, RPCError};
+use http::{Method, Request, Response, Uri};
+use hyper::body::Bytes;
+use std::sync::Arc;
+
+/// Inspects or rewrites outgoing requests and observes their responses.
+///
+/// Methods are called on the task that sends the request, so implementations should not block for long.
+pub trait Interceptor: Send + Sync {
+    /// Called before `request` is sent. Headers such as `authorization` are already set.
+    ///
+    /// Return a response to answer the request without sending it, e.g. from a cache,
+    /// or an error to fail the attempt. Later interceptors are skipped in both cases.
+    fn on_request(
+        &self,
+        request: &mut Request<Bytes>,
+    ) -> Result<Option<Response<Bytes>>, RPCError> {
+        let _ = request;
+        Ok(None)
+    }
+    /// Called with the response to a request to `uri`, before its body is decoded.
+    /// Not called when no response is received, e.g. on transport errors.
+    fn on_response(&self, method: &Method, uri: &Uri, response: &Response<Bytes>) {
+        let _ = (method, uri, response);
+    }
+}
+
+/// Send `request` through `interceptors` and then `transport`.
+pub(crate) async fn send<T: HttpTransport + ?Sized>(
+    interceptors: &[Arc<dyn Interceptor>],
+    transport: &T,
+    mut request: Request<Bytes>,
+) -> Result<Response<Bytes>, RPCError> {
+    let mut answered = None;
+    let mut reached = interceptors.len();
+    for (i, interceptor) in interceptors.iter().enumerate() {
+        if let Some(response) = interceptor.on_request(&mut request)? {
+            answered = Some(response);
+            reached = i;
+            break;
+        }
+    }
+    let method = request.method().clone();
+    let uri = request.uri().clone();
+    let response = match answered {
+        Some(response) => response,
+        None if interceptors.is_empty() => return transport.send(request).await,
+        None => transport.send(request).await?,
+    };
+    for interceptor in interceptors[..reached].iter().rev() {
+        interceptor.on_response(&method, &uri, &response);
+    }
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Interceptor;
+    use crate::{transport::HttpTransport, RPCError};
+    use async_trait::async_trait;
+    use http::{Method, Request, Response, StatusCode, Uri};
+    use hyper::body::Bytes;
+    use std::sync::{Arc, Mutex};
+
+    /// Echo the `x-trace` header of the request in the response body.
+    struct Echo;
+
+    #[async_trait]
+    impl HttpTransport for Echo {
+        async fn send(&self, request: Request<Bytes>) -> Result<Response<Bytes>, RPCError> {
+            let trace = request
+                .headers()
+                .get("x-trace")
+                .map(|v| v.as_bytes().to_vec());
+            Ok(Response::new(Bytes::from(trace.unwrap_or_default())))
+        }
+    }
+
+    /// Append its name to `x-trace` and log the responses it sees.
+    struct Named {
+        name: &'static str,
+        cached: bool,
+        log: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl Interceptor for Named {
+        fn on_request(
+            &self,
+            request: &mut Request<Bytes>,
+        ) -> Result<Option<Response<Bytes>>, RPCError> {
+            let trace = match request.headers().get("x-trace") {
+                Some(trace) => format!("{},{}", trace.to_str().unwrap(), self.name),
+                None => self.name.to_string(),
+            };
+            request
+                .headers_mut()
+                .insert("x-trace", trace.parse().unwrap());
+            Ok(self
+                .cached
+                .then(|| Response::new(Bytes::from_static(b"cached"))))
+        }
+        fn on_response(&self, method: &Method, uri: &Uri, response: &Response<Bytes>) {
+            assert_eq!(response.status(), StatusCode::OK);
+            self.log.lock().unwrap().push(format!(
+                "{} {method} {uri} {}",
+                self.name,
+                std::str::from_utf8(response.body()).unwrap()
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn interceptors_wrap_each_other() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let named = |name, cached| -> Arc<dyn Interceptor> {
+            Arc::new(Named {
+                name,
+                cached,
+                log: log.clone(),
+            })
+        };
+        let request = || {
+            Request::get("https://example.com/info")
+                .body(Bytes::new())
+                .unwrap()
+        };
+
+        let interceptors = [named("a", false), named("b", false)];
+        let response = super::send(&interceptors, &Echo, request()).await.unwrap();
+        assert_eq!(response.body().as_ref(), b"a,b");
+        assert_eq!(
+            std::mem::take(&mut *log.lock().unwrap()),
+            [
+                "b GET https://example.com/info a,b",
+                "a GET https://example.com/info a,b"
+            ]
+        );
+
+        let interceptors = [named("a", false), named("cache", true), named("c", false)];
+        let response = super::send(&interceptors, &Echo, request()).await.unwrap();
+        assert_eq!(response.body().as_ref(), b"cached");
+        assert_eq!(
+            *log.lock().unwrap(),
+            ["a GET https://example.com/info cached"]
+        );
+    }
+}
@@ -1,103 +1,1307 @@
+#[cfg(feature = "admin")]
+pub mod admin;
+pub mod audit;
+pub mod auth;
+pub mod circuit_breaker;
+pub mod config;
+#[cfg(all(feature = "hyper-tls", feature = "hyper-rustls"))]
+pub mod connector;
+pub mod dns;
+mod external_account;
+pub mod interceptor;
+pub mod proxy;
+pub mod rate_limit;
+pub mod serde_helpers;
+pub mod stats;
+/// Re-exported to build a configuration for [FCMClientBuilder::rustls_config] with the same version of rustls.
+#[cfg(feature = "hyper-rustls")]
+pub use rustls;
+/// Re-exported for compatibility.
+/// Prefer typed alternatives such as [fcm::webpush::WebNotification] over raw [serde_json::Value]s.
 #[cfg(feature = "fcm")]
 pub use serde_json;
 #[cfg(feature = "fcm")]
 pub mod fcm;
+#[cfg(all(
+    any(feature = "axum", feature = "actix-web"),
+    any(feature = "fcm", feature = "topic-management")
+))]
+pub mod integration;
+#[cfg(feature = "utoipa")]
+pub mod openapi;
+#[cfg(feature = "test-util")]
+pub mod test_util;
 #[cfg(feature = "topic-management")]
 pub mod topic;
+pub mod transport;
 use async_trait::async_trait;
-use gcloud_sdk::{GoogleAuthTokenGenerator, TokenSourceType, GCP_DEFAULT_SCOPES};
+use dns::{GoogleAccess, HttpConnector};
 use http::{
-    header::{ACCEPT, AUTHORIZATION, CONTENT_LENGTH, CONTENT_TYPE},
-    HeaderName, Request, Response, StatusCode,
+    header::{ACCEPT, AUTHORIZATION, CONTENT_LENGTH, CONTENT_TYPE, RETRY_AFTER},
+    HeaderValue, Method, Request, Response, StatusCode,
 };
-use hyper::{client::HttpConnector, Body};
-#[cfg(feature = "hyper-rustls")]
-use hyper_rustls::HttpsConnector;
-#[cfg(feature = "hyper-tls")]
-use hyper_tls::HttpsConnector;
+use http_body_util::Full;
+use hyper::body::Bytes;
+use hyper_util::{
+    client::legacy::{connect::Connect, Client},
+    rt::{TokioExecutor, TokioTimer},
+};
+#[cfg(all(
+    feature = "hyper-rustls",
+    not(any(feature = "rustls-ring", feature = "rustls-aws-lc-rs"))
+))]
+compile_error!("select a rustls crypto provider with `rustls-ring` or `rustls-aws-lc-rs` feature");
+#[cfg(not(any(feature = "hyper-tls", feature = "hyper-rustls")))]
+compile_error!("select a TLS backend with `native-tls` or `rustls` feature");
+#[cfg(all(feature = "hyper-tls", feature = "hyper-rustls"))]
+pub(crate) use connector::HttpsConnector;
+#[cfg(all(feature = "hyper-rustls", not(feature = "hyper-tls")))]
+pub(crate) use hyper_rustls::HttpsConnector;
+#[cfg(all(feature = "hyper-tls", not(feature = "hyper-rustls")))]
+pub(crate) use hyper_tls::HttpsConnector;
 use serde::Deserialize;
-use std::{sync::Arc, time::Duration};
+use std::{
+    collections::BTreeMap,
+    net::{Ipv4Addr, Ipv6Addr},
+    sync::Arc,
+    time::Duration,
+};
+
+/// Connector used by [FCMClient] unless another one is given to [FCMClientBuilder::build_with_connector].
+pub type DefaultConnector = HttpsConnector<proxy::ProxyConnector>;
+
+/// HTTP client used by [FCMClient] unless another [transport::HttpTransport] is given to the builder,
+/// wrapped in [transport::PooledClient].
+pub type HttpClient<C = DefaultConnector> = Client<C, Full<Bytes>>;
 
 #[doc = include_str!("../README.md")]
 #[derive(Clone)]
-pub struct FCMClient {
-    http_client: hyper::Client<HttpsConnector<HttpConnector>>,
-    token_gen: Arc<GoogleAuthTokenGenerator>,
-    project_id: String,
+pub struct FCMClient<T = transport::PooledClient> {
+    transport: T,
+    token_provider: Option<Arc<dyn auth::TokenProvider>>,
+    access_token: Option<auth::SecretString>,
+    project_id: Option<String>,
+    correlation_id: CorrelationId,
+    retry_policy: RetryPolicy,
+    audit_sink: Option<Arc<dyn audit::AuditSink>>,
+    token_observer: Option<Arc<dyn auth::TokenObserver>>,
+    token_expiry: Arc<auth::ExpiryTracker>,
+    stats: Arc<stats::ClientStats>,
+    labels: Labels,
+    gzip_threshold: Option<usize>,
+    request_timeout: Option<Duration>,
+    max_response_size: Option<usize>,
+    rate_limiter: Arc<rate_limit::RateLimiter>,
+    circuit_breaker: Option<Arc<circuit_breaker::CircuitBreaker>>,
+    circuit_observer: Option<Arc<dyn circuit_breaker::CircuitObserver>>,
+    interceptors: Vec<Arc<dyn interceptor::Interceptor>>,
+    fcm_base_url: String,
+    iid_base_url: String,
+    user_agent: String,
+}
+
+/// Key-value pairs such as `platform` or `campaign_id` attached to [audit::AuditRecord]s and [auth::TokenEvent]s,
+/// so that dashboards can slice them. See [FCMClient::with_labels].
+pub type Labels = std::collections::BTreeMap<String, String>;
+
+/// OAuth scope of FCM, requested for access tokens by default. See [FCMClientBuilder::scopes].
+pub const FIREBASE_MESSAGING_SCOPE: &str = "https://www.googleapis.com/auth/firebase.messaging";
+
+/// OAuth scope of Firebase APIs including Instance ID, which manages topic subscriptions.
+/// Requested for access tokens by default together with [FIREBASE_MESSAGING_SCOPE] with `topic-management` feature.
+#[cfg(feature = "topic-management")]
+pub const IID_SCOPE: &str = "https://www.googleapis.com/auth/firebase";
+
+/// OAuth scope of all Google Cloud APIs, the default of gcloud-sdk. Request it with [FCMClient::with_scope]
+/// when tokens of a client are used for other APIs too, e.g. to impersonate a service account.
+pub const CLOUD_PLATFORM_SCOPE: &str = "https://www.googleapis.com/auth/cloud-platform";
+
+/// Base URL of Firebase Cloud Messaging API. See [FCMClientBuilder::fcm_base_url].
+pub const FCM_BASE_URL: &str = "https://fcm.googleapis.com";
+
+/// Base URL of Instance ID API, which manages topic subscriptions. See [FCMClientBuilder::iid_base_url].
+pub const IID_BASE_URL: &str = "https://iid.googleapis.com";
+
+/// `User-Agent` sent by [FCMClient]. Applications can prepend their own with [FCMClientBuilder::user_agent].
+pub const DEFAULT_USER_AGENT: &str = concat!("firebase-messaging-rs/", env!("CARGO_PKG_VERSION"));
+
+/// Header identifying the client library to Google APIs, sent with every request of [FCMClient].
+pub const API_CLIENT_HEADER: &str = "x-goog-api-client";
+
+/// Value of [API_CLIENT_HEADER].
+pub const API_CLIENT: &str = concat!("gccl/", env!("CARGO_PKG_VERSION"));
+
+/// Default of [FCMClientBuilder::pool_idle_timeout], the same as hyper's.
+pub const DEFAULT_POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// Default of [FCMClientBuilder::max_response_size]: 16 MiB.
+pub const DEFAULT_MAX_RESPONSE_SIZE: usize = 16 * 1024 * 1024;
+
+/// Header used to send a correlation ID. See [FCMClient::with_correlation_id].
+pub const CORRELATION_ID_HEADER: &str = "x-request-id";
+
+/// Correlation ID attached to every outgoing request so that it can be traced across services.
+#[derive(Debug, Clone, Default)]
+pub enum CorrelationId {
+    /// Do not attach a correlation ID.
+    #[default]
+    Disabled,
+    /// Generate a new UUID v4 for each request. Retries of a request reuse its ID.
+    Generated,
+    /// Send the given ID with every request.
+    Fixed(String),
+}
+
+impl CorrelationId {
+    fn next(&self) -> Option<String> {
+        match self {
+            Self::Disabled => None,
+            Self::Generated => Some(uuid::Uuid::new_v4().to_string()),
+            Self::Fixed(id) => Some(id.clone()),
+        }
+    }
+}
+
+impl<T> std::fmt::Debug for FCMClient<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FCMClient")
+            .field("project_id", &self.project_id)
+            .field("correlation_id", &self.correlation_id)
+            .field("labels", &self.labels)
+            .field("retry_policy", &self.retry_policy)
+            .field("audit_sink", &self.audit_sink.is_some())
+            .field("token_observer", &self.token_observer.is_some())
+            .field("stats", &self.stats)
+            .field("gzip_threshold", &self.gzip_threshold)
+            .field("request_timeout", &self.request_timeout)
+            .field("max_response_size", &self.max_response_size)
+            .field("rate_limiter", &self.rate_limiter)
+            .field("circuit_breaker", &self.circuit_breaker)
+            .field("circuit_observer", &self.circuit_observer.is_some())
+            .field("interceptors", &self.interceptors.len())
+            .field("fcm_base_url", &self.fcm_base_url)
+            .field("iid_base_url", &self.iid_base_url)
+            .field("user_agent", &self.user_agent)
+            .field("token_provider", &self.token_provider)
+            .field(
+                "access_token",
+                &self.access_token.as_ref().map(|_| Redacted),
+            )
+            .finish_non_exhaustive()
+    }
+}
+
+/// Placeholder shown by [std::fmt::Debug] implementations in place of credentials and registration tokens.
+pub(crate) struct Redacted;
+
+impl std::fmt::Debug for Redacted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
+/// Turn an authorization header value into a [HeaderValue] that is hidden from its [std::fmt::Debug] output.
+/// With `zeroize` feature, the source string is wiped before it is released.
+fn sensitive_header_value(value: String) -> Result<HeaderValue, RPCError> {
+    let header = HeaderValue::from_str(&value)
+        .map(|mut header| {
+            header.set_sensitive(true);
+            header
+        })
+        .map_err(|e| RPCError::BuildRequestFailure(format!("{e:?}")));
+    #[cfg(feature = "zeroize")]
+    {
+        let mut value = value;
+        zeroize::Zeroize::zeroize(&mut value);
+    }
+    header
 }
 
 impl FCMClient {
-    #[cfg(feature = "fcm")]
     fn google_cloud_project() -> Option<String> {
         std::env::var("GOOGLE_CLOUD_PROJECT")
             .or_else(|_| std::env::var("GCP_PROJECT"))
             .ok()
     }
     /// Create an instance of FCMClient.
+    /// The project id is detected from `GOOGLE_CLOUD_PROJECT` env var. It is required only to send messages,
+    /// so topic management works without it.
     pub async fn new() -> Result<Self, String> {
-        #[cfg(feature = "fcm")]
-        let project_id = Self::google_cloud_project().ok_or(
-            "Cannot detect google project id from env. Provide project id by GOOGLE_CLOUD_PROJECT env var.".to_string(),
-        )?;
-        #[cfg(not(feature = "fcm"))]
-        let project_id = "dummy id for compatibility".to_string();
-        FCMClient::with_scope(&project_id, &GCP_DEFAULT_SCOPES).await
+        FCMClient::builder().build().await
     }
     pub async fn new_with_project(project_id: &str) -> Result<Self, String> {
-        FCMClient::with_scope(project_id, &GCP_DEFAULT_SCOPES).await
+        FCMClient::builder().project_id(project_id).build().await
     }
 
-    /// Create an instance of FCMClient with scopes.
+    /// Create an instance of FCMClient with scopes instead of the default ones.
     /// The scopes could be
-    /// - `"https://www.googleapis.com/auth/firebase.messaging"`
-    /// - `"https://www.googleapis.com/auth/cloud-platform"`
+    /// - [FIREBASE_MESSAGING_SCOPE]
+    /// - [IID_SCOPE], for topic management
+    /// - [CLOUD_PLATFORM_SCOPE], which older versions of this crate requested by default
     pub async fn with_scope(project_id: &str, scopes: &[String]) -> Result<Self, String> {
-        #[cfg(feature = "hyper-tls")]
-        let connector = HttpsConnector::new();
-
-        #[cfg(feature = "hyper-rustls")]
-        let connector = hyper_rustls::HttpsConnectorBuilder::new()
-            .with_native_roots()
-            .map_err(|_| "unable to load native roots for https connector".to_string())?
-            .https_or_http()
-            .enable_http1()
-            .build();
+        FCMClient::builder()
+            .project_id(project_id)
+            .scopes(scopes)
+            .build()
+            .await
+    }
 
-        let token_gen = GoogleAuthTokenGenerator::new(TokenSourceType::Default, scopes.to_vec())
+    /// Create an instance of FCMClient that gets access tokens from `source` with the default scopes,
+    /// e.g. a service account key file managed by the application.
+    ///
+    /// ```no_run
+    /// # async fn run() -> Result<(), String> {
+    /// use firebase_messaging_rs::{auth::TokenSource, FCMClient};
+    ///
+    /// let source = TokenSource::File("/etc/secrets/fcm-sender.json".into());
+    /// let client = FCMClient::with_token_source("my-project", source).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn with_token_source(
+        project_id: &str,
+        source: auth::TokenSource,
+    ) -> Result<Self, String> {
+        FCMClient::builder()
+            .project_id(project_id)
+            .token_source(source)
+            .build()
             .await
-            .map_err(|_| "unable to initialize token generator")?;
-        Ok(Self {
-            token_gen: Arc::new(token_gen),
-            http_client: hyper::Client::builder().build::<_, Body>(connector),
-            project_id: project_id.to_string(),
+    }
+
+    /// Create an instance of FCMClient from the content of a service account key,
+    /// e.g. fetched from a secret manager, without writing it to disk.
+    /// The project id is taken from the `project_id` field of the key.
+    ///
+    /// ```no_run
+    /// # async fn run(key: String) -> Result<(), String> {
+    /// use firebase_messaging_rs::FCMClient;
+    ///
+    /// let client = FCMClient::from_service_account_json(&key).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn from_service_account_json(json: impl AsRef<[u8]>) -> Result<Self, String> {
+        #[derive(serde::Deserialize)]
+        struct Key {
+            project_id: Option<String>,
+        }
+        let json = std::str::from_utf8(json.as_ref())
+            .map_err(|e| format!("service account key is not valid UTF-8: {e}"))?;
+        let key: Key = serde_json::from_str(json)
+            .map_err(|e| format!("service account key is not valid JSON: {e}"))?;
+        let mut builder =
+            FCMClient::builder().token_source(auth::TokenSource::Json(json.to_string()));
+        if let Some(project_id) = key.project_id {
+            builder = builder.project_id(&project_id);
+        }
+        builder.build().await
+    }
+
+    /// Start configuring an FCMClient, including options for the underlying connector.
+    pub fn builder() -> FCMClientBuilder {
+        FCMClientBuilder::default()
+    }
+}
+
+impl<T> FCMClient<T> {
+    /// Retry failed requests according to `retry_policy`.
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Report every operation sent by this client to `sink`. See [audit].
+    pub fn with_audit_sink(mut self, sink: Arc<dyn audit::AuditSink>) -> Self {
+        self.audit_sink = Some(sink);
+        self
+    }
+
+    /// Notify `observer` of every access token lookup. See [auth].
+    pub fn with_token_observer(mut self, observer: Arc<dyn auth::TokenObserver>) -> Self {
+        self.token_observer = Some(observer);
+        self
+    }
+
+    /// Notify `observer` when a circuit opens or closes. See [circuit_breaker].
+    pub fn with_circuit_observer(
+        mut self,
+        observer: Arc<dyn circuit_breaker::CircuitObserver>,
+    ) -> Self {
+        self.circuit_observer = Some(observer);
+        self
+    }
+
+    /// Pass every request and response of this client through `interceptor`, after the ones already added.
+    /// See [interceptor].
+    pub fn with_interceptor(mut self, interceptor: Arc<dyn interceptor::Interceptor>) -> Self {
+        self.interceptors.push(interceptor);
+        self
+    }
+
+    /// Add labels to every record reported by this client. Existing labels with the same key are overwritten.
+    ///
+    /// The client is cheap to clone, so per-call labels can be attached to a copy:
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "fcm")]
+    /// # async fn run(client: &firebase_messaging_rs::FCMClient, message: firebase_messaging_rs::fcm::Message) {
+    /// use firebase_messaging_rs::fcm::FCMApi;
+    ///
+    /// let _ = client
+    ///     .clone()
+    ///     .with_labels([("campaign_id", "spring-sale")])
+    ///     .send(&message)
+    ///     .await;
+    /// # }
+    /// ```
+    pub fn with_labels<I, K, V>(mut self, labels: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.labels
+            .extend(labels.into_iter().map(|(k, v)| (k.into(), v.into())));
+        self
+    }
+
+    /// Authorize requests with `access_token`, an OAuth 2.0 access token minted elsewhere, instead of generating one.
+    /// Token events are not reported for such requests, and the token is not refreshed when it expires.
+    /// With `zeroize` feature, the token is wiped from memory when the client holding it is dropped.
+    ///
+    /// Attach it to a copy of the client to use it for a single call, e.g. a token handed out by a central auth service.
+    /// Combine with [FCMClientBuilder::without_credentials] so that the process never loads credentials:
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "fcm")]
+    /// # async fn run(message: firebase_messaging_rs::fcm::Message, access_token: &str) -> Result<(), String> {
+    /// use firebase_messaging_rs::{fcm::FCMApi, FCMClient};
+    ///
+    /// let client = FCMClient::builder()
+    ///     .project_id("my-project")
+    ///     .without_credentials()
+    ///     .build()
+    ///     .await?;
+    /// let _ = client.clone().with_access_token(access_token).send(&message).await;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_access_token(mut self, access_token: &str) -> Self {
+        self.access_token = Some(auth::SecretString::from(access_token.to_string()));
+        self
+    }
+
+    /// Get access tokens from `provider` instead of the credentials the client was built with.
+    /// See [auth::TokenProvider].
+    pub fn with_token_provider(mut self, provider: Arc<dyn auth::TokenProvider>) -> Self {
+        self.token_provider = Some(provider);
+        self
+    }
+
+    /// Send requests to another Firebase project, sharing connections, credentials and [stats] with this client.
+    /// The credentials must be authorized for `project_id`. [stats::QuotaBudget] and [rate_limit::RateLimit]s
+    /// are tracked separately for each project.
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "fcm")]
+    /// # async fn run(client: &firebase_messaging_rs::FCMClient, message: firebase_messaging_rs::fcm::Message) {
+    /// use firebase_messaging_rs::fcm::FCMApi;
+    ///
+    /// let tenant = client.clone().with_project_id("tenant-project");
+    /// let _ = tenant.send(&message).await;
+    /// # }
+    /// ```
+    pub fn with_project_id(mut self, project_id: &str) -> Self {
+        self.project_id = Some(project_id.to_string());
+        self
+    }
+
+    /// Counters accumulated since the client was built, shared by all its clones. See [stats].
+    pub fn stats(&self) -> stats::StatsSnapshot {
+        self.stats.snapshot()
+    }
+
+    /// Attach a correlation ID to every request sent by this client.
+    /// The ID is sent as [CORRELATION_ID_HEADER] and written to `log` output together with failures.
+    /// It is also kept in the [RequestContext] of errors and in [audit::AuditRecord]s.
+    pub fn with_correlation_id(mut self, correlation_id: CorrelationId) -> Self {
+        self.correlation_id = correlation_id;
+        self
+    }
+}
+
+impl<T: transport::HttpTransport + Clone + 'static> FCMClient<T> {
+    /// Send requests as the service account `target`, e.g. `sender@my-project.iam.gserviceaccount.com`,
+    /// with access tokens minted from the credentials of this client, so that no key of `target` is needed.
+    ///
+    /// The identity of this client needs `roles/iam.serviceAccountTokenCreator` on `target`, and its tokens
+    /// the [CLOUD_PLATFORM_SCOPE]. `scopes` are those of the impersonated tokens, e.g. [FIREBASE_MESSAGING_SCOPE].
+    ///
+    /// ```no_run
+    /// # async fn run() -> Result<(), String> {
+    /// use firebase_messaging_rs::{FCMClient, CLOUD_PLATFORM_SCOPE, FIREBASE_MESSAGING_SCOPE};
+    ///
+    /// let client = FCMClient::with_scope("my-project", &[CLOUD_PLATFORM_SCOPE.to_string()])
+    ///     .await?
+    ///     .with_impersonated_account(
+    ///         "sender@my-project.iam.gserviceaccount.com",
+    ///         &[FIREBASE_MESSAGING_SCOPE.to_string()],
+    ///     );
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn with_impersonated_account(mut self, target: &str, scopes: &[String]) -> Self {
+        let provider = auth::ImpersonatedTokenProvider::new(
+            self.token_provider.take(),
+            Arc::new(self.transport.clone()),
+            auth::generate_access_token_url(target),
+            scopes,
+            &self.user_agent,
+        );
+        self.token_provider = Some(Arc::new(provider));
+        self
+    }
+}
+
+/// Address family used to connect to Google APIs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IpPreference {
+    /// Use every resolved address, falling back between IPv6 and IPv4 ("Happy Eyeballs").
+    #[default]
+    Any,
+    /// Connect over IPv4 only.
+    V4Only,
+    /// Connect over IPv6 only.
+    V6Only,
+}
+
+/// TLS library used to connect to Google APIs. See [FCMClientBuilder::tls_backend].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TlsBackend {
+    /// The platform TLS library, enabled by `native-tls` feature.
+    NativeTls,
+    /// rustls, enabled by `rustls`, `rustls-ring` or `rustls-aws-lc-rs` feature.
+    Rustls,
+}
+
+/// Builder for [FCMClient]. Create one with [FCMClient::builder].
+///
+/// ```no_run
+/// # async fn run() -> Result<(), String> {
+/// use firebase_messaging_rs::{FCMClient, IpPreference};
+///
+/// let client = FCMClient::builder()
+///     .project_id("my-project")
+///     .ip_preference(IpPreference::V4Only)
+///     .build()
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct FCMClientBuilder {
+    project_id: Option<String>,
+    scopes: Vec<String>,
+    ip_preference: IpPreference,
+    happy_eyeballs_timeout: Option<Duration>,
+    tcp_keepalive: Option<Duration>,
+    keep_alive_interval: Option<Duration>,
+    pool_idle_timeout: Option<Duration>,
+    max_connection_lifetime: Option<Duration>,
+    tcp_nodelay: bool,
+    http2: bool,
+    connect_timeout: Option<Duration>,
+    request_timeout: Option<Duration>,
+    max_response_size: Option<usize>,
+    correlation_id: CorrelationId,
+    retry_policy: RetryPolicy,
+    quota_budget: Option<stats::QuotaBudget>,
+    rate_limits: BTreeMap<rate_limit::EndpointClass, rate_limit::RateLimit>,
+    circuit_breaker: Option<circuit_breaker::CircuitBreakerPolicy>,
+    fcm_base_url: Option<String>,
+    iid_base_url: Option<String>,
+    user_agent: Option<String>,
+    labels: Labels,
+    load_credentials: bool,
+    token_source: auth::TokenSource,
+    token_provider: Option<Arc<dyn auth::TokenProvider>>,
+    tls_backend: Option<TlsBackend>,
+    #[cfg(feature = "hyper-rustls")]
+    rustls_config: Option<Arc<rustls::ClientConfig>>,
+    gzip_threshold: Option<usize>,
+    google_access: GoogleAccess,
+    resolver: Option<Arc<dyn dns::Resolve>>,
+    proxy: ProxySetting,
+}
+
+/// Proxy of [FCMClientBuilder], resolved when the client is built.
+#[derive(Debug, Clone, Default)]
+enum ProxySetting {
+    #[default]
+    FromEnv,
+    Disabled,
+    Explicit(proxy::Proxy),
+}
+
+/// [FIREBASE_MESSAGING_SCOPE], plus [IID_SCOPE] when topic management is compiled in.
+fn default_scopes() -> Vec<String> {
+    vec![
+        FIREBASE_MESSAGING_SCOPE.to_string(),
+        #[cfg(feature = "topic-management")]
+        IID_SCOPE.to_string(),
+    ]
+}
+
+impl Default for FCMClientBuilder {
+    fn default() -> Self {
+        Self {
+            project_id: None,
+            scopes: default_scopes(),
+            ip_preference: IpPreference::default(),
+            happy_eyeballs_timeout: Some(Duration::from_millis(300)),
+            tcp_keepalive: None,
+            keep_alive_interval: None,
+            pool_idle_timeout: Some(DEFAULT_POOL_IDLE_TIMEOUT),
+            max_connection_lifetime: None,
+            tcp_nodelay: false,
+            http2: true,
+            connect_timeout: None,
+            request_timeout: None,
+            max_response_size: Some(DEFAULT_MAX_RESPONSE_SIZE),
+            correlation_id: CorrelationId::default(),
+            retry_policy: RetryPolicy::default(),
+            quota_budget: None,
+            rate_limits: BTreeMap::new(),
+            circuit_breaker: None,
+            fcm_base_url: None,
+            iid_base_url: None,
+            user_agent: None,
+            labels: Labels::new(),
+            load_credentials: true,
+            token_source: auth::TokenSource::default(),
+            token_provider: None,
+            tls_backend: None,
+            #[cfg(feature = "hyper-rustls")]
+            rustls_config: None,
+            gzip_threshold: None,
+            google_access: GoogleAccess::default(),
+            resolver: None,
+            proxy: ProxySetting::default(),
+        }
+    }
+}
+
+impl FCMClientBuilder {
+    /// Google Cloud project id. Detected from `GOOGLE_CLOUD_PROJECT` env var when omitted.
+    /// It is required only by [fcm::FCMApi], which fails with [RPCError::BuildRequestFailure] without it.
+    pub fn project_id(mut self, project_id: &str) -> Self {
+        self.project_id = Some(project_id.to_string());
+        self
+    }
+    /// OAuth scopes requested for access tokens. Defaults to [FIREBASE_MESSAGING_SCOPE],
+    /// plus [IID_SCOPE] with `topic-management` feature.
+    pub fn scopes(mut self, scopes: &[String]) -> Self {
+        self.scopes = scopes.to_vec();
+        self
+    }
+    /// Restrict connections to an address family. Defaults to [IpPreference::Any].
+    pub fn ip_preference(mut self, ip_preference: IpPreference) -> Self {
+        self.ip_preference = ip_preference;
+        self
+    }
+    /// How long to wait for the preferred address family before racing the other one.
+    /// `None` disables the fallback race. Defaults to 300ms.
+    /// It has no effect unless [IpPreference::Any] is used.
+    pub fn happy_eyeballs_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.happy_eyeballs_timeout = timeout;
+        self
+    }
+    /// Send TCP keepalive probes on idle connections after `interval`. Disabled by default.
+    /// Enable it when connections pass through NATs or firewalls that drop idle flows silently.
+    pub fn tcp_keepalive(mut self, interval: Option<Duration>) -> Self {
+        self.tcp_keepalive = interval;
+        self
+    }
+    /// Send HTTP/2 PINGs every `interval`, also while no request is in flight, and close connections
+    /// whose PINGs are not answered within 20 seconds. Disabled by default.
+    /// Connections that a middlebox dropped silently are then replaced before the next send instead of failing it.
+    /// HTTP/1.1 connections get TCP keepalive probes every `interval` instead, unless [FCMClientBuilder::tcp_keepalive] is set.
+    pub fn keep_alive_interval(mut self, interval: Option<Duration>) -> Self {
+        self.keep_alive_interval = interval;
+        self
+    }
+    /// Close pooled connections that have been idle for `timeout`, or never with `None`.
+    /// Defaults to [DEFAULT_POOL_IDLE_TIMEOUT]. Keep it below the idle timeout of NATs and load balancers on the way,
+    /// so that connections are recycled before they are dropped silently.
+    pub fn pool_idle_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.pool_idle_timeout = timeout;
+        self
+    }
+    /// Stop reusing connections once they are `lifetime` old, even if they are busy. Disabled by default.
+    /// The whole connection pool is replaced then: requests in flight complete on their connections,
+    /// which are closed afterwards, and later requests open new ones.
+    /// It applies to clients built with [FCMClientBuilder::build] and [FCMClientBuilder::build_with_connector].
+    pub fn max_connection_lifetime(mut self, lifetime: Option<Duration>) -> Self {
+        self.max_connection_lifetime = lifetime;
+        self
+    }
+    /// Set `TCP_NODELAY` on new connections. Defaults to `false`.
+    pub fn tcp_nodelay(mut self, nodelay: bool) -> Self {
+        self.tcp_nodelay = nodelay;
+        self
+    }
+    /// Offer HTTP/2 with ALPN when connecting over TLS. Defaults to `true`.
+    /// Google APIs accept it, so concurrent requests of an FCMClient and its clones are multiplexed
+    /// as streams over a shared connection. With `false`, HTTP/1.1 is used and each request in flight
+    /// takes a connection of its own.
+    pub fn http2(mut self, enabled: bool) -> Self {
+        self.http2 = enabled;
+        self
+    }
+    /// Give up establishing a TCP connection after `timeout`.
+    /// This is separate from the time spent waiting for a response. No timeout by default.
+    pub fn connect_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+    /// Give up each attempt of a request after `timeout`, including the time to get an access token,
+    /// to connect and to read the response. Attempts that time out fail with [RPCError::Timeout]
+    /// and are retried like transport errors. See [RetryPolicy::deadline] to bound all attempts together.
+    /// No timeout by default.
+    pub fn request_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.request_timeout = timeout;
+        self
+    }
+    /// Fail requests with [RPCError::ResponseTooLarge] instead of buffering response bodies larger than `max_size` bytes,
+    /// e.g. detailed token info of a token subscribed to thousands of topics. Compressed bodies are limited
+    /// once decompressed as well. Defaults to [DEFAULT_MAX_RESPONSE_SIZE]. `None` accepts bodies of any size.
+    pub fn max_response_size(mut self, max_size: Option<usize>) -> Self {
+        self.max_response_size = max_size;
+        self
+    }
+    /// See [FCMClient::with_correlation_id].
+    pub fn correlation_id(mut self, correlation_id: CorrelationId) -> Self {
+        self.correlation_id = correlation_id;
+        self
+    }
+    /// See [FCMClient::with_retry_policy].
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+    /// Warn when requests approach `budget`. Usage is reported by [FCMClient::stats].
+    pub fn quota_budget(mut self, budget: stats::QuotaBudget) -> Self {
+        self.quota_budget = Some(budget);
+        self
+    }
+    /// Delay requests of `class` so that they do not exceed `limit`, instead of letting Google APIs reject them
+    /// with `429 Too Many Requests`. Requests are not limited by default. See [rate_limit].
+    pub fn rate_limit(
+        mut self,
+        class: rate_limit::EndpointClass,
+        limit: rate_limit::RateLimit,
+    ) -> Self {
+        self.rate_limits.insert(class, limit);
+        self
+    }
+    /// Fail requests fast with [RPCError::CircuitOpen] while a host keeps returning server errors, according to `policy`.
+    /// Disabled by default. See [circuit_breaker].
+    pub fn circuit_breaker(
+        mut self,
+        policy: Option<circuit_breaker::CircuitBreakerPolicy>,
+    ) -> Self {
+        self.circuit_breaker = policy;
+        self
+    }
+    /// Do not load credentials. Every request must then be authorized with [FCMClient::with_access_token],
+    /// otherwise it fails with [RPCError::Unauthorized].
+    pub fn without_credentials(mut self) -> Self {
+        self.load_credentials = false;
+        self
+    }
+    /// Get access tokens from `source` instead of looking for Application Default Credentials.
+    pub fn token_source(mut self, source: auth::TokenSource) -> Self {
+        self.token_source = source;
+        self.token_provider = None;
+        self.load_credentials = true;
+        self
+    }
+    /// Get access tokens from `provider` instead of loading credentials. See [auth::TokenProvider].
+    pub fn token_provider(mut self, provider: Arc<dyn auth::TokenProvider>) -> Self {
+        self.token_provider = Some(provider);
+        self.load_credentials = true;
+        self
+    }
+    /// Choose the TLS library when both `native-tls` and `rustls` features are enabled.
+    /// Defaults to [TlsBackend::NativeTls] in that case, otherwise to the only one enabled.
+    /// [FCMClientBuilder::build] fails if `backend` is not enabled.
+    pub fn tls_backend(mut self, backend: TlsBackend) -> Self {
+        self.tls_backend = Some(backend);
+        self
+    }
+    /// Connect with `config` instead of the default rustls configuration, which trusts the native root certificates.
+    /// Use it to trust a custom CA or bundled roots such as `webpki-roots` in containers without a CA bundle,
+    /// or to present a client certificate. ALPN protocols of `config` are replaced according to [FCMClientBuilder::http2].
+    ///
+    /// It selects [TlsBackend::Rustls] unless [FCMClientBuilder::tls_backend] chooses another one.
+    ///
+    /// ```no_run
+    /// # async fn run(ca: firebase_messaging_rs::rustls::pki_types::CertificateDer<'static>) -> Result<(), String> {
+    /// use firebase_messaging_rs::{rustls, FCMClient};
+    ///
+    /// let mut roots = rustls::RootCertStore::empty();
+    /// roots.add(ca).map_err(|e| e.to_string())?;
+    /// let config = rustls::ClientConfig::builder()
+    ///     .with_root_certificates(roots)
+    ///     .with_no_client_auth();
+    /// let client = FCMClient::builder()
+    ///     .project_id("my-project")
+    ///     .rustls_config(config)
+    ///     .build()
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(feature = "hyper-rustls")]
+    pub fn rustls_config(mut self, config: rustls::ClientConfig) -> Self {
+        self.rustls_config = Some(Arc::new(config));
+        self
+    }
+    /// Compress request bodies of at least `min_bytes` with gzip and send them with `Content-Encoding: gzip`.
+    /// Google APIs accept gzip-encoded bodies, which mostly pays off for large topic batches. Disabled by default.
+    #[cfg(feature = "gzip")]
+    pub fn gzip_requests(mut self, min_bytes: Option<usize>) -> Self {
+        self.gzip_threshold = min_bytes;
+        self
+    }
+    /// Reach Google APIs through Private Google Access or VPC Service Controls addresses.
+    /// Defaults to [GoogleAccess::Public]. Access tokens are fetched by gcloud-sdk, which resolves hosts on its own.
+    pub fn google_access(mut self, access: GoogleAccess) -> Self {
+        self.google_access = access;
+        self
+    }
+    /// Resolve host names with `resolver` instead of `getaddrinfo`, e.g. with a [dns::StaticResolver]
+    /// in split-horizon setups. Host names of [GoogleAccess] are resolved with it as well. See [dns].
+    /// Access tokens are fetched by gcloud-sdk, which resolves hosts on its own.
+    pub fn resolver(mut self, resolver: Arc<dyn dns::Resolve>) -> Self {
+        self.resolver = Some(resolver);
+        self
+    }
+    /// Tunnel connections to Google APIs through `proxy`, or connect directly with `None`.
+    /// By default, `HTTPS_PROXY` and `NO_PROXY` environment variables are honored. See [proxy::Proxy::from_env].
+    ///
+    /// NOTE
+    ///
+    /// Access tokens of service account keys, user credentials and the metadata server are fetched by gcloud-sdk, which ignores this setting and honors
+    /// `HTTPS_PROXY` and `NO_PROXY` only. Behind an egress-only proxy, set these environment variables as well,
+    /// or supply tokens with [FCMClientBuilder::token_provider]. Token exchanges of [FCMClient::with_impersonated_account]
+    /// and workload identity federation are sent through this proxy.
+    pub fn proxy(mut self, proxy: Option<proxy::Proxy>) -> Self {
+        self.proxy = match proxy {
+            Some(proxy) => ProxySetting::Explicit(proxy),
+            None => ProxySetting::Disabled,
+        };
+        self
+    }
+    /// Send FCM requests to `url` instead of [FCM_BASE_URL], e.g. a stub server in tests or a private API gateway.
+    /// Paths such as `/v1/projects/{project_id}/messages:send` are appended to it.
+    /// [FCMClientBuilder::build] fails unless it is an absolute `http` or `https` URL.
+    pub fn fcm_base_url(mut self, url: &str) -> Self {
+        self.fcm_base_url = Some(url.to_string());
+        self
+    }
+    /// Send topic management requests to `url` instead of [IID_BASE_URL]. See [FCMClientBuilder::fcm_base_url].
+    pub fn iid_base_url(mut self, url: &str) -> Self {
+        self.iid_base_url = Some(url.to_string());
+        self
+    }
+    /// Identify the application, e.g. `my-app/1.2`, at the start of the `User-Agent` header, before [DEFAULT_USER_AGENT].
+    /// [FCMClientBuilder::build] fails if it is not a valid header value.
+    pub fn user_agent(mut self, application: &str) -> Self {
+        self.user_agent = Some(application.to_string());
+        self
+    }
+    /// Add a label. See [FCMClient::with_labels].
+    pub fn label(mut self, key: &str, value: &str) -> Self {
+        self.labels.insert(key.to_string(), value.to_string());
+        self
+    }
+
+    /// TCP connector configured by this builder, e.g. to be wrapped by a connector given to [FCMClientBuilder::build_with_connector].
+    pub fn http_connector(&self) -> HttpConnector {
+        let mut resolver = dns::Resolver::new(self.google_access.clone());
+        if let Some(custom) = &self.resolver {
+            resolver = resolver.with_custom(custom.clone());
+        }
+        let mut http = HttpConnector::new_with_resolver(resolver);
+        http.enforce_http(false);
+        http.set_happy_eyeballs_timeout(self.happy_eyeballs_timeout);
+        http.set_keepalive(self.tcp_keepalive.or(self.keep_alive_interval));
+        http.set_nodelay(self.tcp_nodelay);
+        http.set_connect_timeout(self.connect_timeout);
+        // hyper only dials addresses of the family of the configured local address.
+        match self.ip_preference {
+            IpPreference::Any => {}
+            IpPreference::V4Only => http.set_local_address(Some(Ipv4Addr::UNSPECIFIED.into())),
+            IpPreference::V6Only => http.set_local_address(Some(Ipv6Addr::UNSPECIFIED.into())),
+        }
+        http
+    }
+
+    /// Crypto provider selected by features. aws-lc-rs takes precedence when both are enabled.
+    #[cfg(feature = "hyper-rustls")]
+    fn crypto_provider() -> rustls::crypto::CryptoProvider {
+        #[cfg(feature = "rustls-aws-lc-rs")]
+        return rustls::crypto::aws_lc_rs::default_provider();
+        #[cfg(not(feature = "rustls-aws-lc-rs"))]
+        rustls::crypto::ring::default_provider()
+    }
+
+    /// TCP connector configured by this builder, tunneling through the proxy if any. See [FCMClientBuilder::proxy].
+    pub fn proxy_connector(&self) -> proxy::ProxyConnector {
+        let proxy = match &self.proxy {
+            ProxySetting::FromEnv => proxy::Proxy::from_env(),
+            ProxySetting::Disabled => None,
+            ProxySetting::Explicit(proxy) => Some(proxy.clone()),
+        };
+        proxy::ProxyConnector::new(self.http_connector(), proxy)
+    }
+
+    #[cfg(feature = "hyper-tls")]
+    fn native_tls_connector(
+        &self,
+    ) -> Result<hyper_tls::HttpsConnector<proxy::ProxyConnector>, String> {
+        let mut tls = hyper_tls::native_tls::TlsConnector::builder();
+        if self.http2 {
+            tls.request_alpns(&["h2", "http/1.1"]);
+        }
+        let tls = tls
+            .build()
+            .map_err(|e| format!("unable to create TLS context for https connector: {e}"))?;
+        Ok(hyper_tls::HttpsConnector::from((
+            self.proxy_connector(),
+            tls.into(),
+        )))
+    }
+
+    #[cfg(feature = "hyper-rustls")]
+    fn rustls_connector(
+        &self,
+    ) -> Result<hyper_rustls::HttpsConnector<proxy::ProxyConnector>, String> {
+        let builder = match &self.rustls_config {
+            Some(config) => {
+                let mut config = rustls::ClientConfig::clone(config);
+                config.alpn_protocols.clear();
+                hyper_rustls::HttpsConnectorBuilder::new().with_tls_config(config)
+            }
+            None => hyper_rustls::HttpsConnectorBuilder::new()
+                .with_provider_and_native_roots(Self::crypto_provider())
+                .map_err(|_| "unable to load native roots for https connector".to_string())?,
+        }
+        .https_or_http();
+        let connector = if self.http2 {
+            builder
+                .enable_all_versions()
+                .wrap_connector(self.proxy_connector())
+        } else {
+            builder
+                .enable_http1()
+                .wrap_connector(self.proxy_connector())
+        };
+        Ok(connector)
+    }
+
+    fn https_connector(&self) -> Result<DefaultConnector, String> {
+        #[cfg(all(feature = "hyper-tls", feature = "hyper-rustls"))]
+        let connector = match self.tls_backend {
+            None if self.rustls_config.is_some() => {
+                HttpsConnector::Rustls(self.rustls_connector()?)
+            }
+            Some(TlsBackend::NativeTls) | None => {
+                HttpsConnector::NativeTls(self.native_tls_connector()?)
+            }
+            Some(TlsBackend::Rustls) => HttpsConnector::Rustls(self.rustls_connector()?),
+        };
+
+        #[cfg(all(feature = "hyper-tls", not(feature = "hyper-rustls")))]
+        let connector = match self.tls_backend {
+            Some(TlsBackend::NativeTls) | None => self.native_tls_connector()?,
+            Some(TlsBackend::Rustls) => {
+                return Err("rustls backend requires `rustls` feature".to_string())
+            }
+        };
+
+        #[cfg(all(feature = "hyper-rustls", not(feature = "hyper-tls")))]
+        let connector = match self.tls_backend {
+            Some(TlsBackend::Rustls) | None => self.rustls_connector()?,
+            Some(TlsBackend::NativeTls) => {
+                return Err("native-tls backend requires `native-tls` feature".to_string())
+            }
+        };
+
+        Ok(connector)
+    }
+
+    /// HTTP client configured by this builder, e.g. to be wrapped by a transport given to [FCMClientBuilder::build_with_transport].
+    /// Its connections are not subject to [FCMClientBuilder::max_connection_lifetime].
+    pub fn http_client(&self) -> Result<HttpClient, String> {
+        let connector = self.https_connector()?;
+        Ok(self.client_builder().build(connector))
+    }
+
+    /// Builder of [HttpClient] with the connection pool and keep-alive options of this builder.
+    fn client_builder(&self) -> hyper_util::client::legacy::Builder {
+        let mut builder = Client::builder(TokioExecutor::new());
+        builder
+            .timer(TokioTimer::new())
+            .pool_timer(TokioTimer::new())
+            .pool_idle_timeout(self.pool_idle_timeout);
+        if let Some(interval) = self.keep_alive_interval {
+            builder
+                .http2_keep_alive_interval(interval)
+                .http2_keep_alive_while_idle(true);
+        }
+        builder
+    }
+
+    /// [auth::TokenProvider] of [FCMClientBuilder::token_source]. External account credentials are exchanged
+    /// by this crate, over a client with the connection options of this builder, and others by gcloud-sdk.
+    async fn default_token_provider(
+        &self,
+        user_agent: &str,
+    ) -> Result<Arc<dyn auth::TokenProvider>, String> {
+        match external_account::ExternalAccount::of(&self.token_source)
+            .map_err(|e| e.to_string())?
+        {
+            Some(account) => {
+                let transport = Arc::new(self.http_client()?);
+                Ok(external_account::token_provider(
+                    account,
+                    &self.scopes,
+                    transport,
+                    user_agent,
+                ))
+            }
+            None => {
+                let provider =
+                    auth::GoogleTokenProvider::new(self.token_source.clone(), self.scopes.clone())
+                        .await
+                        .map_err(|e| e.to_string())?;
+                Ok(Arc::new(provider))
+            }
+        }
+    }
+
+    /// Create an instance of FCMClient.
+    pub async fn build(self) -> Result<FCMClient, String> {
+        let connector = self.https_connector()?;
+        self.build_with_connector(connector).await
+    }
+
+    /// Create an instance of FCMClient that opens connections with `connector` instead of the HTTPS connector
+    /// configured by this builder. Connection options such as [FCMClientBuilder::ip_preference] and
+    /// [FCMClientBuilder::tls_backend] are then up to `connector`.
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "native-tls")]
+    /// # async fn run() -> Result<(), String> {
+    /// use firebase_messaging_rs::FCMClient;
+    ///
+    /// let builder = FCMClient::builder().project_id("my-project");
+    /// let connector = hyper_tls::HttpsConnector::new_with_connector(builder.proxy_connector());
+    /// let client: FCMClient<_> = builder.build_with_connector(connector).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn build_with_connector<C>(
+        self,
+        connector: C,
+    ) -> Result<FCMClient<transport::PooledClient<C>>, String>
+    where
+        C: Connect + Clone + Send + Sync + 'static,
+    {
+        let client = transport::PooledClient::new(
+            self.client_builder(),
+            connector,
+            self.max_connection_lifetime,
+        );
+        self.build_with_transport(client).await
+    }
+
+    /// Create an instance of FCMClient that sends requests with `client`, e.g. one shared with the rest of the application.
+    /// Connection options of this builder do not apply. Configure proxies, TLS and timeouts of `client` instead.
+    ///
+    /// ```no_run
+    /// # async fn run() -> Result<(), String> {
+    /// use firebase_messaging_rs::FCMClient;
+    ///
+    /// let http = reqwest::Client::builder()
+    ///     .proxy(reqwest::Proxy::https("http://proxy.internal:3128").map_err(|e| e.to_string())?)
+    ///     .build()
+    ///     .map_err(|e| e.to_string())?;
+    /// let client = FCMClient::builder()
+    ///     .project_id("my-project")
+    ///     .build_with_reqwest(http)
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(feature = "reqwest")]
+    pub async fn build_with_reqwest(
+        self,
+        client: reqwest::Client,
+    ) -> Result<FCMClient<reqwest::Client>, String> {
+        self.build_with_transport(client).await
+    }
+
+    /// Create an instance of FCMClient that sends requests with `transport`.
+    /// Connection options of this builder do not apply. See [transport] for an example.
+    pub async fn build_with_transport<T: transport::HttpTransport>(
+        self,
+        transport: T,
+    ) -> Result<FCMClient<T>, String> {
+        let fcm_base_url = base_url(self.fcm_base_url.as_deref().unwrap_or(FCM_BASE_URL))?;
+        let iid_base_url = base_url(self.iid_base_url.as_deref().unwrap_or(IID_BASE_URL))?;
+        let user_agent = match self.user_agent.as_deref().map(str::trim) {
+            Some(application) if !application.is_empty() => {
+                format!("{application} {DEFAULT_USER_AGENT}")
+            }
+            _ => DEFAULT_USER_AGENT.to_string(),
+        };
+        HeaderValue::from_str(&user_agent)
+            .map_err(|_| format!("invalid user agent: {user_agent:?}"))?;
+        let project_id = self
+            .project_id
+            .clone()
+            .or_else(FCMClient::google_cloud_project);
+        let token_provider = match &self.token_provider {
+            _ if !self.load_credentials => None,
+            Some(provider) => Some(provider.clone()),
+            None => Some(
+                self.default_token_provider(&user_agent)
+                    .await
+                    .map_err(|e| format!("unable to initialize token generator: {e}"))?,
+            ),
+        };
+        Ok(FCMClient {
+            token_provider,
+            access_token: None,
+            transport,
+            project_id,
+            correlation_id: self.correlation_id,
+            retry_policy: self.retry_policy,
+            audit_sink: None,
+            token_observer: None,
+            token_expiry: Arc::default(),
+            stats: Arc::new(stats::ClientStats::new(self.quota_budget)),
+            labels: self.labels,
+            gzip_threshold: self.gzip_threshold,
+            request_timeout: self.request_timeout,
+            max_response_size: self.max_response_size,
+            rate_limiter: Arc::new(rate_limit::RateLimiter::new(self.rate_limits)),
+            circuit_breaker: self
+                .circuit_breaker
+                .map(|policy| Arc::new(circuit_breaker::CircuitBreaker::new(policy))),
+            circuit_observer: None,
+            interceptors: Vec::new(),
+            fcm_base_url,
+            iid_base_url,
+            user_agent,
         })
     }
 }
 
+/// `url` without trailing slashes, if it is an absolute HTTP(S) URL without query.
+fn base_url(url: &str) -> Result<String, String> {
+    let trimmed = url.trim_end_matches('/');
+    let uri = trimmed
+        .parse::<http::Uri>()
+        .map_err(|e| format!("invalid base URL {url:?}: {e}"))?;
+    let supported = matches!(uri.scheme_str(), Some("http" | "https"))
+        && uri.host().is_some()
+        && uri.query().is_none();
+    if !supported {
+        return Err(format!(
+            "base URL must be an absolute http or https URL without query: {url:?}"
+        ));
+    }
+    Ok(trimmed.to_string())
+}
+
 #[cfg(feature = "topic-management")]
-impl crate::topic::TopicManagementSupport for FCMClient {}
+impl<T: transport::HttpTransport> crate::topic::TopicManagementSupport for FCMClient<T> {}
 #[cfg(feature = "fcm")]
-impl crate::fcm::FCMApi for FCMClient {}
+impl<T: transport::HttpTransport> crate::fcm::FCMApi for FCMClient<T> {}
 
 #[async_trait]
-impl GenericGoogleRestAPISupport for FCMClient {
-    fn get_http_client(&self) -> hyper::Client<HttpsConnector<HttpConnector>, Body> {
-        self.http_client.clone()
+impl<T: transport::HttpTransport> GenericGoogleRestAPISupport for FCMClient<T> {
+    type Transport = T;
+    fn transport(&self) -> &T {
+        &self.transport
     }
     fn project_id(&self) -> String {
-        self.project_id.to_string()
+        self.project_id.clone().unwrap_or_default()
+    }
+    fn correlation_id(&self) -> Option<String> {
+        self.correlation_id.next()
+    }
+    fn retry_policy(&self) -> RetryPolicy {
+        self.retry_policy.clone()
+    }
+    fn audit_sink(&self) -> Option<&dyn audit::AuditSink> {
+        self.audit_sink.as_deref()
+    }
+    fn labels(&self) -> Option<&Labels> {
+        Some(&self.labels)
+    }
+    fn client_stats(&self) -> Option<&stats::ClientStats> {
+        Some(&self.stats)
+    }
+    fn gzip_threshold(&self) -> Option<usize> {
+        self.gzip_threshold
     }
-    async fn get_header_token(&self) -> Result<String, gcloud_sdk::error::Error> {
-        let token = self.token_gen.create_token().await?;
-        Ok(token.header_value())
+    fn request_timeout(&self) -> Option<Duration> {
+        self.request_timeout
+    }
+    fn max_response_size(&self) -> Option<usize> {
+        self.max_response_size
+    }
+    fn rate_limiter(&self) -> Option<&rate_limit::RateLimiter> {
+        Some(&self.rate_limiter)
+    }
+    fn circuit_breaker(&self) -> Option<&circuit_breaker::CircuitBreaker> {
+        self.circuit_breaker.as_deref()
+    }
+    fn circuit_observer(&self) -> Option<&dyn circuit_breaker::CircuitObserver> {
+        self.circuit_observer.as_deref()
+    }
+    fn interceptors(&self) -> &[Arc<dyn interceptor::Interceptor>] {
+        &self.interceptors
+    }
+    fn fcm_base_url(&self) -> &str {
+        &self.fcm_base_url
+    }
+    fn iid_base_url(&self) -> &str {
+        &self.iid_base_url
+    }
+    fn user_agent(&self) -> &str {
+        &self.user_agent
+    }
+    async fn get_header_token(&self) -> Result<String, auth::AuthError> {
+        if let Some(access_token) = &self.access_token {
+            return Ok(format!("Bearer {}", access_token.as_str()));
+        }
+        let provider = self
+            .token_provider
+            .as_ref()
+            .ok_or(auth::AuthError::NoCredentials)?;
+        let started = std::time::Instant::now();
+        let token = provider.bearer_with_expiry().await;
+        let latency = started.elapsed();
+        let event = match &token {
+            Ok((_, Some(expiry))) if self.token_expiry.is_new(*expiry) => {
+                auth::TokenEvent::Refreshed {
+                    latency,
+                    expires_at: *expiry,
+                }
+            }
+            Ok(_) => auth::TokenEvent::CacheHit { latency },
+            Err(e) => auth::TokenEvent::Failed {
+                latency,
+                error: e.to_string(),
+            },
+        };
+        match &event {
+            auth::TokenEvent::Failed { error, .. } => {
+                log::warn!("unable to get access token after {latency:?}: {error}")
+            }
+            auth::TokenEvent::Refreshed { expires_at, .. } => {
+                log::debug!("refreshed access token in {latency:?}, expires at {expires_at}")
+            }
+            auth::TokenEvent::CacheHit { .. } => {}
+        }
+        self.stats.record_token_event(&event);
+        if let Some(observer) = &self.token_observer {
+            observer.on_token_event(&event, &self.labels);
+        }
+        token.map(|(token, _)| format!("Bearer {token}"))
     }
 }
 
 #[async_trait]
 pub trait GenericGoogleRestAPISupport {
-    async fn get_header_token(&self) -> Result<String, gcloud_sdk::error::Error>;
+    /// HTTP client of [GenericGoogleRestAPISupport::transport], usually [HttpClient].
+    type Transport: transport::HttpTransport;
+    /// Value of the `Authorization` header of every request, e.g. `Bearer <access token>`.
+    async fn get_header_token(&self) -> Result<String, auth::AuthError>;
+    /// Google Cloud project id, or an empty string if unknown. Instance ID APIs do not use it.
     fn project_id(&self) -> String;
-    fn get_http_client(&self) -> hyper::Client<HttpsConnector<HttpConnector>, Body>;
+    /// HTTP client that sends every request.
+    fn transport(&self) -> &Self::Transport;
+    /// URL under which FCM endpoints are requested, without trailing slash.
+    fn fcm_base_url(&self) -> &str {
+        FCM_BASE_URL
+    }
+    /// URL under which Instance ID endpoints are requested, without trailing slash.
+    fn iid_base_url(&self) -> &str {
+        IID_BASE_URL
+    }
+    /// `User-Agent` header of every request. [DEFAULT_USER_AGENT] by default.
+    fn user_agent(&self) -> &str {
+        DEFAULT_USER_AGENT
+    }
+    /// Correlation ID sent as [CORRELATION_ID_HEADER] and written to logs for each request.
+    /// Called once per request, and reused by its retries. No ID is attached by default.
+    fn correlation_id(&self) -> Option<String> {
+        None
+    }
+    /// Labels attached to records reported to [GenericGoogleRestAPISupport::audit_sink].
+    fn labels(&self) -> Option<&Labels> {
+        None
+    }
+    /// Sink notified once per operation, after retries have finished. Nothing is recorded by default.
+    fn audit_sink(&self) -> Option<&dyn audit::AuditSink> {
+        None
+    }
+    /// Counters updated for each request and retry. Nothing is counted by default.
+    fn client_stats(&self) -> Option<&stats::ClientStats> {
+        None
+    }
+    /// Retry policy applied to each request. Requests are not retried by default.
+    fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy::default()
+    }
+    /// Minimum size of request bodies compressed with gzip. Requires `gzip` feature. Bodies are not compressed by default.
+    fn gzip_threshold(&self) -> Option<usize> {
+        None
+    }
+    /// Time after which each attempt of a request fails with [RPCError::Timeout]. Attempts are not limited by default.
+    fn request_timeout(&self) -> Option<Duration> {
+        None
+    }
+    /// Largest response body accepted, in bytes. Responses are not limited by default.
+    fn max_response_size(&self) -> Option<usize> {
+        None
+    }
+    /// Limits that delay each attempt of a request before it is sent. Requests are not limited by default.
+    fn rate_limiter(&self) -> Option<&rate_limit::RateLimiter> {
+        None
+    }
+    /// Circuits that reject requests while hosts keep failing. Requests are always sent by default.
+    fn circuit_breaker(&self) -> Option<&circuit_breaker::CircuitBreaker> {
+        None
+    }
+    /// Observer notified when circuits of [GenericGoogleRestAPISupport::circuit_breaker] change their state.
+    fn circuit_observer(&self) -> Option<&dyn circuit_breaker::CircuitObserver> {
+        None
+    }
+    /// Interceptors that every request and response pass through. See [interceptor].
+    fn interceptors(&self) -> &[Arc<dyn interceptor::Interceptor>] {
+        &[]
+    }
     async fn post_request<
         P: serde::Serialize + Send + Sync,
         R: for<'a> Deserialize<'a> + Clone,
@@ -120,82 +1324,446 @@ pub trait GenericGoogleRestAPISupport {
         payloadable: P,
         extra_headers: &[(&str, &str)],
     ) -> Result<R, E> {
-        let auth_header_value = self
-            .get_header_token()
+        let payload = serialize_payload(&payloadable).map_err(E::from)?;
+        self.post_bytes_with(endpoint, payload, extra_headers).await
+    }
+
+    /// Post an already serialized JSON payload.
+    /// [Bytes] is reference counted, so the same payload can be sent repeatedly without copying.
+    async fn post_bytes_with<R: for<'a> Deserialize<'a> + Clone, E: From<RPCError>>(
+        &self,
+        endpoint: &str,
+        payload: Bytes,
+        extra_headers: &[(&str, &str)],
+    ) -> Result<R, E> {
+        self.request_with(Method::POST, endpoint, Some(payload), extra_headers)
             .await
-            .map_err(|_| RPCError::Unauthorized("unable to get header token".into()))
-            .map_err(E::from)?;
-        let payload = serde_json::to_vec(&payloadable).unwrap();
-        let mut builder = Request::builder()
-            .uri(endpoint)
-            .method("POST")
-            .header(CONTENT_TYPE, "application/json")
-            .header(ACCEPT, "application/json")
-            .header(AUTHORIZATION, auth_header_value)
-            .header(CONTENT_LENGTH, format!("{}", payload.len() as u64));
-        for (key, value) in extra_headers {
-            builder = builder.header(*key, *value)
-        }
-        let req = builder
-            .body(Body::from(payload))
-            .map_err(|e| RPCError::BuildRequestFailure(format!("{e:?}")))
-            .map_err(E::from)?;
-        let res = self
-            .get_http_client()
-            .request(req)
+    }
+
+    async fn get_request<R: for<'a> Deserialize<'a> + Clone, E: From<RPCError>>(
+        &self,
+        endpoint: &str,
+    ) -> Result<R, E> {
+        self.get_request_with(endpoint, &[]).await
+    }
+    async fn get_request_with<R: for<'a> Deserialize<'a> + Clone, E: From<RPCError>>(
+        &self,
+        endpoint: &str,
+        extra_headers: &[(&str, &str)],
+    ) -> Result<R, E> {
+        self.request_with(Method::GET, endpoint, None, extra_headers)
             .await
-            .map_err(|_| RPCError::HttpRequestFailure) // FIXME: propagate error info
-            .map_err(E::from)?;
-        Self::handle_response_body(res).await
     }
 
-    async fn get_request<R: for<'a> Deserialize<'a> + Clone, E: From<RPCError>>(
+    /// GET `endpoint` with `query` appended. See [Query].
+    async fn get_request_with_query<R: for<'a> Deserialize<'a> + Clone, E: From<RPCError>>(
+        &self,
+        endpoint: &str,
+        query: &Query,
+        extra_headers: &[(&str, &str)],
+    ) -> Result<R, E> {
+        self.get_request_with(&query.append_to(endpoint), extra_headers)
+            .await
+    }
+
+    async fn put_request<
+        P: serde::Serialize + Send + Sync,
+        R: for<'a> Deserialize<'a> + Clone,
+        E: From<RPCError>,
+    >(
+        &self,
+        endpoint: &str,
+        payloadable: P,
+    ) -> Result<R, E> {
+        self.put_request_with(endpoint, payloadable, &[]).await
+    }
+    async fn put_request_with<
+        P: serde::Serialize + Send + Sync,
+        R: for<'a> Deserialize<'a> + Clone,
+        E: From<RPCError>,
+    >(
+        &self,
+        endpoint: &str,
+        payloadable: P,
+        extra_headers: &[(&str, &str)],
+    ) -> Result<R, E> {
+        let payload = serialize_payload(&payloadable).map_err(E::from)?;
+        self.request_with(Method::PUT, endpoint, Some(payload), extra_headers)
+            .await
+    }
+
+    async fn patch_request<
+        P: serde::Serialize + Send + Sync,
+        R: for<'a> Deserialize<'a> + Clone,
+        E: From<RPCError>,
+    >(
+        &self,
+        endpoint: &str,
+        payloadable: P,
+    ) -> Result<R, E> {
+        self.patch_request_with(endpoint, payloadable, &[]).await
+    }
+    async fn patch_request_with<
+        P: serde::Serialize + Send + Sync,
+        R: for<'a> Deserialize<'a> + Clone,
+        E: From<RPCError>,
+    >(
+        &self,
+        endpoint: &str,
+        payloadable: P,
+        extra_headers: &[(&str, &str)],
+    ) -> Result<R, E> {
+        let payload = serialize_payload(&payloadable).map_err(E::from)?;
+        self.request_with(Method::PATCH, endpoint, Some(payload), extra_headers)
+            .await
+    }
+
+    async fn delete_request<R: for<'a> Deserialize<'a> + Clone, E: From<RPCError>>(
+        &self,
+        endpoint: &str,
+    ) -> Result<R, E> {
+        self.delete_request_with(endpoint, &[]).await
+    }
+    async fn delete_request_with<R: for<'a> Deserialize<'a> + Clone, E: From<RPCError>>(
+        &self,
+        endpoint: &str,
+        extra_headers: &[(&str, &str)],
+    ) -> Result<R, E> {
+        self.request_with(Method::DELETE, endpoint, None, extra_headers)
+            .await
+    }
+
+    /// Send an authorized request with an optional JSON payload and decode the response.
+    /// All the other request helpers delegate to this method.
+    ///
+    /// Failed attempts are retried according to [GenericGoogleRestAPISupport::retry_policy].
+    async fn request_with<R: for<'a> Deserialize<'a> + Clone, E: From<RPCError>>(
+        &self,
+        method: Method,
+        endpoint: &str,
+        payload: Option<Bytes>,
+        extra_headers: &[(&str, &str)],
+    ) -> Result<R, E> {
+        self.request_with_metadata(method, endpoint, payload, extra_headers)
+            .await
+            .map(|(body, _)| body)
+    }
+
+    /// Same as [GenericGoogleRestAPISupport::request_with], but also return the [ResponseMetadata] of the last attempt.
+    /// On failure, it is available from [RequestContext::response] of the error.
+    async fn request_with_metadata<R: for<'a> Deserialize<'a> + Clone, E: From<RPCError>>(
+        &self,
+        method: Method,
+        endpoint: &str,
+        payload: Option<Bytes>,
+        extra_headers: &[(&str, &str)],
+    ) -> Result<(R, Option<ResponseMetadata>), E> {
+        let policy = self.retry_policy();
+        let timestamp = chrono::Utc::now();
+        let started = std::time::Instant::now();
+        let payload_sha256 = payload.as_deref().map(audit::sha256_hex);
+        let correlation_id = self.correlation_id();
+        #[cfg(feature = "gzip")]
+        let mut headers = extra_headers.to_vec();
+        #[cfg(feature = "gzip")]
+        let payload = match (payload, self.gzip_threshold()) {
+            (Some(payload), Some(min_bytes)) if payload.len() >= min_bytes => {
+                match gzip(&payload) {
+                    Ok(compressed) => {
+                        headers.push((http::header::CONTENT_ENCODING.as_str(), "gzip"));
+                        Some(compressed)
+                    }
+                    Err(e) => {
+                        log::warn!("{method} {endpoint} sending uncompressed body: {e}");
+                        Some(payload)
+                    }
+                }
+            }
+            (payload, _) => payload,
+        };
+        #[cfg(feature = "gzip")]
+        let extra_headers = headers.as_slice();
+        let deadline = policy.deadline.map(|deadline| started + deadline);
+        let timeout = self.request_timeout();
+        let host = endpoint
+            .parse::<http::Uri>()
+            .ok()
+            .and_then(|uri| uri.host().map(str::to_string))
+            .unwrap_or_default();
+        let mut failed = Vec::new();
+        let mut attempt = 0;
+        let mut response = None;
+        let result = loop {
+            // Ask the breaker first so that rejected requests neither wait for nor use up the rate limit.
+            if let Some(breaker) = self.circuit_breaker() {
+                let (permit, transition) = breaker.acquire(&host, std::time::Instant::now());
+                circuit_breaker::report(&host, transition, self.circuit_observer(), self.labels());
+                if let Err(retry_after) = permit {
+                    log::warn!("{method} {endpoint} rejected while the circuit of {host} is open");
+                    break Err(RPCError::CircuitOpen { retry_after });
+                }
+            }
+            if let Some(limiter) = self.rate_limiter() {
+                let wait = limiter.reserve(&self.project_id(), audit::operation_name(endpoint));
+                let wait = match deadline {
+                    Some(deadline) => {
+                        wait.min(deadline.saturating_duration_since(std::time::Instant::now()))
+                    }
+                    None => wait,
+                };
+                if !wait.is_zero() {
+                    log::debug!("{method} {endpoint} waiting {wait:?} for the rate limit");
+                    tokio::time::sleep(wait).await;
+                }
+            }
+            let attempt_started = started.elapsed();
+            let remaining = deadline
+                .map(|deadline| deadline.saturating_duration_since(std::time::Instant::now()));
+            let limit = match (remaining, timeout) {
+                (Some(remaining), Some(timeout)) => Some(remaining.min(timeout)),
+                (remaining, timeout) => remaining.or(timeout),
+            };
+            let error = match within(
+                limit,
+                self.request_once::<R>(
+                    method.clone(),
+                    endpoint,
+                    payload.clone(),
+                    extra_headers,
+                    correlation_id.as_deref(),
+                ),
+            )
+            .await
+            {
+                None if limit == remaining => {
+                    failed.push(FailedAttempt {
+                        started_after: attempt_started,
+                        error: RPCError::Timeout {
+                            timeout: limit.unwrap_or_default(),
+                        },
+                    });
+                    break Err(RPCError::DeadlineExceeded {
+                        deadline: policy.deadline.unwrap_or_default(),
+                        attempts: failed,
+                    });
+                }
+                None => {
+                    log::warn!("{method} {endpoint} timed out after {limit:?}");
+                    response = None;
+                    RPCError::Timeout {
+                        timeout: timeout.unwrap_or_default(),
+                    }
+                }
+                Some(Attempt {
+                    result: Ok(body),
+                    response: last,
+                }) => {
+                    self.record_circuit(&host, None, last.as_ref());
+                    response = last;
+                    break Ok(body);
+                }
+                Some(Attempt {
+                    result: Err(e),
+                    response: last,
+                }) => {
+                    response = last;
+                    e
+                }
+            };
+            self.record_circuit(&host, Some(&error), response.as_ref());
+            let delay = if attempt < policy.max_retries {
+                policy.delay(&method, &error, attempt)
+            } else {
+                None
+            };
+            let exceeds_deadline = |delay: Duration| {
+                deadline.map_or(false, |deadline| {
+                    std::time::Instant::now() + delay >= deadline
+                })
+            };
+            match delay {
+                Some(delay) if exceeds_deadline(delay) => {
+                    failed.push(FailedAttempt {
+                        started_after: attempt_started,
+                        error,
+                    });
+                    break Err(RPCError::DeadlineExceeded {
+                        deadline: policy.deadline.unwrap_or_default(),
+                        attempts: failed,
+                    });
+                }
+                Some(delay) => {
+                    log::info!("{method} {endpoint} retrying in {delay:?} after {error:?}");
+                    if let Some(stats) = self.client_stats() {
+                        let rate_limited = matches!(
+                            error,
+                            RPCError::Internal {
+                                retry_after: Some(_)
+                            } | RPCError::TooManyRequests { .. }
+                        );
+                        stats.record_retry(delay, rate_limited);
+                    }
+                    failed.push(FailedAttempt {
+                        started_after: attempt_started,
+                        error,
+                    });
+                    tokio::time::sleep(delay).await;
+                    attempt += 1;
+                }
+                None => break Err(error),
+            }
+        };
+        if let Some(sink) = self.audit_sink() {
+            sink.record(&audit::AuditRecord {
+                timestamp,
+                operation: audit::operation_name(endpoint).to_string(),
+                target: format!("{method} {endpoint}"),
+                payload_sha256,
+                outcome: match &result {
+                    Ok(_) => audit::AuditOutcome::Success,
+                    Err(e) => audit::AuditOutcome::Failure {
+                        error: format!("{e:?}"),
+                    },
+                },
+                latency: started.elapsed(),
+                attempts: attempt + 1,
+                labels: self.labels().cloned().unwrap_or_default(),
+                correlation_id: correlation_id.clone(),
+            });
+        }
+        match result {
+            Ok(body) => Ok((body, response)),
+            Err(e) => Err(E::from(RPCError::Request {
+                context: RequestContext {
+                    response,
+                    correlation_id,
+                    ..RequestContext::new(&method, endpoint)
+                },
+                source: Box::new(e),
+            })),
+        }
+    }
+
+    /// Report the outcome of an attempt to the circuit of `host`, if any.
+    fn record_circuit(
         &self,
-        endpoint: &str,
-    ) -> Result<R, E> {
-        self.get_request_with(endpoint, &[]).await
+        host: &str,
+        error: Option<&RPCError>,
+        response: Option<&ResponseMetadata>,
+    ) {
+        if let Some(breaker) = self.circuit_breaker() {
+            let outcome = circuit_breaker::Outcome::of(error, response);
+            let transition = breaker.record(host, outcome, std::time::Instant::now());
+            circuit_breaker::report(host, transition, self.circuit_observer(), self.labels());
+        }
     }
-    async fn get_request_with<R: for<'a> Deserialize<'a> + Clone, E: From<RPCError>>(
+
+    /// Send a request exactly once. See [GenericGoogleRestAPISupport::request_with].
+    /// `correlation_id` is the [GenericGoogleRestAPISupport::correlation_id] of the request, shared by all its attempts.
+    async fn request_once<R: for<'a> Deserialize<'a> + Clone>(
         &self,
+        method: Method,
         endpoint: &str,
+        payload: Option<Bytes>,
         extra_headers: &[(&str, &str)],
-    ) -> Result<R, E> {
-        let auth_header_value = self
+        correlation_id: Option<&str>,
+    ) -> Attempt<R> {
+        if let Some(stats) = self.client_stats() {
+            let project_id = self.project_id();
+            if let Err(resets_in) = stats.acquire(&project_id, audit::operation_name(endpoint)) {
+                log::warn!("{method} {endpoint} exceeds the quota budget of project {project_id}, resets in {resets_in:?}");
+                return Attempt::failed(RPCError::QuotaExceeded {
+                    project_id,
+                    resets_in,
+                });
+            }
+        }
+        let auth_header_value = match self
             .get_header_token()
             .await
-            .map_err(|_| RPCError::Unauthorized("unable to get header token".into()))
-            .map_err(E::from)?;
-        let mut builder = Request::builder()
-            .uri(endpoint)
-            .method("GET")
-            .header(CONTENT_TYPE, "application/json")
-            .header(ACCEPT, "application/json")
-            .header(AUTHORIZATION, auth_header_value);
-        for (key, value) in extra_headers {
-            builder = builder.header(*key, *value)
-        }
-        let req = builder
-            .body(Body::empty()) // NOTE: what is difference between Body::empty() and ()?
-            .map_err(|e| RPCError::BuildRequestFailure(format!("{e:?}")))
-            .map_err(E::from)?;
-        let res = self
-            .get_http_client()
-            .request(req)
-            .await
-            .map_err(|_| RPCError::HttpRequestFailure) // FIXME: don't swallow error! propagate error info
-            .map_err(E::from)?;
-        Self::handle_response_body(res).await
+            .map_err(|e| RPCError::Unauthorized(format!("unable to get header token: {e}")))
+            .and_then(sensitive_header_value)
+        {
+            Ok(value) => value,
+            Err(e) => return Attempt::failed(e),
+        };
+        let mut headers = extra_headers.to_vec();
+        for (name, value) in [
+            (http::header::USER_AGENT.as_str(), self.user_agent()),
+            (API_CLIENT_HEADER, API_CLIENT),
+        ] {
+            // A header given by the caller replaces the default instead of being sent next to it.
+            if !extra_headers
+                .iter()
+                .any(|(key, _)| key.eq_ignore_ascii_case(name))
+            {
+                headers.push((name, value));
+            }
+        }
+        if let Some(request_id) = correlation_id {
+            headers.push((CORRELATION_ID_HEADER, request_id));
+        }
+        let request_id = correlation_id.unwrap_or("-");
+        log::debug!("{method} {endpoint} request_id={request_id}");
+        let mut req = match build_request(
+            method.clone(),
+            endpoint,
+            Some(auth_header_value),
+            payload,
+            &headers,
+        ) {
+            Ok(req) => req,
+            Err(e) => return Attempt::failed(e),
+        };
+        if let Some(max_size) = self.max_response_size() {
+            req.extensions_mut()
+                .insert(transport::MaxResponseSize(max_size));
+        }
+        let res = interceptor::send(self.interceptors(), self.transport(), req).await;
+        if let Some(stats) = self.client_stats() {
+            let status = res.as_ref().ok().map(|res| res.status().as_u16());
+            stats.record_request(audit::operation_name(endpoint), status);
+        }
+        let res = match res {
+            Ok(res) => res,
+            Err(e) => {
+                log::warn!("{method} {endpoint} request_id={request_id} failed: {e:?}");
+                return Attempt::failed(e);
+            }
+        };
+        let response = ResponseMetadata::from_response(&res);
+        if !res.status().is_success() {
+            log::warn!(
+                "{method} {endpoint} request_id={request_id} returned {} {response}",
+                res.status()
+            );
+        }
+        Attempt {
+            result: Self::handle_response_body_with_limit(res, self.max_response_size()).await,
+            response: Some(response),
+        }
     }
 
+    /// Decode a response into `R` or an error. With `compression` feature, gzip and deflate bodies are decompressed first.
     async fn handle_response_body<R: for<'a> Deserialize<'a> + Clone, E: From<RPCError>>(
-        mut res: Response<Body>,
+        res: Response<Bytes>,
+    ) -> Result<R, E> {
+        Self::handle_response_body_with_limit(res, None).await
+    }
+
+    /// Same as [GenericGoogleRestAPISupport::handle_response_body], but fail with [RPCError::ResponseTooLarge]
+    /// if the body is larger than `max_size` bytes, once decompressed.
+    async fn handle_response_body_with_limit<
+        R: for<'a> Deserialize<'a> + Clone,
+        E: From<RPCError>,
+    >(
+        res: Response<Bytes>,
+        max_size: Option<usize>,
     ) -> Result<R, E> {
+        let res = limit_body(res, max_size).map_err(E::from)?;
         match res.status() {
             StatusCode::OK => {
-                let buf = hyper::body::to_bytes(res)
-                    .await
-                    .map_err(|_| RPCError::DecodeFailure)
-                    .map_err(E::from)?;
+                let buf = res.into_body();
                 let text = std::str::from_utf8(&buf).unwrap_or_default();
                 serde_json::from_slice::<R>(&buf)
                     .map_err(|e| RPCError::DeserializeFailure {
@@ -211,38 +1779,503 @@ pub trait GenericGoogleRestAPISupport {
             }
             .map_err(E::from),
             StatusCode::BAD_REQUEST => {
-                let data = hyper::body::to_bytes(res.body_mut())
-                    .await
-                    .map_err(|_| RPCError::DecodeFailure)?;
+                let data = res.into_body();
                 let data = String::from_utf8(data.to_vec()).ok();
                 Err(E::from(RPCError::InvalidRequest { details: data }))
             }
-            e if e.is_client_error() => Err(E::from(RPCError::invalid_request())),
-            e if e.is_server_error() => {
-                if let Some(retry_after_sec) = res
-                    .headers()
-                    .get(HeaderName::from_static("Retry-After"))
-                    .and_then(|h| h.to_str().ok().and_then(|s| s.parse::<u64>().ok()))
-                {
-                    Err(E::from(RPCError::retryable_internal(Duration::from_secs(
-                        retry_after_sec,
-                    ))))
-                } else {
-                    Err(E::from(RPCError::internal()))
-                }
+            // keep error details, which tell an unregistered token from an unknown project
+            StatusCode::NOT_FOUND => {
+                let data = res.into_body();
+                let data = String::from_utf8(data.to_vec())
+                    .ok()
+                    .filter(|data| !data.is_empty());
+                Err(E::from(RPCError::InvalidRequest { details: data }))
+            }
+            // keep error details, which tell an exhausted quota such as QUOTA_EXCEEDED from other rate limits
+            StatusCode::TOO_MANY_REQUESTS => {
+                let retry_after = retry_after(&res);
+                let data = res.into_body();
+                let data = String::from_utf8(data.to_vec())
+                    .ok()
+                    .filter(|data| !data.is_empty());
+                Err(E::from(RPCError::TooManyRequests {
+                    details: data,
+                    retry_after,
+                }))
             }
+            e if e.is_client_error() => Err(E::from(RPCError::invalid_request())),
+            e if e.is_server_error() => match retry_after(&res) {
+                Some(retry_after) => Err(E::from(RPCError::retryable_internal(retry_after))),
+                None => Err(E::from(RPCError::internal())),
+            },
             e => Err(E::from(RPCError::Unknown(e.as_u16()))),
         }
     }
 }
 
+/// Query string whose names and values are percent-encoded.
+///
+/// ```
+/// use firebase_messaging_rs::Query;
+///
+/// let query = Query::new()
+///     .param("details", true)
+///     .param_opt("filter", Some("a b&c"))
+///     .param_opt::<u32>("page", None);
+/// assert_eq!(query.to_string(), "details=true&filter=a%20b%26c");
+/// assert_eq!(query.append_to("https://example.com/info?x=1"), "https://example.com/info?x=1&details=true&filter=a%20b%26c");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Query {
+    params: Vec<(String, String)>,
+}
+
+impl Query {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn param(mut self, name: &str, value: impl ToString) -> Self {
+        self.params.push((name.to_string(), value.to_string()));
+        self
+    }
+    /// Add the parameter only if `value` is present.
+    pub fn param_opt<V: ToString>(self, name: &str, value: Option<V>) -> Self {
+        match value {
+            Some(value) => self.param(name, value),
+            None => self,
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.params.is_empty()
+    }
+    /// Append this query to `endpoint`, which may already have a query string.
+    pub fn append_to(&self, endpoint: &str) -> String {
+        if self.is_empty() {
+            endpoint.to_string()
+        } else if endpoint.contains('?') {
+            format!("{endpoint}&{self}")
+        } else {
+            format!("{endpoint}?{self}")
+        }
+    }
+}
+
+impl std::fmt::Display for Query {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut separator = "";
+        for (name, value) in &self.params {
+            write!(
+                f,
+                "{separator}{}={}",
+                percent_encode(name, b""),
+                percent_encode(value, b"")
+            )?;
+            separator = "&";
+        }
+        Ok(())
+    }
+}
+
+/// Percent-encode `segment` to embed it in a URL path, e.g. a registration token.
+pub fn encode_path_segment(segment: &str) -> String {
+    percent_encode(segment, b":@")
+}
+
+/// Percent-encode every byte except RFC 3986 unreserved characters and `keep`.
+pub(crate) fn percent_encode(s: &str, keep: &[u8]) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for byte in s.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) || keep.contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    encoded
+}
+
+/// Delay requested by the `Retry-After` header of `res`, in seconds.
+fn retry_after<B>(res: &Response<B>) -> Option<Duration> {
+    res.headers()
+        .get(RETRY_AFTER)
+        .and_then(|h| h.to_str().ok()?.parse::<u64>().ok())
+        .map(Duration::from_secs)
+}
+
+/// Serialize a request payload to JSON.
+fn serialize_payload<P: serde::Serialize>(payload: &P) -> Result<Bytes, RPCError> {
+    serde_json::to_vec(payload)
+        .map(Bytes::from)
+        .map_err(|e| RPCError::SerializeFailure {
+            reason: e.to_string(),
+        })
+}
+
+/// Run `future` to completion, or give up after `timeout` if any.
+async fn within<F: std::future::Future>(timeout: Option<Duration>, future: F) -> Option<F::Output> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, future).await.ok(),
+        None => Some(future.await),
+    }
+}
+
+/// Run `future` to completion, or drop it with any request in flight as soon as `cancel` completes.
+#[cfg(feature = "fcm")]
+pub(crate) async fn until<F, C>(future: F, cancel: C) -> Option<F::Output>
+where
+    F: std::future::Future,
+    C: std::future::Future<Output = ()>,
+{
+    futures::pin_mut!(future, cancel);
+    match futures::future::select(future, cancel).await {
+        futures::future::Either::Left((output, _)) => Some(output),
+        futures::future::Either::Right(_) => None,
+    }
+}
+
+#[cfg(feature = "gzip")]
+fn gzip(payload: &[u8]) -> std::io::Result<Bytes> {
+    use std::io::Write;
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(payload)?;
+    Ok(Bytes::from(encoder.finish()?))
+}
+
+/// Fail with [RPCError::ResponseTooLarge] if the body of `res` is larger than `max_size` bytes, once decompressed.
+fn limit_body(res: Response<Bytes>, max_size: Option<usize>) -> Result<Response<Bytes>, RPCError> {
+    if let Some(limit) = max_size {
+        if res.body().len() > limit {
+            return Err(RPCError::ResponseTooLarge { limit });
+        }
+    }
+    #[cfg(feature = "compression")]
+    let res = decompress(res, max_size)?;
+    Ok(res)
+}
+
+/// Decode the body of `res` according to its `Content-Encoding`, which is then removed with the stale `Content-Length`.
+/// Bodies in other encodings are left as they are. Decoding stops as soon as the body exceeds `max_size`.
+#[cfg(feature = "compression")]
+fn decompress(res: Response<Bytes>, max_size: Option<usize>) -> Result<Response<Bytes>, RPCError> {
+    use http::header::CONTENT_ENCODING;
+    use std::io::Read;
+    let encoding = match res.headers().get(CONTENT_ENCODING) {
+        Some(encoding) if !res.body().is_empty() => encoding
+            .to_str()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase(),
+        _ => return Ok(res),
+    };
+    let (mut parts, body) = res.into_parts();
+    // One byte more than the limit is enough to tell that the body exceeds it.
+    let take = max_size.map_or(u64::MAX, |max_size| (max_size as u64).saturating_add(1));
+    let mut decoded = Vec::new();
+    let result = match encoding.as_str() {
+        "gzip" | "x-gzip" => flate2::read::MultiGzDecoder::new(&body[..])
+            .take(take)
+            .read_to_end(&mut decoded),
+        // `deflate` is meant to be zlib-wrapped, but some servers send raw deflate streams.
+        "deflate" => flate2::read::ZlibDecoder::new(&body[..])
+            .take(take)
+            .read_to_end(&mut decoded)
+            .or_else(|_| {
+                decoded.clear();
+                flate2::read::DeflateDecoder::new(&body[..])
+                    .take(take)
+                    .read_to_end(&mut decoded)
+            }),
+        _ => return Ok(Response::from_parts(parts, body)),
+    };
+    result.map_err(|_| RPCError::DecodeFailure)?;
+    if let Some(limit) = max_size {
+        if decoded.len() > limit {
+            return Err(RPCError::ResponseTooLarge { limit });
+        }
+    }
+    parts.headers.remove(CONTENT_ENCODING);
+    parts.headers.remove(CONTENT_LENGTH);
+    Ok(Response::from_parts(parts, Bytes::from(decoded)))
+}
+
+/// Build a JSON request with the headers shared by all the request helpers.
+/// `Authorization` header is set only if `authorization` is given.
+pub fn build_request(
+    method: Method,
+    endpoint: &str,
+    authorization: Option<HeaderValue>,
+    payload: Option<Bytes>,
+    extra_headers: &[(&str, &str)],
+) -> Result<Request<Bytes>, RPCError> {
+    let mut builder = Request::builder()
+        .uri(endpoint)
+        .method(method)
+        .header(CONTENT_TYPE, "application/json")
+        .header(ACCEPT, "application/json");
+    #[cfg(feature = "compression")]
+    {
+        builder = builder.header(http::header::ACCEPT_ENCODING, "gzip, deflate");
+    }
+    if let Some(authorization) = authorization {
+        builder = builder.header(AUTHORIZATION, authorization);
+    }
+    if let Some(payload) = &payload {
+        builder = builder.header(CONTENT_LENGTH, format!("{}", payload.len() as u64));
+    }
+    for (key, value) in extra_headers {
+        builder = builder.header(*key, *value)
+    }
+    builder
+        .body(payload.unwrap_or_default())
+        .map_err(|e| RPCError::BuildRequestFailure(format!("{e:?}")))
+}
+
+/// Render a request as a copy-pasteable `curl` command for debugging or bug reports.
+///
+/// The bearer token is never included. The command refers to `$TOKEN` instead, so export it before running,
+/// e.g. `export TOKEN=$(gcloud auth print-access-token)`.
+pub fn curl_command(
+    method: &str,
+    endpoint: &str,
+    extra_headers: &[(&str, &str)],
+    body: Option<&[u8]>,
+) -> String {
+    fn quote(s: &str) -> String {
+        format!("'{}'", s.replace('\'', "'\\''"))
+    }
+    let mut command = format!("curl -X {method} {}", quote(endpoint));
+    command.push_str(" \\\n  -H 'Content-Type: application/json'");
+    command.push_str(" \\\n  -H 'Accept: application/json'");
+    command.push_str(" \\\n  -H \"Authorization: Bearer $TOKEN\"");
+    for (key, value) in extra_headers {
+        command.push_str(&format!(" \\\n  -H {}", quote(&format!("{key}: {value}"))));
+    }
+    if let Some(body) = body {
+        command.push_str(&format!(
+            " \\\n  --data-raw {}",
+            quote(&String::from_utf8_lossy(body))
+        ));
+    }
+    command
+}
+
+/// Connection level failure classes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum TransportErrorKind {
+    /// Unable to establish a connection, e.g. DNS failure or connection refused.
+    /// The request has never been sent, so it is always safe to retry.
+    Connect,
+    Timeout,
+    /// The connection was closed before the response was received.
+    ConnectionClosed,
+    Other,
+}
+
+impl TransportErrorKind {
+    /// Status reported to downstream clients when the upstream could not be reached.
+    #[cfg(any(feature = "fcm", feature = "topic-management"))]
+    fn gateway_status(self) -> StatusCode {
+        match self {
+            Self::Timeout => StatusCode::GATEWAY_TIMEOUT,
+            _ => StatusCode::BAD_GATEWAY,
+        }
+    }
+}
+
+/// Controls how failed requests are retried.
+///
+/// Connection level failures and server errors (5xx) are classified separately.
+/// Transport failures of non-idempotent requests (e.g. POST) are retried only if
+/// the connection could not be established, because the request may have reached the server otherwise.
+/// Attach a [RetryClassifier] to override this classification.
+///
+/// The policy applies to every request, including each batch sent by topic management and bulk send helpers.
+#[derive(Clone)]
+pub struct RetryPolicy {
+    /// The maximum number of retries after the first attempt. `0` disables retries.
+    pub max_retries: u32,
+    /// Delay before the first retry. It is doubled for each retry.
+    pub initial_backoff: Duration,
+    /// Upper bound of the delay between retries.
+    pub max_backoff: Duration,
+    /// Retry connection level failures.
+    pub retry_transport_errors: bool,
+    /// Retry server errors (5xx) and `429 Too Many Requests`, honoring `Retry-After` header.
+    pub retry_server_errors: bool,
+    /// Consulted before the built-in classification. See [RetryPolicy::with_classifier].
+    pub classifier: Option<Arc<dyn RetryClassifier>>,
+    /// Total time allowed for all attempts of a request, including delays between them.
+    /// When it runs out, the request fails with [RPCError::DeadlineExceeded]. No deadline by default.
+    pub deadline: Option<Duration>,
+}
+
+impl std::fmt::Debug for RetryPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RetryPolicy")
+            .field("max_retries", &self.max_retries)
+            .field("initial_backoff", &self.initial_backoff)
+            .field("max_backoff", &self.max_backoff)
+            .field("retry_transport_errors", &self.retry_transport_errors)
+            .field("retry_server_errors", &self.retry_server_errors)
+            .field("classifier", &self.classifier.is_some())
+            .field("deadline", &self.deadline)
+            .finish()
+    }
+}
+
+/// Outcome of a [RetryClassifier].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetryDecision {
+    /// Retry with backoff, honoring the delay requested by the server if any.
+    Retry,
+    /// Return the error without retrying.
+    Stop,
+    /// Fall back to the classification of [RetryPolicy].
+    Default,
+}
+
+/// Decides whether a failed request should be retried.
+/// `attempt` is `0` for the first failure. [RetryPolicy::max_retries] is enforced regardless of the decision.
+///
+/// Closures with the same signature implement this trait:
+///
+/// ```
+/// use firebase_messaging_rs::{RPCError, RetryDecision, RetryPolicy, TransportErrorKind};
+///
+/// let policy = RetryPolicy::with_max_retries(3).with_classifier(|_: &http::Method, error: &RPCError, attempt: u32| {
+///     match error {
+///         // retry sends cut off by a proxy, accepting that a message may be delivered twice
+///         RPCError::Transport { kind: TransportErrorKind::ConnectionClosed, .. } => RetryDecision::Retry,
+///         // give up early on server errors without Retry-After
+///         RPCError::Internal { retry_after: None } if attempt > 0 => RetryDecision::Stop,
+///         // an exhausted project quota does not recover within the retries of a request
+///         RPCError::TooManyRequests { details: Some(details), .. } if details.contains("QUOTA_EXCEEDED") => {
+///             RetryDecision::Stop
+///         }
+///         _ => RetryDecision::Default,
+///     }
+/// });
+/// ```
+pub trait RetryClassifier: Send + Sync {
+    fn classify(&self, method: &Method, error: &RPCError, attempt: u32) -> RetryDecision;
+}
+
+impl<F> RetryClassifier for F
+where
+    F: Fn(&Method, &RPCError, u32) -> RetryDecision + Send + Sync,
+{
+    fn classify(&self, method: &Method, error: &RPCError, attempt: u32) -> RetryDecision {
+        self(method, error, attempt)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 0,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            retry_transport_errors: true,
+            retry_server_errors: true,
+            classifier: None,
+            deadline: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Retry up to `max_retries` times with the default backoff.
+    pub fn with_max_retries(max_retries: u32) -> Self {
+        Self {
+            max_retries,
+            ..Default::default()
+        }
+    }
+    /// Give up all attempts of a request after `deadline`. See [RetryPolicy::deadline].
+    pub fn with_deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+    /// Let `classifier` decide which errors are retried.
+    pub fn with_classifier<C: RetryClassifier + 'static>(mut self, classifier: C) -> Self {
+        self.classifier = Some(Arc::new(classifier));
+        self
+    }
+    pub(crate) fn backoff(&self, attempt: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_backoff)
+    }
+    /// Delay before retrying `error`, or `None` if it must not be retried.
+    fn delay(&self, method: &Method, error: &RPCError, attempt: u32) -> Option<Duration> {
+        let decision = match &self.classifier {
+            Some(classifier) => classifier.classify(method, error, attempt),
+            None => RetryDecision::Default,
+        };
+        match decision {
+            RetryDecision::Retry => {
+                let retry_after = match error {
+                    RPCError::Internal { retry_after }
+                    | RPCError::TooManyRequests { retry_after, .. } => *retry_after,
+                    RPCError::QuotaExceeded { resets_in, .. } => Some(*resets_in),
+                    _ => None,
+                };
+                return Some(retry_after.unwrap_or_default().max(self.backoff(attempt)));
+            }
+            RetryDecision::Stop => return None,
+            RetryDecision::Default => {}
+        }
+        match error {
+            RPCError::Transport { kind, .. } if self.retry_transport_errors => {
+                let idempotent = !matches!(*method, Method::POST | Method::PATCH);
+                (idempotent || *kind == TransportErrorKind::Connect).then(|| self.backoff(attempt))
+            }
+            RPCError::Timeout { .. } if self.retry_transport_errors => {
+                let idempotent = !matches!(*method, Method::POST | Method::PATCH);
+                idempotent.then(|| self.backoff(attempt))
+            }
+            RPCError::Internal { retry_after } if self.retry_server_errors => {
+                Some(retry_after.unwrap_or_default().max(self.backoff(attempt)))
+            }
+            // rejected before being processed, so even non-idempotent requests are safe to resend
+            RPCError::TooManyRequests { retry_after, .. } if self.retry_server_errors => {
+                Some(retry_after.unwrap_or_default().max(self.backoff(attempt)))
+            }
+            _ => None,
+        }
+    }
+}
+
 /// [RPCError] is internal error types. Please use dedicated error types like [topic::TopicManagementError] in general.
 #[derive(Debug, Clone)]
 pub enum RPCError {
     Unauthorized(String),
     BuildRequestFailure(String),
+    /// Superseded by [RPCError::Transport]. Kept for compatibility.
     HttpRequestFailure,
+    /// The request did not complete at the connection level, e.g. DNS failure, connection reset or timeout.
+    Transport {
+        kind: TransportErrorKind,
+        reason: String,
+    },
+    /// An attempt did not complete within [FCMClientBuilder::request_timeout].
+    Timeout {
+        timeout: Duration,
+    },
+    /// The request was not sent because the circuit of its host is open. See [circuit_breaker].
+    CircuitOpen {
+        retry_after: Duration,
+    },
+    /// The request was not sent because it exceeds an enforced [stats::QuotaBudget] of `project_id`.
+    QuotaExceeded {
+        project_id: String,
+        resets_in: Duration,
+    },
     DecodeFailure,
+    /// The request payload could not be serialized to JSON, e.g. a map with non-string keys.
+    SerializeFailure {
+        reason: String,
+    },
     DeserializeFailure {
         reason: String,
         source: String,
@@ -255,9 +2288,206 @@ pub enum RPCError {
     Internal {
         retry_after: Option<Duration>,
     },
+    /// The API rejected the request with `429 Too Many Requests`. Retried by default, honoring `Retry-After`.
+    /// `details` is the response body, e.g. a google.rpc.Status with `QUOTA_EXCEEDED`.
+    TooManyRequests {
+        details: Option<String>,
+        retry_after: Option<Duration>,
+    },
     Unknown(u16),
+    /// [RetryPolicy::deadline] ran out. `attempts` are all failed attempts, oldest first.
+    DeadlineExceeded {
+        deadline: Duration,
+        attempts: Vec<FailedAttempt>,
+    },
+    /// The caller cancelled the request before it completed, e.g. during shutdown.
+    Cancelled,
+    /// The response body was larger than [FCMClientBuilder::max_response_size] bytes, once decompressed.
+    ResponseTooLarge {
+        limit: usize,
+    },
+    /// `source` annotated with the request that failed.
+    /// Errors returned from [GenericGoogleRestAPISupport::request_with] are wrapped after retries have finished.
+    Request {
+        context: RequestContext,
+        source: Box<RPCError>,
+    },
+}
+
+/// A failed attempt of a request that exceeded [RetryPolicy::deadline].
+#[derive(Debug, Clone)]
+pub struct FailedAttempt {
+    /// Time from the start of the request to the start of this attempt.
+    pub started_after: Duration,
+    pub error: RPCError,
+}
+
+/// HTTP method and endpoint of a failed request, to tell which call failed in logs.
+/// Registration tokens in the endpoint are replaced with `<redacted>`.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct RequestContext {
+    pub method: String,
+    pub endpoint: String,
+    /// Metadata of the last response, unless the request failed before any response was received.
+    #[serde(default)]
+    pub response: Option<ResponseMetadata>,
+    /// Correlation ID sent with every attempt of the request, if any. See [FCMClient::with_correlation_id].
+    #[serde(default)]
+    pub correlation_id: Option<String>,
+}
+
+impl RequestContext {
+    pub(crate) fn new(method: &Method, endpoint: &str) -> Self {
+        Self {
+            method: method.to_string(),
+            endpoint: redact_endpoint(endpoint),
+            response: None,
+            correlation_id: None,
+        }
+    }
+}
+
+impl std::fmt::Display for RequestContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.method, self.endpoint)?;
+        if let Some(correlation_id) = &self.correlation_id {
+            write!(f, " (correlation id {correlation_id})")?;
+        }
+        match self
+            .response
+            .as_ref()
+            .and_then(ResponseMetadata::request_id)
+        {
+            Some(request_id) => write!(f, " (request id {request_id})"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Status and debug headers of a response, e.g. to quote the request ID when contacting Google support.
+///
+/// Only `x-goog-*`, `*request-id` and `Retry-After` headers are kept, with lowercase names.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct ResponseMetadata {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+}
+
+impl ResponseMetadata {
+    pub fn from_response<B>(res: &Response<B>) -> Self {
+        let headers = res
+            .headers()
+            .iter()
+            .filter(|(name, _)| {
+                let name = name.as_str();
+                name.starts_with("x-goog-") || name.ends_with("request-id") || name == RETRY_AFTER
+            })
+            .filter_map(|(name, value)| {
+                Some((name.as_str().to_string(), value.to_str().ok()?.to_string()))
+            })
+            .collect();
+        Self {
+            status: res.status().as_u16(),
+            headers,
+        }
+    }
+    /// The value of the first header named `name`, which must be lowercase.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+    /// The value of the first `*request-id` header, e.g. `x-goog-request-id`.
+    pub fn request_id(&self) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.ends_with("request-id"))
+            .map(|(_, value)| value.as_str())
+    }
+    pub fn retry_after(&self) -> Option<&str> {
+        self.header(RETRY_AFTER.as_str())
+    }
+}
+
+impl std::fmt::Display for ResponseMetadata {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut separator = "";
+        for (key, value) in &self.headers {
+            write!(f, "{separator}{key}={value}")?;
+            separator = " ";
+        }
+        Ok(())
+    }
+}
+
+/// Result of [GenericGoogleRestAPISupport::request_once] with the metadata of the response, if any was received.
+#[derive(Debug)]
+pub struct Attempt<R> {
+    pub result: Result<R, RPCError>,
+    pub response: Option<ResponseMetadata>,
+}
+
+impl<R> Attempt<R> {
+    /// An attempt that failed before any response was received.
+    pub fn failed(error: RPCError) -> Self {
+        Self {
+            result: Err(error),
+            response: None,
+        }
+    }
+}
+
+/// Replace registration tokens in Instance ID API paths, i.e. `/iid/info/{token}` and `/iid/v1/{token}/rel/...`.
+fn redact_endpoint(endpoint: &str) -> String {
+    let (path, query) = match endpoint.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (endpoint, None),
+    };
+    let segments = path.split('/').collect::<Vec<_>>();
+    let mut redacted = segments
+        .iter()
+        .enumerate()
+        .map(|(i, segment)| match (i.checked_sub(2), i.checked_sub(1)) {
+            (Some(iid), Some(parent))
+                if segments[iid] == "iid" && matches!(segments[parent], "info" | "v1") =>
+            {
+                "<redacted>"
+            }
+            _ => segment,
+        })
+        .collect::<Vec<_>>()
+        .join("/");
+    if let Some(query) = query {
+        redacted.push('?');
+        redacted.push_str(query);
+    }
+    redacted
 }
+
 impl RPCError {
+    /// The request that failed, if known.
+    pub fn context(&self) -> Option<&RequestContext> {
+        match self {
+            Self::Request { context, .. } => Some(context),
+            _ => None,
+        }
+    }
+    fn transport(e: &hyper_util::client::legacy::Error) -> Self {
+        let source = std::error::Error::source(e);
+        let kind = match source.and_then(|source| source.downcast_ref::<hyper::Error>()) {
+            _ if e.is_connect() => TransportErrorKind::Connect,
+            Some(e) if e.is_timeout() => TransportErrorKind::Timeout,
+            Some(e) if e.is_closed() || e.is_incomplete_message() || e.is_canceled() => {
+                TransportErrorKind::ConnectionClosed
+            }
+            _ => TransportErrorKind::Other,
+        };
+        Self::Transport {
+            kind,
+            reason: transport::error_chain(e),
+        }
+    }
     pub fn invalid_request() -> Self {
         Self::InvalidRequest { details: None }
     }
@@ -278,7 +2508,6 @@ impl RPCError {
 }
 
 #[cfg(test)]
-
 mod tests {
 
     #[cfg(feature = "fcm")]
@@ -291,10 +2520,534 @@ mod tests {
     use crate::fcm::*;
     #[cfg(feature = "topic-management")]
     use crate::topic::*;
-    use crate::FCMClient;
+    use crate::{
+        curl_command, sensitive_header_value, CorrelationId, FCMClient,
+        GenericGoogleRestAPISupport, RPCError, RequestContext, RetryDecision, RetryPolicy,
+        TransportErrorKind,
+    };
+    use http::Method;
     #[cfg(feature = "fcm")]
     use std::collections::HashMap;
+    use std::time::Duration;
+    #[test]
+    fn curl_command_substitutes_token() {
+        let command = curl_command(
+            "POST",
+            "https://example.com/v1",
+            &[("access_token_auth", "true")],
+            Some(br#"{"text":"it's"}"#),
+        );
+        assert_eq!(
+            command,
+            r#"curl -X POST 'https://example.com/v1' \
+  -H 'Content-Type: application/json' \
+  -H 'Accept: application/json' \
+  -H "Authorization: Bearer $TOKEN" \
+  -H 'access_token_auth: true' \
+  --data-raw '{"text":"it'\''s"}'"#
+        );
+    }
+    #[test]
+    fn retry_policy_classifies_errors() {
+        let policy = RetryPolicy::with_max_retries(3);
+        let connect = RPCError::Transport {
+            kind: TransportErrorKind::Connect,
+            reason: "dns error".to_string(),
+        };
+        let reset = RPCError::Transport {
+            kind: TransportErrorKind::ConnectionClosed,
+            reason: "connection reset".to_string(),
+        };
+        assert_eq!(
+            policy.delay(&Method::POST, &connect, 1),
+            Some(Duration::from_secs(1))
+        );
+        assert_eq!(policy.delay(&Method::POST, &reset, 0), None);
+        assert!(policy.delay(&Method::GET, &reset, 0).is_some());
+        assert_eq!(
+            policy.delay(
+                &Method::POST,
+                &RPCError::retryable_internal(Duration::from_secs(10)),
+                0
+            ),
+            Some(Duration::from_secs(10))
+        );
+        assert_eq!(
+            policy.delay(&Method::POST, &RPCError::invalid_request(), 0),
+            None
+        );
+        let too_many_requests = RPCError::TooManyRequests {
+            details: None,
+            retry_after: Some(Duration::from_secs(5)),
+        };
+        assert_eq!(
+            policy.delay(&Method::POST, &too_many_requests, 0),
+            Some(Duration::from_secs(5))
+        );
+
+        let policy = policy.with_classifier(|_: &Method, error: &RPCError, _: u32| match error {
+            RPCError::Transport {
+                kind: TransportErrorKind::ConnectionClosed,
+                ..
+            } => RetryDecision::Retry,
+            RPCError::Internal { .. } => RetryDecision::Stop,
+            _ => RetryDecision::Default,
+        });
+        assert_eq!(
+            policy.delay(&Method::POST, &reset, 1),
+            Some(Duration::from_secs(1))
+        );
+        assert_eq!(policy.delay(&Method::POST, &RPCError::internal(), 0), None);
+        assert!(policy.delay(&Method::POST, &connect, 0).is_some());
+    }
+    #[test]
+    fn tokens_are_redacted_from_request_context() {
+        assert_eq!(
+            RequestContext::new(
+                &Method::GET,
+                "https://iid.googleapis.com/iid/info/a-token?details=true"
+            )
+            .to_string(),
+            "GET https://iid.googleapis.com/iid/info/<redacted>?details=true"
+        );
+        assert_eq!(
+            RequestContext::new(
+                &Method::POST,
+                "https://iid.googleapis.com/iid/v1/a-token/rel/topics/news"
+            )
+            .endpoint,
+            "https://iid.googleapis.com/iid/v1/<redacted>/rel/topics/news"
+        );
+        assert_eq!(
+            RequestContext::new(&Method::POST, "https://iid.googleapis.com/iid/v1:batchAdd")
+                .endpoint,
+            "https://iid.googleapis.com/iid/v1:batchAdd"
+        );
+    }
+    #[test]
+    fn correlation_ids() {
+        assert_eq!(CorrelationId::Disabled.next(), None);
+        assert_eq!(
+            CorrelationId::Fixed("id".to_string()).next().as_deref(),
+            Some("id")
+        );
+        let generated = CorrelationId::Generated;
+        assert_ne!(generated.next(), generated.next());
+    }
+    #[test]
+    fn path_segments_are_percent_encoded() {
+        assert_eq!(
+            crate::encode_path_segment("fcm:APA91b-_x/y?z"),
+            "fcm:APA91b-_x%2Fy%3Fz"
+        );
+        assert_eq!(
+            crate::Query::new().append_to("https://example.com"),
+            "https://example.com"
+        );
+    }
+    #[test]
+    fn tls_backend_is_selected_at_runtime() {
+        let builder = crate::FCMClient::builder();
+        assert!(builder.clone().http2(false).https_connector().is_ok());
+        #[cfg(all(feature = "hyper-tls", feature = "hyper-rustls"))]
+        assert!(matches!(
+            builder
+                .tls_backend(crate::TlsBackend::Rustls)
+                .https_connector(),
+            Ok(crate::HttpsConnector::Rustls(_))
+        ));
+        #[cfg(not(feature = "hyper-rustls"))]
+        assert!(builder
+            .tls_backend(crate::TlsBackend::Rustls)
+            .https_connector()
+            .is_err());
+        #[cfg(not(feature = "hyper-tls"))]
+        assert!(builder
+            .tls_backend(crate::TlsBackend::NativeTls)
+            .https_connector()
+            .is_err());
+    }
+    #[tokio::test]
+    async fn idle_connections_are_recycled() {
+        use crate::transport::HttpTransport;
+        use http::Request;
+        use hyper::body::Bytes;
+        use std::sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        };
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}/", listener.local_addr().unwrap());
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let counter = accepted.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                counter.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(async move {
+                    let mut buf = [0; 1024];
+                    while matches!(stream.read(&mut buf).await, Ok(read) if read > 0) {
+                        let response = "HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\n{}";
+                        stream.write_all(response.as_bytes()).await.unwrap();
+                    }
+                });
+            }
+        });
+        let builder = FCMClient::builder()
+            .proxy(None)
+            .keep_alive_interval(Some(Duration::from_secs(30)));
+        for (idle_timeout, connections) in [(None, 1), (Some(Duration::from_millis(50)), 2)] {
+            accepted.store(0, Ordering::SeqCst);
+            let client = builder
+                .clone()
+                .pool_idle_timeout(idle_timeout)
+                .http_client()
+                .unwrap();
+            for _ in 0..2 {
+                let request = Request::get(&endpoint).body(Bytes::new()).unwrap();
+                client.send(request).await.unwrap();
+                tokio::time::sleep(Duration::from_millis(200)).await;
+            }
+            assert_eq!(accepted.load(Ordering::SeqCst), connections);
+        }
+    }
+    #[tokio::test]
+    async fn connections_are_recycled_after_max_lifetime() {
+        use crate::transport::{HttpTransport, PooledClient};
+        use http::Request;
+        use hyper::body::Bytes;
+        use std::sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        };
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}/", listener.local_addr().unwrap());
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let counter = accepted.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                counter.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(async move {
+                    let mut buf = [0; 1024];
+                    while matches!(stream.read(&mut buf).await, Ok(read) if read > 0) {
+                        let response = "HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\n{}";
+                        stream.write_all(response.as_bytes()).await.unwrap();
+                    }
+                });
+            }
+        });
+        let builder = FCMClient::builder().proxy(None).pool_idle_timeout(None);
+        for (lifetime, connections) in [(None, 1), (Some(Duration::from_millis(100)), 3)] {
+            accepted.store(0, Ordering::SeqCst);
+            let client = PooledClient::new(
+                builder.client_builder(),
+                builder.https_connector().unwrap(),
+                lifetime,
+            );
+            for _ in 0..3 {
+                let request = Request::get(&endpoint).body(Bytes::new()).unwrap();
+                client.send(request).await.unwrap();
+                tokio::time::sleep(Duration::from_millis(150)).await;
+            }
+            assert_eq!(accepted.load(Ordering::SeqCst), connections);
+        }
+    }
+
+    #[cfg(feature = "topic-management")]
+    #[tokio::test]
+    async fn too_many_requests_are_retried_unless_classified() {
+        use crate::{transport::HttpTransport, RetryDecision};
+        use async_trait::async_trait;
+        use http::{Request, Response, StatusCode};
+        use hyper::body::Bytes;
+        use std::{
+            collections::VecDeque,
+            sync::{Arc, Mutex},
+        };
+
+        const QUOTA_EXCEEDED: &str = r#"{"error": {"code": 429, "status": "RESOURCE_EXHAUSTED", "details": [{"@type": "type.googleapis.com/google.firebase.fcm.v1.FcmError", "errorCode": "QUOTA_EXCEEDED"}]}}"#;
+
+        #[derive(Default)]
+        struct Stub(Mutex<VecDeque<Response<Bytes>>>, Mutex<usize>);
+
+        #[async_trait]
+        impl HttpTransport for Stub {
+            async fn send(&self, _: Request<Bytes>) -> Result<Response<Bytes>, RPCError> {
+                *self.1.lock().unwrap() += 1;
+                Ok(self.0.lock().unwrap().pop_front().unwrap())
+            }
+        }
+        fn too_many_requests() -> Response<Bytes> {
+            Response::builder()
+                .status(StatusCode::TOO_MANY_REQUESTS)
+                .header("retry-after", "0")
+                .body(Bytes::from(QUOTA_EXCEEDED))
+                .unwrap()
+        }
+
+        let stub = Arc::new(Stub::default());
+        let transport: Arc<dyn HttpTransport> = stub.clone();
+        let client = FCMClient::builder()
+            .without_credentials()
+            .build_with_transport(transport)
+            .await
+            .unwrap()
+            .with_access_token("token")
+            .with_retry_policy(RetryPolicy {
+                max_retries: 1,
+                initial_backoff: Duration::ZERO,
+                ..Default::default()
+            });
+        stub.0.lock().unwrap().extend([
+            too_many_requests(),
+            Response::new(Bytes::from(r#"{"results": [{}]}"#)),
+        ]);
+        client
+            .register_tokens_to_topic("news", ["a"])
+            .await
+            .unwrap();
+        assert_eq!(*stub.1.lock().unwrap(), 2);
+
+        let client = client.with_retry_policy(RetryPolicy::with_max_retries(1).with_classifier(
+            |_: &Method, error: &RPCError, _: u32| match error {
+                RPCError::TooManyRequests {
+                    details: Some(details),
+                    ..
+                } if details.contains("QUOTA_EXCEEDED") => RetryDecision::Stop,
+                _ => RetryDecision::Default,
+            },
+        ));
+        stub.0.lock().unwrap().push_back(too_many_requests());
+        let error = client
+            .register_tokens_to_topic("news", ["a"])
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error.kind(),
+            TopicManagementError::TooManyRequests {
+                retry_after: Some(Duration::ZERO)
+            }
+        ));
+        assert_eq!(error.status_code(), http::StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(*stub.1.lock().unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn headers_given_by_caller_replace_defaults() {
+        use crate::transport::HttpTransport;
+        use async_trait::async_trait;
+        use http::{HeaderMap, Request, Response};
+        use hyper::body::Bytes;
+        use std::sync::{Arc, Mutex};
+
+        #[derive(Default)]
+        struct Stub(Mutex<Option<HeaderMap>>);
+
+        #[async_trait]
+        impl HttpTransport for Stub {
+            async fn send(&self, req: Request<Bytes>) -> Result<Response<Bytes>, RPCError> {
+                *self.0.lock().unwrap() = Some(req.headers().clone());
+                Ok(Response::new(Bytes::from("{}")))
+            }
+        }
+
+        let stub = Arc::new(Stub::default());
+        let transport: Arc<dyn HttpTransport> = stub.clone();
+        let client = FCMClient::builder()
+            .without_credentials()
+            .build_with_transport(transport)
+            .await
+            .unwrap()
+            .with_access_token("token");
+        client
+            .post_request_with::<_, serde_json::Value, RPCError>(
+                "https://fcm.googleapis.com/v1/projects/p/messages:send",
+                serde_json::json!({}),
+                &[("User-Agent", "my-app/1.0")],
+            )
+            .await
+            .unwrap();
+        let headers = stub.0.lock().unwrap().take().unwrap();
+        let user_agents = headers
+            .get_all(http::header::USER_AGENT)
+            .iter()
+            .collect::<Vec<_>>();
+        assert_eq!(user_agents, ["my-app/1.0"]);
+        assert_eq!(headers.get_all(crate::API_CLIENT_HEADER).iter().count(), 1);
+    }
+
+    #[cfg(feature = "topic-management")]
+    #[tokio::test]
+    async fn requests_go_to_overridden_base_url() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = [0; 1024];
+            let read = stream.read(&mut buf).await.unwrap();
+            let body = r#"{"platform":"WEBPUSH"}"#;
+            let response = format!(
+                "HTTP/1.1 200 OK\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                body.len()
+            );
+            stream.write_all(response.as_bytes()).await.unwrap();
+            String::from_utf8_lossy(&buf[..read]).to_string()
+        });
+        let client = FCMClient::builder()
+            .iid_base_url(&format!("http://{addr}/gateway/"))
+            .proxy(None)
+            .without_credentials()
+            .build()
+            .await
+            .unwrap()
+            .with_access_token("token");
+        client
+            .get_info_by_iid_token("a-token", false)
+            .await
+            .unwrap();
+        let request = server.await.unwrap();
+        assert!(
+            request.starts_with("GET /gateway/iid/info/a-token HTTP/1.1\r\n"),
+            "{request}"
+        );
+
+        for invalid in [
+            "localhost:8080",
+            "ftp://example.com",
+            "https://example.com?x=1",
+        ] {
+            assert!(FCMClient::builder()
+                .fcm_base_url(invalid)
+                .without_credentials()
+                .build()
+                .await
+                .is_err());
+        }
+    }
+    #[cfg(feature = "hyper-rustls")]
+    #[test]
+    fn custom_rustls_config_is_used() {
+        let mut config = rustls::ClientConfig::builder_with_provider(std::sync::Arc::new(
+            crate::FCMClientBuilder::crypto_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(rustls::RootCertStore::empty())
+        .with_no_client_auth();
+        config.alpn_protocols = vec![b"h2".to_vec()];
+        let connector = FCMClient::builder().rustls_config(config).https_connector();
+        #[cfg(feature = "hyper-tls")]
+        assert!(matches!(connector, Ok(crate::HttpsConnector::Rustls(_))));
+        #[cfg(not(feature = "hyper-tls"))]
+        assert!(connector.is_ok());
+    }
+    #[cfg(feature = "compression")]
+    #[tokio::test]
+    async fn compressed_responses_are_decoded() {
+        use crate::{GenericGoogleRestAPISupport, RPCError};
+        use std::io::Write;
+
+        let request = crate::build_request(Method::GET, "https://example.com", None, None, &[]);
+        assert_eq!(
+            request.unwrap().headers()["accept-encoding"],
+            "gzip, deflate"
+        );
+
+        let body = br#"{"topics": ["news"]}"#;
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(body).unwrap();
+        let mut deflate =
+            flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+        deflate.write_all(body).unwrap();
+        for (encoding, compressed) in [
+            ("gzip", gzip.finish().unwrap()),
+            ("deflate", deflate.finish().unwrap()),
+            ("identity", body.to_vec()),
+        ] {
+            let res = http::Response::builder()
+                .header("content-encoding", encoding)
+                .body(hyper::body::Bytes::from(compressed))
+                .unwrap();
+            let decoded: serde_json::Value =
+                <FCMClient as GenericGoogleRestAPISupport>::handle_response_body::<_, RPCError>(
+                    res,
+                )
+                .await
+                .unwrap();
+            assert_eq!(decoded["topics"][0], "news", "{encoding}");
+        }
+
+        let mut bomb = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+        bomb.write_all(&[b' '; 64 * 1024]).unwrap();
+        let res = http::Response::builder()
+            .header("content-encoding", "gzip")
+            .body(hyper::body::Bytes::from(bomb.finish().unwrap()))
+            .unwrap();
+        assert!(matches!(
+            <FCMClient as GenericGoogleRestAPISupport>::handle_response_body_with_limit::<
+                serde_json::Value,
+                RPCError,
+            >(res, Some(1024))
+            .await,
+            Err(RPCError::ResponseTooLarge { limit: 1024 })
+        ));
+
+        let res = http::Response::builder()
+            .header("content-encoding", "gzip")
+            .body(hyper::body::Bytes::from_static(b"not gzip"))
+            .unwrap();
+        assert!(matches!(
+            <FCMClient as GenericGoogleRestAPISupport>::handle_response_body::<
+                serde_json::Value,
+                RPCError,
+            >(res)
+            .await,
+            Err(RPCError::DecodeFailure)
+        ));
+    }
+    #[test]
+    fn tokens_are_scoped_to_firebase_messaging_by_default() {
+        use crate::{CLOUD_PLATFORM_SCOPE, FIREBASE_MESSAGING_SCOPE};
+        #[cfg(feature = "topic-management")]
+        assert_eq!(
+            FCMClient::builder().scopes,
+            [FIREBASE_MESSAGING_SCOPE, crate::IID_SCOPE]
+        );
+        #[cfg(not(feature = "topic-management"))]
+        assert_eq!(FCMClient::builder().scopes, [FIREBASE_MESSAGING_SCOPE]);
+        let scopes = [CLOUD_PLATFORM_SCOPE.to_string()];
+        assert_eq!(FCMClient::builder().scopes(&scopes).scopes, scopes);
+    }
+    #[tokio::test]
+    async fn access_token_bypasses_credentials() {
+        let client = FCMClient::builder()
+            .project_id("my-project")
+            .without_credentials()
+            .build()
+            .await
+            .unwrap();
+        assert!(client.get_header_token().await.is_err());
+        let client = client.with_access_token("minted-elsewhere");
+        assert_eq!(
+            client.get_header_token().await.unwrap(),
+            "Bearer minted-elsewhere"
+        );
+        assert!(!format!("{client:?}").contains("minted-elsewhere"));
+    }
+    #[test]
+    fn authorization_header_is_hidden_from_debug() {
+        let header = sensitive_header_value("Bearer secret".to_string()).unwrap();
+        assert!(!format!("{header:?}").contains("secret"));
+    }
     #[cfg(feature = "fcm")]
+    #[allow(deprecated)]
     #[tokio::test{flavor = "multi_thread"}]
     async fn full_message_payload_should_pass_validation() {
         let client = FCMClient::new().await.unwrap();
@@ -334,6 +3087,7 @@ mod tests {
         };
         let msg = Message::Topic {
             topic: "example".to_string(),
+            data: None,
             fcm_options: Some(FcmOptions::new("example")),
             notification: Some(Notification {
                 title: Some("example".to_string()),
@@ -408,9 +3162,12 @@ mod tests {
         let res = FCMClient::new()
             .await
             .expect("FCMClient initialization failed. Did you set GOOGLE_APPLICATION_CREDENTIALS?")
-            .register_token_to_topic("topic_name".into(), "")
+            .register_token_to_topic("topic_name", "")
             .await;
-        assert!(matches!(res, Err(TopicManagementError::InvalidRequest)));
+        assert!(matches!(
+            res.as_ref().map_err(TopicManagementError::kind),
+            Err(TopicManagementError::InvalidRequest)
+        ));
     }
     #[cfg(feature = "topic-management")]
     #[tokio::test{flavor = "multi_thread"}]
@@ -418,7 +3175,7 @@ mod tests {
         let res = FCMClient::new()
             .await
             .expect("FCMClient initialization failed. Did you set GOOGLE_APPLICATION_CREDENTIALS?")
-            .register_tokens_to_topic("topic_name".into(), vec!["".into(), "".into(), "".into()])
+            .register_tokens_to_topic("topic_name", ["", "", ""])
             .await
             .expect("Request Failed Due to: ");
         let error_results = res.results;
@@ -442,9 +3199,7 @@ mod tests {
             .expect("FCMClient initialization failed. Did you set GOOGLE_APPLICATION_CREDENTIALS?");
         let sts = c.get_info_by_iid_token(&tkn, true).await;
         let res = c.register_token_to_topic(&topic_name, &tkn).await;
-        let res = c
-            .unregister_tokens_from_topic(&topic_name, vec![tkn.clone().into()])
-            .await;
+        let res = c.unregister_tokens_from_topic(&topic_name, [&tkn]).await;
         let sts = c.get_info_by_iid_token(&tkn, true).await;
     }
 }
@@ -0,0 +1,125 @@
+//! OpenAPI schemas of messages and responses, for services that expose push-sending endpoints.
+//!
+//! Message and response types derive [utoipa::ToSchema] with the `utoipa` feature.
+//! They refer to each other by name, so register all of them by merging [FcmSchemas]
+//! or [TopicSchemas] into your own document.
+//!
+//! ```
+//! use firebase_messaging_rs::openapi::FcmSchemas;
+//! use utoipa::OpenApi;
+//!
+//! #[derive(OpenApi)]
+//! #[openapi(paths())]
+//! struct ApiDoc;
+//!
+//! let mut doc = ApiDoc::openapi();
+//! doc.merge(FcmSchemas::openapi());
+//! assert!(doc.components.unwrap().schemas.contains_key("Message"));
+//! ```
+#[cfg(feature = "fcm")]
+use crate::fcm::{
+    android::{
+        AndroidConfig, AndroidFcmOptions, AndroidMessagePriority, AndroidNotification, Color,
+        LightSettings, NotificationPriority, Proxy, Visibility,
+    },
+    ios::{
+        APNSFcmOptions, Alert, ApnsConfig, ApnsHeaders, ApnsPriority, ApnsPushType, Aps, RichAlert,
+    },
+    webpush::{
+        WebNotification, WebNotificationAction, WebNotificationDirection, WebPushConfig,
+        WebPushFcmOptions,
+    },
+    FcmOptions, Message, MessageName, MessageOutput, Notification,
+};
+#[cfg(feature = "topic-management")]
+use crate::topic::{
+    ImportResponse, ImportResult, Rel, TopicInfoResponseKind, TopicManagementResponse,
+};
+use utoipa::OpenApi;
+
+/// Schemas of [Message] and [MessageOutput] with everything they refer to.
+#[cfg(feature = "fcm")]
+#[derive(OpenApi)]
+#[openapi(components(schemas(
+    Message,
+    MessageOutput,
+    MessageName,
+    FcmOptions,
+    Notification,
+    AndroidConfig,
+    AndroidNotification,
+    AndroidFcmOptions,
+    AndroidMessagePriority,
+    LightSettings,
+    Color,
+    NotificationPriority,
+    Proxy,
+    Visibility,
+    ApnsConfig,
+    ApnsHeaders,
+    ApnsPriority,
+    ApnsPushType,
+    APNSFcmOptions,
+    Aps,
+    Alert,
+    RichAlert,
+    WebPushConfig,
+    WebPushFcmOptions,
+    WebNotification,
+    WebNotificationAction,
+    WebNotificationDirection,
+)))]
+pub struct FcmSchemas;
+
+/// Schemas of topic management and Instance ID responses with everything they refer to.
+#[cfg(feature = "topic-management")]
+#[derive(OpenApi)]
+#[openapi(components(schemas(
+    TopicManagementResponse,
+    TopicInfoResponseKind,
+    Rel,
+    ImportResponse,
+    ImportResult,
+)))]
+pub struct TopicSchemas;
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+    use utoipa::OpenApi;
+
+    fn refs<'a>(value: &'a Value, found: &mut Vec<&'a str>) {
+        match value {
+            Value::Object(object) => {
+                if let Some(Value::String(target)) = object.get("$ref") {
+                    found.push(target);
+                }
+                object.values().for_each(|value| refs(value, found));
+            }
+            Value::Array(values) => values.iter().for_each(|value| refs(value, found)),
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn every_reference_is_registered() {
+        let mut doc = utoipa::openapi::OpenApi::default();
+        #[cfg(feature = "fcm")]
+        doc.merge(super::FcmSchemas::openapi());
+        #[cfg(feature = "topic-management")]
+        doc.merge(super::TopicSchemas::openapi());
+        let doc = serde_json::to_value(doc).unwrap();
+        let schemas = &doc["components"]["schemas"];
+        let mut found = Vec::new();
+        refs(schemas, &mut found);
+        for target in found {
+            let name = target.trim_start_matches("#/components/schemas/");
+            assert!(schemas.get(name).is_some(), "{target} is not registered");
+        }
+        #[cfg(feature = "fcm")]
+        assert_eq!(
+            schemas["AndroidConfig"]["properties"]["ttl"]["type"],
+            "string"
+        );
+    }
+}
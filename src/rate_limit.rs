@@ -0,0 +1,197 @@
+//! Client-side rate limits that smooth bursts of requests to stay within per-project quotas.
+//!
+//! FCM limits the messages sent per project and the Instance ID API limits batch topic requests.
+//! Bulk callers exceeding them get `429 Too Many Requests`. With a [RateLimit] configured for an [EndpointClass],
+//! requests of that class wait for their turn before they are sent instead, including retries.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), String> {
+//! use firebase_messaging_rs::{
+//!     rate_limit::{EndpointClass, RateLimit},
+//!     FCMClient,
+//! };
+//!
+//! let client = FCMClient::builder()
+//!     .rate_limit(EndpointClass::Send, RateLimit::per_second(5_000.0))
+//!     .rate_limit(EndpointClass::TopicBatch, RateLimit::per_second(10.0).with_burst(20))
+//!     .build()
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+use std::{
+    collections::BTreeMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Group of endpoints sharing a quota.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EndpointClass {
+    /// `messages:send` of FCM v1.
+    Send,
+    /// `batchAdd`, `batchRemove` and `batchImport` of the Instance ID API.
+    TopicBatch,
+    /// Other requests of the Instance ID API, such as token info lookups and single topic subscriptions.
+    InstanceId,
+}
+
+impl EndpointClass {
+    /// Class of an operation named by [crate::audit::AuditRecord::operation], if it is rate limited.
+    pub(crate) fn of(operation: &str) -> Option<Self> {
+        match operation {
+            "fcm.send" => Some(Self::Send),
+            "iid.batchAdd" | "iid.batchRemove" | "iid.batchImport" => Some(Self::TopicBatch),
+            "iid.addTopic" | "iid.info" => Some(Self::InstanceId),
+            _ => None,
+        }
+    }
+}
+
+/// A token bucket refilled at `requests_per_second` that holds up to `burst` requests.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    requests_per_second: f64,
+    burst: u32,
+}
+
+impl RateLimit {
+    /// Allow `requests_per_second` on average. Values that are not positive are treated as one request per second.
+    pub fn per_second(requests_per_second: f64) -> Self {
+        let requests_per_second = if requests_per_second > 0.0 {
+            requests_per_second
+        } else {
+            1.0
+        };
+        Self {
+            requests_per_second,
+            burst: (requests_per_second.ceil() as u32).max(1),
+        }
+    }
+    /// See [RateLimit::burst]. `0` is treated as `1`.
+    pub fn with_burst(mut self, burst: u32) -> Self {
+        self.burst = burst.max(1);
+        self
+    }
+    pub fn requests_per_second(&self) -> f64 {
+        self.requests_per_second
+    }
+    /// Requests sent without waiting after the client has been idle. Defaults to one second worth of requests.
+    pub fn burst(&self) -> u32 {
+        self.burst
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(limit: &RateLimit, now: Instant) -> Self {
+        Self {
+            tokens: limit.burst as f64,
+            updated: now,
+        }
+    }
+    /// Take a token, going into debt if the bucket is empty, and return how long to wait for it.
+    fn reserve(&mut self, limit: &RateLimit, now: Instant) -> Duration {
+        let refill = now.duration_since(self.updated).as_secs_f64() * limit.requests_per_second;
+        self.tokens = (self.tokens + refill).min(limit.burst as f64);
+        self.updated = now;
+        self.tokens -= 1.0;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / limit.requests_per_second)
+        }
+    }
+}
+
+/// Rate limits of a client, shared by all its clones. Each project has its own buckets, like FCM quotas.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    limits: BTreeMap<EndpointClass, RateLimit>,
+    buckets: Mutex<BTreeMap<(String, EndpointClass), Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(limits: BTreeMap<EndpointClass, RateLimit>) -> Self {
+        Self {
+            limits,
+            buckets: Mutex::default(),
+        }
+    }
+    /// Reserve a request of `operation` to `project_id` and return how long to wait before sending it.
+    pub(crate) fn reserve(&self, project_id: &str, operation: &str) -> Duration {
+        self.reserve_at(project_id, operation, Instant::now())
+    }
+    fn reserve_at(&self, project_id: &str, operation: &str, now: Instant) -> Duration {
+        let (class, limit) = match EndpointClass::of(operation)
+            .and_then(|class| self.limits.get(&class).map(|limit| (class, limit)))
+        {
+            Some(found) => found,
+            None => return Duration::ZERO,
+        };
+        let mut buckets = match self.buckets.lock() {
+            Ok(buckets) => buckets,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        buckets
+            .entry((project_id.to_string(), class))
+            .or_insert_with(|| Bucket::new(limit, now))
+            .reserve(limit, now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EndpointClass, RateLimit, RateLimiter};
+    use std::{
+        collections::BTreeMap,
+        time::{Duration, Instant},
+    };
+
+    #[test]
+    fn requests_beyond_burst_are_spaced_out() {
+        let limiter = RateLimiter::new(BTreeMap::from([(
+            EndpointClass::TopicBatch,
+            RateLimit::per_second(10.0).with_burst(2),
+        )]));
+        let now = Instant::now();
+        let waits: Vec<_> = (0..4)
+            .map(|_| limiter.reserve_at("p", "iid.batchAdd", now))
+            .collect();
+        assert_eq!(waits[..2], [Duration::ZERO, Duration::ZERO]);
+        assert_eq!(waits[2], Duration::from_millis(100));
+        assert_eq!(waits[3], Duration::from_millis(200));
+        // The bucket refills over time, but never beyond the burst.
+        let later = now + Duration::from_secs(10);
+        assert_eq!(
+            limiter.reserve_at("p", "iid.batchRemove", later),
+            Duration::ZERO
+        );
+        assert_eq!(
+            limiter.reserve_at("p", "iid.batchRemove", later),
+            Duration::ZERO
+        );
+        assert!(limiter.reserve_at("p", "iid.batchImport", later) > Duration::ZERO);
+
+        assert_eq!(
+            limiter.reserve_at("other", "iid.batchAdd", now),
+            Duration::ZERO
+        );
+        assert_eq!(limiter.reserve_at("p", "fcm.send", now), Duration::ZERO);
+        assert_eq!(limiter.reserve_at("p", "other", now), Duration::ZERO);
+    }
+
+    #[test]
+    fn non_positive_rates_are_clamped() {
+        for rate in [0.0, -1.0, f64::NAN] {
+            let limit = RateLimit::per_second(rate).with_burst(0);
+            assert_eq!((limit.requests_per_second(), limit.burst()), (1.0, 1));
+        }
+    }
+}
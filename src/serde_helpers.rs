@@ -0,0 +1,179 @@
+//! `#[serde(with = ...)]` modules for the JSON encodings of protobuf well-known types used by FCM.
+//!
+//! - [duration]: [google.protobuf.Duration](https://protobuf.dev/reference/protobuf/google.protobuf/#duration) as `"3.5s"`
+//! - [timestamp]: [google.protobuf.Timestamp](https://protobuf.dev/reference/protobuf/google.protobuf/#timestamp) as `"2014-10-02T15:01:23.045Z"`
+//!
+//! ```
+//! use chrono::{DateTime, Utc};
+//! use firebase_messaging_rs::serde_helpers;
+//! use serde::{Deserialize, Serialize};
+//! use std::time::Duration;
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Campaign {
+//!     #[serde(with = "serde_helpers::duration")]
+//!     ttl: Duration,
+//!     #[serde(with = "serde_helpers::option_timestamp", default)]
+//!     starts_at: Option<DateTime<Utc>>,
+//! }
+//!
+//! let campaign: Campaign = serde_json::from_str(r#"{"ttl": "3.5s", "starts_at": "2024-01-01T09:00:00+09:00"}"#).unwrap();
+//! assert_eq!(campaign.ttl, Duration::from_millis(3500));
+//! assert_eq!(
+//!     serde_json::to_string(&campaign).unwrap(),
+//!     r#"{"ttl":"3.500s","starts_at":"2024-01-01T00:00:00Z"}"#
+//! );
+//! ```
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{de::Error, Deserialize, Deserializer, Serializer};
+use std::time::Duration;
+
+/// Render `duration` with 0, 3, 6 or 9 fractional digits as recommended by the protobuf JSON mapping.
+pub(crate) fn format_duration(duration: &Duration) -> String {
+    let secs = duration.as_secs();
+    let nanos = duration.subsec_nanos();
+    if nanos == 0 {
+        format!("{secs}s")
+    } else if nanos % 1_000_000 == 0 {
+        format!("{secs}.{:03}s", nanos / 1_000_000)
+    } else if nanos % 1_000 == 0 {
+        format!("{secs}.{:06}s", nanos / 1_000)
+    } else {
+        format!("{secs}.{nanos:09}s")
+    }
+}
+
+/// Parse a non-negative duration with `s` suffix and up to 9 fractional digits.
+pub(crate) fn parse_duration(s: &str) -> Option<Duration> {
+    let (secs, fraction) = match s.strip_suffix('s')?.split_once('.') {
+        Some((secs, fraction)) => (secs, Some(fraction)),
+        None => (s.strip_suffix('s')?, None),
+    };
+    if secs.is_empty() || !secs.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let nanos = match fraction {
+        Some(fraction)
+            if (1..=9).contains(&fraction.len())
+                && fraction.bytes().all(|b| b.is_ascii_digit()) =>
+        {
+            format!("{fraction:0<9}").parse().ok()?
+        }
+        Some(_) => return None,
+        None => 0,
+    };
+    Some(Duration::new(secs.parse().ok()?, nanos))
+}
+
+const DURATION_EXPECTED: &str = "a non-negative number of seconds with `s` suffix, e.g. \"3.5s\"";
+
+/// [std::time::Duration] as a protobuf Duration string.
+pub mod duration {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&format_duration(duration))
+    }
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        parse_duration(&s).ok_or_else(|| {
+            D::Error::invalid_value(serde::de::Unexpected::Str(&s), &DURATION_EXPECTED)
+        })
+    }
+}
+
+/// Optional [std::time::Duration] as a protobuf Duration string. Combine with `#[serde(default)]`.
+pub mod option_duration {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        duration: &Option<Duration>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match duration {
+            Some(duration) => super::duration::serialize(duration, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Duration>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|s| {
+                parse_duration(&s).ok_or_else(|| {
+                    D::Error::invalid_value(serde::de::Unexpected::Str(&s), &DURATION_EXPECTED)
+                })
+            })
+            .transpose()
+    }
+}
+
+/// [DateTime] as a protobuf Timestamp string in UTC.
+/// Any RFC 3339 offset is accepted on deserialization and converted to UTC.
+pub mod timestamp {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        timestamp: &DateTime<Utc>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&timestamp.to_rfc3339_opts(SecondsFormat::AutoSi, true))
+    }
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<DateTime<Utc>, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        DateTime::parse_from_rfc3339(&s)
+            .map(|timestamp| timestamp.with_timezone(&Utc))
+            .map_err(D::Error::custom)
+    }
+}
+
+/// Optional [DateTime] as a protobuf Timestamp string. Combine with `#[serde(default)]`.
+pub mod option_timestamp {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        timestamp: &Option<DateTime<Utc>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match timestamp {
+            Some(timestamp) => super::timestamp::serialize(timestamp, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<DateTime<Utc>>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|s| {
+                DateTime::parse_from_rfc3339(&s)
+                    .map(|timestamp| timestamp.with_timezone(&Utc))
+                    .map_err(D::Error::custom)
+            })
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_duration, parse_duration};
+    use std::time::Duration;
+
+    #[test]
+    fn durations_round_trip() {
+        for (text, duration) in [
+            ("3s", Duration::from_secs(3)),
+            ("3.500s", Duration::from_millis(3500)),
+            ("0.000001s", Duration::from_micros(1)),
+            ("3.000000001s", Duration::new(3, 1)),
+        ] {
+            assert_eq!(format_duration(&duration), text);
+            assert_eq!(parse_duration(text), Some(duration));
+        }
+        assert_eq!(parse_duration("3.5s"), Some(Duration::from_millis(3500)));
+        for invalid in ["3", "-1s", "s", ".5s", "1.s", "1.0000000001s", "1e3s"] {
+            assert_eq!(parse_duration(invalid), None, "{invalid}");
+        }
+    }
+}
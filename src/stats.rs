@@ -0,0 +1,298 @@
+//! Counters accumulated by a client since it was built. See [crate::FCMClient::stats].
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), String> {
+//! use firebase_messaging_rs::{stats::QuotaBudget, FCMClient};
+//! use std::time::Duration;
+//!
+//! let client = FCMClient::builder()
+//!     .quota_budget(QuotaBudget::new("fcm.send", 600_000, Duration::from_secs(60)))
+//!     .build()
+//!     .await?;
+//! let stats = client.stats();
+//! println!("sent {} messages, retried {} times", stats.requests_for("fcm.send"), stats.retries);
+//! # Ok(())
+//! # }
+//! ```
+use crate::auth::TokenEvent;
+use std::{
+    collections::BTreeMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Requests are counted per operation and response status.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RequestKey {
+    /// Operation name such as `fcm.send` or `iid.batchAdd`. See [crate::audit::AuditRecord::operation].
+    pub operation: String,
+    /// HTTP status of the response, or `None` if no response was received.
+    pub status: Option<u16>,
+}
+
+/// Point-in-time copy of the counters of a client.
+#[derive(Debug, Clone, Default)]
+pub struct StatsSnapshot {
+    /// Number of requests sent, including retries.
+    pub requests: BTreeMap<RequestKey, u64>,
+    /// Number of retries.
+    pub retries: u64,
+    /// Total time spent waiting before retries, including `Retry-After` delays.
+    pub retry_wait: Duration,
+    /// Number of retries delayed by a `Retry-After` header sent by the server.
+    pub rate_limit_waits: u64,
+    pub token_refreshes: u64,
+    pub token_failures: u64,
+    /// Usage of the configured [QuotaBudget] by project id. Each project has its own window.
+    pub quotas: BTreeMap<String, QuotaUsage>,
+}
+
+impl StatsSnapshot {
+    /// Number of requests of `operation` regardless of status.
+    pub fn requests_for(&self, operation: &str) -> u64 {
+        self.requests
+            .iter()
+            .filter(|(key, _)| key.operation == operation)
+            .map(|(_, count)| count)
+            .sum()
+    }
+}
+
+/// A request budget for an operation in a fixed time window, e.g. the FCM quota of sends per minute.
+///
+/// The budget applies to each project independently, like FCM quotas, so a client sending to several projects
+/// with [crate::FCMClient::with_project_id] cannot exhaust the budget of one project with requests to another.
+///
+/// The client logs a warning once per window when usage reaches `warn_ratio` of `limit`,
+/// and reports usage in [StatsSnapshot::quotas]. Requests beyond `limit` are sent anyway unless `enforce` is set.
+#[derive(Debug, Clone)]
+pub struct QuotaBudget {
+    pub operation: String,
+    pub limit: u64,
+    pub window: Duration,
+    /// Fraction of `limit` at which a warning is logged. Defaults to `0.8`.
+    pub warn_ratio: f64,
+    /// Fail requests beyond `limit` without sending them, with [crate::RPCError::QuotaExceeded] whose `resets_in`
+    /// is the time until the window of the project resets. They are not retried by default. Defaults to `false`.
+    pub enforce: bool,
+}
+
+impl QuotaBudget {
+    pub fn new(operation: &str, limit: u64, window: Duration) -> Self {
+        Self {
+            operation: operation.to_string(),
+            limit,
+            window,
+            warn_ratio: 0.8,
+            enforce: false,
+        }
+    }
+    pub fn with_warn_ratio(mut self, warn_ratio: f64) -> Self {
+        self.warn_ratio = warn_ratio;
+        self
+    }
+    /// Reject requests beyond `limit`. See [QuotaBudget::enforce].
+    pub fn enforced(mut self) -> Self {
+        self.enforce = true;
+        self
+    }
+}
+
+/// Usage of a [QuotaBudget] in the current window.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuotaUsage {
+    pub operation: String,
+    pub used: u64,
+    pub limit: u64,
+    /// Time until the current window ends.
+    pub resets_in: Duration,
+}
+
+#[derive(Debug)]
+struct QuotaWindow {
+    started: Instant,
+    used: u64,
+    warned: bool,
+}
+
+impl QuotaWindow {
+    fn new(now: Instant) -> Self {
+        Self {
+            started: now,
+            used: 0,
+            warned: false,
+        }
+    }
+    fn roll(&mut self, budget: &QuotaBudget, now: Instant) {
+        if now.duration_since(self.started) >= budget.window {
+            *self = Self::new(now);
+        }
+    }
+    fn resets_in(&self, budget: &QuotaBudget, now: Instant) -> Duration {
+        budget
+            .window
+            .saturating_sub(now.duration_since(self.started))
+    }
+    fn usage(&self, budget: &QuotaBudget, now: Instant) -> QuotaUsage {
+        QuotaUsage {
+            operation: budget.operation.clone(),
+            used: self.used,
+            limit: budget.limit,
+            resets_in: self.resets_in(budget, now),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    requests: BTreeMap<RequestKey, u64>,
+    retries: u64,
+    retry_wait: Duration,
+    rate_limit_waits: u64,
+    token_refreshes: u64,
+    token_failures: u64,
+    budget: Option<QuotaBudget>,
+    quotas: BTreeMap<String, QuotaWindow>,
+}
+
+/// Shared, thread-safe counters updated by the request helpers of [crate::GenericGoogleRestAPISupport].
+#[derive(Debug, Default)]
+pub struct ClientStats {
+    counters: Mutex<Counters>,
+}
+
+impl ClientStats {
+    pub fn new(quota: Option<QuotaBudget>) -> Self {
+        let counters = Counters {
+            budget: quota,
+            ..Default::default()
+        };
+        Self {
+            counters: Mutex::new(counters),
+        }
+    }
+    fn counters(&self) -> std::sync::MutexGuard<'_, Counters> {
+        match self.counters.lock() {
+            Ok(counters) => counters,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+    pub fn snapshot(&self) -> StatsSnapshot {
+        let counters = self.counters();
+        StatsSnapshot {
+            requests: counters.requests.clone(),
+            retries: counters.retries,
+            retry_wait: counters.retry_wait,
+            rate_limit_waits: counters.rate_limit_waits,
+            token_refreshes: counters.token_refreshes,
+            token_failures: counters.token_failures,
+            quotas: match &counters.budget {
+                Some(budget) => counters
+                    .quotas
+                    .iter()
+                    .map(|(project_id, quota)| {
+                        (project_id.clone(), quota.usage(budget, Instant::now()))
+                    })
+                    .collect(),
+                None => BTreeMap::new(),
+            },
+        }
+    }
+    /// Count a request of `operation` to `project_id` against the budget before it is sent.
+    /// Returns the time until the window resets if the budget is enforced and exhausted.
+    pub(crate) fn acquire(&self, project_id: &str, operation: &str) -> Result<(), Duration> {
+        let mut counters = self.counters();
+        let Counters { budget, quotas, .. } = &mut *counters;
+        let budget = match budget {
+            Some(budget) if budget.operation == operation => budget,
+            _ => return Ok(()),
+        };
+        let now = Instant::now();
+        let quota = quotas
+            .entry(project_id.to_string())
+            .or_insert_with(|| QuotaWindow::new(now));
+        quota.roll(budget, now);
+        if budget.enforce && quota.used >= budget.limit {
+            return Err(quota.resets_in(budget, now));
+        }
+        quota.used += 1;
+        let threshold = budget.limit as f64 * budget.warn_ratio;
+        if !quota.warned && quota.used as f64 >= threshold {
+            quota.warned = true;
+            log::warn!(
+                "{operation} used {} of {} requests allowed in {:?} for project {project_id}",
+                quota.used,
+                budget.limit,
+                budget.window
+            );
+        }
+        Ok(())
+    }
+    pub(crate) fn record_request(&self, operation: &str, status: Option<u16>) {
+        *self
+            .counters()
+            .requests
+            .entry(RequestKey {
+                operation: operation.to_string(),
+                status,
+            })
+            .or_default() += 1;
+    }
+    pub(crate) fn record_retry(&self, delay: Duration, rate_limited: bool) {
+        let mut counters = self.counters();
+        counters.retries += 1;
+        counters.retry_wait += delay;
+        if rate_limited {
+            counters.rate_limit_waits += 1;
+        }
+    }
+    pub(crate) fn record_token_event(&self, event: &TokenEvent) {
+        let mut counters = self.counters();
+        match event {
+            TokenEvent::Refreshed { .. } => counters.token_refreshes += 1,
+            TokenEvent::Failed { .. } => counters.token_failures += 1,
+            TokenEvent::CacheHit { .. } => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ClientStats, QuotaBudget};
+    use std::time::Duration;
+
+    #[test]
+    fn requests_are_counted_against_quota() {
+        let stats = ClientStats::new(Some(QuotaBudget::new(
+            "fcm.send",
+            2,
+            Duration::from_secs(60),
+        )));
+        for status in [Some(200), Some(200), None] {
+            assert!(stats.acquire("p", "fcm.send").is_ok());
+            stats.record_request("fcm.send", status);
+        }
+        assert!(stats.acquire("p", "iid.batchAdd").is_ok());
+        stats.record_request("iid.batchAdd", Some(500));
+        stats.record_retry(Duration::from_secs(1), true);
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.requests_for("fcm.send"), 3);
+        assert_eq!(snapshot.requests_for("iid.batchAdd"), 1);
+        assert_eq!((snapshot.retries, snapshot.rate_limit_waits), (1, 1));
+        assert_eq!(snapshot.retry_wait, Duration::from_secs(1));
+        let quota = &snapshot.quotas["p"];
+        assert_eq!((quota.used, quota.limit), (3, 2));
+    }
+
+    #[test]
+    fn enforced_budget_is_per_project() {
+        let stats = ClientStats::new(Some(
+            QuotaBudget::new("fcm.send", 1, Duration::from_secs(60)).enforced(),
+        ));
+        assert!(stats.acquire("noisy", "fcm.send").is_ok());
+        let resets_in = stats.acquire("noisy", "fcm.send").unwrap_err();
+        assert!(resets_in <= Duration::from_secs(60));
+        assert!(stats.acquire("quiet", "fcm.send").is_ok());
+        assert_eq!(stats.snapshot().quotas["noisy"].used, 1);
+    }
+}
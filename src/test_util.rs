@@ -0,0 +1,794 @@
+//! In-process fake of Google APIs for testing code built on top of this crate.
+//!
+//! [FakeClient] implements the same traits as [crate::FCMClient] but never opens a connection.
+//! Responses are scripted per request, so failures such as server errors with `Retry-After`,
+//! rate limiting, malformed JSON, slow responses or partially failed batches can be reproduced deterministically.
+//!
+//! ```
+//! # #[cfg(feature = "topic-management")]
+//! # async fn run() {
+//! use firebase_messaging_rs::test_util::{FakeClient, FakeResponse};
+//! use firebase_messaging_rs::topic::TopicManagementSupport;
+//! use firebase_messaging_rs::RetryPolicy;
+//! use std::time::Duration;
+//!
+//! let client = FakeClient::new().with_retry_policy(RetryPolicy::with_max_retries(1));
+//! client
+//!     .push(FakeResponse::server_error(Some(Duration::from_secs(1))))
+//!     .push(FakeResponse::batch(&[None, Some("NOT_FOUND")]));
+//! let response = client
+//!     .register_tokens_to_topic("news", ["a", "b"])
+//!     .await
+//!     .unwrap();
+//! assert_eq!(response.results.len(), 2);
+//! assert_eq!(client.requests().len(), 2);
+//! # }
+//! ```
+use crate::{
+    audit, auth, circuit_breaker, rate_limit, stats, Attempt, CorrelationId, FCMClient,
+    GenericGoogleRestAPISupport, HttpClient, RPCError, ResponseMetadata, RetryPolicy,
+    TransportErrorKind, CORRELATION_ID_HEADER,
+};
+use async_trait::async_trait;
+use http::{header::RETRY_AFTER, Method, Response, StatusCode};
+use hyper::body::Bytes;
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// A scripted response returned by [FakeClient].
+#[derive(Debug, Clone)]
+pub struct FakeResponse {
+    status: StatusCode,
+    headers: Vec<(String, String)>,
+    body: String,
+    delay: Option<Duration>,
+    transport_error: Option<TransportErrorKind>,
+}
+
+impl FakeResponse {
+    /// Respond with `status` and `body`.
+    pub fn new(status: StatusCode, body: &str) -> Self {
+        Self {
+            status,
+            headers: vec![],
+            body: body.to_string(),
+            delay: None,
+            transport_error: None,
+        }
+    }
+    /// Respond with 200 OK and a JSON body.
+    pub fn ok(body: &str) -> Self {
+        Self::new(StatusCode::OK, body)
+    }
+    /// Respond with 500 Internal Server Error, optionally with `Retry-After` header.
+    pub fn server_error(retry_after: Option<Duration>) -> Self {
+        let response = Self::new(StatusCode::INTERNAL_SERVER_ERROR, "");
+        match retry_after {
+            Some(retry_after) => {
+                response.with_header(RETRY_AFTER.as_str(), &retry_after.as_secs().to_string())
+            }
+            None => response,
+        }
+    }
+    /// Respond with 429 Too Many Requests.
+    pub fn too_many_requests() -> Self {
+        Self::new(StatusCode::TOO_MANY_REQUESTS, "")
+    }
+    /// Respond with 200 OK and a body that is not valid JSON.
+    pub fn malformed_json() -> Self {
+        Self::ok("{\"results\": [")
+    }
+    /// Respond to a topic batch request. `None` is a succeeded token and `Some(code)` is a failed one, e.g. `Some("NOT_FOUND")`.
+    pub fn batch(results: &[Option<&str>]) -> Self {
+        let results = results
+            .iter()
+            .map(|result| match result {
+                Some(code) => serde_json::json!({ "error": code }),
+                None => serde_json::json!({}),
+            })
+            .collect::<Vec<_>>();
+        Self::ok(&serde_json::json!({ "results": results }).to_string())
+    }
+    /// Fail at the connection level without any response.
+    pub fn transport_error(kind: TransportErrorKind) -> Self {
+        Self {
+            transport_error: Some(kind),
+            ..Self::new(StatusCode::OK, "")
+        }
+    }
+    /// Add a response header.
+    pub fn with_header(mut self, key: &str, value: &str) -> Self {
+        self.headers.push((key.to_string(), value.to_string()));
+        self
+    }
+    /// Wait for `delay` before responding.
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = Some(delay);
+        self
+    }
+}
+
+/// A request received by [FakeClient].
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub method: Method,
+    pub endpoint: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Option<Bytes>,
+}
+
+/// A client that answers requests with scripted [FakeResponse]s in the order they were pushed.
+/// When the script runs out, requests are answered with 200 OK and `{}`.
+#[derive(Clone)]
+pub struct FakeClient {
+    http_client: HttpClient,
+    project_id: String,
+    retry_policy: RetryPolicy,
+    responses: Arc<Mutex<VecDeque<FakeResponse>>>,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+    stats: Arc<stats::ClientStats>,
+    gzip_threshold: Option<usize>,
+    request_timeout: Option<Duration>,
+    max_response_size: Option<usize>,
+    rate_limits: BTreeMap<rate_limit::EndpointClass, rate_limit::RateLimit>,
+    rate_limiter: Arc<rate_limit::RateLimiter>,
+    circuit_breaker: Option<Arc<circuit_breaker::CircuitBreaker>>,
+    correlation_id: CorrelationId,
+}
+
+impl std::fmt::Debug for FakeClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FakeClient")
+            .field("project_id", &self.project_id)
+            .field("retry_policy", &self.retry_policy)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for FakeClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FakeClient {
+    pub fn new() -> Self {
+        let http_client = FCMClient::builder()
+            .http_client()
+            .expect("unable to initialize https connector");
+        Self {
+            http_client,
+            project_id: "fake-project".to_string(),
+            retry_policy: RetryPolicy::default(),
+            responses: Arc::default(),
+            requests: Arc::default(),
+            stats: Arc::default(),
+            gzip_threshold: None,
+            request_timeout: None,
+            max_response_size: None,
+            rate_limits: BTreeMap::new(),
+            rate_limiter: Arc::default(),
+            circuit_breaker: None,
+            correlation_id: CorrelationId::default(),
+        }
+    }
+    /// See [FCMClient::with_retry_policy].
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+    /// See [FCMClient::with_project_id].
+    pub fn with_project_id(mut self, project_id: &str) -> Self {
+        self.project_id = project_id.to_string();
+        self
+    }
+    /// See [crate::FCMClientBuilder::quota_budget]. Stats recorded so far are discarded.
+    pub fn with_quota_budget(mut self, budget: stats::QuotaBudget) -> Self {
+        self.stats = Arc::new(stats::ClientStats::new(Some(budget)));
+        self
+    }
+    /// See [crate::FCMClientBuilder::gzip_requests].
+    #[cfg(feature = "gzip")]
+    pub fn with_gzip_requests(mut self, min_bytes: Option<usize>) -> Self {
+        self.gzip_threshold = min_bytes;
+        self
+    }
+    /// See [crate::FCMClientBuilder::request_timeout]. Delays of [FakeResponse::with_delay] count towards it.
+    pub fn with_request_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.request_timeout = timeout;
+        self
+    }
+    /// See [crate::FCMClientBuilder::max_response_size]. Responses are not limited by default.
+    pub fn with_max_response_size(mut self, max_size: Option<usize>) -> Self {
+        self.max_response_size = max_size;
+        self
+    }
+    /// See [crate::FCMClientBuilder::rate_limit]. Requests reserved so far are forgotten.
+    pub fn with_rate_limit(
+        mut self,
+        class: rate_limit::EndpointClass,
+        limit: rate_limit::RateLimit,
+    ) -> Self {
+        self.rate_limits.insert(class, limit);
+        self.rate_limiter = Arc::new(rate_limit::RateLimiter::new(self.rate_limits.clone()));
+        self
+    }
+    /// See [crate::FCMClientBuilder::circuit_breaker]. Circuits start closed.
+    pub fn with_circuit_breaker(
+        mut self,
+        policy: Option<circuit_breaker::CircuitBreakerPolicy>,
+    ) -> Self {
+        self.circuit_breaker =
+            policy.map(|policy| Arc::new(circuit_breaker::CircuitBreaker::new(policy)));
+        self
+    }
+    /// See [FCMClient::with_correlation_id].
+    pub fn with_correlation_id(mut self, correlation_id: CorrelationId) -> Self {
+        self.correlation_id = correlation_id;
+        self
+    }
+    /// Append a response to the script.
+    pub fn push(&self, response: FakeResponse) -> &Self {
+        self.responses.lock().unwrap().push_back(response);
+        self
+    }
+    /// Requests received so far, including retries.
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+    /// See [FCMClient::stats].
+    pub fn stats(&self) -> stats::StatsSnapshot {
+        self.stats.snapshot()
+    }
+}
+
+#[cfg(feature = "topic-management")]
+impl crate::topic::TopicManagementSupport for FakeClient {}
+#[cfg(feature = "fcm")]
+impl crate::fcm::FCMApi for FakeClient {}
+
+#[async_trait]
+impl GenericGoogleRestAPISupport for FakeClient {
+    type Transport = HttpClient;
+    fn transport(&self) -> &HttpClient {
+        &self.http_client
+    }
+    fn project_id(&self) -> String {
+        self.project_id.clone()
+    }
+    fn retry_policy(&self) -> RetryPolicy {
+        self.retry_policy.clone()
+    }
+    fn client_stats(&self) -> Option<&stats::ClientStats> {
+        Some(&self.stats)
+    }
+    fn gzip_threshold(&self) -> Option<usize> {
+        self.gzip_threshold
+    }
+    fn request_timeout(&self) -> Option<Duration> {
+        self.request_timeout
+    }
+    fn max_response_size(&self) -> Option<usize> {
+        self.max_response_size
+    }
+    fn rate_limiter(&self) -> Option<&rate_limit::RateLimiter> {
+        Some(&self.rate_limiter)
+    }
+    fn circuit_breaker(&self) -> Option<&circuit_breaker::CircuitBreaker> {
+        self.circuit_breaker.as_deref()
+    }
+    fn correlation_id(&self) -> Option<String> {
+        self.correlation_id.next()
+    }
+    async fn get_header_token(&self) -> Result<String, auth::AuthError> {
+        Ok("Bearer fake-token".to_string())
+    }
+    async fn request_once<R: for<'a> Deserialize<'a> + Clone>(
+        &self,
+        method: Method,
+        endpoint: &str,
+        payload: Option<Bytes>,
+        extra_headers: &[(&str, &str)],
+        correlation_id: Option<&str>,
+    ) -> Attempt<R> {
+        if let Err(resets_in) = self
+            .stats
+            .acquire(&self.project_id, audit::operation_name(endpoint))
+        {
+            return Attempt::failed(RPCError::QuotaExceeded {
+                project_id: self.project_id.clone(),
+                resets_in,
+            });
+        }
+        let mut headers = extra_headers
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect::<Vec<_>>();
+        if let Some(request_id) = correlation_id {
+            headers.push((CORRELATION_ID_HEADER.to_string(), request_id.to_string()));
+        }
+        self.requests.lock().unwrap().push(RecordedRequest {
+            method,
+            endpoint: endpoint.to_string(),
+            headers,
+            body: payload,
+        });
+        let scripted = self.responses.lock().unwrap().pop_front();
+        let scripted = scripted.unwrap_or_else(|| FakeResponse::ok("{}"));
+        if let Some(delay) = scripted.delay {
+            tokio::time::sleep(delay).await;
+        }
+        let status = scripted
+            .transport_error
+            .is_none()
+            .then(|| scripted.status.as_u16());
+        self.stats
+            .record_request(audit::operation_name(endpoint), status);
+        if let Some(kind) = scripted.transport_error {
+            return Attempt::failed(RPCError::Transport {
+                kind,
+                reason: "injected by FakeClient".to_string(),
+            });
+        }
+        let mut builder = Response::builder().status(scripted.status);
+        for (key, value) in &scripted.headers {
+            builder = builder.header(key.as_str(), value.as_str());
+        }
+        let res = match builder.body(Bytes::from(scripted.body)) {
+            Ok(res) => res,
+            Err(e) => return Attempt::failed(RPCError::BuildRequestFailure(format!("{e:?}"))),
+        };
+        let response = ResponseMetadata::from_response(&res);
+        Attempt {
+            result: Self::handle_response_body_with_limit(res, self.max_response_size).await,
+            response: Some(response),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "topic-management"))]
+mod tests {
+    use super::{FakeClient, FakeResponse};
+    use crate::{
+        topic::{TopicManagementError, TopicManagementSupport},
+        CorrelationId, RPCError, RetryPolicy, TransportErrorKind, CORRELATION_ID_HEADER,
+    };
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn scripted_failures_are_returned_in_order() {
+        let client = FakeClient::new().with_retry_policy(RetryPolicy {
+            max_retries: 2,
+            initial_backoff: Duration::ZERO,
+            ..Default::default()
+        });
+        client
+            .push(FakeResponse::server_error(Some(Duration::ZERO)))
+            .push(FakeResponse::transport_error(TransportErrorKind::Connect))
+            .push(FakeResponse::batch(&[None, Some("NOT_FOUND")]))
+            .push(FakeResponse::new(http::StatusCode::BAD_REQUEST, ""))
+            .push(FakeResponse::malformed_json());
+        let tokens = vec!["a".to_string(), "b".to_string()];
+
+        let response = client
+            .register_tokens_to_topic("news", &tokens)
+            .await
+            .unwrap();
+        assert_eq!(client.requests().len(), 3);
+        assert_eq!(response.failures(&tokens)[0].token, "b");
+
+        let error = client
+            .unregister_tokens_from_topic("news", &tokens)
+            .await
+            .unwrap_err();
+        assert!(matches!(error.kind(), TopicManagementError::InvalidRequest));
+        assert_eq!(
+            error.context().unwrap().to_string(),
+            "POST https://iid.googleapis.com/iid/v1:batchRemove"
+        );
+
+        let error = client
+            .unregister_tokens_from_topic("news", tokens)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error.kind(),
+            TopicManagementError::InternalResponseError { .. }
+        ));
+        assert_eq!(client.requests().len(), 5);
+        let stats = client.stats();
+        assert_eq!((stats.requests_for("iid.batchAdd"), stats.retries), (3, 2));
+        assert_eq!(stats.rate_limit_waits, 1);
+    }
+
+    #[tokio::test]
+    async fn failed_chunk_keeps_results_of_earlier_chunks() {
+        use crate::topic::MAX_TOKENS_PER_REQUEST;
+
+        let client = FakeClient::new();
+        client
+            .push(FakeResponse::batch(&[None; MAX_TOKENS_PER_REQUEST]))
+            .push(FakeResponse::new(http::StatusCode::BAD_REQUEST, ""));
+        let tokens = vec!["a"; MAX_TOKENS_PER_REQUEST + 1];
+
+        let error = client
+            .register_tokens_to_topic("news", &tokens)
+            .await
+            .unwrap_err();
+        assert_eq!(error.status_code(), http::StatusCode::MULTI_STATUS);
+        match error {
+            TopicManagementError::ChunkFailure {
+                response,
+                failed_at,
+                source,
+            } => {
+                assert_eq!(response.results.len(), MAX_TOKENS_PER_REQUEST);
+                assert_eq!(failed_at, MAX_TOKENS_PER_REQUEST);
+                assert!(matches!(
+                    source.kind(),
+                    TopicManagementError::InvalidRequest
+                ));
+            }
+            other => panic!("unexpected error: {other:?}"),
+        }
+
+        client.push(FakeResponse::new(http::StatusCode::BAD_REQUEST, ""));
+        let error = client
+            .register_tokens_to_topic("news", &tokens)
+            .await
+            .unwrap_err();
+        assert!(matches!(error.kind(), TopicManagementError::InvalidRequest));
+        assert_eq!(client.requests().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn unserializable_payloads_are_rejected() {
+        use crate::{GenericGoogleRestAPISupport, RPCError};
+        use std::collections::HashMap;
+
+        let client = FakeClient::new();
+        let payload = HashMap::from([((1, 2), "non-string key")]);
+        let error = client
+            .post_request::<_, serde_json::Value, TopicManagementError>(
+                "https://iid.googleapis.com/iid/v1:batchAdd",
+                &payload,
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            TopicManagementError::SerializeFailure { .. }
+        ));
+        let error = client
+            .put_request::<_, serde_json::Value, RPCError>("https://example.com", &payload)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, RPCError::SerializeFailure { .. }));
+        assert!(client.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn deadline_bounds_all_attempts() {
+        let client = FakeClient::new().with_retry_policy(
+            RetryPolicy {
+                max_retries: 5,
+                initial_backoff: Duration::from_millis(10),
+                ..Default::default()
+            }
+            .with_deadline(Duration::from_millis(100)),
+        );
+        client
+            .push(FakeResponse::server_error(None))
+            .push(FakeResponse::batch(&[None]).with_delay(Duration::from_secs(5)));
+        let error = client
+            .register_tokens_to_topic("news", ["a"])
+            .await
+            .unwrap_err();
+        match error.kind() {
+            TopicManagementError::DeadlineExceeded { deadline, attempts } => {
+                assert_eq!(*deadline, Duration::from_millis(100));
+                assert_eq!(attempts.len(), 2);
+                assert!(attempts[0].started_after < Duration::from_millis(10));
+                assert!(matches!(attempts[1].error, RPCError::Timeout { .. }));
+            }
+            other => panic!("unexpected error: {other:?}"),
+        }
+        assert_eq!(client.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn slow_attempts_time_out() {
+        let client = FakeClient::new()
+            .with_request_timeout(Some(Duration::from_millis(50)))
+            .with_retry_policy(RetryPolicy {
+                max_retries: 1,
+                initial_backoff: Duration::from_millis(10),
+                ..Default::default()
+            });
+        client
+            .push(FakeResponse::ok("{}").with_delay(Duration::from_secs(5)))
+            .push(FakeResponse::ok(r#"{"platform":"WEBPUSH"}"#));
+        client.get_info_by_iid_token("a", false).await.unwrap();
+        assert_eq!(client.requests().len(), 2);
+
+        client.push(FakeResponse::batch(&[None]).with_delay(Duration::from_secs(5)));
+        let error = client
+            .register_tokens_to_topic("news", ["a"])
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error.kind(),
+            TopicManagementError::Timeout { timeout } if *timeout == Duration::from_millis(50)
+        ));
+        assert_eq!(client.requests().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn batches_are_spaced_out_by_rate_limit() {
+        use crate::rate_limit::{EndpointClass, RateLimit};
+
+        let client = FakeClient::new().with_rate_limit(
+            EndpointClass::TopicBatch,
+            RateLimit::per_second(20.0).with_burst(1),
+        );
+        for _ in 0..3 {
+            client.push(FakeResponse::batch(&[None]));
+        }
+        client.push(FakeResponse::ok(r#"{"platform":"WEBPUSH"}"#));
+        let started = std::time::Instant::now();
+        for _ in 0..3 {
+            client
+                .register_tokens_to_topic("news", ["a"])
+                .await
+                .unwrap();
+        }
+        assert!(started.elapsed() >= Duration::from_millis(100));
+        client.get_info_by_iid_token("a", false).await.unwrap();
+        assert_eq!(client.requests().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn open_circuit_fails_fast() {
+        use crate::circuit_breaker::{CircuitBreakerPolicy, CircuitState};
+
+        let client = FakeClient::new()
+            .with_circuit_breaker(Some(CircuitBreakerPolicy::new(2, Duration::from_secs(60))));
+        client
+            .push(FakeResponse::server_error(None))
+            .push(FakeResponse::server_error(None));
+        for _ in 0..3 {
+            assert!(client
+                .register_tokens_to_topic("news", ["a"])
+                .await
+                .is_err());
+        }
+        let error = client
+            .register_tokens_to_topic("news", ["a"])
+            .await
+            .unwrap_err();
+        match error.kind() {
+            TopicManagementError::CircuitOpen { retry_after } => {
+                assert!(*retry_after <= Duration::from_secs(60))
+            }
+            other => panic!("unexpected error: {other:?}"),
+        }
+        assert_eq!(error.status_code(), http::StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(client.requests().len(), 2);
+        assert_eq!(
+            client
+                .circuit_breaker
+                .as_ref()
+                .unwrap()
+                .state("iid.googleapis.com"),
+            CircuitState::Open
+        );
+    }
+
+    #[tokio::test]
+    async fn open_circuit_does_not_wait_for_rate_limit() {
+        use crate::{
+            circuit_breaker::CircuitBreakerPolicy,
+            rate_limit::{EndpointClass, RateLimit},
+        };
+
+        let client = FakeClient::new()
+            .with_rate_limit(EndpointClass::TopicBatch, RateLimit::per_second(1.0))
+            .with_circuit_breaker(Some(CircuitBreakerPolicy::new(1, Duration::from_secs(60))));
+        client.push(FakeResponse::server_error(None));
+        assert!(client
+            .register_tokens_to_topic("news", ["a"])
+            .await
+            .is_err());
+        let started = std::time::Instant::now();
+        for _ in 0..3 {
+            let error = client
+                .register_tokens_to_topic("news", ["a"])
+                .await
+                .unwrap_err();
+            assert!(matches!(
+                error.kind(),
+                TopicManagementError::CircuitOpen { .. }
+            ));
+        }
+        assert!(started.elapsed() < Duration::from_millis(500));
+        assert_eq!(client.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn oversized_responses_are_rejected() {
+        let topics = (0..1000)
+            .map(|i| format!(r#""topic-{i}": {{"addDate": "2024-01-01"}}"#))
+            .collect::<Vec<_>>()
+            .join(",");
+        let info = format!(
+            r#"{{"application": "app", "platform": "ANDROID", "rel": {{"topics": {{{topics}}}}}}}"#
+        );
+        let client = FakeClient::new().with_max_response_size(Some(16 * 1024));
+        client
+            .push(FakeResponse::ok(&info))
+            .push(FakeResponse::batch(&[None]));
+        let error = client
+            .get_info_by_iid_token("token", true)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error.kind(),
+            TopicManagementError::ResponseTooLarge { limit: 16384 }
+        ));
+        assert_eq!(error.status_code(), http::StatusCode::BAD_GATEWAY);
+        client
+            .register_tokens_to_topic("news", ["a"])
+            .await
+            .unwrap();
+    }
+
+    #[cfg(feature = "gzip")]
+    #[tokio::test]
+    async fn large_bodies_are_gzipped() {
+        use std::io::Read;
+
+        let client = FakeClient::new().with_gzip_requests(Some(100));
+        client
+            .push(FakeResponse::batch(&[None]))
+            .push(FakeResponse::batch(&[None]));
+        client
+            .register_tokens_to_topic("news", ["a"])
+            .await
+            .unwrap();
+        let tokens = vec!["t".repeat(200)];
+        client
+            .register_tokens_to_topic("news", &tokens)
+            .await
+            .unwrap();
+        let requests = client.requests();
+        let encoding = |i: usize| {
+            requests[i]
+                .headers
+                .iter()
+                .any(|(key, value)| key == "content-encoding" && value == "gzip")
+        };
+        assert!(!encoding(0));
+        assert!(encoding(1));
+        let mut body = String::new();
+        flate2::read::GzDecoder::new(requests[1].body.as_deref().unwrap())
+            .read_to_string(&mut body)
+            .unwrap();
+        assert!(body.contains(&tokens[0]));
+    }
+
+    #[tokio::test]
+    async fn response_metadata_is_attached_to_errors() {
+        let client = FakeClient::new();
+        client.push(
+            FakeResponse::new(http::StatusCode::BAD_REQUEST, "{}")
+                .with_header("x-goog-request-id", "abc")
+                .with_header("content-type", "application/json"),
+        );
+        let error = client
+            .register_tokens_to_topic("news", ["a"])
+            .await
+            .unwrap_err();
+        let context = error.context().unwrap();
+        let response = context.response.as_ref().unwrap();
+        assert_eq!((response.status, response.request_id()), (400, Some("abc")));
+        assert_eq!(response.header("content-type"), None);
+        assert_eq!(
+            context.to_string(),
+            "POST https://iid.googleapis.com/iid/v1:batchAdd (request id abc)"
+        );
+    }
+
+    #[tokio::test]
+    async fn retries_reuse_correlation_id() {
+        let client = FakeClient::new()
+            .with_correlation_id(CorrelationId::Generated)
+            .with_retry_policy(RetryPolicy {
+                max_retries: 2,
+                initial_backoff: Duration::ZERO,
+                ..Default::default()
+            });
+        client
+            .push(FakeResponse::server_error(Some(Duration::ZERO)))
+            .push(FakeResponse::transport_error(TransportErrorKind::Connect))
+            .push(FakeResponse::batch(&[None]))
+            .push(FakeResponse::new(http::StatusCode::BAD_REQUEST, "{}"));
+        client
+            .register_tokens_to_topic("news", ["a"])
+            .await
+            .unwrap();
+        let error = client
+            .register_tokens_to_topic("news", ["a"])
+            .await
+            .unwrap_err();
+        let ids = client
+            .requests()
+            .iter()
+            .map(|request| {
+                request
+                    .headers
+                    .iter()
+                    .find(|(key, _)| key == CORRELATION_ID_HEADER)
+                    .map(|(_, value)| value.clone())
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(ids.len(), 4);
+        assert!(ids[..3].iter().all(|id| *id == ids[0]));
+        assert_ne!(ids[3], ids[0]);
+        let context = error.context().unwrap();
+        assert_eq!(context.correlation_id.as_ref(), Some(&ids[3]));
+        assert_eq!(
+            context.to_string(),
+            format!(
+                "POST https://iid.googleapis.com/iid/v1:batchAdd (correlation id {})",
+                ids[3]
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn exhausted_quota_does_not_open_circuit() {
+        use crate::circuit_breaker::{CircuitBreakerPolicy, CircuitState};
+        use crate::stats::QuotaBudget;
+
+        let client = FakeClient::new()
+            .with_project_id("noisy")
+            .with_quota_budget(
+                QuotaBudget::new("iid.batchAdd", 1, Duration::from_secs(60)).enforced(),
+            )
+            .with_circuit_breaker(Some(CircuitBreakerPolicy::new(1, Duration::from_secs(60))))
+            .with_retry_policy(RetryPolicy::with_max_retries(2));
+        client.push(FakeResponse::batch(&[None]));
+        assert!(client.register_tokens_to_topic("news", ["a"]).await.is_ok());
+        let error = client
+            .register_tokens_to_topic("news", ["a"])
+            .await
+            .unwrap_err();
+        match error.kind() {
+            TopicManagementError::QuotaExceeded {
+                project_id,
+                resets_in,
+            } => {
+                assert_eq!(project_id, "noisy");
+                assert!(*resets_in <= Duration::from_secs(60));
+            }
+            other => panic!("unexpected error: {other:?}"),
+        }
+        assert_eq!(error.status_code(), http::StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(client.requests().len(), 1);
+        let stats = client.stats();
+        assert_eq!((stats.retries, stats.rate_limit_waits), (0, 0));
+        assert_eq!(
+            client
+                .circuit_breaker
+                .as_ref()
+                .unwrap()
+                .state("iid.googleapis.com"),
+            CircuitState::Closed
+        );
+    }
+}
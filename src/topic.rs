@@ -1,18 +1,66 @@
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
-use crate::{GenericGoogleRestAPISupport, RPCError};
+use crate::{
+    encode_path_segment, FailedAttempt, GenericGoogleRestAPISupport, Query, RPCError,
+    RequestContext, RetryPolicy, TransportErrorKind, IID_BASE_URL,
+};
 use async_trait::async_trait;
-const INFO_ENDPOINT: &str = "https://iid.googleapis.com/iid/info"; // + IID_TOKEN
+use futures::stream::{BoxStream, Stream, StreamExt};
+const INFO_PATH: &str = "/iid/info"; // + IID_TOKEN
 
-const BATCH_ENDPOINT: &str = "https://iid.googleapis.com/iid/v1";
+const BATCH_PATH: &str = "/iid/v1";
+
+/// Path of the relation between `iid_token` and `topic_name`.
+fn relation_path(iid_token: &str, topic_name: &str) -> String {
+    format!(
+        "{BATCH_PATH}/{}/rel/topics/{}",
+        encode_path_segment(iid_token),
+        encode_path_segment(topic_name)
+    )
+}
+
+/// The maximum number of registration tokens accepted by a single batch request.
+pub const MAX_TOKENS_PER_REQUEST: usize = 1000;
+
+/// Split tokens into batch-sized chunks. An empty list yields one empty chunk so that the API reports the error.
+fn chunks<T>(tokens: &[T]) -> Vec<&[T]> {
+    if tokens.is_empty() {
+        vec![tokens]
+    } else {
+        tokens.chunks(MAX_TOKENS_PER_REQUEST).collect()
+    }
+}
+
+/// Append the results of the `index`-th chunk to `response`.
+/// If a chunk after the first fails, the results of the chunks before it are kept in [TopicManagementError::ChunkFailure].
+fn append_chunk(
+    mut response: TopicManagementResponse,
+    index: usize,
+    result: Result<TopicManagementResponse, TopicManagementError>,
+) -> Result<TopicManagementResponse, TopicManagementError> {
+    match result {
+        Ok(chunk) => {
+            response.results.extend(chunk.results);
+            Ok(response)
+        }
+        Err(e) if index == 0 => Err(e),
+        Err(e) => Err(TopicManagementError::ChunkFailure {
+            response,
+            failed_at: index * MAX_TOKENS_PER_REQUEST,
+            source: Box::new(e),
+        }),
+    }
+}
 
 /// [TopicManagementSupport] trait support APIs in <https://developers.google.com/instance-id/reference/server>
 /// This trait provides topic management utilities.
 #[async_trait]
 pub trait TopicManagementSupport: GenericGoogleRestAPISupport {
+    /// Endpoint of [TopicManagementSupport::register_token_to_topic] under [IID_BASE_URL].
+    /// Requests are sent under [GenericGoogleRestAPISupport::iid_base_url] instead.
     fn put_endpoint(iid_token: &str, topic_name: &str) -> String {
-        format!("https://iid.googleapis.com/iid/v1/{iid_token}/rel/topics/{topic_name}")
+        format!("{IID_BASE_URL}{}", relation_path(iid_token, topic_name))
     }
     /// [[TopicManagementSupport::register_token_to_topic]] registers a token to topic.
     /// * topic - topic to follow. You don't need to add `/topics/` prefix.
@@ -29,49 +77,489 @@ pub trait TopicManagementSupport: GenericGoogleRestAPISupport {
         &self,
         topic: &str,
         token: &str,
-    ) -> Result<HashMap<String, String>, TopicManagementError> {
+    ) -> Result<TopicSubscription, TopicManagementError> {
         // `access_token_auth` enables authorization based on oauth2 access_token. Without this, We must use unsafe serverKey.
         // https://github.com/firebase/firebase-admin-go/blob/beaa6ae763d2fb57650760b9703cd91cc7c14b9b/messaging/topic_mgt.go#L69
-        self.post_request_with(
-            &Self::put_endpoint(token, topic),
-            (),
-            &[("access_token_auth", "true")],
-        )
-        .await
+        let raw = self
+            .post_request_with::<_, HashMap<String, String>, TopicManagementError>(
+                &format!("{}{}", self.iid_base_url(), relation_path(token, topic)),
+                (),
+                &[("access_token_auth", "true")],
+            )
+            .await?;
+        Ok(TopicSubscription {
+            topic: topic.to_string(),
+            token: token.to_string(),
+            error: raw
+                .get("error")
+                .map(|code| TopicErrorCode::from(code.as_str())),
+            raw,
+        })
     }
 
     /// [[TopicManagementSupport::register_tokens_to_topic]] registers tokens to topic.
     /// * topic - topic to follow. You don't need to add `/topics/` prefix.
-    /// * tokens - A non-empty list of device registration tokens to be associated with the topic. List may not have more than 1000 elements and any list element must not be empty.
-    async fn register_tokens_to_topic(
+    /// * tokens - A non-empty list of device registration tokens to be associated with the topic. Any list element must not be empty.
+    ///
+    /// Tokens are sent in chunks of [MAX_TOKENS_PER_REQUEST]. Results are concatenated in the order of `tokens`,
+    /// so use [TopicManagementResponse::failures] to find out which tokens failed.
+    /// If a chunk fails after earlier ones went through, [TopicManagementError::ChunkFailure] reports how far it got.
+    ///
+    /// `tokens` can be borrowed, e.g. `&tokens` or `tokens.iter()`, so the list need not be cloned.
+    async fn register_tokens_to_topic<I>(
         &self,
-        topic: String,
-        tokens: Vec<String>,
-    ) -> Result<TopicManagementResponse, TopicManagementError> {
-        let req = Request::subscribe(format!("/topics/{topic}"), tokens);
-        self.post_request_with(
-            &format!("{BATCH_ENDPOINT}:batchAdd"),
-            req,
-            &[("access_token_auth", "true")],
-        )
-        .await
+        topic: &str,
+        tokens: I,
+    ) -> Result<TopicManagementResponse, TopicManagementError>
+    where
+        I: IntoIterator + Send,
+        I::Item: AsRef<str> + Send,
+    {
+        let tokens = tokens.into_iter().collect::<Vec<_>>();
+        let tokens = tokens.iter().map(AsRef::as_ref).collect::<Vec<&str>>();
+        let mut response = TopicManagementResponse::default();
+        for (index, chunk) in chunks(&tokens).into_iter().enumerate() {
+            let req = Request::subscribe(format!("/topics/{topic}"), chunk);
+            let result = self
+                .post_request_with::<_, TopicManagementResponse, TopicManagementError>(
+                    &TopicOperation::Subscribe.endpoint(self.iid_base_url()),
+                    req,
+                    &[("access_token_auth", "true")],
+                )
+                .await;
+            response = append_chunk(response, index, result)?;
+        }
+        Ok(response)
     }
     /// [[TopicManagementSupport::unregister_tokens_from_topic]] unregisters tokens from topic.
     /// * topic - topic to follow. You don't need to add `/topics/` prefix.
-    /// * tokens - A non-empty list of device registration tokens to be unregistered from the topic. List may not have more than 1000 elements.
-    async fn unregister_tokens_from_topic(
+    /// * tokens - A non-empty list of device registration tokens to be unregistered from the topic.
+    ///   It can be borrowed like in [TopicManagementSupport::register_tokens_to_topic].
+    ///
+    /// Tokens are sent in chunks of [MAX_TOKENS_PER_REQUEST]. Results are concatenated in the order of `tokens`,
+    /// so use [TopicManagementResponse::failures] to find out which tokens failed.
+    /// If a chunk fails after earlier ones went through, [TopicManagementError::ChunkFailure] reports how far it got.
+    async fn unregister_tokens_from_topic<I>(
         &self,
         topic: &str,
-        tokens: Vec<String>,
-    ) -> Result<TopicManagementResponse, TopicManagementError> {
-        let req = Request::unsubscribe(format!("/topics/{topic}"), tokens);
-        self.post_request_with(
-            &format!("{BATCH_ENDPOINT}:batchRemove"),
-            req,
-            &[("access_token_auth", "true")],
-        )
+        tokens: I,
+    ) -> Result<TopicManagementResponse, TopicManagementError>
+    where
+        I: IntoIterator + Send,
+        I::Item: AsRef<str> + Send,
+    {
+        let tokens = tokens.into_iter().collect::<Vec<_>>();
+        let tokens = tokens.iter().map(AsRef::as_ref).collect::<Vec<&str>>();
+        let mut response = TopicManagementResponse::default();
+        for (index, chunk) in chunks(&tokens).into_iter().enumerate() {
+            let req = Request::unsubscribe(format!("/topics/{topic}"), chunk);
+            let result = self
+                .post_request_with::<_, TopicManagementResponse, TopicManagementError>(
+                    &TopicOperation::Unsubscribe.endpoint(self.iid_base_url()),
+                    req,
+                    &[("access_token_auth", "true")],
+                )
+                .await;
+            response = append_chunk(response, index, result)?;
+        }
+        Ok(response)
+    }
+    /// [[TopicManagementSupport::register_tokens_to_topic_with_retry]] registers tokens to topic like
+    /// [TopicManagementSupport::register_tokens_to_topic], then re-submits only the tokens whose result is retryable
+    /// (see [TopicErrorCode::is_retryable]) up to [RetryPolicy::max_retries] times, waiting with its backoff in between.
+    ///
+    /// The final result of each token is merged into one response in the order of `tokens`.
+    /// Failures of whole requests are retried according to the retry policy of the client, not `policy`.
+    /// If a re-submission still fails, the merged response is returned, and the tokens it did not cover keep
+    /// their last retryable result.
+    async fn register_tokens_to_topic_with_retry<I>(
+        &self,
+        topic: &str,
+        tokens: I,
+        policy: &RetryPolicy,
+    ) -> Result<TopicManagementResponse, TopicManagementError>
+    where
+        I: IntoIterator + Send,
+        I::Item: AsRef<str> + Send,
+        Self: Sync,
+    {
+        let tokens = tokens.into_iter().collect::<Vec<_>>();
+        let tokens = tokens.iter().map(AsRef::as_ref).collect::<Vec<&str>>();
+        resubmit_failed_tokens(&tokens, policy, |tokens| {
+            self.register_tokens_to_topic(topic, tokens)
+        })
+        .await
+    }
+    /// [[TopicManagementSupport::unregister_tokens_from_topic_with_retry]] unregisters tokens from topic
+    /// and re-submits only failed tokens like [TopicManagementSupport::register_tokens_to_topic_with_retry].
+    async fn unregister_tokens_from_topic_with_retry<I>(
+        &self,
+        topic: &str,
+        tokens: I,
+        policy: &RetryPolicy,
+    ) -> Result<TopicManagementResponse, TopicManagementError>
+    where
+        I: IntoIterator + Send,
+        I::Item: AsRef<str> + Send,
+        Self: Sync,
+    {
+        let tokens = tokens.into_iter().collect::<Vec<_>>();
+        let tokens = tokens.iter().map(AsRef::as_ref).collect::<Vec<&str>>();
+        resubmit_failed_tokens(&tokens, policy, |tokens| {
+            self.unregister_tokens_from_topic(topic, tokens)
+        })
         .await
     }
+    /// Same as [TopicManagementSupport::register_tokens_to_topic] with [TopicBatchOptions].
+    async fn register_tokens_to_topic_with_options<I>(
+        &self,
+        topic: &str,
+        tokens: I,
+        options: &TopicBatchOptions,
+    ) -> Result<TopicBatchResponse, TopicManagementError>
+    where
+        I: IntoIterator + Send,
+        I::Item: AsRef<str> + Send,
+        Self: Sync,
+    {
+        let tokens = tokens.into_iter().collect::<Vec<_>>();
+        let tokens = tokens.iter().map(AsRef::as_ref).collect::<Vec<&str>>();
+        submit_with_options(&tokens, options, |tokens| {
+            self.register_tokens_to_topic(topic, tokens)
+        })
+        .await
+    }
+    /// Same as [TopicManagementSupport::unregister_tokens_from_topic] with [TopicBatchOptions].
+    async fn unregister_tokens_from_topic_with_options<I>(
+        &self,
+        topic: &str,
+        tokens: I,
+        options: &TopicBatchOptions,
+    ) -> Result<TopicBatchResponse, TopicManagementError>
+    where
+        I: IntoIterator + Send,
+        I::Item: AsRef<str> + Send,
+        Self: Sync,
+    {
+        let tokens = tokens.into_iter().collect::<Vec<_>>();
+        let tokens = tokens.iter().map(AsRef::as_ref).collect::<Vec<&str>>();
+        submit_with_options(&tokens, options, |tokens| {
+            self.unregister_tokens_from_topic(topic, tokens)
+        })
+        .await
+    }
+    /// [[TopicManagementSupport::register_token_stream_to_topic]] registers tokens from a stream to topic.
+    /// * topic - topic to follow. You don't need to add `/topics/` prefix.
+    /// * tokens - registration tokens, e.g. read from a database cursor.
+    /// * concurrency - the maximum number of batch requests in flight.
+    ///
+    /// Tokens are grouped into batches of [MAX_TOKENS_PER_REQUEST] and the result of each batch is yielded in order.
+    /// Tokens are pulled from `tokens` only as fast as batches complete, so the whole list is never held in memory.
+    fn register_token_stream_to_topic<'a, S>(
+        &'a self,
+        topic: &'a str,
+        tokens: S,
+        concurrency: usize,
+    ) -> BoxStream<'a, TopicBatch>
+    where
+        S: Stream<Item = String> + Send + 'a,
+        Self: Sync,
+    {
+        tokens
+            .chunks(MAX_TOKENS_PER_REQUEST)
+            .enumerate()
+            .map(move |(index, tokens)| async move {
+                TopicBatch {
+                    offset: index * MAX_TOKENS_PER_REQUEST,
+                    result: self.register_tokens_to_topic(topic, &tokens).await,
+                    tokens,
+                }
+            })
+            .buffered(concurrency.max(1))
+            .boxed()
+    }
+    /// [[TopicManagementSupport::apply_topic_changes]] subscribes and unsubscribes tokens across many topics at once.
+    /// * changes - tokens to add to or remove from each topic.
+    /// * concurrency - the maximum number of batch requests in flight.
+    ///
+    /// Every topic is sent in chunks of [MAX_TOKENS_PER_REQUEST]. A failed batch does not stop the others;
+    /// inspect [TopicChangesReport] to find out which topics and tokens need another attempt.
+    async fn apply_topic_changes(
+        &self,
+        changes: &TopicChanges,
+        concurrency: usize,
+    ) -> TopicChangesReport
+    where
+        Self: Sync,
+    {
+        let requests = changes
+            .operations()
+            .flat_map(|(operation, topic, tokens)| {
+                tokens
+                    .chunks(MAX_TOKENS_PER_REQUEST)
+                    .enumerate()
+                    .map(move |(index, chunk)| (operation, topic, index, chunk))
+            })
+            .map(|(operation, topic, index, chunk)| {
+                let tokens = chunk.to_vec();
+                let topic = topic.to_string();
+                async move {
+                    let tokens_ref = tokens.iter().map(String::as_str).collect::<Vec<_>>();
+                    let req = Request::new(format!("/topics/{topic}"), &tokens_ref);
+                    let result = self
+                        .post_request_with::<_, TopicManagementResponse, TopicManagementError>(
+                            &operation.endpoint(self.iid_base_url()),
+                            req,
+                            &[("access_token_auth", "true")],
+                        )
+                        .await;
+                    TopicChange {
+                        topic,
+                        operation,
+                        batch: TopicBatch {
+                            offset: index * MAX_TOKENS_PER_REQUEST,
+                            result,
+                            tokens,
+                        },
+                    }
+                }
+            })
+            .collect::<Vec<_>>();
+        let batches = futures::stream::iter(requests)
+            .buffered(concurrency.max(1))
+            .collect()
+            .await;
+        TopicChangesReport { batches }
+    }
+    /// [[TopicManagementSupport::run_auto_subscriber]] applies [TopicEvent]s from `events` until the stream ends.
+    /// * events - e.g. the receiver of a `futures::channel::mpsc` channel fed by a signup flow.
+    /// * options - batching window and retry policy.
+    /// * on_result - called with the result of each topic and operation flushed.
+    ///
+    /// Events are grouped per topic and operation, and flushed once [AutoSubscriberOptions::window] has passed
+    /// since the first pending event, [MAX_TOKENS_PER_REQUEST] tokens are pending or the stream ends.
+    /// If a token is subscribed to and unsubscribed from a topic within one window, only the latest event is applied.
+    /// Tokens are sent like [TopicManagementSupport::register_tokens_to_topic_with_retry].
+    async fn run_auto_subscriber<S, F>(
+        &self,
+        events: S,
+        options: &AutoSubscriberOptions,
+        mut on_result: F,
+    ) where
+        S: Stream<Item = TopicEvent> + Send,
+        F: FnMut(TopicChange) + Send,
+        Self: Sync,
+    {
+        futures::pin_mut!(events);
+        let mut pending = TopicChanges::default();
+        let mut deadline = None;
+        loop {
+            let event = match deadline {
+                Some(deadline) => tokio::time::timeout_at(deadline, events.next()).await,
+                None => Ok(events.next().await),
+            };
+            let done = match event {
+                Ok(Some(event)) => {
+                    pending.record(event);
+                    deadline.get_or_insert_with(|| tokio::time::Instant::now() + options.window);
+                    if pending.token_count() < MAX_TOKENS_PER_REQUEST {
+                        continue;
+                    }
+                    false
+                }
+                Ok(None) => true,
+                Err(_elapsed) => false,
+            };
+            deadline = None;
+            let changes = std::mem::take(&mut pending);
+            for (operation, topic, tokens) in changes.operations() {
+                let result = match operation {
+                    TopicOperation::Subscribe => {
+                        self.register_tokens_to_topic_with_retry(
+                            topic,
+                            tokens,
+                            &options.retry_policy,
+                        )
+                        .await
+                    }
+                    TopicOperation::Unsubscribe => {
+                        self.unregister_tokens_from_topic_with_retry(
+                            topic,
+                            tokens,
+                            &options.retry_policy,
+                        )
+                        .await
+                    }
+                };
+                on_result(TopicChange {
+                    topic: topic.to_string(),
+                    operation,
+                    batch: TopicBatch {
+                        offset: 0,
+                        tokens: tokens.to_vec(),
+                        result,
+                    },
+                });
+            }
+            if done {
+                break;
+            }
+        }
+    }
+    /// [[TopicManagementSupport::plan_token_topics]] computes the changes needed so that `token` follows exactly `desired` topics.
+    /// Current subscriptions are fetched with [TopicManagementSupport::get_info_by_iid_token].
+    /// * token - registration token to reconcile.
+    /// * desired - topics the token should follow. You don't need to add `/topics/` prefix.
+    async fn plan_token_topics<D>(
+        &self,
+        token: &str,
+        desired: D,
+    ) -> Result<TopicPlan, TopicManagementError>
+    where
+        D: IntoIterator + Send,
+        D::Item: AsRef<str>,
+    {
+        let info = self.get_info_by_iid_token(token, true).await?;
+        let current = info.rel().map(|rel| rel.topics).unwrap_or_default();
+        Ok(TopicPlan::new(token, current.keys(), desired))
+    }
+    /// [[TopicManagementSupport::reconcile_token_topics]] computes a [TopicPlan] like [TopicManagementSupport::plan_token_topics] and applies it.
+    /// * concurrency - the maximum number of requests in flight.
+    async fn reconcile_token_topics<D>(
+        &self,
+        token: &str,
+        desired: D,
+        concurrency: usize,
+    ) -> Result<TopicReconciliation, TopicManagementError>
+    where
+        D: IntoIterator + Send,
+        D::Item: AsRef<str>,
+        Self: Sync,
+    {
+        let plan = self.plan_token_topics(token, desired).await?;
+        let report = self
+            .apply_topic_changes(&plan.to_changes(), concurrency)
+            .await;
+        Ok(TopicReconciliation { plan, report })
+    }
+    /// [[TopicManagementSupport::move_tokens]] moves tokens from `from_topic` to `to_topic`, e.g. to rename a topic.
+    ///
+    /// With [MoveOrder::AddFirst], tokens are removed from `from_topic` only if they were added to `to_topic`,
+    /// so no token stops receiving messages. With [MoveOrder::RemoveFirst], tokens are added to `to_topic` only
+    /// if they were removed from `from_topic`, so no token receives messages of both topics.
+    ///
+    /// Both steps are sent in chunks of [MAX_TOKENS_PER_REQUEST]. Per-token results are reported in [TopicMoveReport],
+    /// while a failure of a whole request aborts the move with the error.
+    async fn move_tokens<I>(
+        &self,
+        from_topic: &str,
+        to_topic: &str,
+        tokens: I,
+        order: MoveOrder,
+    ) -> Result<TopicMoveReport, TopicManagementError>
+    where
+        I: IntoIterator + Send,
+        I::Item: AsRef<str> + Send,
+    {
+        let tokens = tokens.into_iter().collect::<Vec<_>>();
+        let tokens = tokens.iter().map(AsRef::as_ref).collect::<Vec<&str>>();
+        let first = match order {
+            MoveOrder::AddFirst => self.register_tokens_to_topic(to_topic, &tokens).await?,
+            MoveOrder::RemoveFirst => {
+                self.unregister_tokens_from_topic(from_topic, &tokens)
+                    .await?
+            }
+        };
+        let first = first.token_results(tokens.len());
+        let pending = tokens
+            .iter()
+            .zip(&first)
+            .filter(|(_, result)| result.is_ok())
+            .map(|(token, _)| *token)
+            .collect::<Vec<_>>();
+        let second = match order {
+            _ if pending.is_empty() => TopicManagementResponse::default(),
+            MoveOrder::AddFirst => {
+                self.unregister_tokens_from_topic(from_topic, &pending)
+                    .await?
+            }
+            MoveOrder::RemoveFirst => self.register_tokens_to_topic(to_topic, &pending).await?,
+        };
+        let mut second = second.token_results(pending.len()).into_iter();
+        let tokens = tokens
+            .into_iter()
+            .zip(first)
+            .map(|(token, first)| {
+                let second = if first.is_ok() { second.next() } else { None };
+                let (added, removed) = match order {
+                    MoveOrder::AddFirst => (Some(first), second),
+                    MoveOrder::RemoveFirst => (second, Some(first)),
+                };
+                TokenMove {
+                    token: token.to_string(),
+                    added,
+                    removed,
+                }
+            })
+            .collect();
+        Ok(TopicMoveReport { tokens })
+    }
+    /// [[TopicManagementSupport::build_topic_index]] builds a reverse index from topics to the tokens following them,
+    /// since Google does not provide an API to list the tokens of a topic.
+    /// * tokens - registration tokens to look up with [TopicManagementSupport::get_info_by_iid_token].
+    /// * options - concurrency and rate limit of the lookups.
+    /// * on_progress - called after each token with the counts so far.
+    ///
+    /// Tokens whose information cannot be retrieved are reported in [TopicIndex::errors] and do not stop the others.
+    async fn build_topic_index<I, F>(
+        &self,
+        tokens: I,
+        options: &TopicIndexOptions,
+        mut on_progress: F,
+    ) -> TopicIndex
+    where
+        I: IntoIterator<Item = String> + Send,
+        I::IntoIter: Send,
+        F: FnMut(TopicIndexProgress) + Send,
+        Self: Sync,
+    {
+        let period = options
+            .max_requests_per_second
+            .filter(|rate| *rate > 0)
+            .map(|rate| std::time::Duration::from_secs(1) / rate);
+        let started = tokio::time::Instant::now();
+        let lookups = tokens
+            .into_iter()
+            .enumerate()
+            .map(|(index, token)| async move {
+                if let Some(period) = period {
+                    tokio::time::sleep_until(started + period.saturating_mul(index as u32)).await;
+                }
+                let info = self.get_info_by_iid_token(&token, true).await;
+                (token, info)
+            });
+        let lookups = futures::stream::iter(lookups).buffered(options.concurrency.max(1));
+        futures::pin_mut!(lookups);
+        let mut index = TopicIndex::default();
+        let mut progress = TopicIndexProgress::default();
+        while let Some((token, info)) = lookups.next().await {
+            progress.processed += 1;
+            match info {
+                Ok(info) => {
+                    let topics = info.rel().map(|rel| rel.topics).unwrap_or_default();
+                    for topic in topics.into_keys() {
+                        index.topics.entry(topic).or_default().push(token.clone());
+                    }
+                }
+                Err(error) => {
+                    progress.failed += 1;
+                    index.errors.push((token, error));
+                }
+            }
+            on_progress(progress);
+        }
+        index
+    }
     /// [[TopicManagementSupport::get_info_by_iid_token]] gets information about topics associated to the given token.
     /// Information may contain application id, authorized_entity, platform, etc.
     ///
@@ -85,31 +573,143 @@ pub trait TopicManagementSupport: GenericGoogleRestAPISupport {
         token: &str,
         details: bool,
     ) -> Result<TopicInfoResponseKind, TopicManagementError> {
-        let request_url = if details {
-            format!("{INFO_ENDPOINT}/{token}?details=true")
-        } else {
-            format!("{INFO_ENDPOINT}/{token}")
-        };
-        self.get_request_with(&request_url, &[("access_token_auth", "true")])
-            .await
+        let query = Query::new().param_opt("details", details.then_some(true));
+        self.get_request_with_query(
+            &format!(
+                "{}{INFO_PATH}/{}",
+                self.iid_base_url(),
+                encode_path_segment(token)
+            ),
+            &query,
+            &[("access_token_auth", "true")],
+        )
+        .await
     }
 }
 
-#[derive(Debug, Clone, Serialize)]
-struct Request {
+/// Send `tokens` with `send`, then send again only the tokens with retryable results and merge the results.
+/// Only the first send fails the whole operation. Later failures stop retrying and keep the results so far.
+async fn resubmit_failed_tokens<'a, F, Fut>(
+    tokens: &[&'a str],
+    policy: &RetryPolicy,
+    send: F,
+) -> Result<TopicManagementResponse, TopicManagementError>
+where
+    F: Fn(Vec<&'a str>) -> Fut,
+    Fut: std::future::Future<Output = Result<TopicManagementResponse, TopicManagementError>>,
+{
+    let mut response = send(tokens.to_vec()).await?;
+    for attempt in 0..policy.max_retries {
+        let pending = response
+            .results
+            .iter()
+            .enumerate()
+            .filter(|(_, result)| {
+                result.get("error").map_or(false, |code| {
+                    TopicErrorCode::from(code.as_str()).is_retryable()
+                })
+            })
+            .map(|(index, _)| index)
+            .collect::<Vec<_>>();
+        if pending.is_empty() {
+            break;
+        }
+        tokio::time::sleep(policy.backoff(attempt)).await;
+        let (retried, failed) =
+            match send(pending.iter().map(|&index| tokens[index]).collect()).await {
+                Ok(retried) => (retried, false),
+                Err(TopicManagementError::ChunkFailure {
+                    response: retried, ..
+                }) => (retried, true),
+                Err(e) => {
+                    log::warn!("re-submitting {} tokens failed: {e}", pending.len());
+                    break;
+                }
+            };
+        for (index, result) in pending.into_iter().zip(retried.results) {
+            response.results[index] = result;
+        }
+        if failed {
+            break;
+        }
+    }
+    Ok(response)
+}
+
+/// Send `tokens` with `send` as `options` specify and map the results back to the positions in `tokens`.
+async fn submit_with_options<'a, F, Fut>(
+    tokens: &[&'a str],
+    options: &TopicBatchOptions,
+    send: F,
+) -> Result<TopicBatchResponse, TopicManagementError>
+where
+    F: Fn(Vec<&'a str>) -> Fut,
+    Fut: std::future::Future<Output = Result<TopicManagementResponse, TopicManagementError>>,
+{
+    if !options.deduplicate {
+        return Ok(TopicBatchResponse {
+            response: send(tokens.to_vec()).await?,
+            duplicates_removed: 0,
+        });
+    }
+    let mut unique = Vec::new();
+    let mut first_seen = HashMap::new();
+    let positions = tokens
+        .iter()
+        .map(|&token| {
+            *first_seen.entry(token).or_insert_with(|| {
+                unique.push(token);
+                unique.len() - 1
+            })
+        })
+        .collect::<Vec<_>>();
+    let response = send(unique.clone()).await?;
+    let results = response
+        .token_results(unique.len())
+        .into_iter()
+        .map(|result| match result {
+            Ok(()) => HashMap::new(),
+            Err(code) => HashMap::from([("error".to_string(), code.to_string())]),
+        })
+        .collect::<Vec<_>>();
+    Ok(TopicBatchResponse {
+        response: TopicManagementResponse {
+            results: positions
+                .into_iter()
+                .map(|index| results[index].clone())
+                .collect(),
+        },
+        duplicates_removed: tokens.len() - unique.len(),
+    })
+}
+
+#[derive(Clone, Serialize)]
+struct Request<'a> {
     #[serde(rename = "to")]
     topic: String,
     #[serde(rename = "registration_tokens")]
-    tokens: Vec<String>,
+    tokens: &'a [&'a str],
 }
 
-impl Request {
-    fn subscribe(topic: String, tokens: Vec<String>) -> Self {
-        Self { topic, tokens }
+impl std::fmt::Debug for Request<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Request")
+            .field("topic", &self.topic)
+            .field("tokens", &format_args!("<{} redacted>", self.tokens.len()))
+            .finish()
     }
-    fn unsubscribe(topic: String, tokens: Vec<String>) -> Self {
+}
+
+impl<'a> Request<'a> {
+    fn new(topic: String, tokens: &'a [&'a str]) -> Self {
         Self { topic, tokens }
     }
+    fn subscribe(topic: String, tokens: &'a [&'a str]) -> Self {
+        Self::new(topic, tokens)
+    }
+    fn unsubscribe(topic: String, tokens: &'a [&'a str]) -> Self {
+        Self::new(topic, tokens)
+    }
 }
 // FIXME: better error modeling
 ///
@@ -129,10 +729,562 @@ impl Request {
 ///  ]
 ///}
 /// ```
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
 pub struct TopicManagementResponse {
     pub results: Vec<HashMap<String, String>>,
 }
+
+impl TopicManagementResponse {
+    /// Pair each failed result with the token at the same position in `tokens`.
+    /// `tokens` must be the list passed to the batch operation that returned this response.
+    pub fn failures<T: AsRef<str>>(&self, tokens: &[T]) -> Vec<TokenFailure> {
+        self.results
+            .iter()
+            .enumerate()
+            .filter_map(|(index, result)| {
+                result.get("error").map(|code| TokenFailure {
+                    index,
+                    token: tokens
+                        .get(index)
+                        .map(|token| token.as_ref().to_string())
+                        .unwrap_or_default(),
+                    code: TopicErrorCode::from(code.as_str()),
+                })
+            })
+            .collect()
+    }
+    /// Result of each of `len` tokens in order. A missing result is reported as an `UNKNOWN` error.
+    fn token_results(&self, len: usize) -> Vec<Result<(), TopicErrorCode>> {
+        (0..len)
+            .map(|index| match self.results.get(index) {
+                Some(result) => match result.get("error") {
+                    Some(code) => Err(TopicErrorCode::from(code.as_str())),
+                    None => Ok(()),
+                },
+                None => Err(TopicErrorCode::Other("UNKNOWN".to_string())),
+            })
+            .collect()
+    }
+    /// Return [TopicManagementError::PartialFailure] if any of `tokens` failed.
+    pub fn into_result<T: AsRef<str>>(self, tokens: &[T]) -> Result<Self, TopicManagementError> {
+        let failures = self.failures(tokens);
+        if failures.is_empty() {
+            Ok(self)
+        } else {
+            Err(TopicManagementError::PartialFailure {
+                succeeded: self.results.len() - failures.len(),
+                failures,
+            })
+        }
+    }
+}
+
+/// Options of [TopicManagementSupport::register_tokens_to_topic_with_options]
+/// and [TopicManagementSupport::unregister_tokens_from_topic_with_options].
+#[derive(Debug, Clone, Default)]
+pub struct TopicBatchOptions {
+    /// Send each distinct token only once. Duplicates get the result of the token they repeat.
+    pub deduplicate: bool,
+}
+
+impl TopicBatchOptions {
+    pub fn deduplicate(mut self, deduplicate: bool) -> Self {
+        self.deduplicate = deduplicate;
+        self
+    }
+}
+
+/// Response of a batch operation with [TopicBatchOptions].
+#[derive(Debug, Clone)]
+pub struct TopicBatchResponse {
+    /// One result for each of the submitted tokens, in their original order, duplicates included.
+    pub response: TopicManagementResponse,
+    /// How many tokens were not sent because they repeat an earlier one.
+    pub duplicates_removed: usize,
+}
+
+/// Result of a batch sent by [TopicManagementSupport::register_token_stream_to_topic].
+#[derive(Debug, Clone)]
+pub struct TopicBatch {
+    /// Position of the first token of this batch in the whole stream.
+    pub offset: usize,
+    pub tokens: Vec<String>,
+    pub result: Result<TopicManagementResponse, TopicManagementError>,
+}
+
+impl TopicBatch {
+    /// Failed tokens of this batch. [TokenFailure::index] is the position in the whole stream.
+    pub fn failures(&self) -> Vec<TokenFailure> {
+        match &self.result {
+            Ok(response) => response
+                .failures(&self.tokens)
+                .into_iter()
+                .map(|failure| TokenFailure {
+                    index: failure.index + self.offset,
+                    ..failure
+                })
+                .collect(),
+            Err(_) => vec![],
+        }
+    }
+}
+
+/// Whether tokens are added to or removed from a topic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TopicOperation {
+    Subscribe,
+    Unsubscribe,
+}
+
+impl TopicOperation {
+    fn endpoint(self, base_url: &str) -> String {
+        match self {
+            Self::Subscribe => format!("{base_url}{BATCH_PATH}:batchAdd"),
+            Self::Unsubscribe => format!("{base_url}{BATCH_PATH}:batchRemove"),
+        }
+    }
+}
+
+/// Tokens to subscribe to and unsubscribe from each topic. See [TopicManagementSupport::apply_topic_changes].
+///
+/// ```
+/// use firebase_messaging_rs::topic::TopicChanges;
+///
+/// let mut changes = TopicChanges::default();
+/// changes
+///     .subscribe("news", ["token-1".to_string(), "token-2".to_string()])
+///     .unsubscribe("sports", ["token-1".to_string()]);
+/// // or collect `(topic, token)` pairs to subscribe.
+/// let changes: TopicChanges = vec![("news".to_string(), "token-1".to_string())].into_iter().collect();
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct TopicChanges {
+    subscribe: BTreeMap<String, Vec<String>>,
+    unsubscribe: BTreeMap<String, Vec<String>>,
+}
+
+impl TopicChanges {
+    /// Add `tokens` to `topic`. You don't need to add `/topics/` prefix.
+    pub fn subscribe<I>(&mut self, topic: &str, tokens: I) -> &mut Self
+    where
+        I: IntoIterator<Item = String>,
+    {
+        self.subscribe
+            .entry(topic.to_string())
+            .or_default()
+            .extend(tokens);
+        self
+    }
+    /// Remove `tokens` from `topic`. You don't need to add `/topics/` prefix.
+    pub fn unsubscribe<I>(&mut self, topic: &str, tokens: I) -> &mut Self
+    where
+        I: IntoIterator<Item = String>,
+    {
+        self.unsubscribe
+            .entry(topic.to_string())
+            .or_default()
+            .extend(tokens);
+        self
+    }
+    pub fn is_empty(&self) -> bool {
+        self.operations().next().is_none()
+    }
+    /// Number of batch requests needed to apply these changes.
+    pub fn batch_count(&self) -> usize {
+        self.operations()
+            .map(|(_, _, tokens)| {
+                (tokens.len() + MAX_TOKENS_PER_REQUEST - 1) / MAX_TOKENS_PER_REQUEST
+            })
+            .sum()
+    }
+    /// Add the token of `event` to its topic and operation, cancelling a pending opposite operation.
+    fn record(&mut self, event: TopicEvent) {
+        let (list, opposite) = match event.operation {
+            TopicOperation::Subscribe => (&mut self.subscribe, &mut self.unsubscribe),
+            TopicOperation::Unsubscribe => (&mut self.unsubscribe, &mut self.subscribe),
+        };
+        if let Some(tokens) = opposite.get_mut(&event.topic) {
+            tokens.retain(|token| *token != event.token);
+        }
+        let tokens = list.entry(event.topic).or_default();
+        if !tokens.contains(&event.token) {
+            tokens.push(event.token);
+        }
+    }
+    fn token_count(&self) -> usize {
+        self.operations().map(|(_, _, tokens)| tokens.len()).sum()
+    }
+    fn operations(&self) -> impl Iterator<Item = (TopicOperation, &str, &[String])> {
+        let subscribe = self
+            .subscribe
+            .iter()
+            .map(|(topic, tokens)| (TopicOperation::Subscribe, topic.as_str(), tokens.as_slice()));
+        let unsubscribe = self.unsubscribe.iter().map(|(topic, tokens)| {
+            (
+                TopicOperation::Unsubscribe,
+                topic.as_str(),
+                tokens.as_slice(),
+            )
+        });
+        subscribe
+            .chain(unsubscribe)
+            .filter(|(_, _, tokens)| !tokens.is_empty())
+    }
+}
+
+impl From<HashMap<String, Vec<String>>> for TopicChanges {
+    /// Subscribe tokens to each topic.
+    fn from(subscribe: HashMap<String, Vec<String>>) -> Self {
+        Self {
+            subscribe: subscribe.into_iter().collect(),
+            unsubscribe: BTreeMap::new(),
+        }
+    }
+}
+
+impl FromIterator<(String, String)> for TopicChanges {
+    /// Subscribe each token to the paired topic.
+    fn from_iter<T: IntoIterator<Item = (String, String)>>(pairs: T) -> Self {
+        let mut changes = Self::default();
+        for (topic, token) in pairs {
+            changes.subscribe(&topic, [token]);
+        }
+        changes
+    }
+}
+
+/// A request to subscribe a token to or unsubscribe it from a topic. See [TopicManagementSupport::run_auto_subscriber].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TopicEvent {
+    pub token: String,
+    /// Topic name without `/topics/` prefix.
+    pub topic: String,
+    pub operation: TopicOperation,
+}
+
+impl TopicEvent {
+    pub fn subscribe(token: &str, topic: &str) -> Self {
+        Self {
+            token: token.to_string(),
+            topic: topic.to_string(),
+            operation: TopicOperation::Subscribe,
+        }
+    }
+    pub fn unsubscribe(token: &str, topic: &str) -> Self {
+        Self {
+            token: token.to_string(),
+            topic: topic.to_string(),
+            operation: TopicOperation::Unsubscribe,
+        }
+    }
+}
+
+/// Options of [TopicManagementSupport::run_auto_subscriber].
+#[derive(Debug, Clone)]
+pub struct AutoSubscriberOptions {
+    /// How long events are collected before they are sent. Defaults to 1 second.
+    pub window: std::time::Duration,
+    /// Policy to re-submit tokens with retryable results. Defaults to 3 retries.
+    pub retry_policy: RetryPolicy,
+}
+
+impl Default for AutoSubscriberOptions {
+    fn default() -> Self {
+        Self {
+            window: std::time::Duration::from_secs(1),
+            retry_policy: RetryPolicy::with_max_retries(3),
+        }
+    }
+}
+
+/// Changes that bring the subscriptions of a token to a desired set of topics.
+/// See [TopicManagementSupport::plan_token_topics].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TopicPlan {
+    pub token: String,
+    /// Desired topics the token does not follow yet, in sorted order.
+    pub subscribe: Vec<String>,
+    /// Current topics the token should no longer follow, in sorted order.
+    pub unsubscribe: Vec<String>,
+}
+
+impl TopicPlan {
+    /// Compare `current` subscriptions of `token` with `desired` topics.
+    pub fn new<C, D>(token: &str, current: C, desired: D) -> Self
+    where
+        C: IntoIterator,
+        C::Item: AsRef<str>,
+        D: IntoIterator,
+        D::Item: AsRef<str>,
+    {
+        let current = current
+            .into_iter()
+            .map(|topic| topic.as_ref().to_string())
+            .collect::<BTreeSet<_>>();
+        let desired = desired
+            .into_iter()
+            .map(|topic| topic.as_ref().to_string())
+            .collect::<BTreeSet<_>>();
+        Self {
+            token: token.to_string(),
+            subscribe: desired.difference(&current).cloned().collect(),
+            unsubscribe: current.difference(&desired).cloned().collect(),
+        }
+    }
+    /// `true` if the token already follows exactly the desired topics.
+    pub fn is_empty(&self) -> bool {
+        self.subscribe.is_empty() && self.unsubscribe.is_empty()
+    }
+    /// Express this plan as [TopicChanges] for [TopicManagementSupport::apply_topic_changes].
+    pub fn to_changes(&self) -> TopicChanges {
+        let mut changes = TopicChanges::default();
+        for topic in &self.subscribe {
+            changes.subscribe(topic, [self.token.clone()]);
+        }
+        for topic in &self.unsubscribe {
+            changes.unsubscribe(topic, [self.token.clone()]);
+        }
+        changes
+    }
+}
+
+/// Result of [TopicManagementSupport::reconcile_token_topics].
+#[derive(Debug, Clone)]
+pub struct TopicReconciliation {
+    pub plan: TopicPlan,
+    pub report: TopicChangesReport,
+}
+
+/// Result of a batch sent by [TopicManagementSupport::apply_topic_changes].
+#[derive(Debug, Clone)]
+pub struct TopicChange {
+    pub topic: String,
+    pub operation: TopicOperation,
+    /// [TopicBatch::offset] is the position in the tokens given for this topic and operation.
+    pub batch: TopicBatch,
+}
+
+/// Consolidated result of [TopicManagementSupport::apply_topic_changes].
+#[derive(Debug, Clone, Default)]
+pub struct TopicChangesReport {
+    /// Results in the order of topics, subscriptions first.
+    pub batches: Vec<TopicChange>,
+}
+
+impl TopicChangesReport {
+    /// `true` if every batch succeeded and no token was rejected.
+    pub fn is_success(&self) -> bool {
+        self.errors().next().is_none() && self.failures().next().is_none()
+    }
+    /// Batches that failed as a whole, e.g. because of authorization or transport errors.
+    pub fn errors(&self) -> impl Iterator<Item = (&TopicChange, &TopicManagementError)> {
+        self.batches
+            .iter()
+            .filter_map(|change| change.batch.result.as_ref().err().map(|e| (change, e)))
+    }
+    /// Tokens rejected individually, paired with their topic and operation.
+    pub fn failures(&self) -> impl Iterator<Item = (&str, TopicOperation, TokenFailure)> + '_ {
+        self.batches.iter().flat_map(|change| {
+            change
+                .batch
+                .failures()
+                .into_iter()
+                .map(move |failure| (change.topic.as_str(), change.operation, failure))
+        })
+    }
+    /// Number of tokens accepted by the API.
+    pub fn succeeded(&self) -> usize {
+        self.batches
+            .iter()
+            .filter_map(|change| {
+                let response = change.batch.result.as_ref().ok()?;
+                Some(response.results.len() - change.batch.failures().len())
+            })
+            .sum()
+    }
+}
+
+/// Which step of [TopicManagementSupport::move_tokens] runs first.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MoveOrder {
+    /// Add tokens to the new topic, then remove the added ones from the old topic.
+    #[default]
+    AddFirst,
+    /// Remove tokens from the old topic, then add the removed ones to the new topic.
+    RemoveFirst,
+}
+
+/// Result of a token moved by [TopicManagementSupport::move_tokens].
+/// A step is `None` if it was skipped because the other step failed first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenMove {
+    pub token: String,
+    pub added: Option<Result<(), TopicErrorCode>>,
+    pub removed: Option<Result<(), TopicErrorCode>>,
+}
+
+impl TokenMove {
+    /// `true` if the token was both added to the new topic and removed from the old topic.
+    pub fn is_moved(&self) -> bool {
+        matches!((&self.added, &self.removed), (Some(Ok(())), Some(Ok(()))))
+    }
+}
+
+/// Report of [TopicManagementSupport::move_tokens] in the order of the given tokens.
+#[derive(Debug, Clone, Default)]
+pub struct TopicMoveReport {
+    pub tokens: Vec<TokenMove>,
+}
+
+impl TopicMoveReport {
+    /// Number of tokens moved completely.
+    pub fn moved(&self) -> usize {
+        self.tokens.iter().filter(|token| token.is_moved()).count()
+    }
+    /// Tokens that were not moved completely.
+    pub fn failures(&self) -> impl Iterator<Item = &TokenMove> {
+        self.tokens.iter().filter(|token| !token.is_moved())
+    }
+}
+
+/// Options of [TopicManagementSupport::build_topic_index].
+#[derive(Debug, Clone)]
+pub struct TopicIndexOptions {
+    /// The maximum number of lookups in flight. Defaults to 8.
+    pub concurrency: usize,
+    /// The maximum number of lookups started per second. Unlimited by default.
+    pub max_requests_per_second: Option<u32>,
+}
+
+impl Default for TopicIndexOptions {
+    fn default() -> Self {
+        Self {
+            concurrency: 8,
+            max_requests_per_second: None,
+        }
+    }
+}
+
+/// Counts reported to the progress callback of [TopicManagementSupport::build_topic_index].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TopicIndexProgress {
+    /// Number of tokens looked up so far, including failures.
+    pub processed: usize,
+    pub failed: usize,
+}
+
+/// Reverse index built by [TopicManagementSupport::build_topic_index].
+#[derive(Debug, Clone, Default)]
+pub struct TopicIndex {
+    /// Tokens following each topic, in the order they were given.
+    pub topics: HashMap<String, Vec<String>>,
+    /// Tokens whose information could not be retrieved.
+    pub errors: Vec<(String, TopicManagementError)>,
+}
+
+impl TopicIndex {
+    /// Number of indexed tokens following `topic`.
+    pub fn audience_size(&self, topic: &str) -> usize {
+        self.topics.get(topic).map_or(0, Vec::len)
+    }
+}
+
+/// Result of [TopicManagementSupport::register_token_to_topic].
+#[derive(Clone, PartialEq, Eq)]
+pub struct TopicSubscription {
+    pub topic: String,
+    pub token: String,
+    /// Error code returned for the token, or `None` if it follows `topic`.
+    pub error: Option<TopicErrorCode>,
+    /// Response body as returned from the API, including fields not covered above.
+    pub raw: HashMap<String, String>,
+}
+
+impl TopicSubscription {
+    pub fn is_success(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+impl std::fmt::Debug for TopicSubscription {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TopicSubscription")
+            .field("topic", &self.topic)
+            .field("token", &crate::Redacted)
+            .field("error", &self.error)
+            .field("raw", &self.raw)
+            .finish()
+    }
+}
+
+/// A token that failed in a batch operation.
+#[derive(Clone, PartialEq, Eq)]
+pub struct TokenFailure {
+    /// Position of the token in the list passed to the batch operation.
+    pub index: usize,
+    pub token: String,
+    pub code: TopicErrorCode,
+}
+
+impl std::fmt::Debug for TokenFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TokenFailure")
+            .field("index", &self.index)
+            .field("token", &crate::Redacted)
+            .field("code", &self.code)
+            .finish()
+    }
+}
+
+/// Per-token error code returned from batch operations.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TopicErrorCode {
+    /// Registration token has been deleted or app has been uninstalled.
+    NotFound,
+    /// Registration token is invalid.
+    InvalidArgument,
+    /// Internal server error.
+    Internal,
+    /// App has too many topics.
+    TooManyTopics,
+    Other(String),
+}
+
+impl TopicErrorCode {
+    /// `true` if sending the token again may succeed, i.e. for `INTERNAL` and `UNAVAILABLE`.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::Internal => true,
+            Self::Other(code) => code == "UNAVAILABLE",
+            _ => false,
+        }
+    }
+}
+
+impl From<&str> for TopicErrorCode {
+    fn from(code: &str) -> Self {
+        match code {
+            "NOT_FOUND" => Self::NotFound,
+            "INVALID_ARGUMENT" => Self::InvalidArgument,
+            "INTERNAL" => Self::Internal,
+            "TOO_MANY_TOPICS" => Self::TooManyTopics,
+            other => Self::Other(other.to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for TopicErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NotFound => f.write_str("NOT_FOUND"),
+            Self::InvalidArgument => f.write_str("INVALID_ARGUMENT"),
+            Self::Internal => f.write_str("INTERNAL"),
+            Self::TooManyTopics => f.write_str("TOO_MANY_TOPICS"),
+            Self::Other(code) => f.write_str(code),
+        }
+    }
+}
 #[derive(Debug, Clone)]
 pub enum TopicManagementError {
     /// Unauthorized. Check
@@ -143,13 +1295,197 @@ pub enum TopicManagementError {
     /// 1. your topic name is correct
     InvalidRequest,
     ServerError,
+    /// Instance ID API rejected the request with `429 Too Many Requests`.
+    TooManyRequests {
+        retry_after: Option<std::time::Duration>,
+    },
     InternalRequestError {
         msg: String,
     },
+    /// The request payload could not be serialized to JSON. Nothing was sent.
+    SerializeFailure {
+        msg: String,
+    },
     InternalResponseError {
         msg: String,
     },
+    /// The request did not complete at the connection level.
+    Transport {
+        kind: TransportErrorKind,
+        msg: String,
+    },
+    /// An attempt did not complete within [crate::FCMClientBuilder::request_timeout].
+    Timeout {
+        timeout: std::time::Duration,
+    },
+    /// The request was not sent because Instance ID API kept failing. See [crate::circuit_breaker].
+    CircuitOpen {
+        retry_after: std::time::Duration,
+    },
+    /// The request was not sent because it exceeds an enforced [crate::stats::QuotaBudget] of `project_id`.
+    QuotaExceeded {
+        project_id: String,
+        resets_in: std::time::Duration,
+    },
+    /// Some tokens in a batch operation failed. See [TopicManagementResponse::into_result].
+    PartialFailure {
+        succeeded: usize,
+        failures: Vec<TokenFailure>,
+    },
+    /// A chunk of a batch operation failed after the chunks before it were applied.
+    /// `response` holds the results of the tokens before `failed_at`, in their order.
+    /// Tokens from `failed_at` on were not sent, and `source` is the error of the failed chunk.
+    ChunkFailure {
+        response: TopicManagementResponse,
+        failed_at: usize,
+        source: Box<TopicManagementError>,
+    },
     Unknown,
+    /// [RetryPolicy::deadline] ran out. `attempts` are all failed attempts, oldest first.
+    DeadlineExceeded {
+        deadline: std::time::Duration,
+        attempts: Vec<FailedAttempt>,
+    },
+    /// The caller cancelled the request before it completed.
+    Cancelled,
+    /// The response was larger than [crate::FCMClientBuilder::max_response_size] bytes,
+    /// e.g. details of a token subscribed to many topics.
+    ResponseTooLarge {
+        limit: usize,
+    },
+    /// `source` annotated with the request that failed. Use [TopicManagementError::kind] to match on the cause.
+    ///
+    /// Since 0.9, errors returned from requests are wrapped in this variant, so matching them directly,
+    /// e.g. `matches!(e, TopicManagementError::Unauthorized(_))`, no longer succeeds. Match on `e.kind()` instead.
+    Request {
+        context: RequestContext,
+        source: Box<TopicManagementError>,
+    },
+}
+
+impl std::fmt::Display for TopicManagementError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Unauthorized(msg) => write!(f, "unauthorized: {msg}"),
+            Self::InvalidRequest => write!(f, "invalid request"),
+            Self::ServerError => write!(f, "instance id API internal error"),
+            Self::TooManyRequests { .. } => write!(f, "too many requests"),
+            Self::InternalRequestError { msg } => write!(f, "unable to send request: {msg}"),
+            Self::SerializeFailure { msg } => {
+                write!(f, "unable to serialize request payload: {msg}")
+            }
+            Self::InternalResponseError { msg } => write!(f, "unable to handle response: {msg}"),
+            Self::Transport { kind, msg } => write!(f, "transport error ({kind:?}): {msg}"),
+            Self::Timeout { timeout } => write!(f, "request timed out after {timeout:?}"),
+            Self::CircuitOpen { retry_after } => write!(
+                f,
+                "instance id API is failing, circuit is open for {retry_after:?}"
+            ),
+            Self::QuotaExceeded {
+                project_id,
+                resets_in,
+            } => write!(
+                f,
+                "quota budget of project {project_id} is exhausted, resets in {resets_in:?}"
+            ),
+            Self::PartialFailure {
+                succeeded,
+                failures,
+            } => write!(
+                f,
+                "{} tokens failed, {succeeded} tokens succeeded",
+                failures.len()
+            ),
+            Self::ChunkFailure {
+                failed_at, source, ..
+            } => write!(f, "chunk starting at token {failed_at} failed: {source}"),
+            Self::Unknown => write!(f, "unknown error"),
+            Self::DeadlineExceeded { deadline, attempts } => write!(
+                f,
+                "deadline of {deadline:?} exceeded after {} failed attempts",
+                attempts.len()
+            ),
+            Self::Cancelled => write!(f, "request was cancelled"),
+            Self::ResponseTooLarge { limit } => {
+                write!(f, "response body is larger than {limit} bytes")
+            }
+            Self::Request { context, source } => write!(f, "{context}: {source}"),
+        }
+    }
+}
+
+impl std::error::Error for TopicManagementError {}
+
+impl TopicManagementError {
+    /// The cause of the error without [TopicManagementError::Request] context.
+    pub fn kind(&self) -> &Self {
+        match self {
+            Self::Request { source, .. } => source.kind(),
+            other => other,
+        }
+    }
+    /// The request that failed, if known.
+    pub fn context(&self) -> Option<&RequestContext> {
+        match self {
+            Self::Request { context, .. } => Some(context),
+            _ => None,
+        }
+    }
+    /// HTTP status with which a service relaying this error should respond to its own clients.
+    /// See [crate::fcm::FCMError::status_code].
+    pub fn status_code(&self) -> http::StatusCode {
+        use http::StatusCode;
+        match self.kind() {
+            Self::InvalidRequest => StatusCode::BAD_REQUEST,
+            Self::PartialFailure { .. } | Self::ChunkFailure { .. } => StatusCode::MULTI_STATUS,
+            Self::ServerError | Self::CircuitOpen { .. } | Self::Cancelled => {
+                StatusCode::SERVICE_UNAVAILABLE
+            }
+            Self::QuotaExceeded { .. } | Self::TooManyRequests { .. } => {
+                StatusCode::TOO_MANY_REQUESTS
+            }
+            Self::Transport { kind, .. } => kind.gateway_status(),
+            Self::Timeout { .. } | Self::DeadlineExceeded { .. } => StatusCode::GATEWAY_TIMEOUT,
+            Self::Unauthorized(_) | Self::Unknown | Self::ResponseTooLarge { .. } => {
+                StatusCode::BAD_GATEWAY
+            }
+            Self::InternalRequestError { .. }
+            | Self::SerializeFailure { .. }
+            | Self::InternalResponseError { .. }
+            | Self::Request { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+    /// Description of the error that is safe to show to clients of a service relaying it.
+    /// It never contains registration tokens, upstream response bodies or credential details.
+    pub fn client_message(&self) -> &'static str {
+        match self.kind() {
+            Self::InvalidRequest => "invalid topic or registration token",
+            Self::PartialFailure { .. } | Self::ChunkFailure { .. } => {
+                "some registration tokens failed"
+            }
+            Self::ServerError | Self::CircuitOpen { .. } => {
+                "topic service is temporarily unavailable"
+            }
+            Self::QuotaExceeded { .. } | Self::TooManyRequests { .. } => {
+                "too many requests, try again later"
+            }
+            Self::Transport {
+                kind: TransportErrorKind::Timeout,
+                ..
+            }
+            | Self::Timeout { .. }
+            | Self::DeadlineExceeded { .. } => "topic service timed out",
+            Self::Transport { .. } | Self::Unauthorized(_) | Self::Unknown => {
+                "topic service is unreachable"
+            }
+            Self::ResponseTooLarge { .. } => "topic service returned an oversized response",
+            Self::Cancelled => "request was cancelled",
+            Self::InternalRequestError { .. }
+            | Self::SerializeFailure { .. }
+            | Self::InternalResponseError { .. }
+            | Self::Request { .. } => "internal error",
+        }
+    }
 }
 
 impl From<RPCError> for TopicManagementError {
@@ -161,6 +1497,17 @@ impl From<RPCError> for TopicManagementError {
             RPCError::HttpRequestFailure => Self::InternalRequestError {
                 msg: "unable to process http request".to_string(),
             },
+            RPCError::Transport { kind, reason } => Self::Transport { kind, msg: reason },
+            RPCError::Timeout { timeout } => Self::Timeout { timeout },
+            RPCError::CircuitOpen { retry_after } => Self::CircuitOpen { retry_after },
+            RPCError::QuotaExceeded {
+                project_id,
+                resets_in,
+            } => Self::QuotaExceeded {
+                project_id,
+                resets_in,
+            },
+            RPCError::SerializeFailure { reason } => Self::SerializeFailure { msg: reason },
             RPCError::DecodeFailure => Self::InternalResponseError {
                 msg: "unable to decode response body bytes".to_string(),
             },
@@ -170,47 +1517,55 @@ impl From<RPCError> for TopicManagementError {
             RPCError::Unauthorized(msg) => Self::Unauthorized(msg),
             RPCError::InvalidRequest { .. } => Self::InvalidRequest,
             RPCError::Internal { .. } => Self::ServerError,
+            RPCError::TooManyRequests { retry_after, .. } => Self::TooManyRequests { retry_after },
             RPCError::Unknown(_) => Self::Unknown,
+            RPCError::DeadlineExceeded { deadline, attempts } => {
+                Self::DeadlineExceeded { deadline, attempts }
+            }
+            RPCError::Cancelled => Self::Cancelled,
+            RPCError::ResponseTooLarge { limit } => Self::ResponseTooLarge { limit },
+            RPCError::Request { context, source } => Self::Request {
+                context,
+                source: Box::new(Self::from(*source)),
+            },
         }
     }
 }
-#[deprecated(since = "0.8.3", note = "Use TopicInfoResponseKind instead.")]
-#[derive(Debug, Clone, Deserialize)]
-pub struct TopicInfoResponse {
-    /// example: "com.iid.example"
-    pub application: String,
-    /// example: "123456782354"
-    #[serde(rename = "authorizedEntity")]
-    pub authorized_entity: String,
+#[allow(deprecated)]
+pub use legacy::TopicInfoResponse;
 
-    /// example: "Android", "ANDROID"
-    pub platform: String,
-    /// example: "1a2bc3d4e5"
-    #[serde(rename = "appSigner")]
-    pub app_signer: Option<String>,
-    /// If and only if user specifies `details` flag on request, this field may `Some<Rel>`.
-    pub rel: Option<Rel>,
-}
+// `#[allow(deprecated)]` on the struct itself does not reach the impl emitted by
+// `#[derive(Deserialize)]`, so the deprecated type lives in its own module.
+#[allow(deprecated)]
+mod legacy {
+    use super::Rel;
+    use serde::Deserialize;
 
-#[derive(Clone, Debug, Deserialize)]
-#[serde(untagged)]
-pub enum TopicInfoResponseKind {
-    Android {
-        /// application identifier
-        ///
+    #[deprecated(since = "0.8.3", note = "Use TopicInfoResponseKind instead.")]
+    #[derive(Debug, Clone, Deserialize)]
+    pub struct TopicInfoResponse {
         /// example: "com.iid.example"
-        application: String,
+        pub application: String,
         /// example: "123456782354"
         #[serde(rename = "authorizedEntity")]
-        authorized_entity: String,
+        pub authorized_entity: String,
+
         /// example: "Android", "ANDROID"
-        platform: String,
+        pub platform: String,
         /// example: "1a2bc3d4e5"
         #[serde(rename = "appSigner")]
-        app_signer: Option<String>,
+        pub app_signer: Option<String>,
         /// If and only if user specifies `details` flag on request, this field may `Some<Rel>`.
-        rel: Option<Rel>,
-    },
+        pub rel: Option<Rel>,
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
+#[serde(untagged)]
+pub enum TopicInfoResponseKind {
+    /// Tried before [TopicInfoResponseKind::Android], whose required fields are a subset of these.
+    /// Before 0.9, iOS tokens deserialized to [TopicInfoResponseKind::Android] and this variant had no `rel`.
     IOS {
         /// example: "com.iid.example"
         application: String,
@@ -227,27 +1582,108 @@ pub enum TopicInfoResponseKind {
         gmi_registration_id: String,
         /// example: "*"
         scope: String,
+        /// If and only if user specifies `details` flag on request, this field may `Some<Rel>`.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        rel: Option<Rel>,
+    },
+    Android {
+        /// application identifier
+        ///
+        /// example: "com.iid.example"
+        application: String,
+        /// example: "123456782354"
+        #[serde(rename = "authorizedEntity")]
+        authorized_entity: String,
+        /// example: "Android", "ANDROID"
+        platform: String,
+        /// example: "1a2bc3d4e5"
+        #[serde(rename = "appSigner", skip_serializing_if = "Option::is_none")]
+        app_signer: Option<String>,
+        /// If and only if user specifies `details` flag on request, this field may `Some<Rel>`.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        rel: Option<Rel>,
     },
+    /// Response that matches neither of the shapes above, e.g. for web or legacy tokens, as returned from the API.
+    /// Accessors read the same fields from it when they are present.
+    Unknown(serde_json::Value),
 }
 impl TopicInfoResponseKind {
     pub fn application(&self) -> String {
         match self {
             Self::Android { application, .. } => application.to_string(),
             Self::IOS { application, .. } => application.to_string(),
+            Self::Unknown(raw) => Self::raw_str(raw, "application"),
         }
     }
     pub fn platform(&self) -> String {
         match self {
             Self::Android { platform, .. } => platform.to_string(),
             Self::IOS { platform, .. } => platform.to_string(),
+            Self::Unknown(raw) => Self::raw_str(raw, "platform"),
         }
     }
     pub fn rel(&self) -> Option<Rel> {
         match self {
-            Self::Android { rel, .. } => rel.clone(),
+            Self::Android { rel, .. } | Self::IOS { rel, .. } => rel.clone(),
+            Self::Unknown(raw) => raw.get("rel").and_then(|rel| Rel::deserialize(rel).ok()),
+        }
+    }
+    /// Project number of the sender the token was issued for. It is missing only from [TopicInfoResponseKind::Unknown].
+    pub fn authorized_entity(&self) -> Option<&str> {
+        match self {
+            Self::Android {
+                authorized_entity, ..
+            }
+            | Self::IOS {
+                authorized_entity, ..
+            } => Some(authorized_entity),
+            Self::Unknown(raw) => Self::raw_field(raw, "authorizedEntity"),
+        }
+    }
+    /// Android only.
+    pub fn app_signer(&self) -> Option<&str> {
+        match self {
+            Self::Android { app_signer, .. } => app_signer.as_deref(),
             Self::IOS { .. } => None,
+            Self::Unknown(raw) => Self::raw_field(raw, "appSigner"),
+        }
+    }
+    /// iOS only.
+    pub fn application_version(&self) -> Option<&str> {
+        match self {
+            Self::IOS {
+                application_version,
+                ..
+            } => Some(application_version),
+            Self::Android { .. } => None,
+            Self::Unknown(raw) => Self::raw_field(raw, "applicationVersion"),
+        }
+    }
+    /// iOS only.
+    pub fn gmi_registration_id(&self) -> Option<&str> {
+        match self {
+            Self::IOS {
+                gmi_registration_id,
+                ..
+            } => Some(gmi_registration_id),
+            Self::Android { .. } => None,
+            Self::Unknown(raw) => Self::raw_field(raw, "gmiRegistrationId"),
+        }
+    }
+    /// iOS only.
+    pub fn scope(&self) -> Option<&str> {
+        match self {
+            Self::IOS { scope, .. } => Some(scope),
+            Self::Android { .. } => None,
+            Self::Unknown(raw) => Self::raw_field(raw, "scope"),
         }
     }
+    fn raw_str(raw: &serde_json::Value, key: &str) -> String {
+        Self::raw_field(raw, key).unwrap_or_default().to_string()
+    }
+    fn raw_field<'a>(raw: &'a serde_json::Value, key: &str) -> Option<&'a str> {
+        raw.get(key).and_then(|value| value.as_str())
+    }
 }
 
 /// example
@@ -261,12 +1697,13 @@ impl TopicInfoResponseKind {
 ///     }
 ///  }
 /// ```
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
 pub struct Rel {
     pub topics: HashMap<String, HashMap<String, String>>,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Clone, Serialize)]
 pub struct ImportRequest {
     /// example: "com.google.FCMTestApp"
     application: String,
@@ -282,12 +1719,27 @@ pub struct ImportRequest {
     apns_tokens: Vec<String>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+impl std::fmt::Debug for ImportRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ImportRequest")
+            .field("application", &self.application)
+            .field("sandbox", &self.sandbox)
+            .field(
+                "apns_tokens",
+                &format_args!("<{} redacted>", self.apns_tokens.len()),
+            )
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
 pub struct ImportResponse {
     pub results: Vec<ImportResult>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "utoipa", derive(utoipa::ToSchema))]
 pub struct ImportResult {
     /// example: "368dde283db539abc4a6419b1795b6131194703b816e4f624ffa12"
     pub apn_token: String,
@@ -297,3 +1749,466 @@ pub struct ImportResult {
     /// example: "nKctODamlM4:CKrh_PC8kIb7O...clJONHoA"
     pub registration_token: Option<String>,
 }
+
+/// Error returned when a topic response cannot be converted from or to JSON.
+#[derive(Debug)]
+pub struct JsonError(serde_json::Error);
+
+impl std::fmt::Display for JsonError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unable to convert topic response JSON: {}", self.0)
+    }
+}
+
+impl std::error::Error for JsonError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.0)
+    }
+}
+
+macro_rules! impl_json_persistence {
+    ($($ty:ty),*) => {$(
+        impl $ty {
+            /// Serialize in the same shape as returned from the API.
+            pub fn to_json(&self) -> Result<String, JsonError> {
+                serde_json::to_string(self).map_err(JsonError)
+            }
+            /// Parse JSON returned from the API or produced by `to_json`.
+            pub fn from_json(json: &str) -> Result<Self, JsonError> {
+                serde_json::from_str(json).map_err(JsonError)
+            }
+        }
+    )*};
+}
+
+impl_json_persistence!(
+    TopicManagementResponse,
+    TopicInfoResponseKind,
+    Rel,
+    ImportResponse
+);
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        chunks, ImportResponse, TokenFailure, TopicBatch, TopicChange, TopicChanges,
+        TopicChangesReport, TopicErrorCode, TopicInfoResponseKind, TopicManagementError,
+        TopicManagementResponse, TopicOperation, TopicPlan, TopicSubscription,
+    };
+    use http::StatusCode;
+    use std::collections::HashMap;
+
+    #[cfg(feature = "test-util")]
+    #[tokio::test]
+    async fn duplicate_tokens_are_sent_once() {
+        use super::{TopicBatchOptions, TopicManagementSupport};
+        use crate::test_util::{FakeClient, FakeResponse};
+
+        let client = FakeClient::new();
+        client.push(FakeResponse::batch(&[None, Some("NOT_FOUND"), None]));
+        let tokens = ["a", "b", "a", "c", "b"];
+        let batch = client
+            .register_tokens_to_topic_with_options(
+                "news",
+                tokens,
+                &TopicBatchOptions::default().deduplicate(true),
+            )
+            .await
+            .unwrap();
+        assert_eq!(batch.duplicates_removed, 2);
+        let failures = batch.response.failures(&tokens);
+        assert_eq!(
+            failures.iter().map(|f| f.index).collect::<Vec<_>>(),
+            vec![1, 4]
+        );
+        let body: serde_json::Value =
+            serde_json::from_slice(client.requests()[0].body.as_ref().unwrap()).unwrap();
+        assert_eq!(
+            body["registration_tokens"],
+            serde_json::json!(["a", "b", "c"])
+        );
+    }
+
+    #[cfg(feature = "test-util")]
+    #[tokio::test]
+    async fn only_failed_tokens_are_resubmitted() {
+        use super::TopicManagementSupport;
+        use crate::test_util::{FakeClient, FakeResponse};
+        use crate::RetryPolicy;
+
+        let client = FakeClient::new();
+        client
+            .push(FakeResponse::batch(&[
+                None,
+                Some("INTERNAL"),
+                Some("NOT_FOUND"),
+            ]))
+            .push(FakeResponse::batch(&[None]));
+        let policy = RetryPolicy {
+            initial_backoff: std::time::Duration::ZERO,
+            ..RetryPolicy::with_max_retries(2)
+        };
+        let response = client
+            .register_tokens_to_topic_with_retry("news", ["a", "b", "c"], &policy)
+            .await
+            .unwrap();
+        let failures = response.failures(&["a", "b", "c"]);
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].token, "c");
+        let requests = client.requests();
+        assert_eq!(requests.len(), 2);
+        let body: serde_json::Value =
+            serde_json::from_slice(requests[1].body.as_ref().unwrap()).unwrap();
+        assert_eq!(body["registration_tokens"], serde_json::json!(["b"]));
+    }
+
+    #[cfg(feature = "test-util")]
+    #[tokio::test]
+    async fn failed_resubmission_keeps_earlier_results() {
+        use super::{TopicErrorCode, TopicManagementSupport};
+        use crate::test_util::{FakeClient, FakeResponse};
+        use crate::RetryPolicy;
+
+        let client = FakeClient::new();
+        client
+            .push(FakeResponse::batch(&[
+                None,
+                Some("INTERNAL"),
+                Some("NOT_FOUND"),
+            ]))
+            .push(FakeResponse::server_error(None));
+        let policy = RetryPolicy {
+            initial_backoff: std::time::Duration::ZERO,
+            ..RetryPolicy::with_max_retries(2)
+        };
+        let response = client
+            .register_tokens_to_topic_with_retry("news", ["a", "b", "c"], &policy)
+            .await
+            .unwrap();
+        let failures = response.failures(&["a", "b", "c"]);
+        assert_eq!(failures.len(), 2);
+        assert_eq!(failures[0].token, "b");
+        assert_eq!(failures[0].code, TopicErrorCode::Internal);
+        assert_eq!(client.requests().len(), 2);
+    }
+
+    #[cfg(feature = "test-util")]
+    #[tokio::test]
+    async fn only_added_tokens_are_removed_on_move() {
+        use super::{MoveOrder, TopicErrorCode, TopicManagementSupport};
+        use crate::test_util::{FakeClient, FakeResponse};
+
+        let client = FakeClient::new();
+        client
+            .push(FakeResponse::batch(&[None, Some("NOT_FOUND"), None]))
+            .push(FakeResponse::batch(&[None, Some("INTERNAL")]));
+        let report = client
+            .move_tokens("old", "new", ["a", "b", "c"], MoveOrder::AddFirst)
+            .await
+            .unwrap();
+        assert_eq!(report.moved(), 1);
+        assert_eq!(report.tokens[1].added, Some(Err(TopicErrorCode::NotFound)));
+        assert_eq!(report.tokens[1].removed, None);
+        assert_eq!(
+            report.tokens[2].removed,
+            Some(Err(TopicErrorCode::Internal))
+        );
+        let requests = client.requests();
+        assert!(requests[0].endpoint.ends_with(":batchAdd"));
+        assert!(requests[1].endpoint.ends_with(":batchRemove"));
+        let body: serde_json::Value =
+            serde_json::from_slice(requests[1].body.as_ref().unwrap()).unwrap();
+        assert_eq!(body["to"], "/topics/old");
+        assert_eq!(body["registration_tokens"], serde_json::json!(["a", "c"]));
+    }
+
+    #[cfg(feature = "test-util")]
+    #[tokio::test]
+    async fn auto_subscriber_batches_events_per_topic() {
+        use super::{AutoSubscriberOptions, TopicEvent, TopicManagementSupport, TopicOperation};
+        use crate::test_util::{FakeClient, FakeResponse};
+
+        let client = FakeClient::new();
+        client
+            .push(FakeResponse::batch(&[None, Some("NOT_FOUND"), None]))
+            .push(FakeResponse::batch(&[None]));
+        let events = futures::stream::iter([
+            TopicEvent::subscribe("a", "news"),
+            TopicEvent::unsubscribe("c", "news"),
+            TopicEvent::subscribe("b", "news"),
+            TopicEvent::subscribe("c", "news"),
+            TopicEvent::unsubscribe("a", "sports"),
+        ]);
+        let mut results = vec![];
+        client
+            .run_auto_subscriber(events, &AutoSubscriberOptions::default(), |change| {
+                results.push(change)
+            })
+            .await;
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].operation, TopicOperation::Subscribe);
+        assert_eq!(results[0].batch.tokens, ["a", "b", "c"]);
+        assert_eq!(results[0].batch.failures()[0].token, "b");
+        assert_eq!(results[1].topic, "sports");
+        assert_eq!(client.requests().len(), 2);
+    }
+
+    #[cfg(feature = "test-util")]
+    #[tokio::test]
+    async fn single_token_registration_is_typed() {
+        use super::TopicManagementSupport;
+        use crate::test_util::{FakeClient, FakeResponse};
+
+        let client = FakeClient::new();
+        client
+            .push(FakeResponse::ok("{}"))
+            .push(FakeResponse::ok(r#"{"error":"NOT_FOUND"}"#));
+        let subscription = client.register_token_to_topic("news", "a").await.unwrap();
+        assert!(subscription.is_success());
+        assert_eq!(
+            (subscription.topic.as_str(), subscription.token.as_str()),
+            ("news", "a")
+        );
+        let subscription = client.register_token_to_topic("news", "b").await.unwrap();
+        assert_eq!(subscription.error, Some(TopicErrorCode::NotFound));
+        assert_eq!(subscription.raw["error"], "NOT_FOUND");
+    }
+
+    #[cfg(feature = "test-util")]
+    #[tokio::test]
+    async fn topic_index_is_built_from_token_info() {
+        use super::{TopicIndexOptions, TopicManagementSupport};
+        use crate::test_util::{FakeClient, FakeResponse};
+
+        let client = FakeClient::new();
+        client
+            .push(FakeResponse::ok(
+                r#"{"platform":"WEBPUSH","rel":{"topics":{"news":{},"sports":{}}}}"#,
+            ))
+            .push(FakeResponse::new(StatusCode::NOT_FOUND, ""))
+            .push(FakeResponse::ok(
+                r#"{"platform":"WEBPUSH","rel":{"topics":{"news":{}}}}"#,
+            ));
+        let mut reported = vec![];
+        let index = client
+            .build_topic_index(
+                ["a", "b", "c"].map(String::from),
+                &TopicIndexOptions {
+                    concurrency: 1,
+                    max_requests_per_second: Some(1000),
+                },
+                |progress| reported.push(progress),
+            )
+            .await;
+        assert_eq!(index.topics["news"], ["a", "c"]);
+        assert_eq!(index.audience_size("sports"), 1);
+        assert_eq!(index.errors[0].0, "b");
+        assert_eq!(reported.len(), 3);
+        assert_eq!(reported[2].failed, 1);
+    }
+
+    #[test]
+    fn responses_round_trip_through_json() {
+        let info = r#"{"application":"com.iid.example","authorizedEntity":"123","platform":"ANDROID","rel":{"topics":{"news":{"addDate":"2015-07-30"}}}}"#;
+        assert_eq!(
+            TopicInfoResponseKind::from_json(info)
+                .unwrap()
+                .to_json()
+                .unwrap(),
+            info
+        );
+        let batch = r#"{"results":[{},{"error":"NOT_FOUND"}]}"#;
+        assert_eq!(
+            TopicManagementResponse::from_json(batch)
+                .unwrap()
+                .to_json()
+                .unwrap(),
+            batch
+        );
+        let import = r#"{"results":[{"apn_token":"a","status":"OK","registration_token":"b"}]}"#;
+        assert_eq!(
+            ImportResponse::from_json(import)
+                .unwrap()
+                .to_json()
+                .unwrap(),
+            import
+        );
+    }
+
+    #[test]
+    fn unknown_token_info_is_kept() {
+        let info: TopicInfoResponseKind = serde_json::from_str(
+            r#"{"platform":"WEBPUSH","rel":{"topics":{"news":{"addDate":"2024-01-01"}}}}"#,
+        )
+        .unwrap();
+        assert!(matches!(info, TopicInfoResponseKind::Unknown(_)));
+        assert_eq!(info.platform(), "WEBPUSH");
+        assert_eq!(info.application(), "");
+        assert!(info.rel().unwrap().topics.contains_key("news"));
+        assert_eq!(info.authorized_entity(), None);
+    }
+
+    #[test]
+    fn token_info_fields_are_accessible() {
+        let info: TopicInfoResponseKind = serde_json::from_str(
+            r#"{"application":"com.example","authorizedEntity":"123","platform":"IOS","applicationVersion":"0.1","gmiRegistrationId":"abc","scope":"*"}"#,
+        )
+        .unwrap();
+        assert_eq!(info.authorized_entity(), Some("123"));
+        assert_eq!(info.application_version(), Some("0.1"));
+        assert_eq!(info.gmi_registration_id(), Some("abc"));
+        assert_eq!(info.scope(), Some("*"));
+        assert_eq!(info.app_signer(), None);
+        assert!(info.rel().is_none());
+    }
+
+    #[test]
+    fn failures_are_paired_with_tokens() {
+        let tokens = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        let response = TopicManagementResponse {
+            results: vec![
+                HashMap::new(),
+                HashMap::from_iter([("error".to_string(), "NOT_FOUND".to_string())]),
+                HashMap::from_iter([("error".to_string(), "SOMETHING_NEW".to_string())]),
+            ],
+        };
+        let failures = response.failures(&tokens);
+        assert_eq!(failures.len(), 2);
+        assert_eq!(failures[0].index, 1);
+        assert_eq!(failures[0].token, "b");
+        assert_eq!(failures[0].code, TopicErrorCode::NotFound);
+        assert_eq!(
+            failures[1].code,
+            TopicErrorCode::Other("SOMETHING_NEW".to_string())
+        );
+        assert!(matches!(
+            response.into_result(&tokens),
+            Err(TopicManagementError::PartialFailure { succeeded: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn tokens_are_split_into_batches() {
+        let tokens = vec!["token".to_string(); 2001];
+        let sizes = chunks(&tokens).iter().map(|c| c.len()).collect::<Vec<_>>();
+        assert_eq!(sizes, vec![1000, 1000, 1]);
+        assert_eq!(chunks::<String>(&[]).len(), 1);
+    }
+
+    #[test]
+    fn batch_failures_are_indexed_in_stream() {
+        let batch = TopicBatch {
+            offset: 1000,
+            tokens: vec!["a".to_string(), "b".to_string()],
+            result: Ok(TopicManagementResponse {
+                results: vec![
+                    HashMap::from_iter([("error".to_string(), "INVALID_ARGUMENT".to_string())]),
+                    HashMap::new(),
+                ],
+            }),
+        };
+        let failures = batch.failures();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].index, 1000);
+        assert_eq!(failures[0].token, "a");
+    }
+
+    #[test]
+    fn topic_changes_are_grouped_by_topic() {
+        let mut changes: TopicChanges = vec![
+            ("news".to_string(), "a".to_string()),
+            ("news".to_string(), "b".to_string()),
+            ("sports".to_string(), "a".to_string()),
+        ]
+        .into_iter()
+        .collect();
+        changes
+            .unsubscribe("weather", vec!["token".to_string(); 1001])
+            .unsubscribe("empty", []);
+        assert_eq!(changes.batch_count(), 4);
+        assert!(!changes.is_empty());
+        assert!(TopicChanges::default().is_empty());
+
+        let report = TopicChangesReport {
+            batches: vec![
+                TopicChange {
+                    topic: "news".to_string(),
+                    operation: TopicOperation::Subscribe,
+                    batch: TopicBatch {
+                        offset: 0,
+                        tokens: vec!["a".to_string(), "b".to_string()],
+                        result: Ok(TopicManagementResponse {
+                            results: vec![
+                                HashMap::new(),
+                                HashMap::from_iter([(
+                                    "error".to_string(),
+                                    "NOT_FOUND".to_string(),
+                                )]),
+                            ],
+                        }),
+                    },
+                },
+                TopicChange {
+                    topic: "sports".to_string(),
+                    operation: TopicOperation::Subscribe,
+                    batch: TopicBatch {
+                        offset: 0,
+                        tokens: vec!["a".to_string()],
+                        result: Err(TopicManagementError::ServerError),
+                    },
+                },
+            ],
+        };
+        assert!(!report.is_success());
+        assert_eq!(report.succeeded(), 1);
+        assert_eq!(report.errors().count(), 1);
+        let failures = report.failures().collect::<Vec<_>>();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].0, "news");
+        assert_eq!(failures[0].2.token, "b");
+    }
+
+    #[test]
+    fn topic_plan_is_the_difference_of_topic_sets() {
+        let plan = TopicPlan::new("token", ["news", "sports"], ["weather", "news", "weather"]);
+        assert_eq!(plan.subscribe, vec!["weather".to_string()]);
+        assert_eq!(plan.unsubscribe, vec!["sports".to_string()]);
+        assert_eq!(plan.to_changes().batch_count(), 2);
+        assert!(TopicPlan::new("token", ["news"], ["news"]).is_empty());
+    }
+
+    #[test]
+    fn errors_are_mapped_to_status() {
+        let error = TopicManagementError::PartialFailure {
+            succeeded: 1,
+            failures: vec![],
+        };
+        assert_eq!(error.status_code(), StatusCode::MULTI_STATUS);
+        let error = TopicManagementError::InternalResponseError {
+            msg: "unable to deserialize response body to type: a-token".to_string(),
+        };
+        assert_eq!(error.status_code(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(error.client_message(), "internal error");
+    }
+
+    #[test]
+    fn debug_output_redacts_tokens() {
+        let error = TopicManagementError::PartialFailure {
+            succeeded: 0,
+            failures: vec![TokenFailure {
+                index: 0,
+                token: "a-token".to_string(),
+                code: TopicErrorCode::NotFound,
+            }],
+        };
+        let subscription = TopicSubscription {
+            topic: "news".to_string(),
+            token: "a-token".to_string(),
+            error: None,
+            raw: HashMap::new(),
+        };
+        for debug in [format!("{error:?}"), format!("{subscription:?}")] {
+            assert!(!debug.contains("a-token"), "{debug}");
+        }
+    }
+}
@@ -0,0 +1,357 @@
+//! HTTP clients that send the requests built by [crate::GenericGoogleRestAPISupport].
+//!
+//! [crate::FCMClient] sends requests with the legacy client of hyper-util by default.
+//! With `reqwest` feature, it can share a `reqwest::Client` of the application instead,
+//! so that proxy settings, TLS configuration and connection pools are configured in one place.
+//! See [crate::FCMClientBuilder::build_with_reqwest].
+//!
+//! Implement [HttpTransport] to bring another HTTP client, to wrap one with caching or tracing,
+//! or to stub the network in tests. Pass it to [crate::FCMClientBuilder::build_with_transport],
+//! either as is or as `Arc<dyn HttpTransport>` to choose it at runtime.
+//!
+//! ```
+//! use async_trait::async_trait;
+//! use firebase_messaging_rs::{transport::HttpTransport, FCMClient, RPCError};
+//! use http::{Request, Response};
+//! use hyper::body::Bytes;
+//! use std::sync::Arc;
+//!
+//! /// Log every request before handing it to the default client.
+//! struct Logged<T>(T);
+//!
+//! #[async_trait]
+//! impl<T: HttpTransport> HttpTransport for Logged<T> {
+//!     async fn send(&self, request: Request<Bytes>) -> Result<Response<Bytes>, RPCError> {
+//!         println!("{} {}", request.method(), request.uri());
+//!         self.0.send(request).await
+//!     }
+//! }
+//!
+//! # async fn run() -> Result<(), String> {
+//! let builder = FCMClient::builder().project_id("my-project");
+//! let default = builder.http_client()?;
+//! let transport: Arc<dyn HttpTransport> = Arc::new(Logged(default));
+//! let client = builder.build_with_transport(transport).await?;
+//! # Ok(())
+//! # }
+//! ```
+use crate::{DefaultConnector, HttpClient, RPCError};
+use async_trait::async_trait;
+use http::{Request, Response};
+use http_body_util::{BodyExt, Full, LengthLimitError, Limited};
+use hyper::body::Bytes;
+use hyper_util::client::legacy::{connect::Connect, Builder};
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Largest response body a transport should read, in bytes, set in the extensions of requests by the client.
+/// See [crate::FCMClientBuilder::max_response_size].
+///
+/// Transports that honor it fail with [RPCError::ResponseTooLarge] as soon as a body exceeds it.
+/// Bodies returned by other transports are checked once they have been read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaxResponseSize(pub usize);
+
+/// Sends a request and reads the whole response body.
+#[async_trait]
+pub trait HttpTransport: Send + Sync {
+    /// Send `request`. A failure to get a response is reported as [RPCError::Transport],
+    /// and a failure to read its body as [RPCError::DecodeFailure].
+    async fn send(&self, request: Request<Bytes>) -> Result<Response<Bytes>, RPCError>;
+}
+
+#[async_trait]
+impl<T: HttpTransport + ?Sized> HttpTransport for Arc<T> {
+    async fn send(&self, request: Request<Bytes>) -> Result<Response<Bytes>, RPCError> {
+        self.as_ref().send(request).await
+    }
+}
+
+#[async_trait]
+impl<T: HttpTransport + ?Sized> HttpTransport for Box<T> {
+    async fn send(&self, request: Request<Bytes>) -> Result<Response<Bytes>, RPCError> {
+        self.as_ref().send(request).await
+    }
+}
+
+#[async_trait]
+impl<C: Connect + Clone + Send + Sync + 'static> HttpTransport for HttpClient<C> {
+    async fn send(&self, request: Request<Bytes>) -> Result<Response<Bytes>, RPCError> {
+        let max_size = request.extensions().get::<MaxResponseSize>().copied();
+        let res = self
+            .request(request.map(Full::new))
+            .await
+            .map_err(|e| RPCError::transport(&e))?;
+        let (parts, body) = res.into_parts();
+        let body = match max_size {
+            Some(MaxResponseSize(limit)) => Limited::new(body, limit)
+                .collect()
+                .await
+                .map_err(|e| match e.downcast_ref::<LengthLimitError>() {
+                    Some(_) => RPCError::ResponseTooLarge { limit },
+                    None => RPCError::DecodeFailure,
+                })?
+                .to_bytes(),
+            None => body
+                .collect()
+                .await
+                .map_err(|_| RPCError::DecodeFailure)?
+                .to_bytes(),
+        };
+        Ok(Response::from_parts(parts, body))
+    }
+}
+
+/// [HttpClient] whose connection pool is replaced once it is older than a maximum lifetime,
+/// so that no connection is reused for longer. See [crate::FCMClientBuilder::max_connection_lifetime].
+///
+/// Clones share the pool.
+#[derive(Clone)]
+pub struct PooledClient<C = DefaultConnector> {
+    builder: Builder,
+    connector: C,
+    max_lifetime: Option<Duration>,
+    current: Arc<Mutex<(Instant, HttpClient<C>)>>,
+}
+
+impl<C: Connect + Clone + Send + Sync + 'static> PooledClient<C> {
+    pub(crate) fn new(builder: Builder, connector: C, max_lifetime: Option<Duration>) -> Self {
+        let client = builder.build(connector.clone());
+        Self {
+            builder,
+            connector,
+            max_lifetime,
+            current: Arc::new(Mutex::new((Instant::now(), client))),
+        }
+    }
+    /// Client of the current pool. A new pool is created first if the current one has outlived the maximum lifetime.
+    /// The old one is closed once the requests using it have completed.
+    pub fn client(&self) -> HttpClient<C> {
+        let mut current = match self.current.lock() {
+            Ok(current) => current,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        if let Some(max_lifetime) = self.max_lifetime {
+            if current.0.elapsed() >= max_lifetime {
+                log::debug!("recycling connections older than {max_lifetime:?}");
+                *current = (Instant::now(), self.builder.build(self.connector.clone()));
+            }
+        }
+        current.1.clone()
+    }
+}
+
+impl<C> std::fmt::Debug for PooledClient<C> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PooledClient")
+            .field("max_lifetime", &self.max_lifetime)
+            .finish()
+    }
+}
+
+#[async_trait]
+impl<C: Connect + Clone + Send + Sync + 'static> HttpTransport for PooledClient<C> {
+    async fn send(&self, request: Request<Bytes>) -> Result<Response<Bytes>, RPCError> {
+        let client = self.client();
+        HttpTransport::send(&client, request).await
+    }
+}
+
+#[cfg(feature = "reqwest")]
+#[async_trait]
+impl HttpTransport for reqwest::Client {
+    async fn send(&self, request: Request<Bytes>) -> Result<Response<Bytes>, RPCError> {
+        let max_size = request.extensions().get::<MaxResponseSize>().copied();
+        let request = reqwest::Request::try_from(request)
+            .map_err(|e| RPCError::BuildRequestFailure(format!("{e:?}")))?;
+        let mut res = self.execute(request).await.map_err(|e| {
+            let kind = if e.is_connect() {
+                crate::TransportErrorKind::Connect
+            } else if e.is_timeout() {
+                crate::TransportErrorKind::Timeout
+            } else {
+                crate::TransportErrorKind::Other
+            };
+            RPCError::Transport {
+                kind,
+                reason: error_chain(&e),
+            }
+        })?;
+        let status = res.status();
+        let version = res.version();
+        let headers = res.headers().clone();
+        let body = match max_size {
+            Some(MaxResponseSize(limit)) => {
+                let mut body = Vec::new();
+                while let Some(chunk) = res.chunk().await.map_err(|_| RPCError::DecodeFailure)? {
+                    if body.len() + chunk.len() > limit {
+                        return Err(RPCError::ResponseTooLarge { limit });
+                    }
+                    body.extend_from_slice(&chunk);
+                }
+                Bytes::from(body)
+            }
+            None => res.bytes().await.map_err(|_| RPCError::DecodeFailure)?,
+        };
+        let mut response = Response::new(body);
+        *response.status_mut() = status;
+        *response.version_mut() = version;
+        *response.headers_mut() = headers;
+        Ok(response)
+    }
+}
+
+/// `e` followed by its sources, which HTTP clients tend to keep the details in.
+pub(crate) fn error_chain(e: &dyn std::error::Error) -> String {
+    let mut reason = e.to_string();
+    let mut source = e.source();
+    while let Some(e) = source {
+        reason.push_str(": ");
+        reason.push_str(&e.to_string());
+        source = e.source();
+    }
+    reason
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HttpTransport, MaxResponseSize};
+    use crate::{RPCError, TransportErrorKind};
+    use http::{Request, StatusCode};
+    use hyper::body::Bytes;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    /// Answer a single request on a local port with a fixed response.
+    async fn serve_once(response: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = [0; 1024];
+            let _ = stream.read(&mut buf).await.unwrap();
+            stream.write_all(response.as_bytes()).await.unwrap();
+        });
+        format!("http://{addr}/")
+    }
+
+    async fn exchange(transport: &dyn HttpTransport) {
+        let endpoint = serve_once(
+            "HTTP/1.1 429 Too Many Requests\r\nretry-after: 3\r\ncontent-length: 2\r\nconnection: close\r\n\r\n{}",
+        )
+        .await;
+        let request = Request::post(endpoint).body(Bytes::from("{}")).unwrap();
+        let response = transport.send(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()["retry-after"], "3");
+        assert_eq!(response.body().as_ref(), b"{}");
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let closed = format!("http://{}/", listener.local_addr().unwrap());
+        drop(listener);
+        let request = Request::get(closed).body(Bytes::new()).unwrap();
+        assert!(matches!(
+            transport.send(request).await,
+            Err(RPCError::Transport {
+                kind: TransportErrorKind::Connect,
+                ..
+            })
+        ));
+
+        let endpoint = serve_once(
+            "HTTP/1.1 200 OK\r\ncontent-length: 8\r\nconnection: close\r\n\r\n{\"a\": 1}",
+        )
+        .await;
+        let mut request = Request::get(endpoint).body(Bytes::new()).unwrap();
+        request.extensions_mut().insert(MaxResponseSize(4));
+        assert!(matches!(
+            transport.send(request).await,
+            Err(RPCError::ResponseTooLarge { limit: 4 })
+        ));
+    }
+
+    #[tokio::test]
+    async fn hyper_transport_reads_whole_response() {
+        let connector = crate::FCMClient::builder().http_connector();
+        let client =
+            hyper_util::client::legacy::Client::builder(hyper_util::rt::TokioExecutor::new())
+                .build(connector);
+        exchange(&client).await;
+    }
+
+    #[cfg(feature = "reqwest")]
+    #[tokio::test]
+    async fn reqwest_transport_reads_whole_response() {
+        exchange(&reqwest::Client::new()).await;
+    }
+
+    #[cfg(feature = "fcm")]
+    #[tokio::test]
+    async fn client_sends_through_custom_transport() {
+        use crate::fcm::{FCMApi, Message};
+        use async_trait::async_trait;
+        use http::Response;
+        use std::sync::{Arc, Mutex};
+
+        #[derive(Default)]
+        struct Stub(Mutex<Vec<Request<Bytes>>>);
+
+        #[async_trait]
+        impl HttpTransport for Stub {
+            async fn send(&self, request: Request<Bytes>) -> Result<Response<Bytes>, RPCError> {
+                self.0.lock().unwrap().push(request);
+                Ok(Response::new(Bytes::from(
+                    r#"{"name": "projects/stub/messages/1"}"#,
+                )))
+            }
+        }
+
+        let stub = Arc::new(Stub::default());
+        let transport: Arc<dyn HttpTransport> = stub.clone();
+        let builder = crate::FCMClient::builder()
+            .project_id("stub")
+            .user_agent("my-app/1.2")
+            .without_credentials();
+        assert!(builder
+            .clone()
+            .user_agent("my-app\n1.2")
+            .build_with_transport(transport.clone())
+            .await
+            .is_err());
+        let client = builder
+            .build_with_transport(transport)
+            .await
+            .unwrap()
+            .with_access_token("token");
+        let message = Message::Topic {
+            topic: "news".to_string(),
+            data: None,
+            fcm_options: None,
+            notification: None,
+            android: None,
+            webpush: None,
+            apns: None,
+        };
+        let output = client.send(&message).await.unwrap();
+        assert_eq!(output.name.into_string(), "projects/stub/messages/1");
+        let requests = stub.0.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(
+            requests[0].uri(),
+            "https://fcm.googleapis.com/v1/projects/stub/messages:send"
+        );
+        assert_eq!(requests[0].headers()["authorization"], "Bearer token");
+        assert_eq!(
+            requests[0].headers()["user-agent"],
+            format!("my-app/1.2 {}", crate::DEFAULT_USER_AGENT)
+        );
+        assert_eq!(
+            requests[0].headers()["x-goog-api-client"],
+            crate::API_CLIENT
+        );
+    }
+}